@@ -1,4 +1,4 @@
-use criterion::{criterion_group, criterion_main, Criterion};
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
 use pprof::ProfilerGuard;
 use rand::{thread_rng, Rng};
 use std::fs::File;
@@ -41,29 +41,29 @@ fn random_smt(update_count: usize, rng: &mut impl Rng) -> (SMT, Vec<H256>) {
 }
 
 fn bench(c: &mut Criterion) {
-    c.bench_function_over_inputs(
-        "SMT update",
-        |b, &&size| {
+    let mut group = c.benchmark_group("SMT update");
+    for size in [100, 10_000] {
+        group.bench_with_input(BenchmarkId::from_parameter(size), &size, |b, &size| {
             b.iter(|| {
                 let mut rng = thread_rng();
                 random_smt(size, &mut rng)
             });
-        },
-        &[100, 10_000],
-    );
+        });
+    }
+    group.finish();
 
-    c.bench_function_over_inputs(
-        "SMT get",
-        |b, &&size| {
+    let mut group = c.benchmark_group("SMT get");
+    for size in [5_000, 10_000] {
+        group.bench_with_input(BenchmarkId::from_parameter(size), &size, |b, &size| {
             let mut rng = thread_rng();
             let (smt, _keys) = random_smt(size, &mut rng);
             b.iter(|| {
                 let key = random_h256(&mut rng);
                 smt.get(&key).unwrap();
             });
-        },
-        &[5_000, 10_000],
-    );
+        });
+    }
+    group.finish();
 
     c.bench_function("SMT generate merkle proof", |b| {
         let mut rng = thread_rng();
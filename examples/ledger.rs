@@ -0,0 +1,109 @@
+//!
+//! A tiny account ledger:
+//! genesis, two blocks of transfers, proofs for a light wallet,
+//! and state sync into a fresh node.
+//!
+//! Run with `cargo run --example ledger --features ledger`.
+//!
+
+use xsmt::{
+    ledger::{verify_accounts, verify_transfer, Account, Ledger, Transfer},
+    H256,
+};
+
+fn main() -> Result<(), xsmt::error::Error> {
+    let alice: H256 = [1u8; 32].into();
+    let bob: H256 = [2u8; 32].into();
+    let carol: H256 = [3u8; 32].into();
+
+    let mut node = Ledger::genesis(vec![(alice, 1_000), (bob, 500)])?;
+    println!("genesis state root: {:?}", node.state_root());
+
+    let b1 = node.commit_block(vec![
+        Transfer {
+            from: alice,
+            to: bob,
+            amount: 100,
+            nonce: 0,
+        },
+        Transfer {
+            from: bob,
+            to: carol,
+            amount: 50,
+            nonce: 0,
+        },
+    ])?;
+    println!("block {}: {:?}", b1.height, b1);
+
+    let b2 = node.commit_block(vec![Transfer {
+        from: alice,
+        to: carol,
+        amount: 300,
+        nonce: 1,
+    }])?;
+    println!("block {}: {:?}", b2.height, b2);
+
+    // A light wallet only knows the block headers
+    let carol_now = node.account(&carol)?;
+    let proof = node.prove_accounts(vec![carol])?;
+    assert!(verify_accounts(
+        b2.state_root,
+        proof,
+        &[(carol, carol_now)]
+    )?);
+    println!("carol at block {}: {:?}", b2.height, carol_now);
+
+    let carol_then = Account {
+        nonce: 0,
+        balance: 50,
+    };
+    let proof = node.prove_accounts_at(b1.height, vec![carol])?;
+    assert!(verify_accounts(
+        b1.state_root,
+        proof,
+        &[(carol, carol_then)]
+    )?);
+    println!("carol at block {}: {:?}", b1.height, carol_then);
+
+    let tx = Transfer {
+        from: bob,
+        to: carol,
+        amount: 50,
+        nonce: 0,
+    };
+    let proof = node.prove_transfer(b1.height, 1)?;
+    assert!(verify_transfer(b1.transfers_root, proof, 1, &tx)?);
+    println!("transfer {:?} is in block {}", tx.hash(), b1.height);
+
+    // Invalid blocks are rejected as a whole
+    assert!(node
+        .commit_block(vec![Transfer {
+            from: carol,
+            to: alice,
+            amount: 1_000_000,
+            nonce: 0,
+        }])
+        .is_err());
+    assert_eq!(node.height(), b2.height);
+
+    // State sync
+    let mut state = vec![];
+    let n = node.export_state(&mut state)?;
+    let mut peer = Ledger::from_state(node.height(), &state[..])?;
+    assert_eq!(peer.state_root(), node.state_root());
+    println!("synced {} accounts, {} bytes", n, state.len());
+
+    let b3 = Transfer {
+        from: carol,
+        to: alice,
+        amount: 10,
+        nonce: 0,
+    };
+    assert_eq!(
+        node.commit_block(vec![b3.clone()])?,
+        peer.commit_block(vec![b3])?
+    );
+    println!("both nodes agree at block {}", node.height());
+
+    Ok(())
+}
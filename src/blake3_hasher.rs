@@ -1,6 +1,9 @@
-use crate::{traits::Hasher, H256};
+use crate::{
+    traits::{Digest, Hasher},
+    H256, HN,
+};
 use serde::{Deserialize, Serialize};
-use std::fmt;
+use std::{fmt, marker::PhantomData};
 
 #[derive(Clone, Default, Deserialize, Serialize)]
 pub struct Blake3Hasher {
@@ -8,6 +11,25 @@ pub struct Blake3Hasher {
     hasher: blake3::Hasher,
 }
 
+impl Blake3Hasher {
+    /// A hasher in the `keyed_hash` mode of blake3
+    #[inline(always)]
+    pub fn keyed(key: &[u8; 32]) -> Self {
+        Blake3Hasher {
+            hasher: blake3::Hasher::new_keyed(key),
+        }
+    }
+
+    /// A hasher in the `derive_key` mode of blake3,
+    /// `context` should be a hardcoded, globally unique string
+    #[inline(always)]
+    pub fn derive_key(context: &str) -> Self {
+        Blake3Hasher {
+            hasher: blake3::Hasher::new_derive_key(context),
+        }
+    }
+}
+
 impl Hasher for Blake3Hasher {
     #[inline(always)]
     fn write_h256(&mut self, h: &H256) {
@@ -37,8 +59,106 @@ impl Hasher for Blake3Hasher {
     }
 }
 
+/// Any width, from the extendable output of blake3
+impl<const N: usize> Digest<N> for Blake3Hasher {
+    #[inline(always)]
+    fn update(&mut self, bytes: &[u8]) {
+        self.hasher.update(bytes);
+    }
+
+    #[inline(always)]
+    fn finalize(self) -> HN<N> {
+        let mut hash = [0u8; N];
+        self.hasher.finalize_xof().fill(&mut hash);
+        hash.into()
+    }
+}
+
 impl fmt::Debug for Blake3Hasher {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.debug_tuple("Blake3Hasher").finish()
     }
 }
+
+/// The blake3 mode of a `DomainBlake3Hasher`, e.g.
+///
+/// ```
+/// use xsmt::blake3_hasher::{Blake3Domain, Blake3Hasher, DomainBlake3Hasher};
+///
+/// struct MyApp;
+///
+/// impl Blake3Domain for MyApp {
+///     fn hasher() -> Blake3Hasher {
+///         Blake3Hasher::derive_key("my-app 2024-01-01 state tree")
+///     }
+/// }
+///
+/// type MyHasher = DomainBlake3Hasher<MyApp>;
+/// ```
+pub trait Blake3Domain {
+    fn hasher() -> Blake3Hasher;
+}
+
+/// Blake3 in the mode given by `D`.
+///
+/// Trees and proofs create their hashers by `Default`,
+/// so the mode is carried by the type: roots and proofs of different domains
+/// never match, and can not be replayed against each other.
+#[derive(Deserialize, Serialize)]
+#[serde(bound = "D: Blake3Domain")]
+pub struct DomainBlake3Hasher<D> {
+    #[serde(skip, default = "D::hasher")]
+    inner: Blake3Hasher,
+    #[serde(skip)]
+    phantom: PhantomData<fn() -> D>,
+}
+
+impl<D: Blake3Domain> Default for DomainBlake3Hasher<D> {
+    #[inline(always)]
+    fn default() -> Self {
+        DomainBlake3Hasher {
+            inner: D::hasher(),
+            phantom: PhantomData,
+        }
+    }
+}
+
+impl<D> Clone for DomainBlake3Hasher<D> {
+    #[inline(always)]
+    fn clone(&self) -> Self {
+        DomainBlake3Hasher {
+            inner: self.inner.clone(),
+            phantom: PhantomData,
+        }
+    }
+}
+
+impl<D: Blake3Domain> Hasher for DomainBlake3Hasher<D> {
+    #[inline(always)]
+    fn write_h256(&mut self, h: &H256) {
+        self.inner.write_h256(h);
+    }
+
+    #[inline(always)]
+    fn write_byte(&mut self, b: u8) {
+        self.inner.write_byte(b);
+    }
+
+    #[inline(always)]
+    fn finish(self) -> H256 {
+        self.inner.finish()
+    }
+
+    #[inline(always)]
+    fn hash(bytes: &[u8]) -> H256 {
+        let mut hasher = D::hasher();
+        hasher.hasher.update(bytes);
+        hasher.finish()
+    }
+}
+
+impl<D> fmt::Debug for DomainBlake3Hasher<D> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("DomainBlake3Hasher").finish()
+    }
+}
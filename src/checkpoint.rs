@@ -0,0 +1,228 @@
+//!
+//! A single blob for light clients to bootstrap from.
+//!
+//! Layout(all integers are little-endian):
+//! `b"XSCP" | format version(u8) | root(32) | leaves count(u32) | leaves
+//! | proof length(u32) | compiled proof`,
+//! each leaf is encoded as `key(32) | 0`(absent) or `key(32) | 1 | value length(u32) | value bytes`,
+//! leaves are sorted by key.
+//!
+
+use crate::{
+    error::{Error, Result},
+    merkle_proof::CompiledMerkleProof,
+    traits::{Hasher, Store, Value},
+    SparseMerkleTree, H256,
+};
+use std::io::Read;
+use vsdb::ValueEnDe;
+
+const MAGIC: &[u8; 4] = b"XSCP";
+const FORMAT_VERSION: u8 = 1;
+
+/// A root with the compiled proof and the values of a chosen key set
+#[derive(Debug, Clone)]
+pub struct Checkpoint<V> {
+    root: H256,
+    leaves: Vec<(H256, Option<V>)>,
+    proof: CompiledMerkleProof,
+}
+
+impl<V: ValueEnDe> Checkpoint<V> {
+    /// Capture the current root of `tree` with the proof and values of `keys`,
+    /// absent keys are proved absent.
+    pub fn capture<H: Hasher, S: Store<V>, const DEPTH: usize>(
+        tree: &SparseMerkleTree<H, V, S, DEPTH>,
+        keys: Vec<H256>,
+    ) -> Result<Self>
+    where
+        V: Value<H>,
+    {
+        let root = tree.try_root()?;
+        let (proof, leaves) = tree.merkle_proof_with_values(keys)?;
+        let proof = proof.compile(leaves.iter().map(|(k, _)| *k).collect())?;
+        Ok(Checkpoint {
+            root,
+            leaves,
+            proof,
+        })
+    }
+
+    /// The root the checkpoint was captured at
+    #[inline(always)]
+    pub fn root(&self) -> H256 {
+        self.root
+    }
+
+    /// The captured leaves, sorted by key
+    #[inline(always)]
+    pub fn leaves(&self) -> &[(H256, Option<V>)] {
+        &self.leaves
+    }
+
+    /// The compiled proof of the leaves
+    #[inline(always)]
+    pub fn proof(&self) -> &CompiledMerkleProof {
+        &self.proof
+    }
+
+    /// Check the proof of the leaves against the root of the checkpoint
+    pub fn verify<H: Hasher>(&self) -> Result<bool>
+    where
+        V: Value<H>,
+    {
+        let leaves = self
+            .leaves
+            .iter()
+            .map(|(k, v)| (*k, v.as_ref().map(|v| v.to_h256())))
+            .collect();
+        self.proof.verify::<H>(self.root, leaves)
+    }
+
+    /// Decode a blob and check it against a trusted `root`, return its leaves;
+    /// return RootMismatch error if it was captured at another root
+    /// or its proof does not match its root.
+    pub fn open<H: Hasher>(bytes: &[u8], root: H256) -> Result<Vec<(H256, Option<V>)>>
+    where
+        V: Value<H>,
+    {
+        let checkpoint = Self::from_bytes(bytes)?;
+        if checkpoint.root != root {
+            return Err(Error::RootMismatch {
+                expected: root,
+                actual: checkpoint.root,
+            });
+        }
+        if !checkpoint.verify::<H>()? {
+            let leaves = checkpoint
+                .leaves
+                .iter()
+                .map(|(k, v)| (*k, v.as_ref().map(|v| v.to_h256())))
+                .collect();
+            return Err(Error::RootMismatch {
+                expected: root,
+                actual: checkpoint.proof.compute_root::<H>(leaves)?,
+            });
+        }
+        Ok(checkpoint.leaves)
+    }
+
+    /// Encode into the blob layout
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(45 + self.leaves.len() * 40 + self.proof.0.len());
+        buf.extend_from_slice(MAGIC);
+        buf.push(FORMAT_VERSION);
+        buf.extend_from_slice(self.root.as_slice());
+        buf.extend_from_slice(&(self.leaves.len() as u32).to_le_bytes());
+        for (k, v) in self.leaves.iter() {
+            buf.extend_from_slice(k.as_slice());
+            match v {
+                Some(v) => {
+                    let v = v.encode();
+                    buf.push(1);
+                    buf.extend_from_slice(&(v.len() as u32).to_le_bytes());
+                    buf.extend_from_slice(&v);
+                }
+                None => buf.push(0),
+            }
+        }
+        buf.extend_from_slice(&(self.proof.0.len() as u32).to_le_bytes());
+        buf.extend_from_slice(&self.proof.0);
+        buf
+    }
+
+    /// Decode a blob produced by `to_bytes`, the proof is not verified,
+    /// see `verify` and `open`.
+    pub fn from_bytes(mut bytes: &[u8]) -> Result<Self> {
+        let reader = &mut bytes;
+        let mut magic = [0u8; 4];
+        read_exact(reader, &mut magic)?;
+        if &magic != MAGIC {
+            return Err(Error::Checkpoint("invalid magic bytes".to_owned()));
+        }
+        let version = read_u8(reader)?;
+        if version != FORMAT_VERSION {
+            return Err(Error::Checkpoint(format!(
+                "unsupported format version: {}",
+                version
+            )));
+        }
+        let root = read_h256(reader)?;
+
+        let count = read_u32(reader)? as usize;
+        let mut leaves: Vec<(H256, Option<V>)> =
+            Vec::with_capacity(count.min(reader.len() / 33));
+        for _ in 0..count {
+            let key = read_h256(reader)?;
+            let value = match read_u8(reader)? {
+                0 => None,
+                1 => {
+                    let mut buf = vec![0u8; read_len(reader)?];
+                    read_exact(reader, &mut buf)?;
+                    Some(V::decode(&buf).map_err(|e| Error::Checkpoint(e.to_string()))?)
+                }
+                tag => {
+                    return Err(Error::Checkpoint(format!("invalid leaf tag: {}", tag)));
+                }
+            };
+            if leaves.last().is_some_and(|(k, _)| *k >= key) {
+                return Err(Error::Checkpoint("leaves are not sorted".to_owned()));
+            }
+            leaves.push((key, value));
+        }
+
+        let mut proof = vec![0u8; read_len(reader)?];
+        read_exact(reader, &mut proof)?;
+        if !reader.is_empty() {
+            return Err(Error::Checkpoint("trailing bytes".to_owned()));
+        }
+
+        Ok(Checkpoint {
+            root,
+            leaves,
+            proof: CompiledMerkleProof::from_ckb_bytes(&proof)?,
+        })
+    }
+}
+
+#[inline(always)]
+fn read_exact(reader: &mut &[u8], buf: &mut [u8]) -> Result<()> {
+    reader
+        .read_exact(buf)
+        .map_err(|e| Error::Checkpoint(e.to_string()))
+}
+
+// a length prefix, checked against the bytes left before anything is allocated
+#[inline(always)]
+fn read_len(reader: &mut &[u8]) -> Result<usize> {
+    let len = read_u32(reader)? as usize;
+    if len > reader.len() {
+        return Err(Error::Checkpoint(format!(
+            "length {} exceeds the {} bytes left",
+            len,
+            reader.len()
+        )));
+    }
+    Ok(len)
+}
+
+#[inline(always)]
+fn read_u8(reader: &mut &[u8]) -> Result<u8> {
+    let mut buf = [0u8; 1];
+    read_exact(reader, &mut buf)?;
+    Ok(buf[0])
+}
+
+#[inline(always)]
+fn read_u32(reader: &mut &[u8]) -> Result<u32> {
+    let mut buf = [0u8; 4];
+    read_exact(reader, &mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+#[inline(always)]
+fn read_h256(reader: &mut &[u8]) -> Result<H256> {
+    let mut buf = [0u8; 32];
+    read_exact(reader, &mut buf)?;
+    Ok(buf.into())
+}
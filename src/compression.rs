@@ -0,0 +1,145 @@
+//!
+//! Transport compression of merkle proofs.
+//!
+//! Every compressed payload starts with a one-byte codec tag,
+//! followed by the codec output of `MerkleProof::to_bytes`.
+//!
+
+use crate::{
+    error::{Error, Result},
+    MerkleProof,
+};
+
+const TAG_NONE: u8 = 0;
+#[cfg(feature = "zstd")]
+const TAG_ZSTD: u8 = 1;
+#[cfg(feature = "snappy")]
+const TAG_SNAPPY: u8 = 2;
+
+/// Codecs available for proof transport
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Codec {
+    /// Raw bytes, no compression
+    None,
+    /// Zstandard, optionally with a pre-trained dictionary
+    #[cfg(feature = "zstd")]
+    Zstd { level: i32, dict: Option<Vec<u8>> },
+    /// Snappy raw format
+    #[cfg(feature = "snappy")]
+    Snappy,
+}
+
+impl Codec {
+    #[inline(always)]
+    fn tag(&self) -> u8 {
+        match self {
+            Codec::None => TAG_NONE,
+            #[cfg(feature = "zstd")]
+            Codec::Zstd { .. } => TAG_ZSTD,
+            #[cfg(feature = "snappy")]
+            Codec::Snappy => TAG_SNAPPY,
+        }
+    }
+
+    /// Compress raw bytes, the result is prefixed with the codec tag
+    pub fn compress(&self, raw: &[u8]) -> Result<Vec<u8>> {
+        let mut buf = vec![self.tag()];
+        match self {
+            Codec::None => buf.extend_from_slice(raw),
+            #[cfg(feature = "zstd")]
+            Codec::Zstd { level, dict } => {
+                let mut c = match dict {
+                    Some(d) => zstd::bulk::Compressor::with_dictionary(*level, d),
+                    None => zstd::bulk::Compressor::new(*level),
+                }
+                .map_err(|e| Error::Compression(e.to_string()))?;
+                let data = c
+                    .compress(raw)
+                    .map_err(|e| Error::Compression(e.to_string()))?;
+                buf.extend_from_slice(&data);
+            }
+            #[cfg(feature = "snappy")]
+            Codec::Snappy => {
+                let data = snap::raw::Encoder::new()
+                    .compress_vec(raw)
+                    .map_err(|e| Error::Compression(e.to_string()))?;
+                buf.extend_from_slice(&data);
+            }
+        }
+        Ok(buf)
+    }
+
+    /// Decompress bytes produced by `compress` with the same codec
+    pub fn decompress(&self, bytes: &[u8]) -> Result<Vec<u8>> {
+        let (tag, data) = bytes
+            .split_first()
+            .ok_or_else(|| Error::Compression("empty payload".to_owned()))?;
+        if *tag != self.tag() {
+            return Err(Error::Compression(format!(
+                "codec mismatch, expected tag {} actual {}",
+                self.tag(),
+                tag
+            )));
+        }
+        match self {
+            Codec::None => Ok(data.to_vec()),
+            #[cfg(feature = "zstd")]
+            Codec::Zstd { dict, .. } => {
+                let mut d = match dict {
+                    Some(d) => zstd::bulk::Decompressor::with_dictionary(d),
+                    None => zstd::bulk::Decompressor::new(),
+                }
+                .map_err(|e| Error::Compression(e.to_string()))?;
+                let capacity = zstd::bulk::Decompressor::upper_bound(data)
+                    .unwrap_or(MAX_DECOMPRESSED_SIZE)
+                    .min(MAX_DECOMPRESSED_SIZE);
+                d.decompress(data, capacity)
+                    .map_err(|e| Error::Compression(e.to_string()))
+            }
+            #[cfg(feature = "snappy")]
+            Codec::Snappy => {
+                // the length in the header is checked before allocating for it
+                let len = snap::raw::decompress_len(data)
+                    .map_err(|e| Error::Compression(e.to_string()))?;
+                if len > MAX_DECOMPRESSED_SIZE {
+                    return Err(Error::Compression(format!(
+                        "decompressed size {} exceeds the limit {}",
+                        len, MAX_DECOMPRESSED_SIZE
+                    )));
+                }
+                snap::raw::Decoder::new()
+                    .decompress_vec(data)
+                    .map_err(|e| Error::Compression(e.to_string()))
+            }
+        }
+    }
+}
+
+// Upper limit of a decompressed proof, guards against decompression bombs
+#[cfg(any(feature = "zstd", feature = "snappy"))]
+const MAX_DECOMPRESSED_SIZE: usize = 64 * 1024 * 1024;
+
+impl MerkleProof {
+    /// Serialize and compress the proof with the given codec
+    #[inline(always)]
+    pub fn to_compressed_bytes(&self, codec: &Codec) -> Result<Vec<u8>> {
+        codec.compress(&self.to_bytes())
+    }
+
+    /// Decompress and deserialize a proof produced by `to_compressed_bytes`
+    #[inline(always)]
+    pub fn from_compressed_bytes(codec: &Codec, bytes: &[u8]) -> Result<Self> {
+        codec
+            .decompress(bytes)
+            .and_then(|raw| MerkleProof::from_bytes(&raw))
+    }
+}
+
+/// Train a zstd dictionary over sampled proofs,
+/// the result can be used in `Codec::Zstd { dict, .. }`
+#[cfg(feature = "zstd")]
+pub fn train_zstd_dict(samples: &[MerkleProof], max_size: usize) -> Result<Vec<u8>> {
+    let samples = samples.iter().map(|p| p.to_bytes()).collect::<Vec<_>>();
+    zstd::dict::from_samples(&samples, max_size)
+        .map_err(|e| Error::Compression(e.to_string()))
+}
@@ -1,25 +1,113 @@
 use crate::{
     chg_store,
-    error::Error,
+    error::{Error, StoreOp, StoreTarget},
     traits::{Store, Store2},
     tree::{BranchKey, BranchNode},
     H256,
 };
 use ruc::*;
-use serde::{Deserialize, Serialize};
-use std::result::Result as StdResult;
-#[cfg(test)]
-use vsdb::VsMgmt;
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use std::{
+    fmt, fs,
+    ops::{Bound, RangeBounds},
+    path::{Path, PathBuf},
+    result::Result as StdResult,
+};
 use vsdb::{
-    BranchName, KeyEnDe, MapxDkVs, MapxVs, OrphanVs, ValueEnDe, VersionName, Vs,
+    BranchName, KeyEnDe, MapxDkVs, MapxOrdVs, MapxVs, OrphanVs, ValueEnDe, VersionName,
+    Vs, VsMgmt,
 };
 
+/// Set the directory of the vsdb backend of all default stores,
+/// it must be called before any of them is created.
+///
+/// vsdb keeps one database per process, so the directory can not be set
+/// per store. Stores of the same process never collide, every map of a store
+/// gets its own prefix in the database; it is separate processes,
+/// e.g. parallel test binaries, that need separate directories.
+pub fn set_data_dir(dir: impl AsRef<Path>) -> StdResult<(), Error> {
+    vsdb::vsdb_set_base_dir(dir.as_ref()).map_err(|e| {
+        Error::store(
+            StoreOp::SetDataDir,
+            StoreTarget::DataDir(dir.as_ref().to_path_buf()),
+            e,
+        )
+    })
+}
+
+/// The directory of the vsdb backend, see `set_data_dir`
+#[inline(always)]
+pub fn data_dir() -> PathBuf {
+    vsdb::vsdb_get_base_dir()
+}
+
+/// A branch as stored by the default stores,
+/// in the encoding of `BranchNode::to_compact_bytes`;
+/// branches stored by 3.0.7 and earlier, in the derived encoding of `BranchNode`,
+/// are still decoded(see the README)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct CompactBranch(pub(crate) BranchNode);
+
+impl Serialize for CompactBranch {
+    fn serialize<S: Serializer>(&self, serializer: S) -> StdResult<S::Ok, S::Error> {
+        serializer.serialize_bytes(&self.0.to_compact_bytes())
+    }
+}
+
+impl<'de> Deserialize<'de> for CompactBranch {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> StdResult<Self, D::Error> {
+        // self-describing formats(msgpack) hand a legacy branch to `visit_seq`
+        deserializer.deserialize_byte_buf(CompactBranchVisitor)
+    }
+}
+
+struct CompactBranchVisitor;
+
+impl<'de> de::Visitor<'de> for CompactBranchVisitor {
+    type Value = CompactBranch;
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("a compact branch or a legacy branch node")
+    }
+
+    fn visit_bytes<E: de::Error>(self, v: &[u8]) -> StdResult<CompactBranch, E> {
+        BranchNode::from_compact_bytes(v)
+            .map(CompactBranch)
+            .map_err(de::Error::custom)
+    }
+
+    // the legacy encoding, `BranchNode` as the sequence of its fields
+    fn visit_seq<A: de::SeqAccess<'de>>(
+        self,
+        mut seq: A,
+    ) -> StdResult<CompactBranch, A::Error> {
+        let left = seq
+            .next_element()?
+            .ok_or_else(|| de::Error::invalid_length(0, &self))?;
+        let right = seq
+            .next_element()?
+            .ok_or_else(|| de::Error::invalid_length(1, &self))?;
+        Ok(CompactBranch(BranchNode { left, right }))
+    }
+}
+
+/// A `Store` on vsdb, see `set_data_dir` for where it is stored
 #[derive(Vs, Debug, Clone, Deserialize, Serialize)]
 #[serde(bound = "")]
 pub struct DefaultStore<V: ValueEnDe> {
     root: OrphanVs<H256>,
-    branches_map: MapxVs<BranchKey, BranchNode>,
+    branches_map: MapxVs<BranchKey, CompactBranch>,
     leaves_map: MapxVs<H256, V>,
+    // the maps below are not in stores of 3.0.7 and earlier
+    #[serde(default)]
+    leaf_hashes_map: MapxVs<H256, H256>,
+    #[serde(default)]
+    root_history: MapxOrdVs<u64, H256>,
+    #[serde(default)]
+    leaves_count: OrphanVs<u64>,
+    // the keys of `leaves_map` in order, complete once `leaves_count` is set
+    #[serde(default)]
+    leaf_keys: MapxOrdVs<H256, ()>,
 }
 
 impl<V: ValueEnDe> Default for DefaultStore<V> {
@@ -30,6 +118,10 @@ impl<V: ValueEnDe> Default for DefaultStore<V> {
             root: OrphanVs::new(),
             branches_map: MapxVs::new(),
             leaves_map: MapxVs::new(),
+            leaf_hashes_map: MapxVs::new(),
+            root_history: MapxOrdVs::new(),
+            leaves_count: OrphanVs::new(),
+            leaf_keys: MapxOrdVs::new(),
         }
     }
 
@@ -40,6 +132,10 @@ impl<V: ValueEnDe> Default for DefaultStore<V> {
             root: OrphanVs::new(),
             branches_map: MapxVs::new(),
             leaves_map: MapxVs::new(),
+            leaf_hashes_map: MapxVs::new(),
+            root_history: MapxOrdVs::new(),
+            leaves_count: OrphanVs::new(),
+            leaf_keys: MapxOrdVs::new(),
         };
 
         pnk!(ds.version_create((&[0u8; 0][..]).into()));
@@ -55,13 +151,21 @@ impl<V: ValueEnDe> Store<V> for DefaultStore<V> {
         branch_key: BranchKey,
         branch: BranchNode,
     ) -> StdResult<(), Error> {
-        chg_store!(self.branches_map.insert(&branch_key, &branch));
+        chg_store!(
+            Insert,
+            StoreTarget::Branch(branch_key),
+            self.branches_map.insert(&branch_key, &CompactBranch(branch))
+        );
         Ok(())
     }
 
     #[inline(always)]
     fn remove_branch(&mut self, branch_key: &BranchKey) -> StdResult<(), Error> {
-        chg_store!(self.branches_map.remove(branch_key));
+        chg_store!(
+            Remove,
+            StoreTarget::Branch(branch_key.clone()),
+            self.branches_map.remove(branch_key)
+        );
         Ok(())
     }
 
@@ -70,26 +174,146 @@ impl<V: ValueEnDe> Store<V> for DefaultStore<V> {
         &self,
         branch_key: &BranchKey,
     ) -> StdResult<Option<BranchNode>, Error> {
-        Ok(self.branches_map.get(branch_key))
+        Ok(self.branches_map.get(branch_key).map(|b| b.0))
+    }
+
+    #[inline(always)]
+    fn get_branch_by_branch_version(
+        &self,
+        branch_key: &BranchKey,
+        br: BranchName,
+        ver: VersionName,
+    ) -> StdResult<Option<BranchNode>, Error> {
+        Ok(self
+            .branches_map
+            .get_by_branch_version(branch_key, br, ver)
+            .map(|b| b.0))
     }
 
     #[inline(always)]
     fn insert_leaf(&mut self, leaf_key: H256, leaf: V) -> StdResult<(), Error> {
-        chg_store!(self.leaves_map.insert(&leaf_key, &leaf));
+        chg_store!(
+            Insert,
+            StoreTarget::Leaf(leaf_key),
+            self.leaves_map.insert(&leaf_key, &leaf)
+        );
+        chg_store!(
+            Insert,
+            StoreTarget::Leaf(leaf_key),
+            self.leaf_keys.insert(&leaf_key, &())
+        );
+        chg_store!(
+            Remove,
+            StoreTarget::Leaf(leaf_key),
+            self.leaf_hashes_map.remove(&leaf_key)
+        );
         Ok(())
     }
 
     #[inline(always)]
-    fn remove_leaf(&mut self, leaf_key: &H256) -> StdResult<(), Error> {
-        chg_store!(self.leaves_map.remove(leaf_key));
+    fn insert_leaf_with_hash(
+        &mut self,
+        leaf_key: H256,
+        leaf: V,
+        leaf_hash: H256,
+    ) -> StdResult<(), Error> {
+        chg_store!(
+            Insert,
+            StoreTarget::Leaf(leaf_key),
+            self.leaves_map.insert(&leaf_key, &leaf)
+        );
+        chg_store!(
+            Insert,
+            StoreTarget::Leaf(leaf_key),
+            self.leaf_keys.insert(&leaf_key, &())
+        );
+        chg_store!(
+            Insert,
+            StoreTarget::Leaf(leaf_key),
+            self.leaf_hashes_map.insert(&leaf_key, &leaf_hash)
+        );
         Ok(())
     }
 
+    #[inline(always)]
+    fn get_leaf_hash(&self, leaf_key: &H256) -> StdResult<Option<H256>, Error> {
+        Ok(self.leaf_hashes_map.get(leaf_key))
+    }
+
+    #[inline(always)]
+    fn remove_leaf(&mut self, leaf_key: &H256) -> StdResult<(), Error> {
+        self.take_leaf(leaf_key).map(|_| ())
+    }
+
+    fn take_leaf(&mut self, leaf_key: &H256) -> StdResult<Option<V>, Error> {
+        let target = StoreTarget::Leaf(*leaf_key);
+        let leaf = self
+            .leaves_map
+            .remove(leaf_key)
+            .map_err(|e| Error::store(StoreOp::Remove, target, e))?;
+        chg_store!(
+            Remove,
+            StoreTarget::Leaf(*leaf_key),
+            self.leaf_keys.remove(leaf_key)
+        );
+        chg_store!(
+            Remove,
+            StoreTarget::Leaf(*leaf_key),
+            self.leaf_hashes_map.remove(leaf_key)
+        );
+        Ok(leaf)
+    }
+
     #[inline(always)]
     fn get_leaf(&self, leaf_key: &H256) -> StdResult<Option<V>, Error> {
         Ok(self.leaves_map.get(leaf_key))
     }
 
+    #[inline(always)]
+    fn has_leaf(&self, leaf_key: &H256) -> StdResult<bool, Error> {
+        Ok(self.leaves_map.contains_key(leaf_key))
+    }
+
+    // counted once if unset, e.g. in a store of 3.0.7 and earlier
+    #[inline(always)]
+    fn leaves_count(&self) -> StdResult<u64, Error> {
+        Ok(self
+            .leaves_count
+            .get_value()
+            .unwrap_or_else(|| self.leaves_map.iter().count() as u64))
+    }
+
+    // an unset counter is set by counting the leaves, which are already written,
+    // and the ordered index of their keys is completed
+    fn add_leaves_count(&mut self, delta: i64) -> StdResult<(), Error> {
+        let count = match self.leaves_count.get_value() {
+            Some(_) if delta == 0 => return Ok(()),
+            Some(count) => count.saturating_add_signed(delta),
+            None => {
+                let keys: Vec<H256> = self.leaves_map.iter().map(|(k, _)| k).collect();
+                for k in keys.iter() {
+                    chg_store!(
+                        Insert,
+                        StoreTarget::Leaf(*k),
+                        self.leaf_keys.insert(k, &())
+                    );
+                }
+                keys.len() as u64
+            }
+        };
+        chg_store!(
+            Insert,
+            StoreTarget::LeavesCount,
+            self.leaves_count.set_value(&count)
+        );
+        Ok(())
+    }
+
+    #[inline(always)]
+    fn get_leaves(&self, leaf_keys: &[H256]) -> StdResult<Vec<Option<V>>, Error> {
+        Ok(get_sorted(leaf_keys, |k| self.leaves_map.get(k)))
+    }
+
     #[inline(always)]
     fn get_leaf_by_branch(
         &self,
@@ -111,7 +335,7 @@ impl<V: ValueEnDe> Store<V> for DefaultStore<V> {
 
     #[inline(always)]
     fn update_root(&mut self, new_root: H256) -> StdResult<(), Error> {
-        chg_store!(self.root.set_value(&new_root));
+        chg_store!(Insert, StoreTarget::Root, self.root.set_value(&new_root));
         Ok(())
     }
 
@@ -119,14 +343,161 @@ impl<V: ValueEnDe> Store<V> for DefaultStore<V> {
     fn get_root(&self) -> StdResult<H256, Error> {
         Ok(self.root.get_value().unwrap_or_else(H256::zero))
     }
+
+    #[inline(always)]
+    fn get_root_by_branch_version(
+        &self,
+        br: BranchName,
+        ver: VersionName,
+    ) -> StdResult<H256, Error> {
+        Ok(self
+            .root
+            .get_value_by_branch_version(br, ver)
+            .unwrap_or_else(H256::zero))
+    }
+
+    #[inline(always)]
+    fn get_root_by_branch(&self, br: BranchName) -> StdResult<H256, Error> {
+        Ok(self.root.get_value_by_branch(br).unwrap_or_else(H256::zero))
+    }
+
+    #[inline(always)]
+    fn push_root_history(&mut self, root: H256) -> StdResult<u64, Error> {
+        let seq = self
+            .root_history
+            .last()
+            .map(|(seq, _)| seq + 1)
+            .unwrap_or(0);
+        chg_store!(
+            Insert,
+            StoreTarget::RootHistory(seq),
+            self.root_history.insert(&seq, &root)
+        );
+        Ok(seq)
+    }
+
+    #[inline(always)]
+    fn get_root_by_seq(&self, seq: u64) -> StdResult<Option<H256>, Error> {
+        Ok(self.root_history.get(&seq))
+    }
+
+    #[inline(always)]
+    fn iter_root_history(
+        &self,
+        range: (Bound<u64>, Bound<u64>),
+    ) -> Box<dyn Iterator<Item = (u64, H256)> + '_> {
+        Box::new(self.root_history.range(range))
+    }
+
+    #[inline(always)]
+    fn iter_branches(&self) -> Box<dyn Iterator<Item = (BranchKey, BranchNode)> + '_> {
+        Box::new(self.branches_map.iter().map(|(k, b)| (k, b.0)))
+    }
+
+    #[inline(always)]
+    fn iter_leaves<'a>(&'a self) -> Box<dyn Iterator<Item = (H256, V)> + 'a>
+    where
+        V: 'a,
+    {
+        Box::new(self.leaves_map.iter())
+    }
+
+    // the keys are read in order from `leaf_keys`,
+    // or sorted after a scan if it is not complete yet
+    fn iter_leaves_range<'a>(
+        &'a self,
+        range: (Bound<H256>, Bound<H256>),
+    ) -> Box<dyn Iterator<Item = (H256, V)> + 'a>
+    where
+        V: 'a,
+    {
+        if self.leaves_count.get_value().is_none() {
+            let mut leaves: Vec<(H256, V)> = self
+                .leaves_map
+                .iter()
+                .filter(|(k, _)| range.contains(k))
+                .collect();
+            leaves.sort_unstable_by_key(|(k, _)| *k);
+            return Box::new(leaves.into_iter());
+        }
+        Box::new(
+            self.leaf_keys
+                .range(range)
+                .filter_map(|(k, _)| self.leaves_map.get(&k).map(|v| (k, v))),
+        )
+    }
+
+    #[inline(always)]
+    fn iter_leaves_by_branch_version<'a>(
+        &'a self,
+        br: BranchName,
+        ver: VersionName,
+    ) -> Box<dyn Iterator<Item = (H256, V)> + 'a>
+    where
+        V: 'a,
+    {
+        Box::new(self.leaves_map.iter_by_branch_version(br, ver))
+    }
+
+    // Drop orphan versions, the bytes freed are measured on the whole data directory,
+    // which is shared by all stores of the process and shrinks as the backend
+    // gets to reclaim the space, so they are a hint
+    fn compact(&mut self) -> StdResult<u64, Error> {
+        let dir = data_dir();
+        vsdb::vsdb_flush();
+        let before = dir_size(&dir);
+        chg_store!(
+            Remove,
+            StoreTarget::Versions,
+            self.version_clean_up_globally()
+        );
+        vsdb::vsdb_flush();
+        Ok(before.saturating_sub(dir_size(&dir)))
+    }
+}
+
+// Read the values of `keys` in ascending key order, so that the reads of a batch
+// walk the backend once instead of jumping around, return them in the order of `keys`
+fn get_sorted<T>(
+    keys: &[H256],
+    mut get: impl FnMut(&H256) -> Option<T>,
+) -> Vec<Option<T>> {
+    let mut order: Vec<usize> = (0..keys.len()).collect();
+    order.sort_unstable_by_key(|i| keys[*i]);
+    let mut values: Vec<Option<T>> = keys.iter().map(|_| None).collect();
+    for i in order {
+        values[i] = get(&keys[i]);
+    }
+    values
 }
 
+// Total size of the files under `dir`, unreadable entries are skipped
+fn dir_size(dir: &Path) -> u64 {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return 0;
+    };
+    entries
+        .flatten()
+        .map(|entry| match entry.metadata() {
+            Ok(meta) if meta.is_dir() => dir_size(&entry.path()),
+            Ok(meta) => meta.len(),
+            Err(_) => 0,
+        })
+        .sum()
+}
+
+/// A `Store2` on vsdb, see `set_data_dir` for where it is stored
 #[derive(Vs, Debug, Clone, Deserialize, Serialize)]
 #[serde(bound = "")]
 pub struct DefaultStore2<X: KeyEnDe, V: ValueEnDe> {
     root: MapxVs<X, H256>,
-    branches_map: MapxDkVs<X, BranchKey, BranchNode>,
+    branches_map: MapxDkVs<X, BranchKey, CompactBranch>,
     leaves_map: MapxDkVs<X, H256, V>,
+    // the maps below are not in stores of 3.0.7 and earlier
+    #[serde(default)]
+    leaves_count: MapxVs<X, u64>,
+    #[serde(default)]
+    leaf_hashes_map: MapxDkVs<X, H256, H256>,
 }
 
 impl<X: KeyEnDe, V: ValueEnDe> Default for DefaultStore2<X, V> {
@@ -137,6 +508,8 @@ impl<X: KeyEnDe, V: ValueEnDe> Default for DefaultStore2<X, V> {
             root: MapxVs::new(),
             branches_map: MapxDkVs::new(),
             leaves_map: MapxDkVs::new(),
+            leaves_count: MapxVs::new(),
+            leaf_hashes_map: MapxDkVs::new(),
         }
     }
 
@@ -147,6 +520,8 @@ impl<X: KeyEnDe, V: ValueEnDe> Default for DefaultStore2<X, V> {
             root: MapxVs::new(),
             branches_map: MapxDkVs::new(),
             leaves_map: MapxDkVs::new(),
+            leaves_count: MapxVs::new(),
+            leaf_hashes_map: MapxDkVs::new(),
         };
 
         pnk!(ds.version_create((&[0u8; 0][..]).into()));
@@ -163,13 +538,22 @@ impl<X: KeyEnDe, V: ValueEnDe> Store2<X, V> for DefaultStore2<X, V> {
         node_key: BranchKey,
         branch: BranchNode,
     ) -> StdResult<(), Error> {
-        chg_store!(self.branches_map.insert(&(xid, &node_key), &branch));
+        chg_store!(
+            Insert,
+            StoreTarget::Branch(node_key),
+            self.branches_map
+                .insert(&(xid, &node_key), &CompactBranch(branch))
+        );
         Ok(())
     }
 
     #[inline(always)]
     fn remove_branch(&mut self, xid: &X, node_key: &BranchKey) -> StdResult<(), Error> {
-        chg_store!(self.branches_map.remove(&(xid, Some(node_key))));
+        chg_store!(
+            Remove,
+            StoreTarget::Branch(node_key.clone()),
+            self.branches_map.remove(&(xid, Some(node_key)))
+        );
         Ok(())
     }
 
@@ -179,26 +563,127 @@ impl<X: KeyEnDe, V: ValueEnDe> Store2<X, V> for DefaultStore2<X, V> {
         xid: &X,
         branch_key: &BranchKey,
     ) -> StdResult<Option<BranchNode>, Error> {
-        Ok(self.branches_map.get(&(xid, branch_key)))
+        Ok(self.branches_map.get(&(xid, branch_key)).map(|b| b.0))
+    }
+
+    #[inline(always)]
+    fn get_branch_by_branch_version(
+        &self,
+        xid: &X,
+        branch_key: &BranchKey,
+        br: BranchName,
+        ver: VersionName,
+    ) -> StdResult<Option<BranchNode>, Error> {
+        Ok(self
+            .branches_map
+            .get_by_branch_version(&(xid, branch_key), br, ver)
+            .map(|b| b.0))
     }
 
     #[inline(always)]
     fn insert_leaf(&mut self, xid: &X, leaf_key: H256, leaf: V) -> StdResult<(), Error> {
-        chg_store!(self.leaves_map.insert(&(xid, &leaf_key), &leaf));
+        chg_store!(
+            Insert,
+            StoreTarget::Leaf(leaf_key),
+            self.leaves_map.insert(&(xid, &leaf_key), &leaf)
+        );
+        chg_store!(
+            Remove,
+            StoreTarget::Leaf(leaf_key),
+            self.leaf_hashes_map.remove(&(xid, Some(&leaf_key)))
+        );
         Ok(())
     }
 
     #[inline(always)]
-    fn remove_leaf(&mut self, xid: &X, leaf_key: &H256) -> StdResult<(), Error> {
-        chg_store!(self.leaves_map.remove(&(xid, Some(leaf_key))));
+    fn insert_leaf_with_hash(
+        &mut self,
+        xid: &X,
+        leaf_key: H256,
+        leaf: V,
+        leaf_hash: H256,
+    ) -> StdResult<(), Error> {
+        chg_store!(
+            Insert,
+            StoreTarget::Leaf(leaf_key),
+            self.leaves_map.insert(&(xid, &leaf_key), &leaf)
+        );
+        chg_store!(
+            Insert,
+            StoreTarget::Leaf(leaf_key),
+            self.leaf_hashes_map.insert(&(xid, &leaf_key), &leaf_hash)
+        );
         Ok(())
     }
 
+    #[inline(always)]
+    fn get_leaf_hash(&self, xid: &X, leaf_key: &H256) -> StdResult<Option<H256>, Error> {
+        Ok(self.leaf_hashes_map.get(&(xid, leaf_key)))
+    }
+
+    #[inline(always)]
+    fn remove_leaf(&mut self, xid: &X, leaf_key: &H256) -> StdResult<(), Error> {
+        self.take_leaf(xid, leaf_key).map(|_| ())
+    }
+
+    fn take_leaf(&mut self, xid: &X, leaf_key: &H256) -> StdResult<Option<V>, Error> {
+        let target = StoreTarget::Leaf(*leaf_key);
+        let leaf = self
+            .leaves_map
+            .remove(&(xid, Some(leaf_key)))
+            .map_err(|e| Error::store(StoreOp::Remove, target.clone(), e))?;
+        chg_store!(
+            Remove,
+            target,
+            self.leaf_hashes_map.remove(&(xid, Some(leaf_key)))
+        );
+        Ok(leaf)
+    }
+
     #[inline(always)]
     fn get_leaf(&self, xid: &X, leaf_key: &H256) -> StdResult<Option<V>, Error> {
         Ok(self.leaves_map.get(&(xid, leaf_key)))
     }
 
+    #[inline(always)]
+    fn has_leaf(&self, xid: &X, leaf_key: &H256) -> StdResult<bool, Error> {
+        Ok(self.leaves_map.contains_key(&(xid, leaf_key)))
+    }
+
+    // counted once if unset, e.g. in a store of 3.0.7 and earlier
+    #[inline(always)]
+    fn leaves_count(&self, xid: &X) -> StdResult<u64, Error> {
+        match self.leaves_count.get(xid) {
+            Some(count) => Ok(count),
+            None => self.count_leaves(xid),
+        }
+    }
+
+    // an unset counter is set by counting the leaves,
+    // which are already written
+    fn add_leaves_count(&mut self, xid: &X, delta: i64) -> StdResult<(), Error> {
+        let count = match self.leaves_count.get(xid) {
+            Some(_) if delta == 0 => return Ok(()),
+            Some(count) => count.saturating_add_signed(delta),
+            None => self.count_leaves(xid)?,
+        };
+        chg_store!(
+            Insert,
+            StoreTarget::Xid(xid.encode().into()),
+            self.leaves_count.insert(xid, &count)
+        );
+        Ok(())
+    }
+
+    #[inline(always)]
+    fn get_leaves(
+        &self,
+        xid: &X,
+        leaf_keys: &[H256],
+    ) -> StdResult<Vec<Option<V>>, Error> {
+        Ok(get_sorted(leaf_keys, |k| self.leaves_map.get(&(xid, k))))
+    }
+
     #[inline(always)]
     fn get_leaf_by_branch(
         &self,
@@ -225,15 +710,77 @@ impl<X: KeyEnDe, V: ValueEnDe> Store2<X, V> for DefaultStore2<X, V> {
     // Remove all data under the xid(top-level key).
     #[inline(always)]
     fn remove_x(&mut self, xid: &X) -> StdResult<(), Error> {
-        chg_store!(self.root.remove(xid));
-        chg_store!(self.branches_map.remove(&(xid, None)));
-        chg_store!(self.leaves_map.remove(&(xid, None)));
+        let target = || StoreTarget::Xid(xid.encode().into());
+        chg_store!(Remove, target(), self.root.remove(xid));
+        chg_store!(Remove, target(), self.branches_map.remove(&(xid, None)));
+        chg_store!(Remove, target(), self.leaves_map.remove(&(xid, None)));
+        chg_store!(Remove, target(), self.leaves_count.remove(xid));
+        chg_store!(Remove, target(), self.leaf_hashes_map.remove(&(xid, None)));
+        Ok(())
+    }
+
+    #[inline(always)]
+    fn iter_x<'a>(&'a self) -> Box<dyn Iterator<Item = X> + 'a>
+    where
+        X: 'a,
+    {
+        Box::new(
+            self.root
+                .iter()
+                .filter(|(_, root)| !root.is_zero())
+                .map(|(xid, _)| xid),
+        )
+    }
+
+    // the subtree is streamed: the copies are written through handles sharing
+    // the data of the maps while the subtree of `src` is read
+    fn clone_x(&mut self, src: &X, dst: &X) -> StdResult<(), Error> {
+        if src.encode() == dst.encode() {
+            return Ok(());
+        }
+        self.remove_x(dst)?;
+        let target = || StoreTarget::Xid(src.encode().into());
+
+        let mut branches = self.branches_map.clone();
+        chg_store!(
+            Iter,
+            target(),
+            self.branches_map.iter_op_with_key_prefix(
+                &mut |(_, k), v: CompactBranch| {
+                    branches.insert(&(dst, &k), &v).map(|_| ())
+                },
+                src
+            )
+        );
+        let mut leaves = self.leaves_map.clone();
+        let mut count = 0u64;
+        chg_store!(
+            Iter,
+            target(),
+            self.leaves_map.iter_op_with_key_prefix(
+                &mut |(_, k), v| {
+                    count += 1;
+                    leaves.insert(&(dst, &k), &v).map(|_| ())
+                },
+                src
+            )
+        );
+        chg_store!(
+            Insert,
+            StoreTarget::Xid(dst.encode().into()),
+            self.leaves_count.insert(dst, &count)
+        );
+
+        let root = self.get_root(src)?;
+        if !root.is_zero() {
+            self.update_root(dst, root)?;
+        }
         Ok(())
     }
 
     #[inline(always)]
     fn update_root(&mut self, xid: &X, new_root: H256) -> StdResult<(), Error> {
-        chg_store!(self.root.insert(xid, &new_root));
+        chg_store!(Insert, StoreTarget::Root, self.root.insert(xid, &new_root));
         Ok(())
     }
 
@@ -241,6 +788,37 @@ impl<X: KeyEnDe, V: ValueEnDe> Store2<X, V> for DefaultStore2<X, V> {
     fn get_root(&self, xid: &X) -> StdResult<H256, Error> {
         Ok(self.root.get(xid).unwrap_or_else(H256::zero))
     }
+
+    #[inline(always)]
+    fn get_root_by_branch_version(
+        &self,
+        xid: &X,
+        br: BranchName,
+        ver: VersionName,
+    ) -> StdResult<H256, Error> {
+        Ok(self
+            .root
+            .get_by_branch_version(xid, br, ver)
+            .unwrap_or_else(H256::zero))
+    }
+}
+
+impl<X: KeyEnDe, V: ValueEnDe> DefaultStore2<X, V> {
+    fn count_leaves(&self, xid: &X) -> StdResult<u64, Error> {
+        let mut count = 0;
+        chg_store!(
+            Iter,
+            StoreTarget::Xid(xid.encode().into()),
+            self.leaves_map.iter_op_with_key_prefix(
+                &mut |_, _| {
+                    count += 1;
+                    Ok(())
+                },
+                xid
+            )
+        );
+        Ok(count)
+    }
 }
 
 ///////////////////////////
@@ -250,7 +828,7 @@ impl<X: KeyEnDe, V: ValueEnDe> Store2<X, V> for DefaultStore2<X, V> {
 impl<V: ValueEnDe> DefaultStore<V> {
     #[cfg(test)]
     #[inline(always)]
-    pub(crate) fn branches_map(&self) -> &MapxVs<BranchKey, BranchNode> {
+    pub(crate) fn branches_map(&self) -> &MapxVs<BranchKey, CompactBranch> {
         &self.branches_map
     }
 
@@ -259,4 +837,10 @@ impl<V: ValueEnDe> DefaultStore<V> {
     pub(crate) fn leaves_map(&self) -> &MapxVs<H256, V> {
         &self.leaves_map
     }
+
+    #[cfg(test)]
+    #[inline(always)]
+    pub(crate) fn reset_leaves_count(&mut self) {
+        self.leaves_count = OrphanVs::new();
+    }
 }
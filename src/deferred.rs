@@ -0,0 +1,132 @@
+//!
+//! A tree that defers root recomputation.
+//!
+//! Updates and removals are only recorded, the paths of all recorded keys
+//! are hashed together once, when the root is read or committed,
+//! so overlapping paths are hashed once instead of once per write.
+//!
+
+use crate::{
+    error::Result,
+    traits::{Hasher, Store, Value},
+    tree::{leaf_hash, SparseMerkleTree},
+    H256,
+};
+use std::{collections::BTreeMap, mem, sync::Mutex};
+use vsdb::VsMgmt;
+
+/// A `SparseMerkleTree` whose writes are recorded as dirty keys
+/// and written in one batch by `commit_root`.
+///
+/// Reads of values see the recorded writes, all other reads
+/// through `tree` see the last committed state.
+#[derive(Debug)]
+pub struct DeferredSmt<H, V, S: VsMgmt, const DEPTH: usize = 256> {
+    tree: SparseMerkleTree<H, V, S, DEPTH>,
+    // `None` means removal, the last write of a key wins
+    dirty: BTreeMap<H256, Option<V>>,
+    // the root with the dirty keys, once computed
+    root: Mutex<Option<H256>>,
+}
+
+impl<H: Hasher, V: Value<H>, S: Store<V>, const DEPTH: usize>
+    DeferredSmt<H, V, S, DEPTH>
+{
+    #[inline(always)]
+    pub fn new(tree: SparseMerkleTree<H, V, S, DEPTH>) -> Self {
+        DeferredSmt {
+            tree,
+            dirty: BTreeMap::new(),
+            root: Mutex::new(None),
+        }
+    }
+
+    /// The tree in its last committed state
+    #[inline(always)]
+    pub fn tree(&self) -> &SparseMerkleTree<H, V, S, DEPTH> {
+        &self.tree
+    }
+
+    /// Commit the dirty keys and take the tree back
+    pub fn into_inner(mut self) -> Result<SparseMerkleTree<H, V, S, DEPTH>> {
+        self.commit_root()?;
+        Ok(self.tree)
+    }
+
+    /// Record an update, a zero value removes the key
+    #[inline(always)]
+    pub fn update(&mut self, key: H256, value: V) {
+        self.dirty.insert(key, Some(value));
+        *self.root.get_mut().unwrap() = None;
+    }
+
+    /// Record a removal
+    #[inline(always)]
+    pub fn remove(&mut self, key: H256) {
+        self.dirty.insert(key, None);
+        *self.root.get_mut().unwrap() = None;
+    }
+
+    /// Record multiple updates, the last of each key wins
+    pub fn update_all(&mut self, leaves: Vec<(H256, V)>) {
+        self.dirty
+            .extend(leaves.into_iter().map(|(k, v)| (k, Some(v))));
+        *self.root.get_mut().unwrap() = None;
+    }
+
+    /// Number of dirty keys
+    #[inline(always)]
+    pub fn dirty_len(&self) -> usize {
+        self.dirty.len()
+    }
+
+    /// Whether there are writes not committed yet
+    #[inline(always)]
+    pub fn is_dirty(&self) -> bool {
+        !self.dirty.is_empty()
+    }
+
+    /// Merkle root with the dirty keys, computed on the first call after a write;
+    /// nothing is written, see `commit_root`.
+    pub fn root(&self) -> Result<H256> {
+        let mut root = self.root.lock().unwrap();
+        if let Some(root) = *root {
+            return Ok(root);
+        }
+        let computed = self
+            .tree
+            .root_after(self.dirty.iter().map(|(k, v)| (k, v.as_ref())))?;
+        *root = Some(computed);
+        Ok(computed)
+    }
+
+    /// Write all dirty keys and recompute the root once, return the new root;
+    /// the dirty keys are consumed even on errors, like `Transaction::commit`
+    /// a failed read leaves the store untouched.
+    pub fn commit_root(&mut self) -> Result<H256> {
+        *self.root.get_mut().unwrap() = None;
+        let dirty = mem::take(&mut self.dirty).into_iter().collect();
+        let root = self.tree.write_batch(dirty)?;
+        *self.root.get_mut().unwrap() = Some(root);
+        Ok(root)
+    }
+
+    /// Drop all writes not committed yet
+    pub fn discard(&mut self) {
+        self.dirty.clear();
+        *self.root.get_mut().unwrap() = None;
+    }
+}
+
+impl<H: Hasher, V: Value<H> + Clone, S: Store<V>, const DEPTH: usize>
+    DeferredSmt<H, V, S, DEPTH>
+{
+    /// Get value of a leaf, dirty keys included
+    pub fn get(&self, key: &H256) -> Result<Option<V>> {
+        match self.dirty.get(key) {
+            Some(Some(v)) if !leaf_hash(Some(v)).is_zero() => Ok(Some(v.clone())),
+            Some(_) => Ok(None),
+            None => self.tree.get(key),
+        }
+    }
+}
@@ -0,0 +1,150 @@
+//!
+//! Domain separation of trees sharing a hash function.
+//!
+//! `TaggedHasher` hashes a tag before every input of the wrapped hasher,
+//! so trees with the same leaves but different tags get unrelated roots,
+//! and proofs of one can not be replayed against another.
+//!
+//! `Prefixed` hashes leaf values and tree nodes with distinct prefix bytes,
+//! see `Hasher::PREFIXED`.
+//!
+
+use crate::{merge::LEAF_PREFIX, traits::Hasher, H256};
+use serde::{Deserialize, Serialize};
+use std::{fmt, marker::PhantomData};
+
+/// The domain tag of a `TaggedHasher`, e.g.
+///
+/// ```
+/// use xsmt::{blake3_hasher::Blake3Hasher, domain::{DomainTag, TaggedHasher}};
+///
+/// struct Accounts;
+///
+/// impl DomainTag for Accounts {
+///     const TAG: &'static [u8] = b"my-chain/accounts/v1";
+/// }
+///
+/// type AccountsHasher = TaggedHasher<Blake3Hasher, Accounts>;
+/// ```
+pub trait DomainTag {
+    const TAG: &'static [u8];
+}
+
+/// `H` with `T::TAG`, prefixed by its length(u32 LE), hashed before every input.
+///
+/// Trees and proofs create their hashers by `Default`,
+/// so the tag is carried by the type and mixed into every leaf and node hash.
+#[derive(Deserialize, Serialize)]
+#[serde(bound = "H: Hasher, T: DomainTag")]
+pub struct TaggedHasher<H, T> {
+    #[serde(skip, default = "tagged::<H, T>")]
+    inner: H,
+    #[serde(skip)]
+    phantom: PhantomData<fn() -> T>,
+}
+
+fn tagged<H: Hasher, T: DomainTag>() -> H {
+    let mut hasher = H::default();
+    for b in (T::TAG.len() as u32).to_le_bytes().iter().chain(T::TAG) {
+        hasher.write_byte(*b);
+    }
+    hasher
+}
+
+impl<H: Hasher, T: DomainTag> Default for TaggedHasher<H, T> {
+    #[inline(always)]
+    fn default() -> Self {
+        TaggedHasher {
+            inner: tagged::<H, T>(),
+            phantom: PhantomData,
+        }
+    }
+}
+
+impl<H: Clone, T> Clone for TaggedHasher<H, T> {
+    #[inline(always)]
+    fn clone(&self) -> Self {
+        TaggedHasher {
+            inner: self.inner.clone(),
+            phantom: PhantomData,
+        }
+    }
+}
+
+impl<H: Hasher, T: DomainTag> Hasher for TaggedHasher<H, T> {
+    const PREFIXED: bool = H::PREFIXED;
+
+    #[inline(always)]
+    fn write_h256(&mut self, h: &H256) {
+        self.inner.write_h256(h);
+    }
+
+    #[inline(always)]
+    fn write_byte(&mut self, b: u8) {
+        self.inner.write_byte(b);
+    }
+
+    #[inline(always)]
+    fn finish(self) -> H256 {
+        self.inner.finish()
+    }
+
+    fn hash(bytes: &[u8]) -> H256 {
+        let mut input = Vec::with_capacity(4 + T::TAG.len() + bytes.len());
+        input.extend_from_slice(&(T::TAG.len() as u32).to_le_bytes());
+        input.extend_from_slice(T::TAG);
+        input.extend_from_slice(bytes);
+        H::hash(&input)
+    }
+}
+
+impl<H, T> fmt::Debug for TaggedHasher<H, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("TaggedHasher").finish()
+    }
+}
+
+/// `H` with `Hasher::PREFIXED` turned on,
+/// `hash` prefixes its input with `LEAF_PREFIX`.
+///
+/// The roots differ from the ones of `H`,
+/// existing trees must keep being opened with `H` itself.
+#[derive(Default, Clone, Deserialize, Serialize)]
+#[serde(bound = "H: Hasher")]
+pub struct Prefixed<H> {
+    #[serde(skip)]
+    inner: H,
+}
+
+impl<H: Hasher> Hasher for Prefixed<H> {
+    const PREFIXED: bool = true;
+
+    #[inline(always)]
+    fn write_h256(&mut self, h: &H256) {
+        self.inner.write_h256(h);
+    }
+
+    #[inline(always)]
+    fn write_byte(&mut self, b: u8) {
+        self.inner.write_byte(b);
+    }
+
+    #[inline(always)]
+    fn finish(self) -> H256 {
+        self.inner.finish()
+    }
+
+    #[inline(always)]
+    fn hash(bytes: &[u8]) -> H256 {
+        let mut prefixed = Vec::with_capacity(1 + bytes.len());
+        prefixed.push(LEAF_PREFIX);
+        prefixed.extend_from_slice(bytes);
+        H::hash(&prefixed)
+    }
+}
+
+impl<H> fmt::Debug for Prefixed<H> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Prefixed").finish()
+    }
+}
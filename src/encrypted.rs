@@ -0,0 +1,626 @@
+//!
+//! A store that encrypts leaf values at rest.
+//!
+//! `EncryptedStore` seals every leaf value with XChaCha20-Poly1305
+//! before handing it to the inner store, which only ever sees `Sealed` values,
+//! and opens them on reads. The leaf key is authenticated with the value,
+//! so a sealed value moved to another key fails to open.
+//!
+//! Branches hold the leaf hashes(`Value::to_h256`), which for values like `H256`
+//! are the values themselves. With `seal_branches` they are sealed as well,
+//! in a map of the `EncryptedStore` instead of the inner store; without it
+//! they are stored as they are, for values whose hashes may be disclosed,
+//! as merkle proofs do. The leaf hashes are never cached in the inner store.
+//!
+//! Keys are rotated by `rotate`, new values are sealed with the newest key
+//! while values sealed with older ones stay readable, `reseal` brings
+//! the current values to the newest key and `remove_key` drops an old one
+//! once no version of any branch holds values sealed with it.
+//!
+
+use crate::{
+    chg_store,
+    error::{Error, StoreOp, StoreTarget},
+    merkle_proof::BytesVisitor,
+    traits::Store,
+    tree::{BranchKey, BranchNode},
+    H256,
+};
+use chacha20poly1305::{
+    aead::{Aead, AeadCore, KeyInit, OsRng, Payload},
+    XChaCha20Poly1305, XNonce,
+};
+use ruc::*;
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use std::{collections::BTreeMap, fmt, ops::Bound, result::Result as StdResult};
+use vsdb::{BranchName, MapxVs, ValueEnDe, VersionName, Vs, VsMgmt};
+
+const NONCE_LEN: usize = 24;
+
+/// A leaf value sealed by `EncryptedStore`,
+/// encoded as `key_id(u32, little-endian) | nonce | ciphertext`
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Sealed {
+    key_id: u32,
+    nonce: [u8; NONCE_LEN],
+    ciphertext: Vec<u8>,
+}
+
+impl Sealed {
+    /// Id of the key the value is sealed with
+    #[inline(always)]
+    pub fn key_id(&self) -> u32 {
+        self.key_id
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(4 + NONCE_LEN + self.ciphertext.len());
+        bytes.extend_from_slice(&self.key_id.to_le_bytes());
+        bytes.extend_from_slice(&self.nonce);
+        bytes.extend_from_slice(&self.ciphertext);
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> StdResult<Self, Error> {
+        if bytes.len() < 4 + NONCE_LEN {
+            return Err(Error::InvalidLength {
+                expected: 4 + NONCE_LEN,
+                actual: bytes.len(),
+            });
+        }
+        let (key_id, rest) = bytes.split_at(4);
+        let (nonce, ciphertext) = rest.split_at(NONCE_LEN);
+        Ok(Sealed {
+            key_id: u32::from_le_bytes(key_id.try_into().unwrap()),
+            nonce: nonce.try_into().unwrap(),
+            ciphertext: ciphertext.to_vec(),
+        })
+    }
+}
+
+impl Serialize for Sealed {
+    fn serialize<S: Serializer>(&self, serializer: S) -> StdResult<S::Ok, S::Error> {
+        serializer.serialize_bytes(&self.to_bytes())
+    }
+}
+
+impl<'de> Deserialize<'de> for Sealed {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> StdResult<Self, D::Error> {
+        let bytes = deserializer.deserialize_byte_buf(BytesVisitor)?;
+        Sealed::from_bytes(&bytes).map_err(de::Error::custom)
+    }
+}
+
+// keys by id, the id of the newest one, and whether branches are sealed
+struct Keyring {
+    keys: BTreeMap<u32, XChaCha20Poly1305>,
+    current: u32,
+    seal_branches: bool,
+}
+
+// never print the keys
+impl fmt::Debug for Keyring {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Keyring")
+            .field("key_ids", &self.keys.keys().collect::<Vec<_>>())
+            .field("current", &self.current)
+            .field("seal_branches", &self.seal_branches)
+            .finish()
+    }
+}
+
+// the keys are not versioned
+impl VsMgmt for Keyring {
+    vsdb::impl_vs_methods_nope!();
+}
+
+/// A `Store` sealing the leaf values written to `inner`,
+/// and the branches if asked to.
+///
+/// Version management(`VsMgmt`) is passed through to `inner`
+/// and the map of sealed branches, it must go through the `EncryptedStore`
+/// once `inner` is wrapped.
+#[derive(Vs, Debug)]
+pub struct EncryptedStore<S: VsMgmt> {
+    inner: S,
+    // empty unless `seal_branches`
+    branches: MapxVs<BranchKey, Sealed>,
+    keyring: Keyring,
+}
+
+impl<S: VsMgmt> EncryptedStore<S> {
+    /// Seal the values written to `inner` with `key`, known as `key_id`,
+    /// and the branches too if `seal_branches`.
+    ///
+    /// The versions of the default branch of `inner` are recreated for the map
+    /// of sealed branches, return Encryption error if `inner` has other branches.
+    pub fn new(
+        inner: S,
+        key_id: u32,
+        key: &[u8; 32],
+        seal_branches: bool,
+    ) -> StdResult<Self, Error> {
+        let branches = inner.branch_list();
+        if branches.len() > 1 {
+            return Err(Error::Encryption(format!(
+                "only stores with a single branch can be wrapped, found {}",
+                branches.len()
+            )));
+        }
+        let mut map = MapxVs::new();
+        let versions = inner.version_list().map_err(|e| {
+            Error::store(StoreOp::Get, StoreTarget::Versions, e)
+        })?;
+        for ver in versions.iter() {
+            map.version_create(ver.as_deref()).map_err(|e| {
+                Error::store(StoreOp::Insert, StoreTarget::Version(ver.0.to_vec()), e)
+            })?;
+        }
+        let mut keys = BTreeMap::new();
+        keys.insert(key_id, XChaCha20Poly1305::new(key.into()));
+        Ok(EncryptedStore {
+            inner,
+            branches: map,
+            keyring: Keyring {
+                keys,
+                current: key_id,
+                seal_branches,
+            },
+        })
+    }
+
+    /// The inner store, holding the sealed values
+    #[inline(always)]
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+
+    /// Take the inner store back, without the sealed branches
+    #[inline(always)]
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+
+    /// Whether the branches are sealed
+    #[inline(always)]
+    pub fn seals_branches(&self) -> bool {
+        self.keyring.seal_branches
+    }
+
+    /// Id of the key new values are sealed with
+    #[inline(always)]
+    pub fn current_key_id(&self) -> u32 {
+        self.keyring.current
+    }
+
+    /// Seal all following writes with `key`, known as `key_id`,
+    /// an existing key of the same id is replaced.
+    ///
+    /// The older keys are kept to open the values sealed with them.
+    pub fn rotate(&mut self, key_id: u32, key: &[u8; 32]) {
+        self.keyring
+            .keys
+            .insert(key_id, XChaCha20Poly1305::new(key.into()));
+        self.keyring.current = key_id;
+    }
+
+    fn seal(
+        &self,
+        aad: &[u8],
+        msg: &[u8],
+        target: StoreTarget,
+    ) -> StdResult<Sealed, Error> {
+        let key_id = self.keyring.current;
+        let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let ciphertext = self.keyring.keys[&key_id]
+            .encrypt(&nonce, Payload { msg, aad })
+            .map_err(|e| Error::store(StoreOp::Insert, target, e))?;
+        Ok(Sealed {
+            key_id,
+            nonce: nonce.into(),
+            ciphertext,
+        })
+    }
+
+    fn open(
+        &self,
+        aad: &[u8],
+        sealed: &Sealed,
+        target: StoreTarget,
+    ) -> StdResult<Vec<u8>, Error> {
+        let cipher = self.keyring.keys.get(&sealed.key_id).ok_or_else(|| {
+            let msg = format!("unknown key id {}", sealed.key_id);
+            Error::store(StoreOp::Get, target.clone(), msg)
+        })?;
+        let payload = Payload {
+            msg: &sealed.ciphertext,
+            aad,
+        };
+        cipher
+            .decrypt(XNonce::from_slice(&sealed.nonce), payload)
+            .map_err(|e| Error::store(StoreOp::Get, target, e))
+    }
+
+    fn seal_leaf<V: ValueEnDe>(
+        &self,
+        leaf_key: &H256,
+        leaf: &V,
+    ) -> StdResult<Sealed, Error> {
+        let target = StoreTarget::Leaf(*leaf_key);
+        self.seal(leaf_key.as_slice(), &leaf.encode(), target)
+    }
+
+    fn open_leaf<V: ValueEnDe>(
+        &self,
+        leaf_key: &H256,
+        sealed: &Sealed,
+    ) -> StdResult<V, Error> {
+        let target = StoreTarget::Leaf(*leaf_key);
+        let bytes = self.open(leaf_key.as_slice(), sealed, target.clone())?;
+        V::decode(&bytes).map_err(|e| Error::store(StoreOp::Get, target, e))
+    }
+
+    // the height is a part of the data authenticated with a branch,
+    // so branch and leaf data never have the same length
+    fn branch_aad(branch_key: &BranchKey) -> [u8; 33] {
+        let mut aad = [0u8; 33];
+        aad[0] = branch_key.height;
+        aad[1..].copy_from_slice(branch_key.node_key.as_slice());
+        aad
+    }
+
+    fn seal_branch(
+        &self,
+        branch_key: &BranchKey,
+        branch: &BranchNode,
+    ) -> StdResult<Sealed, Error> {
+        let target = StoreTarget::Branch(branch_key.clone());
+        let aad = Self::branch_aad(branch_key);
+        self.seal(&aad, &branch.to_compact_bytes(), target)
+    }
+
+    fn open_branch(
+        &self,
+        branch_key: &BranchKey,
+        sealed: &Sealed,
+    ) -> StdResult<BranchNode, Error> {
+        let target = StoreTarget::Branch(branch_key.clone());
+        let bytes = self.open(&Self::branch_aad(branch_key), sealed, target)?;
+        BranchNode::from_compact_bytes(&bytes)
+    }
+
+    // the values of a readable version only fail to open if the store is corrupted,
+    // see `remove_key`; iterators skip them instead of failing the whole scan
+    fn open_leaf_or_skip<V: ValueEnDe>(
+        &self,
+        (k, sealed): (H256, Sealed),
+    ) -> Option<(H256, V)> {
+        match self.open_leaf(&k, &sealed) {
+            Ok(leaf) => Some((k, leaf)),
+            Err(_e) => {
+                #[cfg(feature = "tracing")]
+                tracing::warn!(key = ?k, error = %_e, "skip a leaf failing to open");
+                None
+            }
+        }
+    }
+
+    fn open_branch_or_skip(
+        &self,
+        (k, sealed): (BranchKey, Sealed),
+    ) -> Option<(BranchKey, BranchNode)> {
+        match self.open_branch(&k, &sealed) {
+            Ok(branch) => Some((k, branch)),
+            Err(_e) => {
+                #[cfg(feature = "tracing")]
+                tracing::warn!(key = ?k, error = %_e, "skip a branch failing to open");
+                None
+            }
+        }
+    }
+}
+
+impl<S: Store<Sealed>> EncryptedStore<S> {
+    /// Seal again with the current key all values(and branches) of the newest state
+    /// sealed with older keys, return the number of them.
+    ///
+    /// The values are opened with the older keys, which must be known.
+    /// The versions committed before keep the values as they were sealed.
+    pub fn reseal(&mut self) -> StdResult<usize, Error> {
+        let current = self.keyring.current;
+        let leaves: Vec<(H256, Sealed)> = self
+            .inner
+            .iter_leaves()
+            .filter(|(_, sealed)| sealed.key_id != current)
+            .collect();
+        for (k, sealed) in leaves.iter() {
+            let target = StoreTarget::Leaf(*k);
+            let bytes = self.open(k.as_slice(), sealed, target.clone())?;
+            let sealed = self.seal(k.as_slice(), &bytes, target)?;
+            self.inner.insert_leaf(*k, sealed)?;
+        }
+        let branches: Vec<(BranchKey, Sealed)> = self
+            .branches
+            .iter()
+            .filter(|(_, sealed)| sealed.key_id != current)
+            .collect();
+        for (k, sealed) in branches.iter() {
+            let branch = self.open_branch(k, sealed)?;
+            let sealed = self.seal_branch(k, &branch)?;
+            chg_store!(
+                Insert,
+                StoreTarget::Branch(k.clone()),
+                self.branches.insert(k, &sealed)
+            );
+        }
+        Ok(leaves.len() + branches.len())
+    }
+
+    /// Drop an older key.
+    ///
+    /// Return Encryption error if it is the current key, an unknown one,
+    /// or if any version of any branch still holds values sealed with it,
+    /// see `reseal` and `prune_versions_before`; every version is read to know it.
+    pub fn remove_key(&mut self, key_id: u32) -> StdResult<(), Error> {
+        if key_id == self.keyring.current || !self.keyring.keys.contains_key(&key_id) {
+            return Err(Error::Encryption(format!(
+                "key {} is the current key or unknown",
+                key_id
+            )));
+        }
+        for br in self.inner.branch_list() {
+            let br = br.as_deref();
+            let versions = self.inner.version_list_by_branch(br).map_err(|e| {
+                Error::store(StoreOp::Get, StoreTarget::VsBranch(br.0.to_vec()), e)
+            })?;
+            for ver in versions.iter() {
+                let ver = ver.as_deref();
+                let in_use = self
+                    .inner
+                    .iter_leaves_by_branch_version(br, ver)
+                    .any(|(_, sealed)| sealed.key_id == key_id)
+                    || self
+                        .branches
+                        .iter_by_branch_version(br, ver)
+                        .any(|(_, sealed)| sealed.key_id == key_id);
+                if in_use {
+                    return Err(Error::Encryption(format!(
+                        "key {} is in use by version {}",
+                        key_id,
+                        String::from_utf8_lossy(ver.0)
+                    )));
+                }
+            }
+        }
+        self.keyring.keys.remove(&key_id);
+        Ok(())
+    }
+}
+
+impl<S: Store<Sealed>, V: ValueEnDe> Store<V> for EncryptedStore<S> {
+    #[inline(always)]
+    fn insert_branch(
+        &mut self,
+        node_key: BranchKey,
+        branch: BranchNode,
+    ) -> StdResult<(), Error> {
+        if !self.keyring.seal_branches {
+            return Store::<Sealed>::insert_branch(&mut self.inner, node_key, branch);
+        }
+        let sealed = self.seal_branch(&node_key, &branch)?;
+        chg_store!(
+            Insert,
+            StoreTarget::Branch(node_key.clone()),
+            self.branches.insert(&node_key, &sealed)
+        );
+        Ok(())
+    }
+
+    #[inline(always)]
+    fn remove_branch(&mut self, node_key: &BranchKey) -> StdResult<(), Error> {
+        if !self.keyring.seal_branches {
+            return Store::<Sealed>::remove_branch(&mut self.inner, node_key);
+        }
+        chg_store!(
+            Remove,
+            StoreTarget::Branch(node_key.clone()),
+            self.branches.remove(node_key)
+        );
+        Ok(())
+    }
+
+    #[inline(always)]
+    fn get_branch(
+        &self,
+        branch_key: &BranchKey,
+    ) -> StdResult<Option<BranchNode>, Error> {
+        if !self.keyring.seal_branches {
+            return Store::<Sealed>::get_branch(&self.inner, branch_key);
+        }
+        self.branches
+            .get(branch_key)
+            .map(|sealed| self.open_branch(branch_key, &sealed))
+            .transpose()
+    }
+
+    #[inline(always)]
+    fn get_branch_by_branch_version(
+        &self,
+        branch_key: &BranchKey,
+        br: BranchName,
+        ver: VersionName,
+    ) -> StdResult<Option<BranchNode>, Error> {
+        if !self.keyring.seal_branches {
+            return Store::<Sealed>::get_branch_by_branch_version(
+                &self.inner,
+                branch_key,
+                br,
+                ver,
+            );
+        }
+        self.branches
+            .get_by_branch_version(branch_key, br, ver)
+            .map(|sealed| self.open_branch(branch_key, &sealed))
+            .transpose()
+    }
+
+    #[inline(always)]
+    fn insert_leaf(&mut self, leaf_key: H256, leaf: V) -> StdResult<(), Error> {
+        let sealed = self.seal_leaf(&leaf_key, &leaf)?;
+        self.inner.insert_leaf(leaf_key, sealed)
+    }
+
+    #[inline(always)]
+    fn remove_leaf(&mut self, leaf_key: &H256) -> StdResult<(), Error> {
+        self.inner.remove_leaf(leaf_key)
+    }
+
+    #[inline(always)]
+    fn remove_leaves_by_prefix(&mut self, prefix: &[u8]) -> StdResult<Vec<H256>, Error> {
+        self.inner.remove_leaves_by_prefix(prefix)
+    }
+
+    #[inline(always)]
+    fn get_leaf(&self, leaf_key: &H256) -> StdResult<Option<V>, Error> {
+        self.inner
+            .get_leaf(leaf_key)?
+            .map(|sealed| self.open_leaf(leaf_key, &sealed))
+            .transpose()
+    }
+
+    #[inline(always)]
+    fn has_leaf(&self, leaf_key: &H256) -> StdResult<bool, Error> {
+        self.inner.has_leaf(leaf_key)
+    }
+
+    #[inline(always)]
+    fn leaves_count(&self) -> StdResult<u64, Error> {
+        self.inner.leaves_count()
+    }
+
+    #[inline(always)]
+    fn add_leaves_count(&mut self, delta: i64) -> StdResult<(), Error> {
+        self.inner.add_leaves_count(delta)
+    }
+
+    fn get_leaves(&self, leaf_keys: &[H256]) -> StdResult<Vec<Option<V>>, Error> {
+        let sealed = self.inner.get_leaves(leaf_keys)?;
+        leaf_keys
+            .iter()
+            .zip(sealed)
+            .map(|(k, sealed)| sealed.map(|s| self.open_leaf(k, &s)).transpose())
+            .collect()
+    }
+
+    #[inline(always)]
+    fn get_leaf_by_branch(
+        &self,
+        leaf_key: &H256,
+        br: BranchName,
+    ) -> StdResult<Option<V>, Error> {
+        self.inner
+            .get_leaf_by_branch(leaf_key, br)?
+            .map(|sealed| self.open_leaf(leaf_key, &sealed))
+            .transpose()
+    }
+
+    #[inline(always)]
+    fn get_leaf_by_branch_version(
+        &self,
+        leaf_key: &H256,
+        br: BranchName,
+        ver: VersionName,
+    ) -> StdResult<Option<V>, Error> {
+        self.inner
+            .get_leaf_by_branch_version(leaf_key, br, ver)?
+            .map(|sealed| self.open_leaf(leaf_key, &sealed))
+            .transpose()
+    }
+
+    #[inline(always)]
+    fn update_root(&mut self, new_root: H256) -> StdResult<(), Error> {
+        self.inner.update_root(new_root)
+    }
+
+    #[inline(always)]
+    fn get_root(&self) -> StdResult<H256, Error> {
+        self.inner.get_root()
+    }
+
+    #[inline(always)]
+    fn get_root_by_branch_version(
+        &self,
+        br: BranchName,
+        ver: VersionName,
+    ) -> StdResult<H256, Error> {
+        self.inner.get_root_by_branch_version(br, ver)
+    }
+
+    #[inline(always)]
+    fn get_root_by_branch(&self, br: BranchName) -> StdResult<H256, Error> {
+        self.inner.get_root_by_branch(br)
+    }
+
+    #[inline(always)]
+    fn push_root_history(&mut self, root: H256) -> StdResult<u64, Error> {
+        self.inner.push_root_history(root)
+    }
+
+    #[inline(always)]
+    fn get_root_by_seq(&self, seq: u64) -> StdResult<Option<H256>, Error> {
+        self.inner.get_root_by_seq(seq)
+    }
+
+    #[inline(always)]
+    fn iter_root_history(
+        &self,
+        range: (Bound<u64>, Bound<u64>),
+    ) -> Box<dyn Iterator<Item = (u64, H256)> + '_> {
+        self.inner.iter_root_history(range)
+    }
+
+    #[inline(always)]
+    fn iter_branches(&self) -> Box<dyn Iterator<Item = (BranchKey, BranchNode)> + '_> {
+        if !self.keyring.seal_branches {
+            return Store::<Sealed>::iter_branches(&self.inner);
+        }
+        Box::new(
+            self.branches
+                .iter()
+                .filter_map(|branch| self.open_branch_or_skip(branch)),
+        )
+    }
+
+    #[inline(always)]
+    fn iter_leaves<'a>(&'a self) -> Box<dyn Iterator<Item = (H256, V)> + 'a>
+    where
+        V: 'a,
+    {
+        Box::new(
+            self.inner
+                .iter_leaves()
+                .filter_map(|leaf| self.open_leaf_or_skip(leaf)),
+        )
+    }
+
+    #[inline(always)]
+    fn iter_leaves_by_branch_version<'a>(
+        &'a self,
+        br: BranchName,
+        ver: VersionName,
+    ) -> Box<dyn Iterator<Item = (H256, V)> + 'a>
+    where
+        V: 'a,
+    {
+        Box::new(
+            self.inner
+                .iter_leaves_by_branch_version(br, ver)
+                .filter_map(|leaf| self.open_leaf_or_skip(leaf)),
+        )
+    }
+
+    #[inline(always)]
+    fn compact(&mut self) -> StdResult<u64, Error> {
+        self.inner.compact()
+    }
+}
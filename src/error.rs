@@ -1,4 +1,6 @@
-use crate::H256;
+use crate::{tree::BranchKey, H256};
+use core::fmt::Display;
+use std::{path::PathBuf, sync::Arc};
 
 pub type Result<T> = core::result::Result<T, Error>;
 
@@ -9,12 +11,72 @@ pub enum Error {
     CorruptedProof,
     EmptyProof,
     EmptyKeys,
-    IncorrectNumberOfLeaves { expected: usize, actual: usize },
-    Store(String),
+    IncorrectNumberOfLeaves {
+        expected: usize,
+        actual: usize,
+    },
+    Store {
+        op: StoreOp,
+        target: StoreTarget,
+        source: StoreError,
+    },
     CorruptedStack,
     NonSiblings,
     InvalidCode(u8),
     NonMergableRange,
+    Compression(String),
+    Deadlock,
+    Snapshot(String),
+    CorruptedNode {
+        key: H256,
+        height: u8,
+    },
+    VersionNotFound(Vec<u8>),
+    ValueTooLarge {
+        len: usize,
+        max: usize,
+    },
+    Ledger(String),
+    KeyExists(H256),
+    EmptyRange,
+    KeyOutOfRange(H256),
+    ParseH256(String),
+    ParseHex(String),
+    InvalidLength {
+        expected: usize,
+        actual: usize,
+    },
+    MissingLeaves(Vec<H256>),
+    UnprovedKeys(Vec<H256>),
+    RootMismatch {
+        expected: H256,
+        actual: H256,
+    },
+    DuplicatedKeys(H256),
+    UnsortedKeys(H256),
+    TreeNotEmpty,
+    Checkpoint(String),
+    InvalidHeight(u16),
+    StackOverflow,
+    TruncatedProof,
+    UnknownOpcode {
+        code: u8,
+        offset: usize,
+    },
+    LimitExceeded {
+        limit: Limit,
+        max: usize,
+        actual: usize,
+    },
+    ImplicitDelete(H256),
+    Sync(String),
+    KeyMismatch {
+        key: H256,
+        height: u8,
+    },
+    UnusedSiblings(usize),
+    Encryption(String),
+    KeyNotLocked(H256),
 }
 
 impl core::fmt::Display for Error {
@@ -46,8 +108,8 @@ impl core::fmt::Display for Error {
                     expected, actual
                 )?;
             }
-            Error::Store(err_msg) => {
-                write!(f, "Backend store error: {}", err_msg)?;
+            Error::Store { op, target, source } => {
+                write!(f, "Backend store error, {:?} {:?}: {}", op, target, source)?;
             }
             Error::CorruptedStack => {
                 write!(f, "Corrupted serialized proof stack")?;
@@ -61,9 +123,224 @@ impl core::fmt::Display for Error {
             Error::NonMergableRange => {
                 write!(f, "Ranges can not be merged")?;
             }
+            Error::Compression(err_msg) => {
+                write!(f, "Proof compression error: {}", err_msg)?;
+            }
+            Error::Deadlock => {
+                write!(f, "Waiting for the key range would cause a deadlock")?;
+            }
+            Error::Snapshot(err_msg) => {
+                write!(f, "Snapshot error: {}", err_msg)?;
+            }
+            Error::CorruptedNode { key, height } => {
+                write!(
+                    f,
+                    "Corrupted store, node does not match its parent, height:{}, key:{:?}",
+                    height, key
+                )?;
+            }
+            Error::VersionNotFound(ver) => {
+                write!(f, "Version not found: {}", String::from_utf8_lossy(ver))?;
+            }
+            Error::ValueTooLarge { len, max } => {
+                write!(f, "Value too large, encoded length: {}, max: {}", len, max)?;
+            }
+            Error::Ledger(msg) => {
+                write!(f, "Ledger error: {}", msg)?;
+            }
+            Error::KeyExists(key) => {
+                write!(f, "Key exists in the tree: {:?}", key)?;
+            }
+            Error::EmptyRange => {
+                write!(f, "No key lies between the range bounds")?;
+            }
+            Error::KeyOutOfRange(key) => {
+                write!(f, "Key out of the range of the tree depth: {:?}", key)?;
+            }
+            Error::ParseH256(msg) => {
+                write!(f, "Invalid H256: {}", msg)?;
+            }
+            Error::ParseHex(msg) => {
+                write!(f, "Invalid hex: {}", msg)?;
+            }
+            Error::InvalidLength { expected, actual } => {
+                write!(f, "Invalid length, expected {} actual {}", expected, actual)?;
+            }
+            Error::MissingLeaves(keys) => {
+                write!(f, "Missing leaves required by the proof: {:?}", keys)?;
+            }
+            Error::UnprovedKeys(keys) => {
+                write!(f, "Keys not covered by the proof: {:?}", keys)?;
+            }
+            Error::RootMismatch { expected, actual } => {
+                write!(f, "Root mismatch, expected {} actual {}", expected, actual)?;
+            }
+            Error::DuplicatedKeys(key) => {
+                write!(f, "Duplicated key: {:?}", key)?;
+            }
+            Error::UnsortedKeys(key) => {
+                write!(f, "Key not greater than the one before it: {:?}", key)?;
+            }
+            Error::TreeNotEmpty => {
+                write!(f, "The tree is not empty")?;
+            }
+            Error::Checkpoint(msg) => {
+                write!(f, "Checkpoint error: {}", msg)?;
+            }
+            Error::InvalidHeight(height) => {
+                write!(f, "Invalid subtree height: {}, max: 256", height)?;
+            }
+            Error::StackOverflow => {
+                write!(f, "Proof stack overflow, max size: {}", crate::MAX_STACK_SIZE)?;
+            }
+            Error::TruncatedProof => {
+                write!(f, "Truncated proof, an operand is cut off")?;
+            }
+            Error::UnknownOpcode { code, offset } => {
+                write!(f, "Unknown proof opcode {:#04x} at offset {}", code, offset)?;
+            }
+            Error::LimitExceeded { limit, max, actual } => {
+                write!(
+                    f,
+                    "Verification limit exceeded, {:?} max {} actual {}",
+                    limit, max, actual
+                )?;
+            }
+            Error::ImplicitDelete(key) => {
+                write!(f, "Value would delete the leaf: {:?}", key)?;
+            }
+            Error::Sync(msg) => {
+                write!(f, "Sync error: {}", msg)?;
+            }
+            Error::KeyMismatch { key, height } => {
+                write!(
+                    f,
+                    "Key does not match the proof bitmap, height:{}, key:{:?}",
+                    height, key
+                )?;
+            }
+            Error::UnusedSiblings(n) => {
+                write!(f, "Siblings not used by any key of the proof: {}", n)?;
+            }
+            Error::Encryption(msg) => {
+                write!(f, "Encryption error: {}", msg)?;
+            }
+            Error::KeyNotLocked(key) => {
+                write!(f, "Key out of the locked range: {:?}", key)?;
+            }
         }
         Ok(())
     }
 }
 
-impl std::error::Error for Error {}
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Store { source, .. } => Some(source.get_ref()),
+            _ => None,
+        }
+    }
+}
+
+impl Error {
+    pub(crate) fn store(op: StoreOp, target: StoreTarget, source: impl Display) -> Self {
+        Error::Store {
+            op,
+            target,
+            source: StoreError::msg(source),
+        }
+    }
+}
+
+/// The error of a backend store call, see `Error::Store`,
+/// errors are equal if their messages are.
+#[derive(Debug, Clone)]
+pub struct StoreError(Arc<dyn std::error::Error + Send + Sync>);
+
+impl StoreError {
+    /// Wrap the error of a backend, it is kept as the source of `Error::Store`
+    #[inline(always)]
+    pub fn new(e: impl std::error::Error + Send + Sync + 'static) -> Self {
+        StoreError(Arc::new(e))
+    }
+
+    /// An error of a backend known by its message only
+    #[inline(always)]
+    pub fn msg(msg: impl Display) -> Self {
+        StoreError(Arc::new(Message(msg.to_string())))
+    }
+
+    /// The wrapped error, e.g. to downcast it
+    #[inline(always)]
+    pub fn get_ref(&self) -> &(dyn std::error::Error + Send + Sync + 'static) {
+        &*self.0
+    }
+}
+
+impl Display for StoreError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl PartialEq for StoreError {
+    fn eq(&self, other: &Self) -> bool {
+        self.to_string() == other.to_string()
+    }
+}
+
+impl Eq for StoreError {}
+
+// the error of a backend without an error type of its own, e.g. vsdb
+#[derive(Debug)]
+struct Message(String);
+
+impl Display for Message {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for Message {}
+
+/// The operation of a failed store call, see `Error::Store`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StoreOp {
+    Insert,
+    Remove,
+    Get,
+    Iter,
+    /// Set the data directory of the backend
+    SetDataDir,
+    /// Switch the default branch of versions
+    SetDefaultBranch,
+}
+
+/// The bound of `VerifyOptions` a proof exceeds, see `Error::LimitExceeded`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Limit {
+    ProofBytes,
+    Leaves,
+    Stack,
+}
+
+/// The data a failed store call works on, see `Error::Store`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StoreTarget {
+    Branch(BranchKey),
+    Leaf(H256),
+    Root,
+    RootHistory(u64),
+    /// The leaf counter
+    LeavesCount,
+    /// All data under an xid(top-level key), encoded
+    Xid(Vec<u8>),
+    /// A version, by name
+    Version(Vec<u8>),
+    /// All versions of the store
+    Versions,
+    /// A branch of versions, by name
+    VsBranch(Vec<u8>),
+    /// The data directory of the backend
+    DataDir(PathBuf),
+}
@@ -0,0 +1,117 @@
+//!
+//! A read-only view of a tree.
+//!
+//! `SparseMerkleTree::freeze` takes a checkpoint and pins the version it ends,
+//! so the view keeps serving the state it was frozen at while the tree
+//! goes on being written, e.g. to hand the tree to proof-serving threads.
+//! Like `SmtView`, every read goes through the `*_by_branch_version` APIs,
+//! nothing is copied.
+//!
+
+use crate::{
+    error::{Error, Result, StoreOp, StoreTarget},
+    merkle_proof::MerkleProof,
+    traits::{Hasher, Store, Value},
+    tree::SparseMerkleTree,
+    H256,
+};
+use std::sync::Arc;
+use vsdb::{BranchName, VersionName, VsMgmt};
+
+/// A read-only `SparseMerkleTree` pinned to a committed version,
+/// all clones share the same handle to the store
+#[derive(Debug)]
+pub struct FrozenSmt<H, V, S: VsMgmt, const DEPTH: usize = 256> {
+    inner: Arc<SparseMerkleTree<H, V, S, DEPTH>>,
+    br: Arc<[u8]>,
+    ver: Arc<[u8]>,
+}
+
+impl<H, V, S: VsMgmt, const DEPTH: usize> Clone for FrozenSmt<H, V, S, DEPTH> {
+    #[inline(always)]
+    fn clone(&self) -> Self {
+        FrozenSmt {
+            inner: Arc::clone(&self.inner),
+            br: Arc::clone(&self.br),
+            ver: Arc::clone(&self.ver),
+        }
+    }
+}
+
+impl<H: Hasher, V: Value<H>, S: Store<V>, const DEPTH: usize> FrozenSmt<H, V, S, DEPTH> {
+    /// The pinned branch
+    #[inline(always)]
+    pub fn branch(&self) -> BranchName<'_> {
+        BranchName(&self.br)
+    }
+
+    /// The pinned version
+    #[inline(always)]
+    pub fn version(&self) -> VersionName<'_> {
+        VersionName(&self.ver)
+    }
+
+    /// Merkle root, panic on store errors, see `try_root`
+    #[inline(always)]
+    pub fn root(&self) -> H256 {
+        self.try_root().unwrap()
+    }
+
+    /// Merkle root, or the error of the store
+    #[inline(always)]
+    pub fn try_root(&self) -> Result<H256> {
+        self.inner.root_by_branch_version(self.branch(), self.version())
+    }
+
+    /// Get value of a leaf
+    #[inline(always)]
+    pub fn get(&self, key: &H256) -> Result<Option<V>> {
+        // a leaf read does not tell a pruned version from a missing leaf
+        if !self
+            .inner
+            .store
+            .version_exists_on_branch(self.version(), self.branch())
+        {
+            return Err(Error::VersionNotFound(self.ver.to_vec()));
+        }
+        self.inner.get_by_branch_version(key, self.branch(), self.version())
+    }
+
+    /// Generate merkle proof
+    #[inline(always)]
+    pub fn merkle_proof(&self, keys: Vec<H256>) -> Result<MerkleProof> {
+        self.inner
+            .merkle_proof_by_branch_version(keys, self.branch(), self.version())
+    }
+}
+
+impl<H: Hasher, V: Value<H>, S: Store<V> + Clone, const DEPTH: usize>
+    SparseMerkleTree<H, V, S, DEPTH>
+{
+    /// Take the checkpoint `name` and return a read-only view of the state
+    /// right before it, later writes are recorded in `name` and not seen by it.
+    ///
+    /// The view shares the store of the tree, its reads fail
+    /// with `VersionNotFound` once the pinned version is pruned
+    /// or rolled back(e.g. by `prune_versions_before` or `restore`).
+    pub fn freeze(
+        &mut self,
+        name: impl AsRef<[u8]>,
+    ) -> Result<FrozenSmt<H, V, S, DEPTH>> {
+        let ver = self
+            .store
+            .version_list()
+            .map_err(|e| Error::store(StoreOp::Get, StoreTarget::Versions, e))?
+            .pop()
+            .ok_or_else(|| Error::VersionNotFound(vec![]))?;
+        self.checkpoint(name)?;
+
+        let mut tree = Self::new(self.store.clone());
+        tree.set_verify_reads(self.verify_reads);
+        Ok(FrozenSmt {
+            inner: Arc::new(tree),
+            br: self.store.branch_get_default().0.into(),
+            ver: ver.0.into(),
+        })
+    }
+}
@@ -1,25 +1,94 @@
-use core::cmp::Ordering;
-use serde::{Deserialize, Serialize};
-use vsdb::{impl_vs_methods_nope, VsMgmt};
+use crate::{
+    error::Error,
+    traits::{Digest, Hasher},
+};
+use core::{cmp::Ordering, fmt, str::FromStr};
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use vsdb::{impl_vs_methods_nope, KeyEnDeOrdered, VsMgmt};
+
+/// Represent `N * 8` bits, e.g. a digest of `N` bytes
+///
+/// Displayed and parsed as `0x`-prefixed hex of the bytes in order,
+/// which is also the serde form for human-readable formats(e.g. JSON);
+/// other formats keep the plain `N` bytes.
+///
+/// The tree, merge and proofs work on `H256`, whose bits are the paths
+/// of the 256 heights; the other widths are commitments of other systems,
+/// converted at the boundary by `resize`. A tree committing to a digest
+/// of up to 32 bytes is built with a `NarrowHasher`, nodes wider than
+/// 256 bits are not supported.
+#[derive(Eq, PartialEq, Hash, Clone, Copy)]
+#[cfg_attr(
+    feature = "borsh",
+    derive(borsh::BorshSerialize, borsh::BorshDeserialize)
+)]
+pub struct HN<const N: usize>([u8; N]);
 
 /// Represent 256 bits
-#[derive(Eq, PartialEq, Debug, Default, Hash, Clone, Copy, Deserialize, Serialize)]
-pub struct H256([u8; 32]);
+pub type H256 = HN<32>;
+/// Represent 160 bits, e.g. an address
+pub type H160 = HN<20>;
+/// Represent 512 bits
+pub type H512 = HN<64>;
 
-const ZERO: H256 = H256([0u8; 32]);
 const BYTE_SIZE: u8 = 8;
+const LIMBS: usize = 4;
 
-impl H256 {
+impl<const N: usize> HN<N> {
     #[inline(always)]
     pub const fn zero() -> Self {
-        ZERO
+        HN([0u8; N])
     }
 
     #[inline(always)]
     pub fn is_zero(&self) -> bool {
-        self == &ZERO
+        self.0.iter().all(|b| *b == 0)
+    }
+
+    #[inline(always)]
+    pub fn as_slice(&self) -> &[u8] {
+        &self.0[..]
     }
 
+    /// Bytes from the highest bit down, i.e. a big-endian integer
+    #[inline(always)]
+    pub fn to_be_bytes(&self) -> [u8; N] {
+        let mut bytes = self.0;
+        bytes.reverse();
+        bytes
+    }
+
+    /// Inverse of `to_be_bytes`
+    #[inline(always)]
+    pub fn from_be_bytes(mut bytes: [u8; N]) -> Self {
+        bytes.reverse();
+        HN(bytes)
+    }
+
+    /// The same integer in `M` bytes: zero-extended when widening,
+    /// the highest `N - M` bytes are dropped when narrowing.
+    #[inline(always)]
+    pub fn resize<const M: usize>(&self) -> HN<M> {
+        let mut bytes = [0u8; M];
+        let n = N.min(M);
+        bytes[..n].copy_from_slice(&self.0[..n]);
+        HN(bytes)
+    }
+
+    // The name of the width, as in the serde data model
+    const fn name() -> &'static str {
+        match N {
+            20 => "H160",
+            32 => "H256",
+            64 => "H512",
+            _ => "HN",
+        }
+    }
+}
+
+impl H256 {
+    /// Bit `i` is bit `i % 8` of byte `i / 8`, bit 255 is the highest one
+    /// and the first step from the root of a tree.
     #[inline(always)]
     pub fn get_bit(&self, i: u8) -> bool {
         let byte_pos = i / BYTE_SIZE;
@@ -32,7 +101,7 @@ impl H256 {
     pub fn set_bit(&mut self, i: u8) {
         let byte_pos = i / BYTE_SIZE;
         let bit_pos = i % BYTE_SIZE;
-        self.0[byte_pos as usize] |= 1 << bit_pos as u8;
+        self.0[byte_pos as usize] |= 1 << bit_pos;
     }
 
     #[inline(always)]
@@ -42,91 +111,211 @@ impl H256 {
         self.0[byte_pos as usize] &= !((1 << bit_pos) as u8);
     }
 
+    /// If the path goes to the right child at `height`, i.e. bit `height` is set
     #[inline(always)]
     pub fn is_right(&self, height: u8) -> bool {
         self.get_bit(height)
     }
 
+    /// Same path as the `Key` impl of `u64`: `n` in the lowest bits,
+    /// so keys sort as the integers do
     #[inline(always)]
-    pub fn as_slice(&self) -> &[u8] {
-        &self.0[..]
+    pub fn from_u64(n: u64) -> Self {
+        Self::from(n as u128)
+    }
+
+    /// Derive the key of `raw_key` under `namespace`: `H::hash` of
+    /// `namespace length(u32 LE) | namespace | raw_key`.
+    ///
+    /// The length prefix keeps namespaces apart(`b"ab", b"c"` vs `b"a", b"bc"`),
+    /// and the framing is the same as `domain::TaggedHasher`, so it equals the hash
+    /// of `raw_key` by a `TaggedHasher<H, T>` whose tag is `namespace`.
+    pub fn key_of<H: Hasher>(namespace: &[u8], raw_key: &[u8]) -> Self {
+        let mut input = Vec::with_capacity(4 + namespace.len() + raw_key.len());
+        input.extend_from_slice(&(namespace.len() as u32).to_le_bytes());
+        input.extend_from_slice(namespace);
+        input.extend_from_slice(raw_key);
+        H::hash(&input)
+    }
+
+    /// All bits from the root down, i.e. bit 255 first
+    #[inline(always)]
+    pub fn bits(&self) -> impl DoubleEndedIterator<Item = bool> + ExactSizeIterator {
+        let h = *self;
+        (0..=u8::MAX).rev().map(move |i| h.get_bit(i))
     }
 
-    /// Treat H256 as a path in a tree
-    /// fork height is the number of common bits(from heigher to lower: 255..=0) of two H256
+    /// Treat H256 as a path in a tree,
+    /// return the height of the highest different bit of two paths,
+    /// where they fork; 0 if they are the same.
     #[inline(always)]
     pub fn fork_height(&self, key: &H256) -> u8 {
-        for h in (0..=core::u8::MAX).rev() {
-            if self.get_bit(h) != key.get_bit(h) {
-                return h;
+        let (a, b) = (self.limbs(), key.limbs());
+        for i in (0..LIMBS).rev() {
+            let diff = a[i] ^ b[i];
+            if diff != 0 {
+                return (i as u32 * 64 + 63 - diff.leading_zeros()) as u8;
             }
         }
         0
     }
 
-    /// Treat H256 as a path in a tree
-    /// return parent_path of self
+    /// Number of the same highest bits of two paths(0..=256),
+    /// i.e. the depth of their lowest common ancestor.
+    #[inline(always)]
+    pub fn common_prefix_len(&self, key: &H256) -> u16 {
+        if self == key {
+            256
+        } else {
+            255 - self.fork_height(key) as u16
+        }
+    }
+
+    /// Treat H256 as a path in a tree,
+    /// return the key of the node at `height + 1` on the path,
+    /// which is the parent of the node at `height`: bits above `height` are kept.
+    /// The root(height 255) has the zero key.
     #[inline(always)]
     pub fn parent_path(&self, height: u8) -> Self {
-        if height == core::u8::MAX {
+        if height == u8::MAX {
             H256::zero()
         } else {
             self.copy_bits(height + 1)
         }
     }
 
-    /// Copy bits and return a new H256
+    /// Copy bits `start..=255` and clear the lower ones
     #[inline(always)]
     pub fn copy_bits(&self, start: u8) -> Self {
-        let mut target = H256::zero();
-
-        let start_byte = (start / BYTE_SIZE) as usize;
-        // copy bytes
-        target.0[start_byte..].copy_from_slice(&self.0[start_byte..]);
+        let mut limbs = self.limbs();
+        let start_limb = (start / 64) as usize;
+        limbs[..start_limb].fill(0);
+        limbs[start_limb] &= u64::MAX << (start % 64);
+        Self::from_limbs(limbs)
+    }
 
-        // reset remain bytes
-        let remain = start % BYTE_SIZE;
-        if remain > 0 {
-            target.0[start_byte] &= 0b11111111 << remain
+    // Bits `64 * i..64 * (i + 1)` in limb `i`, so limbs compare as the bits do
+    #[inline(always)]
+    fn limbs(&self) -> [u64; LIMBS] {
+        let mut limbs = [0u64; LIMBS];
+        for (limb, bytes) in limbs.iter_mut().zip(self.0.chunks_exact(8)) {
+            *limb = u64::from_le_bytes(bytes.try_into().unwrap());
         }
+        limbs
+    }
 
-        target
+    #[inline(always)]
+    fn from_limbs(limbs: [u64; LIMBS]) -> Self {
+        let mut bytes = [0u8; 32];
+        for (bytes, limb) in bytes.chunks_exact_mut(8).zip(limbs) {
+            bytes.copy_from_slice(&limb.to_le_bytes());
+        }
+        HN(bytes)
     }
 }
 
-impl VsMgmt for H256 {
+impl<const N: usize> VsMgmt for HN<N> {
     impl_vs_methods_nope! {}
 }
 
-impl PartialOrd for H256 {
+// The bytes in reverse order, so that the byte order of the keys
+// in an ordered map(e.g. `MapxOrdVs`) is the order of `Ord`
+impl<const N: usize> KeyEnDeOrdered for HN<N> {
+    #[inline(always)]
+    fn to_bytes(&self) -> Box<[u8]> {
+        self.0.iter().rev().copied().collect()
+    }
+
+    #[inline(always)]
+    fn from_slice(b: &[u8]) -> ruc::Result<Self> {
+        let mut bytes: [u8; N] = b
+            .try_into()
+            .map_err(|_| ruc::eg!(format!("expect {} bytes, got {}", N, b.len())))?;
+        bytes.reverse();
+        Ok(HN(bytes))
+    }
+}
+
+impl<const N: usize> Default for HN<N> {
+    #[inline(always)]
+    fn default() -> Self {
+        Self::zero()
+    }
+}
+
+impl<const N: usize> fmt::Debug for HN<N> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple(Self::name()).field(&self.0).finish()
+    }
+}
+
+impl<const N: usize> PartialOrd for HN<N> {
     #[inline(always)]
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
         Some(self.cmp(other))
     }
 }
 
-impl Ord for H256 {
+impl<const N: usize> Ord for HN<N> {
     #[inline(always)]
     fn cmp(&self, other: &Self) -> Ordering {
-        // Compare bits from heigher to lower (255..0)
-        self.0.iter().rev().cmp(other.0.iter().rev())
+        // Compare bits from heigher to lower, 64 of them at a time
+        let limbs = |h: &Self| {
+            let mut limbs = [0u64; 8];
+            for (limb, bytes) in limbs.iter_mut().zip(h.0.rchunks(8)) {
+                let mut buf = [0u8; 8];
+                buf[..bytes.len()].copy_from_slice(bytes);
+                *limb = u64::from_le_bytes(buf);
+            }
+            limbs
+        };
+        if N <= 64 {
+            limbs(self).cmp(&limbs(other))
+        } else {
+            self.0.iter().rev().cmp(other.0.iter().rev())
+        }
+    }
+}
+
+impl<const N: usize> From<[u8; N]> for HN<N> {
+    #[inline(always)]
+    fn from(h: [u8; N]) -> Self {
+        HN(h)
     }
 }
 
-impl From<[u8; 32]> for H256 {
+impl<const N: usize> From<&[u8; N]> for HN<N> {
     #[inline(always)]
-    fn from(h: [u8; 32]) -> H256 {
-        H256(h)
+    fn from(h: &[u8; N]) -> Self {
+        HN(*h)
     }
 }
 
-impl From<&[u8; 32]> for H256 {
+impl<const N: usize> TryFrom<&[u8]> for HN<N> {
+    type Error = Error;
+
     #[inline(always)]
-    fn from(h: &[u8; 32]) -> H256 {
-        H256(*h)
+    fn try_from(bytes: &[u8]) -> Result<Self, Error> {
+        <[u8; N]>::try_from(bytes)
+            .map(HN)
+            .map_err(|_| Error::InvalidLength {
+                expected: N,
+                actual: bytes.len(),
+            })
     }
 }
 
+impl From<u128> for H256 {
+    /// See `H256::from_u64`
+    #[inline(always)]
+    fn from(n: u128) -> H256 {
+        let mut bytes = [0u8; 32];
+        bytes[..16].copy_from_slice(&n.to_le_bytes());
+        HN(bytes)
+    }
+}
+
+#[cfg(feature = "pt11")]
 impl From<H256> for pt11::H256 {
     #[inline(always)]
     fn from(h: H256) -> pt11::H256 {
@@ -134,6 +323,7 @@ impl From<H256> for pt11::H256 {
     }
 }
 
+#[cfg(feature = "pt10")]
 impl From<H256> for pt10::H256 {
     #[inline(always)]
     fn from(h: H256) -> pt10::H256 {
@@ -141,13 +331,44 @@ impl From<H256> for pt10::H256 {
     }
 }
 
-impl From<H256> for [u8; 32] {
+impl From<H160> for H256 {
+    #[inline(always)]
+    fn from(h: H160) -> H256 {
+        h.resize()
+    }
+}
+
+/// Keeps the lowest 20 bytes
+impl From<H256> for H160 {
+    #[inline(always)]
+    fn from(h: H256) -> H160 {
+        h.resize()
+    }
+}
+
+impl From<H256> for H512 {
+    #[inline(always)]
+    fn from(h: H256) -> H512 {
+        h.resize()
+    }
+}
+
+/// Keeps the lowest 32 bytes
+impl From<H512> for H256 {
+    #[inline(always)]
+    fn from(h: H512) -> H256 {
+        h.resize()
+    }
+}
+
+impl<const N: usize> From<HN<N>> for [u8; N] {
     #[inline(always)]
-    fn from(h256: H256) -> [u8; 32] {
-        h256.0
+    fn from(h: HN<N>) -> [u8; N] {
+        h.0
     }
 }
 
+#[cfg(feature = "pt11")]
 impl From<pt11::H256> for H256 {
     #[inline(always)]
     fn from(h: pt11::H256) -> Self {
@@ -155,6 +376,7 @@ impl From<pt11::H256> for H256 {
     }
 }
 
+#[cfg(feature = "pt11")]
 impl From<&pt11::H256> for H256 {
     #[inline(always)]
     fn from(h: &pt11::H256) -> Self {
@@ -162,6 +384,7 @@ impl From<&pt11::H256> for H256 {
     }
 }
 
+#[cfg(feature = "pt11")]
 impl From<pt11::H160> for H256 {
     #[inline(always)]
     fn from(h: pt11::H160) -> Self {
@@ -169,6 +392,7 @@ impl From<pt11::H160> for H256 {
     }
 }
 
+#[cfg(feature = "pt11")]
 impl From<&pt11::H160> for H256 {
     #[inline(always)]
     fn from(h: &pt11::H160) -> Self {
@@ -176,6 +400,7 @@ impl From<&pt11::H160> for H256 {
     }
 }
 
+#[cfg(feature = "pt10")]
 impl From<pt10::H256> for H256 {
     #[inline(always)]
     fn from(h: pt10::H256) -> Self {
@@ -183,6 +408,7 @@ impl From<pt10::H256> for H256 {
     }
 }
 
+#[cfg(feature = "pt10")]
 impl From<&pt10::H256> for H256 {
     #[inline(always)]
     fn from(h: &pt10::H256) -> Self {
@@ -190,6 +416,7 @@ impl From<&pt10::H256> for H256 {
     }
 }
 
+#[cfg(feature = "pt10")]
 impl From<pt10::H160> for H256 {
     #[inline(always)]
     fn from(h: pt10::H160) -> Self {
@@ -197,6 +424,7 @@ impl From<pt10::H160> for H256 {
     }
 }
 
+#[cfg(feature = "pt10")]
 impl From<&pt10::H160> for H256 {
     #[inline(always)]
     fn from(h: &pt10::H160) -> Self {
@@ -204,8 +432,250 @@ impl From<&pt10::H160> for H256 {
     }
 }
 
-impl AsRef<[u8]> for H256 {
+impl<const N: usize> AsRef<[u8]> for HN<N> {
     fn as_ref(&self) -> &[u8] {
         &self.0[..]
     }
 }
+
+impl<const N: usize> fmt::LowerHex for HN<N> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if f.alternate() {
+            f.write_str("0x")?;
+        }
+        for b in self.0.iter() {
+            write!(f, "{:02x}", b)?;
+        }
+        Ok(())
+    }
+}
+
+impl<const N: usize> fmt::Display for HN<N> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:#x}", self)
+    }
+}
+
+impl<const N: usize> FromStr for HN<N> {
+    type Err = Error;
+
+    /// Parse `2 * N` hex digits, with or without the `0x` prefix
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let digits = s.strip_prefix("0x").unwrap_or(s).as_bytes();
+        if digits.len() != 2 * N {
+            return Err(Error::ParseH256(format!(
+                "expected {} hex digits, got {}",
+                2 * N,
+                digits.len()
+            )));
+        }
+        let nibble = |c: u8| {
+            hex_nibble(c).ok_or_else(|| {
+                Error::ParseH256(format!("invalid hex digit {:?}", c as char))
+            })
+        };
+        let mut h = [0u8; N];
+        for (b, pair) in h.iter_mut().zip(digits.chunks_exact(2)) {
+            *b = (nibble(pair[0])? << 4) | nibble(pair[1])?;
+        }
+        Ok(HN(h))
+    }
+}
+
+#[inline(always)]
+fn hex_nibble(c: u8) -> Option<u8> {
+    match c {
+        b'0'..=b'9' => Some(c - b'0'),
+        b'a'..=b'f' => Some(c - b'a' + 10),
+        b'A'..=b'F' => Some(c - b'A' + 10),
+        _ => None,
+    }
+}
+
+/// `0x`-prefixed lowercase hex of `bytes`
+pub(crate) fn to_hex(bytes: &[u8]) -> String {
+    let mut s = String::with_capacity(2 + bytes.len() * 2);
+    s.push_str("0x");
+    for b in bytes {
+        s.push_str(&format!("{:02x}", b));
+    }
+    s
+}
+
+/// Parse hex digits in pairs, with or without the `0x` prefix
+pub(crate) fn from_hex(s: &str) -> Result<Vec<u8>, Error> {
+    let digits = s.strip_prefix("0x").unwrap_or(s).as_bytes();
+    if !digits.len().is_multiple_of(2) {
+        return Err(Error::ParseHex(format!(
+            "odd number of hex digits: {}",
+            digits.len()
+        )));
+    }
+    digits
+        .chunks_exact(2)
+        .map(|pair| match (hex_nibble(pair[0]), hex_nibble(pair[1])) {
+            (Some(hi), Some(lo)) => Ok((hi << 4) | lo),
+            _ => Err(Error::ParseHex(format!(
+                "invalid hex digits {:?}",
+                String::from_utf8_lossy(pair)
+            ))),
+        })
+        .collect()
+}
+
+impl<const N: usize> Serialize for HN<N> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if serializer.is_human_readable() {
+            serializer.collect_str(self)
+        } else {
+            serializer.serialize_newtype_struct(Self::name(), &ByteTuple(&self.0))
+        }
+    }
+}
+
+// Bytes as a tuple, the serde form of `[u8; 32]` for any length
+struct ByteTuple<'a>(&'a [u8]);
+
+impl Serialize for ByteTuple<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeTuple;
+        let mut tuple = serializer.serialize_tuple(self.0.len())?;
+        for b in self.0 {
+            tuple.serialize_element(b)?;
+        }
+        tuple.end()
+    }
+}
+
+impl<'de, const N: usize> Deserialize<'de> for HN<N> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        if deserializer.is_human_readable() {
+            // byte arrays written before the hex form are still accepted
+            deserializer.deserialize_any(HNVisitor)
+        } else {
+            deserializer.deserialize_newtype_struct(Self::name(), HNVisitor)
+        }
+    }
+}
+
+struct HNVisitor<const N: usize>;
+
+impl<'de, const N: usize> de::Visitor<'de> for HNVisitor<N> {
+    type Value = HN<N>;
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} bytes or {} hex digits", N, 2 * N)
+    }
+
+    fn visit_str<E: de::Error>(self, v: &str) -> Result<HN<N>, E> {
+        v.parse().map_err(E::custom)
+    }
+
+    fn visit_newtype_struct<D: Deserializer<'de>>(
+        self,
+        deserializer: D,
+    ) -> Result<HN<N>, D::Error> {
+        deserializer.deserialize_tuple(N, self)
+    }
+
+    fn visit_seq<A: de::SeqAccess<'de>>(self, mut seq: A) -> Result<HN<N>, A::Error> {
+        let mut h = [0u8; N];
+        for (i, b) in h.iter_mut().enumerate() {
+            *b = seq
+                .next_element()?
+                .ok_or_else(|| de::Error::invalid_length(i, &self))?;
+        }
+        if seq.next_element::<u8>()?.is_some() {
+            return Err(de::Error::invalid_length(N + 1, &self));
+        }
+        Ok(HN(h))
+    }
+}
+
+/// A value of at most 32 bytes copied into the lowest bytes of the leaf,
+/// e.g. a little-endian integer, so no hashing is involved;
+/// an all-zero value(the default) removes the key.
+///
+/// `[u8; N]` itself is hashed as bytes with `bytes-value`.
+#[cfg(feature = "int-value")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(from = "HN<N>", into = "HN<N>")]
+pub struct LowBytes<const N: usize>(pub [u8; N]);
+
+#[cfg(feature = "int-value")]
+impl<const N: usize> Default for LowBytes<N> {
+    #[inline(always)]
+    fn default() -> Self {
+        LowBytes([0u8; N])
+    }
+}
+
+#[cfg(feature = "int-value")]
+impl<const N: usize> From<[u8; N]> for LowBytes<N> {
+    #[inline(always)]
+    fn from(bytes: [u8; N]) -> Self {
+        LowBytes(bytes)
+    }
+}
+
+#[cfg(feature = "int-value")]
+impl<const N: usize> From<HN<N>> for LowBytes<N> {
+    #[inline(always)]
+    fn from(h: HN<N>) -> Self {
+        LowBytes(h.0)
+    }
+}
+
+#[cfg(feature = "int-value")]
+impl<const N: usize> From<LowBytes<N>> for HN<N> {
+    #[inline(always)]
+    fn from(v: LowBytes<N>) -> Self {
+        HN(v.0)
+    }
+}
+
+/// A `Hasher` on the `N`-byte digest of `H`(`N <= 32`), e.g.
+/// `NarrowHasher<Blake3Hasher, 20>` for a tree committing to `H160` roots.
+///
+/// Every leaf and node hash is the digest widened to `H256`,
+/// so the root converts back to `HN<N>` without loss.
+#[derive(Default, Clone, Deserialize, Serialize)]
+#[serde(bound = "H: Default")]
+pub struct NarrowHasher<H, const N: usize> {
+    #[serde(skip)]
+    inner: H,
+}
+
+impl<H, const N: usize> NarrowHasher<H, N> {
+    const FITS: () = assert!(N <= 32, "a digest wider than H256");
+}
+
+impl<H: Digest<N>, const N: usize> Hasher for NarrowHasher<H, N> {
+    #[inline(always)]
+    fn write_h256(&mut self, h: &H256) {
+        self.inner.update(h.as_slice());
+    }
+
+    #[inline(always)]
+    fn write_byte(&mut self, b: u8) {
+        self.inner.update(&[b]);
+    }
+
+    #[inline(always)]
+    fn finish(self) -> H256 {
+        let () = Self::FITS;
+        self.inner.finalize().resize()
+    }
+
+    #[inline(always)]
+    fn hash(bytes: &[u8]) -> H256 {
+        let () = Self::FITS;
+        H::digest(bytes).resize()
+    }
+}
+
+impl<H, const N: usize> fmt::Debug for NarrowHasher<H, N> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "NarrowHasher<{}>", N)
+    }
+}
@@ -0,0 +1,56 @@
+use crate::{traits::Hasher, H256};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use tiny_keccak::{Hasher as _, Keccak};
+
+/// Keccak-256 as used by the EVM,
+/// so that proofs can be checked by `solidity::keccak_verifier`.
+#[derive(Clone, Deserialize, Serialize)]
+pub struct KeccakHasher {
+    #[serde(skip, default = "Keccak::v256")]
+    hasher: Keccak,
+}
+
+impl Default for KeccakHasher {
+    #[inline(always)]
+    fn default() -> Self {
+        KeccakHasher {
+            hasher: Keccak::v256(),
+        }
+    }
+}
+
+impl Hasher for KeccakHasher {
+    #[inline(always)]
+    fn write_h256(&mut self, h: &H256) {
+        self.hasher.update(h.as_slice());
+    }
+
+    #[inline(always)]
+    fn write_byte(&mut self, b: u8) {
+        self.hasher.update(&[b][..]);
+    }
+
+    #[inline(always)]
+    fn finish(self) -> H256 {
+        let mut hash = [0u8; 32];
+        self.hasher.finalize(&mut hash);
+        hash.into()
+    }
+
+    #[inline(always)]
+    fn hash(bytes: &[u8]) -> H256 {
+        let mut kh = Self::default();
+        kh.hasher.update(bytes);
+
+        let mut hash = [0u8; 32];
+        kh.hasher.finalize(&mut hash);
+        hash.into()
+    }
+}
+
+impl fmt::Debug for KeccakHasher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("KeccakHasher").finish()
+    }
+}
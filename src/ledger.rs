@@ -0,0 +1,264 @@
+//!
+//! A minimal account ledger built on the crate,
+//! mostly as a worked example of how the public APIs compose.
+//!
+//! Account states live in a `SparseMerkleTree`, one vsdb version per block;
+//! the transfers of each block live in a `SparseMerkleTree2` keyed by height,
+//! so both the state and the history of a block can be proved to light wallets.
+//!
+
+use crate::{
+    blake3_hasher::Blake3Hasher,
+    error::{Error, Result, StoreTarget},
+    merkle_proof::MerkleProof,
+    traits::{Hasher, Value},
+    VsSmt, VsSmt2, H256,
+};
+use ruc::*;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    io::{Read, Write},
+};
+use vsdb::{VersionName, VsMgmt};
+
+/// State of an account, the all-zero state is treated as non-existent
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Account {
+    pub nonce: u64,
+    pub balance: u128,
+}
+
+impl<H: Hasher> Value<H> for Account {
+    fn to_h256(&self) -> H256 {
+        if *self == Account::default() {
+            return H256::zero();
+        }
+        let mut hasher = H::default();
+        hasher.write_h256(&H256::from_u64(self.nonce));
+        hasher.write_h256(&H256::from(self.balance));
+        hasher.finish()
+    }
+}
+
+/// Move `amount` from `from` to `to`,
+/// `nonce` must equal the current nonce of `from`
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Transfer {
+    pub from: H256,
+    pub to: H256,
+    pub amount: u128,
+    pub nonce: u64,
+}
+
+impl Transfer {
+    /// Hash of the transfer, as recorded in the transfers tree
+    pub fn hash(&self) -> H256 {
+        let mut hasher = Blake3Hasher::default();
+        hasher.write_h256(&self.from);
+        hasher.write_h256(&self.to);
+        hasher.write_h256(&H256::from(self.amount));
+        hasher.write_h256(&H256::from_u64(self.nonce));
+        hasher.finish()
+    }
+}
+
+/// Summary of a committed block
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BlockHeader {
+    pub height: u64,
+    /// Root of all account states after this block
+    pub state_root: H256,
+    /// Root of the transfers included in this block
+    pub transfers_root: H256,
+}
+
+/// Account ledger, one vsdb version per block
+#[derive(Debug, Default)]
+pub struct Ledger {
+    accounts: VsSmt<Account>,
+    transfers: VsSmt2<u64, H256>,
+    height: u64,
+}
+
+impl Ledger {
+    /// Create a ledger with the initial balances as block 0
+    pub fn genesis(alloc: Vec<(H256, u128)>) -> Result<Self> {
+        let mut ledger = Self::default();
+        ledger.new_version(0)?;
+        ledger.accounts.update_all(
+            alloc
+                .into_iter()
+                .map(|(addr, balance)| (addr, Account { nonce: 0, balance }))
+                .collect(),
+        )?;
+        Ok(ledger)
+    }
+
+    /// Rebuild a ledger at `height` from the output of `export_state`,
+    /// the history of earlier blocks is not included.
+    pub fn from_state(height: u64, reader: impl Read) -> Result<Self> {
+        let mut ledger = Self::default();
+        ledger.new_version(height)?;
+        ledger.accounts.import_snapshot(reader)?;
+        ledger.height = height;
+        Ok(ledger)
+    }
+
+    /// Serialize the latest account states for state sync
+    #[inline(always)]
+    pub fn export_state(&self, writer: impl Write) -> Result<u64> {
+        self.accounts.export_snapshot(writer)
+    }
+
+    /// Height of the latest block
+    #[inline(always)]
+    pub fn height(&self) -> u64 {
+        self.height
+    }
+
+    /// Root of the latest account states
+    #[inline(always)]
+    pub fn state_root(&self) -> H256 {
+        self.accounts.root()
+    }
+
+    /// Root of the account states right after the block at `height`
+    pub fn state_root_at(&self, height: u64) -> Result<H256> {
+        let br = self.accounts.branch_get_default();
+        self.accounts
+            .root_by_branch_version(br.as_deref(), VersionName(&height.to_be_bytes()))
+    }
+
+    #[inline(always)]
+    pub fn account(&self, addr: &H256) -> Result<Account> {
+        self.accounts.get(addr).map(Option::unwrap_or_default)
+    }
+
+    /// Apply all transfers as the next block,
+    /// nothing is written if any of them is invalid.
+    pub fn commit_block(&mut self, transfers: Vec<Transfer>) -> Result<BlockHeader> {
+        let mut changed: BTreeMap<H256, Account> = BTreeMap::new();
+        for (i, tx) in transfers.iter().enumerate() {
+            let mut from = match changed.get(&tx.from) {
+                Some(acc) => *acc,
+                None => self.account(&tx.from)?,
+            };
+            if from.nonce != tx.nonce {
+                return Err(Error::Ledger(format!(
+                    "transfer {}: invalid nonce {}, expected {}",
+                    i, tx.nonce, from.nonce
+                )));
+            }
+            if from.balance < tx.amount {
+                return Err(Error::Ledger(format!(
+                    "transfer {}: insufficient balance",
+                    i
+                )));
+            }
+            from.nonce += 1;
+            from.balance -= tx.amount;
+            changed.insert(tx.from, from);
+
+            let mut to = match changed.get(&tx.to) {
+                Some(acc) => *acc,
+                None => self.account(&tx.to)?,
+            };
+            to.balance = to.balance.checked_add(tx.amount).ok_or_else(|| {
+                Error::Ledger(format!("transfer {}: balance overflow", i))
+            })?;
+            changed.insert(tx.to, to);
+        }
+
+        let height = self.height + 1;
+        self.new_version(height)?;
+        let state_root = self.accounts.update_all(changed.into_iter().collect())?;
+        let transfers_root = self.transfers.update_all(
+            &height,
+            transfers
+                .iter()
+                .enumerate()
+                .map(|(i, tx)| (H256::from(i as u128), tx.hash()))
+                .collect(),
+        )?;
+        self.height = height;
+
+        Ok(BlockHeader {
+            height,
+            state_root,
+            transfers_root,
+        })
+    }
+
+    /// Prove the latest states of `addrs`
+    #[inline(always)]
+    pub fn prove_accounts(&self, addrs: Vec<H256>) -> Result<MerkleProof> {
+        self.accounts.merkle_proof(addrs)
+    }
+
+    /// Prove the states of `addrs` right after the block at `height`
+    pub fn prove_accounts_at(
+        &self,
+        height: u64,
+        addrs: Vec<H256>,
+    ) -> Result<MerkleProof> {
+        let br = self.accounts.branch_get_default();
+        self.accounts.merkle_proof_by_branch_version(
+            addrs,
+            br.as_deref(),
+            VersionName(&height.to_be_bytes()),
+        )
+    }
+
+    /// Prove the `index`-th transfer of the block at `height`
+    #[inline(always)]
+    pub fn prove_transfer(&self, height: u64, index: u64) -> Result<MerkleProof> {
+        self.transfers
+            .merkle_proof(&height, vec![H256::from_u64(index)])
+    }
+
+    fn new_version(&mut self, height: u64) -> Result<()> {
+        let ver = height.to_be_bytes();
+        let target = || StoreTarget::Version(ver.to_vec());
+        crate::chg_store!(
+            Insert,
+            target(),
+            self.accounts.version_create(VersionName(&ver))
+        );
+        crate::chg_store!(
+            Insert,
+            target(),
+            self.transfers.version_create(VersionName(&ver))
+        );
+        Ok(())
+    }
+}
+
+/// Light wallet side: check account states against a state root
+pub fn verify_accounts(
+    state_root: H256,
+    proof: MerkleProof,
+    accounts: &[(H256, Account)],
+) -> Result<bool> {
+    let leaves = accounts
+        .iter()
+        .map(|(addr, acc)| {
+            let v = <Account as Value<Blake3Hasher>>::to_h256(acc);
+            (*addr, Some(v))
+        })
+        .collect();
+    proof.verify::<Blake3Hasher>(state_root, leaves)
+}
+
+/// Light wallet side: check a transfer against a transfers root
+pub fn verify_transfer(
+    transfers_root: H256,
+    proof: MerkleProof,
+    index: u64,
+    tx: &Transfer,
+) -> Result<bool> {
+    proof.verify::<Blake3Hasher>(
+        transfers_root,
+        vec![(H256::from_u64(index), Some(tx.hash()))],
+    )
+}
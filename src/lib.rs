@@ -2,23 +2,84 @@
 //! Constructs a new `SparseMerkleTree<H, V, S>`.
 //!
 
+// Bump a counter of `metrics`, a no-op without the `metrics` feature
+macro_rules! metric {
+    ($counter: ident) => {
+        metric!($counter, 1)
+    };
+    ($counter: ident, $n: expr) => {
+        #[cfg(feature = "metrics")]
+        $crate::metrics::add(&$crate::metrics::COUNTERS.$counter, $n as u64);
+    };
+}
+
 pub mod blake3_hasher;
+pub mod checkpoint;
+pub mod compression;
 pub mod default_store;
+pub mod deferred;
+pub mod domain;
+#[cfg(feature = "encryption")]
+pub mod encrypted;
 pub mod error;
+pub mod frozen;
 pub mod h256;
+#[cfg(feature = "keccak")]
+pub mod keccak_hasher;
+#[cfg(feature = "ledger")]
+pub mod ledger;
+pub mod lock;
 pub mod merge;
 pub mod merkle_proof;
+#[cfg(feature = "metrics")]
+pub mod metrics;
+pub mod namespace;
+pub mod overlay;
+#[cfg(feature = "reference-impl")]
+pub mod reference;
+#[cfg(feature = "server")]
+pub mod server;
+pub mod shared;
+pub mod snapshot;
+#[cfg(feature = "keccak")]
+pub mod solidity;
+pub mod stateless;
+pub mod sync;
+#[cfg(feature = "testing")]
+pub mod testing;
 pub mod traits;
+pub mod transaction;
 pub mod tree;
+pub mod view;
 
 #[cfg(test)]
 mod tests;
 
+pub use checkpoint::Checkpoint;
+pub use compression::Codec;
 pub use default_store::{DefaultStore, DefaultStore2};
-pub use h256::H256;
-pub use merkle_proof::{CompiledMerkleProof, MerkleProof};
+pub use deferred::DeferredSmt;
+pub use frozen::FrozenSmt;
+pub use h256::{NarrowHasher, H160, H256, H512, HN};
+#[cfg(feature = "int-value")]
+pub use h256::LowBytes;
+pub use lock::{KeyRange, LockOwner, RangeLocks};
+pub use merkle_proof::{
+    BoundMerkleProof, CompiledMerkleProof, MerkleProof, SubtreeProof, VerifyOptions,
+    VerifyOutcome, XLeaves, XMerkleProof, XMultiMerkleProof,
+};
+pub use namespace::NamespacedKey;
+pub use overlay::OverlayStore;
+pub use shared::{RangeWriter, SharedSmt};
+pub use stateless::WitnessTree;
+pub use sync::{Chunk, ChunkImporter};
 pub use traits::*;
-pub use tree::{SparseMerkleTree, SparseMerkleTree2};
+pub use transaction::Transaction;
+pub use tree::{
+    compute_root_from_leaves, promote_to_smt2, BatchReport, CompactionReport, DiffIter, IntegrityReport, LeafIter, LeafChange, ProofWithValues,
+    SparseMerkleTree, SparseMerkleTree2, VersionDiff,
+};
+pub use view::SmtView;
 
 /// Expected path size: log2(256) * 2, used for hint vector capacity
 pub const EXPECTED_PATH_SIZE: usize = 16;
@@ -39,11 +100,15 @@ pub type VsSmt2<X, V> = SparseMerkleTree2<
     DefaultStore2<X, V>,
 >;
 
-#[macro_export(crate)]
+#[macro_export]
 macro_rules! chg_store {
-    ($op: expr) => {
-        if let Err(e) = $op.c(d!()) {
-            return Err(Error::Store(e.to_string()));
+    ($op: ident, $target: expr, $call: expr) => {
+        if let Err(e) = $call.c(d!()) {
+            return Err($crate::error::Error::Store {
+                op: $crate::error::StoreOp::$op,
+                target: $target,
+                source: $crate::error::StoreError::msg(e),
+            });
         }
     };
 }
@@ -0,0 +1,217 @@
+//!
+//! Advisory locks over key ranges.
+//!
+//! Writers coordinating over disjoint key namespaces can hold their own
+//! ranges at the same time, overlapping requests wait for each other,
+//! and a request that would close a wait cycle fails with `Error::Deadlock`.
+//!
+//! Locks are held by a `LockOwner` rather than by a thread,
+//! so a writer task may move between threads while it holds its ranges.
+//!
+
+use crate::{
+    error::{Error, Result},
+    H256,
+};
+use std::{
+    collections::HashMap,
+    fmt,
+    sync::{Condvar, Mutex},
+};
+
+/// All keys sharing the highest `bits` bits with `prefix`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct KeyRange {
+    prefix: H256,
+    bits: u16,
+}
+
+impl KeyRange {
+    /// Bits out of the range are cleared, `bits` is capped at 256
+    #[inline(always)]
+    pub fn new(prefix: H256, bits: u16) -> Self {
+        let bits = bits.min(256);
+        let prefix = if bits == 0 {
+            H256::zero()
+        } else {
+            prefix.copy_bits((256 - bits) as u8)
+        };
+        KeyRange { prefix, bits }
+    }
+
+    #[inline(always)]
+    pub fn prefix(&self) -> &H256 {
+        &self.prefix
+    }
+
+    #[inline(always)]
+    pub fn bits(&self) -> u16 {
+        self.bits
+    }
+
+    /// The lowest and the highest key of this range
+    #[inline(always)]
+    pub fn bounds(&self) -> (H256, H256) {
+        let mut last = self.prefix;
+        for i in 0..256 - self.bits {
+            last.set_bit(i as u8);
+        }
+        (self.prefix, last)
+    }
+
+    /// Check if the key falls into this range
+    #[inline(always)]
+    pub fn contains(&self, key: &H256) -> bool {
+        KeyRange::new(*key, self.bits).prefix == self.prefix
+    }
+
+    /// Two ranges overlap if one of them contains the other
+    #[inline(always)]
+    pub fn overlaps(&self, other: &KeyRange) -> bool {
+        let bits = self.bits.min(other.bits);
+        KeyRange::new(self.prefix, bits) == KeyRange::new(other.prefix, bits)
+    }
+}
+
+/// The holder of locked ranges, given out by `RangeLocks::owner`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct LockOwner(u64);
+
+#[derive(Debug, Default)]
+struct LockState {
+    next_id: u64,
+    next_owner: u64,
+    // lock id => (locked range, owner)
+    held: HashMap<u64, (KeyRange, LockOwner)>,
+    // waiter => owner it is waiting for
+    waiting: HashMap<LockOwner, LockOwner>,
+}
+
+impl LockState {
+    fn conflict(&self, range: &KeyRange) -> Option<LockOwner> {
+        self.held
+            .values()
+            .find(|(r, _)| r.overlaps(range))
+            .map(|(_, owner)| *owner)
+    }
+
+    // Will `me` waiting for `owner` close a cycle in the wait-for graph?
+    fn would_deadlock(&self, me: LockOwner, mut owner: LockOwner) -> bool {
+        loop {
+            if owner == me {
+                return true;
+            }
+            match self.waiting.get(&owner) {
+                Some(next) => owner = *next,
+                None => return false,
+            }
+        }
+    }
+}
+
+/// Advisory lock manager over key ranges
+#[derive(Debug, Default)]
+pub struct RangeLocks {
+    state: Mutex<LockState>,
+    cond: Condvar,
+}
+
+impl RangeLocks {
+    #[inline(always)]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A new owner, each writer should lock its ranges with its own
+    pub fn owner(&self) -> LockOwner {
+        let mut state = self.state.lock().unwrap();
+        state.next_owner += 1;
+        LockOwner(state.next_owner)
+    }
+
+    /// Lock all keys sharing the highest `bits` bits with `prefix` for `me`,
+    /// blocks while an overlapping range is held by another owner.
+    pub fn lock_range(
+        &self,
+        me: LockOwner,
+        prefix: H256,
+        bits: u16,
+    ) -> Result<RangeGuard<'_>> {
+        let range = KeyRange::new(prefix, bits);
+        let mut state = self.state.lock().unwrap();
+        while let Some(owner) = state.conflict(&range) {
+            if state.would_deadlock(me, owner) {
+                return Err(Error::Deadlock);
+            }
+            state.waiting.insert(me, owner);
+            state = self.cond.wait(state).unwrap();
+            state.waiting.remove(&me);
+        }
+        Ok(self.acquire(&mut state, range, me))
+    }
+
+    /// Like `lock_range`, but returns `None` instead of blocking
+    pub fn try_lock_range(
+        &self,
+        me: LockOwner,
+        prefix: H256,
+        bits: u16,
+    ) -> Option<RangeGuard<'_>> {
+        let range = KeyRange::new(prefix, bits);
+        let mut state = self.state.lock().unwrap();
+        if state.conflict(&range).is_some() {
+            return None;
+        }
+        Some(self.acquire(&mut state, range, me))
+    }
+
+    fn acquire(
+        &self,
+        state: &mut LockState,
+        range: KeyRange,
+        me: LockOwner,
+    ) -> RangeGuard<'_> {
+        let id = state.next_id;
+        state.next_id += 1;
+        state.held.insert(id, (range, me));
+        RangeGuard {
+            locks: self,
+            id,
+            range,
+        }
+    }
+
+    fn release(&self, id: u64) {
+        self.state.lock().unwrap().held.remove(&id);
+        self.cond.notify_all();
+    }
+}
+
+/// The range is unlocked when the guard is dropped
+pub struct RangeGuard<'a> {
+    locks: &'a RangeLocks,
+    id: u64,
+    range: KeyRange,
+}
+
+impl RangeGuard<'_> {
+    /// The locked range
+    #[inline(always)]
+    pub fn range(&self) -> &KeyRange {
+        &self.range
+    }
+}
+
+impl fmt::Debug for RangeGuard<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RangeGuard")
+            .field("range", &self.range)
+            .finish()
+    }
+}
+
+impl Drop for RangeGuard<'_> {
+    fn drop(&mut self) {
+        self.locks.release(self.id);
+    }
+}
@@ -4,7 +4,36 @@ use serde::{Deserialize, Serialize};
 const MERGE_NORMAL: u8 = 1;
 const MERGE_ZEROS: u8 = 2;
 
-#[derive(Debug, Eq, PartialEq, Clone, Deserialize, Serialize)]
+/// The first byte of hashed leaf values, with `Hasher::PREFIXED`
+pub const LEAF_PREFIX: u8 = 0;
+/// The first byte of hashed nodes(base nodes and merges), with `Hasher::PREFIXED`
+pub const NODE_PREFIX: u8 = 1;
+
+// A hasher of a node, starting with `NODE_PREFIX` if `H` is prefixed
+#[inline(always)]
+fn node_hasher<H: Hasher>() -> H {
+    let mut hasher = H::default();
+    if H::PREFIXED {
+        hasher.write_byte(NODE_PREFIX);
+    }
+    hasher
+}
+
+/// A node hash, or a pending run of merges with zero siblings.
+///
+/// Merging with a zero sibling does not hash, it records the side in `zero_bits`
+/// and bumps `zero_count`, the run is hashed only when it meets a non-zero sibling
+/// or when the node itself is hashed(e.g. as the root).
+/// So a leaf alone in a tree costs two hashes whatever its depth.
+///
+/// The tree also stores a run as it is: only the branches where both children
+/// are non-zero(and the top one) are stored, the branches inside a run
+/// are rebuilt from the `MergeWithZero` child of the stored branch above it.
+#[derive(Debug, Eq, PartialEq, Clone, Copy, Deserialize, Serialize)]
+#[cfg_attr(
+    feature = "borsh",
+    derive(borsh::BorshSerialize, borsh::BorshDeserialize)
+)]
 pub enum MergeValue {
     Value(H256),
     MergeWithZero {
@@ -42,7 +71,7 @@ impl MergeValue {
                 zero_bits,
                 zero_count,
             } => {
-                let mut hasher = H::default();
+                let mut hasher = node_hasher::<H>();
                 hasher.write_byte(MERGE_ZEROS);
                 hasher.write_h256(base_node);
                 hasher.write_h256(zero_bits);
@@ -60,7 +89,7 @@ pub fn hash_base_node<H: Hasher + Default>(
     base_key: &H256,
     base_value: &H256,
 ) -> H256 {
-    let mut hasher = H::default();
+    let mut hasher = node_hasher::<H>();
     hasher.write_byte(base_height);
     hasher.write_h256(base_key);
     hasher.write_h256(base_value);
@@ -85,7 +114,7 @@ pub fn merge<H: Hasher + Default>(
     if rhs.is_zero() {
         return merge_with_zero::<H>(height, node_key, lhs, false);
     }
-    let mut hasher = H::default();
+    let mut hasher = node_hasher::<H>();
     hasher.write_byte(MERGE_NORMAL);
     hasher.write_byte(height);
     hasher.write_h256(node_key);
@@ -1,11 +1,27 @@
 use crate::{
-    error::{Error, Result},
+    error::{Error, Limit, Result},
+    h256::{from_hex, to_hex},
     merge::{merge, MergeValue},
     traits::Hasher,
+    tree::merge_levels,
     H256, MAX_STACK_SIZE,
 };
+use core::{fmt, result::Result as StdResult};
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use std::collections::{btree_map, BTreeMap, BTreeSet};
+use vsdb::KeyEnDe;
 
+/// A merkle proof of a set of keys
+///
+/// For human-readable serde formats(e.g. JSON) it is written as
+/// `{"leaves_bitmap": [hash, ..], "merkle_path": [node, ..]}`, hashes in `0x`-prefixed hex,
+/// each path node a hash, or `[base_node, zero_bits, zero_count]` for a run of merges
+/// with zero; other formats keep the `to_bytes` layout as a byte string.
 #[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "borsh",
+    derive(borsh::BorshSerialize, borsh::BorshDeserialize)
+)]
 pub struct MerkleProof {
     // leaf bitmap, bitmap.get_bit(height) is true means there need a non zero sibling in this height
     leaves_bitmap: Vec<H256>,
@@ -25,6 +41,16 @@ impl MerkleProof {
         }
     }
 
+    /// The proof of `keys` against an empty tree, all of them absent,
+    /// the same as `merkle_proof` of the keys on an empty tree returns.
+    #[inline(always)]
+    pub fn empty(keys: &[H256]) -> Self {
+        MerkleProof {
+            leaves_bitmap: vec![H256::zero(); keys.len()],
+            merkle_path: vec![],
+        }
+    }
+
     /// Destruct the structure, useful for serialization
     #[inline(always)]
     pub fn take(self) -> (Vec<H256>, Vec<MergeValue>) {
@@ -53,7 +79,133 @@ impl MerkleProof {
         &self.merkle_path
     }
 
+    /// number of sibling nodes in the merkle path
+    #[inline(always)]
+    pub fn proof_len(&self) -> usize {
+        self.merkle_path.len()
+    }
+
+    /// Size of the `to_bytes` encoding, computed without encoding
+    pub fn serialized_size(&self) -> usize {
+        let path_size: usize = self
+            .merkle_path
+            .iter()
+            .map(|node| match node {
+                MergeValue::Value(_) => 33,
+                MergeValue::MergeWithZero { .. } => 66,
+            })
+            .sum();
+        8 + self.leaves_bitmap.len() * 32 + path_size
+    }
+
+    /// Encode the proof into a plain byte layout:
+    /// `leaves_count(u32 LE) | bitmaps | path_len(u32 LE) | path nodes`,
+    /// each path node is tagged by 0x50(Value) or 0x51(MergeWithZero)
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(self.serialized_size());
+        buf.extend_from_slice(&(self.leaves_bitmap.len() as u32).to_le_bytes());
+        for bitmap in &self.leaves_bitmap {
+            buf.extend_from_slice(bitmap.as_slice());
+        }
+        buf.extend_from_slice(&(self.merkle_path.len() as u32).to_le_bytes());
+        for node in &self.merkle_path {
+            match node {
+                MergeValue::Value(v) => {
+                    buf.push(0x50);
+                    buf.extend_from_slice(v.as_slice());
+                }
+                MergeValue::MergeWithZero {
+                    base_node,
+                    zero_bits,
+                    zero_count,
+                } => {
+                    buf.push(0x51);
+                    buf.push(*zero_count);
+                    buf.extend_from_slice(base_node.as_slice());
+                    buf.extend_from_slice(zero_bits.as_slice());
+                }
+            }
+        }
+        buf
+    }
+
+    /// Decode a proof from the layout produced by `to_bytes`
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let mut reader = ByteReader::new(bytes);
+
+        let leaves_count = reader.read_u32()? as usize;
+        let mut leaves_bitmap = Vec::with_capacity(leaves_count.min(bytes.len() / 32));
+        for _ in 0..leaves_count {
+            leaves_bitmap.push(reader.read_h256()?);
+        }
+
+        let path_len = reader.read_u32()? as usize;
+        let mut merkle_path = Vec::with_capacity(path_len.min(bytes.len() / 33));
+        for _ in 0..path_len {
+            let node = match reader.read_u8()? {
+                0x50 => MergeValue::Value(reader.read_h256()?),
+                0x51 => {
+                    let zero_count = reader.read_u8()?;
+                    let base_node = reader.read_h256()?;
+                    let zero_bits = reader.read_h256()?;
+                    MergeValue::MergeWithZero {
+                        base_node,
+                        zero_bits,
+                        zero_count,
+                    }
+                }
+                code => return Err(Error::InvalidCode(code)),
+            };
+            merkle_path.push(node);
+        }
+
+        if !reader.is_empty() {
+            return Err(Error::CorruptedProof);
+        }
+
+        Ok(MerkleProof::new(leaves_bitmap, merkle_path))
+    }
+
+    /// `0x`-prefixed hex of `to_bytes`
+    #[inline(always)]
+    pub fn to_hex(&self) -> String {
+        to_hex(&self.to_bytes())
+    }
+
+    /// Decode a proof from the hex produced by `to_hex`, the prefix is optional
+    #[inline(always)]
+    pub fn from_hex(s: &str) -> Result<Self> {
+        Self::from_bytes(&from_hex(s)?)
+    }
+
+    /// Compile into the opcode format of `CompiledMerkleProof`,
+    /// siblings shared by several keys are merged on the stack(`H`)
+    /// instead of being emitted once per key.
+    ///
+    /// `leaves_keys` must be the keys the proof was generated for, in any order;
+    /// keys that do not fit the bitmaps are reported by `Error::KeyMismatch`,
+    /// `Error::DuplicatedKeys` or `Error::UnusedSiblings` instead of
+    /// compiling into a program no leaves verify against.
     pub fn compile(self, mut leaves_keys: Vec<H256>) -> Result<CompiledMerkleProof> {
+        let mut proof: Vec<u8> =
+            Vec::with_capacity(self.merkle_path.len() * 33 + leaves_keys.len());
+        self.compile_into(&mut leaves_keys, &mut proof)?;
+        Ok(CompiledMerkleProof(proof))
+    }
+
+    /// Compile with the keys of `leaves` and bind the program to them,
+    /// see `BoundMerkleProof`.
+    pub fn compile_with_values(
+        self,
+        mut leaves: Vec<(H256, Option<H256>)>,
+    ) -> Result<BoundMerkleProof> {
+        leaves.sort_unstable_by_key(|(k, _v)| *k);
+        let proof = self.compile(leaves.iter().map(|(k, _v)| *k).collect())?;
+        Ok(BoundMerkleProof { proof, leaves })
+    }
+
+    // Append the compiled program to `proof`, `leaves_keys` is sorted in place
+    fn compile_into(&self, leaves_keys: &mut [H256], proof: &mut Vec<u8>) -> Result<()> {
         if leaves_keys.is_empty() {
             return Err(Error::EmptyKeys);
         } else if leaves_keys.len() != self.leaves_count() {
@@ -64,11 +216,14 @@ impl MerkleProof {
         }
         // sort leaves keys
         leaves_keys.sort_unstable();
+        // a duplicated key would throw off the fork height stack
+        if let Some(k) = leaves_keys.windows(2).find(|w| w[0] == w[1]) {
+            return Err(Error::DuplicatedKeys(k[0]));
+        }
 
-        let (leaves_bitmap, merkle_path) = self.take();
+        let leaves_bitmap = &self.leaves_bitmap;
+        let merkle_path = &self.merkle_path;
 
-        let mut proof: Vec<u8> =
-            Vec::with_capacity(merkle_path.len() * 33 + leaves_keys.len());
         let mut stack_fork_height = [0u8; MAX_STACK_SIZE]; // store fork height
         let mut stack_top = 0;
         let mut leaf_index = 0;
@@ -78,8 +233,21 @@ impl MerkleProof {
             let fork_height = if leaf_index + 1 < leaves_keys.len() {
                 leaf_key.fork_height(&leaves_keys[leaf_index + 1])
             } else {
-                core::u8::MAX
+                u8::MAX
             };
+            // siblings above the fork are shared with the next key, a bitmap
+            // has them all(`merkle_proof`) or none(`decompile`)
+            if leaf_index + 1 < leaves_keys.len() {
+                let shared = leaves_bitmap[leaf_index].parent_path(fork_height);
+                if !shared.is_zero()
+                    && shared != leaves_bitmap[leaf_index + 1].parent_path(fork_height)
+                {
+                    return Err(Error::KeyMismatch {
+                        key: leaves_keys[leaf_index + 1],
+                        height: fork_height,
+                    });
+                }
+            }
             proof.push(0x4C);
             let mut zero_count = 0u16;
             for height in 0..=fork_height {
@@ -93,7 +261,10 @@ impl MerkleProof {
                         (Some(0x48), None)
                     } else if leaves_bitmap[leaf_index].get_bit(height) {
                         if merkle_path_index >= merkle_path.len() {
-                            return Err(Error::CorruptedProof);
+                            return Err(Error::KeyMismatch {
+                                key: leaf_key,
+                                height,
+                            });
                         }
                         let node = &merkle_path[merkle_path_index];
                         merkle_path_index += 1;
@@ -145,7 +316,10 @@ impl MerkleProof {
                 proof.push(0x4F);
                 proof.push(n);
             }
-            debug_assert!(stack_top < MAX_STACK_SIZE);
+            // duplicated keys never merge, and would pile up
+            if stack_top >= MAX_STACK_SIZE {
+                return Err(Error::StackOverflow);
+            }
             stack_fork_height[stack_top] = fork_height;
             stack_top += 1;
             leaf_index += 1;
@@ -158,9 +332,9 @@ impl MerkleProof {
             return Err(Error::CorruptedProof);
         }
         if merkle_path_index != merkle_path.len() {
-            return Err(Error::CorruptedProof);
+            return Err(Error::UnusedSiblings(merkle_path.len() - merkle_path_index));
         }
-        Ok(CompiledMerkleProof(proof))
+        Ok(())
     }
 
     /// Compute root from proof
@@ -177,8 +351,35 @@ impl MerkleProof {
             .compute_root::<H>(leaves)
     }
 
+    /// Compute the root after the proved leaves `old_leaves` are updated
+    /// to `new_leaves`, without any store access.
+    ///
+    /// `old_leaves` are all the leaves of the proof, `new_leaves` only the changed ones,
+    /// the leaves not in `new_leaves` keep their old values. Verify `old_leaves`
+    /// against the old root first, the new root is only as good as the proof.
+    pub fn compute_new_root<H: Hasher + Default>(
+        self,
+        old_leaves: Vec<(H256, Option<H256>)>,
+        new_leaves: Vec<(H256, Option<H256>)>,
+    ) -> Result<H256> {
+        let mut leaves: BTreeMap<H256, Option<H256>> = old_leaves.into_iter().collect();
+        let unproved: Vec<H256> = new_leaves
+            .iter()
+            .map(|(k, _)| *k)
+            .filter(|k| !leaves.contains_key(k))
+            .collect();
+        if !unproved.is_empty() {
+            return Err(Error::UnprovedKeys(unproved));
+        }
+        leaves.extend(new_leaves);
+        self.compute_root::<H>(leaves.into_iter().collect())
+    }
+
     /// Verify merkle proof
     /// see compute_root_from_proof
+    ///
+    /// `leaves` must be exactly the leaves the proof was generated for,
+    /// see `verify_subset` to check only some of them.
     #[inline(always)]
     pub fn verify<H: Hasher + Default>(
         self,
@@ -188,181 +389,693 @@ impl MerkleProof {
         let calculated_root = self.compute_root::<H>(leaves)?;
         Ok(calculated_root == root)
     }
+
+    /// Same as `verify`, but tell why the proof does not match
+    /// instead of a bare `false` or error, see `VerifyOutcome`.
+    pub fn verify_detailed<H: Hasher + Default>(
+        self,
+        root: H256,
+        leaves: Vec<(H256, Option<H256>)>,
+    ) -> VerifyOutcome {
+        let expected = self.leaves_count();
+        if expected != leaves.len() {
+            return VerifyOutcome::LeafCountMismatch {
+                expected,
+                actual: leaves.len(),
+            };
+        }
+        VerifyOutcome::of(root, self.compute_root::<H>(leaves))
+    }
+
+    /// Same as `verify`, with the size of the proof(`serialized_size`),
+    /// the number of leaves and the depth of the stack bounded by `options`;
+    /// return LimitExceeded error, before any hashing, if a bound is exceeded.
+    pub fn verify_with_options<H: Hasher + Default>(
+        self,
+        root: H256,
+        leaves: Vec<(H256, Option<H256>)>,
+        options: &VerifyOptions,
+    ) -> Result<bool> {
+        options.check(Limit::ProofBytes, self.serialized_size())?;
+        options.check(Limit::Leaves, leaves.len())?;
+        self.compile(leaves.iter().map(|(key, _value)| *key).collect())?
+            .verify_with_options::<H>(root, leaves, options)
+    }
+
+    /// Verify `leaves`, a subset of the leaves the proof was generated for.
+    ///
+    /// `keys` is the full key set of the proof, the leaves of the keys
+    /// not in `leaves` are taken from `embedded`, e.g. the values returned
+    /// along the proof by `merkle_proof_with_values`.
+    ///
+    /// Keys found in neither are reported by `Error::MissingLeaves`,
+    /// keys of `leaves` outside of `keys` by `Error::UnprovedKeys`.
+    pub fn verify_subset<H: Hasher + Default>(
+        self,
+        root: H256,
+        keys: &[H256],
+        leaves: Vec<(H256, Option<H256>)>,
+        embedded: &[(H256, Option<H256>)],
+    ) -> Result<bool> {
+        let proved: BTreeSet<H256> = keys.iter().copied().collect();
+        let unproved: Vec<H256> = leaves
+            .iter()
+            .map(|(k, _)| *k)
+            .filter(|k| !proved.contains(k))
+            .collect();
+        if !unproved.is_empty() {
+            return Err(Error::UnprovedKeys(unproved));
+        }
+
+        let mut all: BTreeMap<H256, Option<H256>> = leaves.into_iter().collect();
+        let embedded: BTreeMap<H256, Option<H256>> = embedded.iter().copied().collect();
+        let mut missing = vec![];
+        for key in proved {
+            if let btree_map::Entry::Vacant(e) = all.entry(key) {
+                match embedded.get(&key) {
+                    Some(value) => {
+                        e.insert(*value);
+                    }
+                    None => missing.push(key),
+                }
+            }
+        }
+        if !missing.is_empty() {
+            return Err(Error::MissingLeaves(missing));
+        }
+
+        self.verify::<H>(root, all.into_iter().collect())
+    }
+
+    /// Check that the proof of `keys` stays valid after writes to the `changed` keys,
+    /// without reading the tree again, and return it.
+    ///
+    /// This is a validation check, the proof itself is never adjusted:
+    /// its siblings only cover the subtrees that hold none of its keys,
+    /// so writes to proved keys leave them unchanged, and the proof is verified
+    /// against the new root with the new values. A changed key outside of `keys`
+    /// would alter a sibling and is reported by `Error::UnprovedKeys`.
+    pub fn apply_update(&self, keys: &[H256], changed: &[H256]) -> Result<MerkleProof> {
+        if keys.len() != self.leaves_count() {
+            return Err(Error::IncorrectNumberOfLeaves {
+                expected: self.leaves_count(),
+                actual: keys.len(),
+            });
+        }
+        let proved: BTreeSet<&H256> = keys.iter().collect();
+        let unproved: Vec<H256> = changed
+            .iter()
+            .filter(|k| !proved.contains(k))
+            .copied()
+            .collect();
+        if !unproved.is_empty() {
+            return Err(Error::UnprovedKeys(unproved));
+        }
+        Ok(self.clone())
+    }
+
+    /// Verify that none of `keys` is in the tree of `root`,
+    /// i.e. they all hold the zero value.
+    ///
+    /// `false` means the proof does not match `root`,
+    /// or at least one of the keys exists.
+    #[inline(always)]
+    pub fn verify_absence<H: Hasher + Default>(
+        self,
+        root: H256,
+        keys: Vec<H256>,
+    ) -> Result<bool> {
+        self.verify::<H>(root, keys.into_iter().map(|k| (k, None)).collect())
+    }
+
+    /// Verify that no key lies strictly between `start` and `end`
+    /// in the tree of `root`, see `SparseMerkleTree::prove_empty_range`.
+    pub fn verify_empty_range<H: Hasher + Default>(
+        self,
+        root: H256,
+        start: &H256,
+        end: &H256,
+    ) -> Result<bool> {
+        let subtrees = range_subtrees(start, end)?;
+        if subtrees.len() != self.leaves_bitmap.len() {
+            return Err(Error::IncorrectNumberOfLeaves {
+                expected: subtrees.len(),
+                actual: self.leaves_bitmap.len(),
+            });
+        }
+        // siblings without a bit are taken as zero rather than read from the proof,
+        // so a matching root shows every subtree below its height is empty
+        let all_zero = subtrees
+            .iter()
+            .zip(self.leaves_bitmap.iter())
+            .all(|((_, height), bitmap)| (0..*height).all(|h| !bitmap.get_bit(h as u8)));
+        if !all_zero {
+            return Ok(false);
+        }
+        self.verify_absence::<H>(root, subtrees.into_iter().map(|(k, _)| k).collect())
+    }
+
+    /// Verify many `(proof, root, leaves)` sets in one pass,
+    /// the compile and stack buffers are reused between them.
+    ///
+    /// One result per set, in the input order.
+    pub fn verify_batch<'a, H: Hasher + Default>(
+        items: impl IntoIterator<Item = (&'a MerkleProof, H256, Vec<(H256, Option<H256>)>)>,
+    ) -> Vec<Result<bool>> {
+        let mut program = vec![];
+        let mut keys = vec![];
+        let mut stack = vec![];
+        items
+            .into_iter()
+            .map(|(proof, root, mut leaves)| {
+                program.clear();
+                keys.clear();
+                keys.extend(leaves.iter().map(|(key, _value)| *key));
+                proof.compile_into(&mut keys, &mut program)?;
+                compute_root_with::<H>(&program, &mut leaves, &mut stack, MAX_STACK_SIZE)
+                    .map(|calculated_root| calculated_root == root)
+            })
+            .collect()
+    }
+}
+
+/// Bounds on the work verifying a proof from an untrusted peer may take,
+/// see `MerkleProof::verify_with_options` and `CompiledMerkleProof::verify_with_options`.
+///
+/// The default bounds nothing but the stack, which is never deeper than
+/// `MAX_STACK_SIZE`(257) whatever `max_stack` is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VerifyOptions {
+    /// size of the proof in bytes
+    pub max_proof_bytes: usize,
+    /// number of proved leaves
+    pub max_leaves: usize,
+    /// depth of the verification stack
+    pub max_stack: usize,
+}
+
+impl Default for VerifyOptions {
+    fn default() -> Self {
+        VerifyOptions {
+            max_proof_bytes: usize::MAX,
+            max_leaves: usize::MAX,
+            max_stack: MAX_STACK_SIZE,
+        }
+    }
+}
+
+impl VerifyOptions {
+    #[inline(always)]
+    fn check(&self, limit: Limit, actual: usize) -> Result<()> {
+        let max = match limit {
+            Limit::ProofBytes => self.max_proof_bytes,
+            Limit::Leaves => self.max_leaves,
+            Limit::Stack => self.max_stack,
+        };
+        if actual > max {
+            return Err(Error::LimitExceeded { limit, max, actual });
+        }
+        Ok(())
+    }
+}
+
+/// Why a proof does or does not match a root,
+/// see `MerkleProof::verify_detailed` and `CompiledMerkleProof::verify_detailed`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VerifyOutcome {
+    /// the proof of the leaves matches the root
+    Valid,
+    /// the proof is well-formed for the leaves, but leads to another root
+    RootMismatch { expected: H256, computed: H256 },
+    /// fewer leaves(some are missing) or more leaves
+    /// than the proof was generated for
+    LeafCountMismatch { expected: usize, actual: usize },
+    /// the keys of the leaves do not fit the bitmap or the siblings of the proof,
+    /// e.g. a key the proof was not generated for
+    KeyMismatch,
+    /// the proof itself can not be read, e.g. an unknown opcode
+    Invalid(Error),
+}
+
+impl VerifyOutcome {
+    #[inline(always)]
+    pub fn is_valid(&self) -> bool {
+        matches!(self, VerifyOutcome::Valid)
+    }
+
+    fn of(expected: H256, computed: Result<H256>) -> Self {
+        match computed {
+            Ok(computed) if computed == expected => VerifyOutcome::Valid,
+            Ok(computed) => VerifyOutcome::RootMismatch { expected, computed },
+            Err(Error::IncorrectNumberOfLeaves { expected, actual }) => {
+                VerifyOutcome::LeafCountMismatch { expected, actual }
+            }
+            Err(
+                Error::CorruptedProof
+                | Error::CorruptedStack
+                | Error::KeyMismatch { .. }
+                | Error::UnusedSiblings(_)
+                | Error::DuplicatedKeys(_),
+            ) => VerifyOutcome::KeyMismatch,
+            Err(e) => VerifyOutcome::Invalid(e),
+        }
+    }
+}
+
+/// A two-layer proof of `SparseMerkleTree2`: leaves under an xid
+/// against the global root, via the root of that xid.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "borsh",
+    derive(borsh::BorshSerialize, borsh::BorshDeserialize)
+)]
+pub struct XMerkleProof {
+    // merkle root of the subtree under the xid
+    root: H256,
+    // proof of the subtree root in the global tree
+    xroot_proof: MerkleProof,
+    // proof of the leaves in the subtree
+    proof: MerkleProof,
+}
+
+impl XMerkleProof {
+    /// Create XMerkleProof
+    #[inline(always)]
+    pub fn new(root: H256, xroot_proof: MerkleProof, proof: MerkleProof) -> Self {
+        XMerkleProof {
+            root,
+            xroot_proof,
+            proof,
+        }
+    }
+
+    /// Destruct the structure, useful for serialization
+    #[inline(always)]
+    pub fn take(self) -> (H256, MerkleProof, MerkleProof) {
+        (self.root, self.xroot_proof, self.proof)
+    }
+
+    /// return the merkle root of the subtree
+    #[inline(always)]
+    pub fn root(&self) -> H256 {
+        self.root
+    }
+
+    /// Verify the leaves under `xid` against the global root `xroot`,
+    /// an empty subtree is proved as absent from the global tree.
+    pub fn verify<H: Hasher + Default, X: KeyEnDe>(
+        self,
+        xroot: H256,
+        xid: &X,
+        leaves: Vec<(H256, Option<H256>)>,
+    ) -> Result<bool> {
+        let xid_key = H::hash(&xid.encode()[..]);
+        let root = Some(self.root).filter(|r| !r.is_zero());
+        if !self.xroot_proof.verify::<H>(xroot, vec![(xid_key, root)])? {
+            return Ok(false);
+        }
+        self.proof.verify::<H>(self.root, leaves)
+    }
+}
+
+/// Leaves to check against an `XMultiMerkleProof`, grouped by xid
+pub type XLeaves<X> = Vec<(X, Vec<(H256, Option<H256>)>)>;
+
+/// A two-layer proof of `SparseMerkleTree2` covering leaves under several xids:
+/// one proof of all their roots against the global root, and one proof per xid.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "borsh",
+    derive(borsh::BorshSerialize, borsh::BorshDeserialize)
+)]
+pub struct XMultiMerkleProof {
+    // proof of the subtree roots in the global tree
+    xroot_proof: MerkleProof,
+    // merkle root of the subtree and proof of the leaves in it, per xid
+    proofs: Vec<(H256, MerkleProof)>,
+}
+
+impl XMultiMerkleProof {
+    /// Create XMultiMerkleProof
+    #[inline(always)]
+    pub fn new(xroot_proof: MerkleProof, proofs: Vec<(H256, MerkleProof)>) -> Self {
+        XMultiMerkleProof {
+            xroot_proof,
+            proofs,
+        }
+    }
+
+    /// Destruct the structure, useful for serialization
+    #[inline(always)]
+    pub fn take(self) -> (MerkleProof, Vec<(H256, MerkleProof)>) {
+        (self.xroot_proof, self.proofs)
+    }
+
+    /// return the merkle roots of the subtrees, in the order of the xids
+    #[inline(always)]
+    pub fn roots(&self) -> Vec<H256> {
+        self.proofs.iter().map(|(root, _)| *root).collect()
+    }
+
+    /// Verify the leaves under every xid against the global root `xroot`,
+    /// `leaves` must list the xids in the order the proof was generated for;
+    /// return IncorrectNumberOfLeaves error if the number of xids differs.
+    pub fn verify<H: Hasher + Default, X: KeyEnDe>(
+        self,
+        xroot: H256,
+        leaves: XLeaves<X>,
+    ) -> Result<bool> {
+        if leaves.len() != self.proofs.len() {
+            return Err(Error::IncorrectNumberOfLeaves {
+                expected: self.proofs.len(),
+                actual: leaves.len(),
+            });
+        }
+        let mut xleaves: Vec<(H256, Option<H256>)> = leaves
+            .iter()
+            .zip(self.proofs.iter())
+            .map(|((xid, _), (root, _))| {
+                (H::hash(&xid.encode()[..]), Some(*root).filter(|r| !r.is_zero()))
+            })
+            .collect();
+        xleaves.sort_unstable_by_key(|(k, _)| *k);
+        if !self.xroot_proof.verify::<H>(xroot, xleaves)? {
+            return Ok(false);
+        }
+        for ((_, leaves), (root, proof)) in leaves.into_iter().zip(self.proofs) {
+            if !proof.verify::<H>(root, leaves)? {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+}
+
+/// A proof of the node of a whole subtree: the `2^height` keys
+/// sharing all bits of `key` from `height` up.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "borsh",
+    derive(borsh::BorshSerialize, borsh::BorshDeserialize)
+)]
+pub struct SubtreeProof {
+    // smallest key of the subtree
+    key: H256,
+    // 0(a leaf)..=256(the whole tree)
+    height: u16,
+    // the node of the subtree
+    node: MergeValue,
+    // bitmap.get_bit(h) is true means there is a non zero sibling in this height
+    bitmap: H256,
+    // non zero siblings from `height` up
+    merkle_path: Vec<MergeValue>,
+}
+
+impl SubtreeProof {
+    /// Create SubtreeProof
+    #[inline(always)]
+    pub fn new(
+        key: H256,
+        height: u16,
+        node: MergeValue,
+        bitmap: H256,
+        merkle_path: Vec<MergeValue>,
+    ) -> Self {
+        SubtreeProof {
+            key,
+            height,
+            node,
+            bitmap,
+            merkle_path,
+        }
+    }
+
+    /// Smallest key of the subtree
+    #[inline(always)]
+    pub fn key(&self) -> H256 {
+        self.key
+    }
+
+    /// Height of the subtree, `2^height` keys
+    #[inline(always)]
+    pub fn height(&self) -> u16 {
+        self.height
+    }
+
+    /// The node of the subtree, zero if it holds no leaf
+    #[inline(always)]
+    pub fn node(&self) -> &MergeValue {
+        &self.node
+    }
+
+    /// Whether `key` lies in the subtree
+    #[inline(always)]
+    pub fn contains(&self, key: &H256) -> bool {
+        self.height == 256 || key.copy_bits(self.height as u8) == self.key
+    }
+
+    /// Merkle root of the node merged with the siblings of the proof
+    pub fn compute_root<H: Hasher + Default>(&self) -> Result<H256> {
+        if self.height > 256 || !self.contains(&self.key) {
+            return Err(Error::CorruptedProof);
+        }
+        if (0..self.height).any(|h| self.bitmap.get_bit(h as u8)) {
+            return Err(Error::CorruptedProof);
+        }
+        let mut node = self.node;
+        let mut path = self.merkle_path.iter();
+        for height in self.height..256 {
+            let height = height as u8;
+            let sibling = if self.bitmap.get_bit(height) {
+                *path.next().ok_or(Error::CorruptedProof)?
+            } else {
+                MergeValue::zero()
+            };
+            let parent_key = self.key.parent_path(height);
+            node = if self.key.is_right(height) {
+                merge::<H>(height, &parent_key, &sibling, &node)
+            } else {
+                merge::<H>(height, &parent_key, &node, &sibling)
+            };
+        }
+        if path.next().is_some() {
+            return Err(Error::CorruptedProof);
+        }
+        Ok(node.hash::<H>())
+    }
+
+    /// Verify the node of the subtree against `root`
+    #[inline(always)]
+    pub fn verify<H: Hasher + Default>(&self, root: H256) -> Result<bool> {
+        Ok(self.compute_root::<H>()? == root)
+    }
+
+    /// Verify that `leaves`(key, value hash) are all the leaves of the subtree,
+    /// by recomputing its node from them, and that the node matches `root`.
+    ///
+    /// Keys out of the subtree are reported by `Error::UnprovedKeys`;
+    /// a later leaf replaces an earlier one with the same key,
+    /// a zero value leaves the key out.
+    pub fn verify_leaves<H: Hasher + Default>(
+        &self,
+        root: H256,
+        leaves: Vec<(H256, H256)>,
+    ) -> Result<bool> {
+        let outside: Vec<H256> = leaves
+            .iter()
+            .map(|(k, _)| *k)
+            .filter(|k| !self.contains(k))
+            .collect();
+        if !outside.is_empty() {
+            return Err(Error::UnprovedKeys(outside));
+        }
+        let leaves: BTreeMap<H256, H256> = leaves.into_iter().collect();
+        let nodes = leaves
+            .into_iter()
+            .filter(|(_, v)| !v.is_zero())
+            .map(|(k, v)| (k, MergeValue::from_h256(v)))
+            .collect();
+        let node = merge_levels::<H>(self.height as usize, nodes, |_, _| Ok(()))?;
+        Ok(node == self.node && self.verify::<H>(root)?)
+    }
 }
 
 /// An structure optimized for verify merkle proof
+///
+/// Serialized as the `0x`-prefixed hex of the program for human-readable
+/// serde formats(e.g. JSON), as a byte string otherwise;
+/// the program is checked by `from_ckb_bytes` when deserialized.
 #[derive(Debug, Clone)]
+#[cfg_attr(
+    feature = "borsh",
+    derive(borsh::BorshSerialize, borsh::BorshDeserialize)
+)]
 pub struct CompiledMerkleProof(pub Vec<u8>);
 
 impl CompiledMerkleProof {
-    pub fn compute_root<H: Hasher + Default>(
-        &self,
-        mut leaves: Vec<(H256, Option<H256>)>,
-    ) -> Result<H256> {
-        leaves.sort_unstable_by_key(|(k, _v)| *k);
-        let mut program_index = 0;
-        let mut leaf_index = 0;
-        let mut stack: Vec<(u16, H256, MergeValue)> = Vec::new();
-        while program_index < self.0.len() {
-            let code = self.0[program_index];
-            program_index += 1;
-            match code {
-                // L : push leaf value
-                0x4C => {
-                    if leaf_index >= leaves.len() {
-                        return Err(Error::CorruptedStack);
-                    }
-                    let (k, v) = leaves[leaf_index];
-                    stack.push((
-                        0,
-                        k,
-                        v.map(MergeValue::from_h256)
-                            .unwrap_or_else(MergeValue::zero),
-                    ));
-                    leaf_index += 1;
-                }
-                // P : hash stack top item with sibling node in proof
-                0x50 => {
-                    if stack.is_empty() {
-                        return Err(Error::CorruptedStack);
-                    }
-                    if program_index + 32 > self.0.len() {
+    /// Serialize into the compiled proof format of the upstream
+    /// nervosnetwork sparse-merkle-tree(v0.5+), as consumed by CKB contracts.
+    ///
+    /// Both sides share the same opcodes(L/P/Q/H/O) and operand layout,
+    /// so the bytes are passed through unchanged.
+    #[inline(always)]
+    pub fn to_ckb_bytes(&self) -> Vec<u8> {
+        self.0.clone()
+    }
+
+    /// Size of the compiled program in bytes
+    #[inline(always)]
+    pub fn serialized_size(&self) -> usize {
+        self.0.len()
+    }
+
+    /// Parse a proof in the upstream nervosnetwork compiled format,
+    /// the program is checked to be well-formed: known opcodes,
+    /// complete operands, and a stack that ends at the root.
+    pub fn from_ckb_bytes(bytes: &[u8]) -> Result<Self> {
+        if bytes.is_empty() {
+            return Err(Error::EmptyProof);
+        }
+
+        // heights of the stack items
+        let mut stack: Vec<u16> = Vec::new();
+        let mut reader = ByteReader::new(bytes);
+        while !reader.is_empty() {
+            match reader.read_u8()? {
+                0x4C => stack.push(0),
+                code @ (0x50 | 0x51) => {
+                    reader.read_slice(if code == 0x50 { 32 } else { 65 })?;
+                    let height = stack.pop().ok_or(Error::CorruptedStack)?;
+                    if height > 255 {
                         return Err(Error::CorruptedProof);
                     }
-                    let mut data = [0u8; 32];
-                    data.copy_from_slice(&self.0[program_index..program_index + 32]);
-                    program_index += 32;
-                    let sibling_node = MergeValue::from_h256(H256::from(data));
-                    let (height_u16, key, value) = stack.pop().unwrap();
-                    if height_u16 > 255 {
+                    stack.push(height + 1);
+                }
+                0x48 => {
+                    let height_b = stack.pop().ok_or(Error::CorruptedStack)?;
+                    let height_a = stack.pop().ok_or(Error::CorruptedStack)?;
+                    if height_a != height_b || height_a > 255 {
                         return Err(Error::CorruptedProof);
                     }
-                    let height = height_u16 as u8;
-                    let parent_key = key.parent_path(height);
-                    let parent = if key.get_bit(height) {
-                        merge::<H>(height, &parent_key, &sibling_node, &value)
-                    } else {
-                        merge::<H>(height, &parent_key, &value, &sibling_node)
-                    };
-                    stack.push((height_u16 + 1, parent_key, parent));
+                    stack.push(height_a + 1);
                 }
-                // Q : hash stack top item with sibling node in proof,
-                // this is similar to P except that proof comes in using
-                // MergeWithZero format.
-                0x51 => {
-                    if stack.is_empty() {
-                        return Err(Error::CorruptedStack);
-                    }
-                    if program_index + 65 > self.0.len() {
+                0x4F => {
+                    let n = reader.read_u8()?;
+                    let zero_count: u16 = if n == 0 { 256 } else { n as u16 };
+                    let height = stack.pop().ok_or(Error::CorruptedStack)?;
+                    if height + zero_count > 256 {
                         return Err(Error::CorruptedProof);
                     }
-                    let zero_count = self.0[program_index];
-                    let base_node = {
-                        let mut data = [0u8; 32];
-                        data.copy_from_slice(
-                            &self.0[program_index + 1..program_index + 33],
-                        );
-                        H256::from(data)
-                    };
-                    let zero_bits = {
-                        let mut data = [0u8; 32];
-                        data.copy_from_slice(
-                            &self.0[program_index + 33..program_index + 65],
-                        );
-                        H256::from(data)
-                    };
-                    program_index += 65;
-                    let sibling_node = MergeValue::MergeWithZero {
-                        base_node,
-                        zero_bits,
-                        zero_count,
+                    stack.push(height + zero_count);
+                }
+                code => return Err(Error::InvalidCode(code)),
+            }
+            if stack.len() > MAX_STACK_SIZE {
+                return Err(Error::CorruptedStack);
+            }
+        }
+        if stack != [256] {
+            return Err(Error::CorruptedProof);
+        }
+
+        Ok(CompiledMerkleProof(bytes.to_vec()))
+    }
+
+    /// `0x`-prefixed hex of the program
+    #[inline(always)]
+    pub fn to_hex(&self) -> String {
+        to_hex(&self.0)
+    }
+
+    /// Parse the hex produced by `to_hex`, the prefix is optional,
+    /// the program is checked as by `from_ckb_bytes`
+    #[inline(always)]
+    pub fn from_hex(s: &str) -> Result<Self> {
+        Self::from_ckb_bytes(&from_hex(s)?)
+    }
+
+    /// Recover the structured proof, which `compile`s back to the same program
+    /// with the same keys, and verifies the same leaves against the same root.
+    ///
+    /// Only the bitmap bits the program uses can be recovered: a bitmap has no bits
+    /// at or above the height where its leaf merges into another proved subtree,
+    /// so it may differ from the proof it was compiled from.
+    pub fn decompile(&self) -> Result<MerkleProof> {
+        if self.0.is_empty() {
+            return Err(Error::EmptyProof);
+        }
+
+        let mut leaves_bitmap: Vec<H256> = vec![];
+        let mut merkle_path = vec![];
+        // heights of the stack items, siblings go to the last leaf
+        let mut stack: Vec<u16> = vec![];
+        let mut reader = ByteReader::new(&self.0);
+        while !reader.is_empty() {
+            match reader.read_u8()? {
+                0x4C => {
+                    stack.push(0);
+                    leaves_bitmap.push(H256::zero());
+                }
+                code @ (0x50 | 0x51) => {
+                    let node = if code == 0x50 {
+                        MergeValue::Value(reader.read_h256()?)
+                    } else {
+                        let zero_count = reader.read_u8()?;
+                        let base_node = reader.read_h256()?;
+                        let zero_bits = reader.read_h256()?;
+                        MergeValue::MergeWithZero {
+                            base_node,
+                            zero_bits,
+                            zero_count,
+                        }
                     };
-                    let (height_u16, key, value) = stack.pop().unwrap();
-                    if height_u16 > 255 {
+                    let height = stack.last_mut().ok_or(Error::CorruptedStack)?;
+                    if *height > 255 {
                         return Err(Error::CorruptedProof);
                     }
-                    let height = height_u16 as u8;
-                    let parent_key = key.parent_path(height);
-                    let parent = if key.get_bit(height) {
-                        merge::<H>(height, &parent_key, &sibling_node, &value)
-                    } else {
-                        merge::<H>(height, &parent_key, &value, &sibling_node)
-                    };
-                    stack.push((height_u16 + 1, parent_key, parent));
+                    leaves_bitmap
+                        .last_mut()
+                        .ok_or(Error::CorruptedStack)?
+                        .set_bit(*height as u8);
+                    *height += 1;
+                    merkle_path.push(node);
                 }
-                // H : pop 2 items in stack hash them then push the result
                 0x48 => {
-                    if stack.len() < 2 {
-                        return Err(Error::CorruptedStack);
-                    }
-                    let (height_b, key_b, value_b) = stack.pop().unwrap();
-                    let (height_a, key_a, value_a) = stack.pop().unwrap();
-                    if height_a != height_b {
-                        return Err(Error::CorruptedProof);
-                    }
-                    if height_a > 255 {
-                        return Err(Error::CorruptedProof);
-                    }
-                    let height_u16 = height_a;
-                    let height = height_u16 as u8;
-                    let parent_key_a = key_a.parent_path(height);
-                    let parent_key_b = key_b.parent_path(height);
-                    if parent_key_a != parent_key_b {
+                    let height_b = stack.pop().ok_or(Error::CorruptedStack)?;
+                    let height_a = stack.pop().ok_or(Error::CorruptedStack)?;
+                    if height_a != height_b || height_a > 255 {
                         return Err(Error::CorruptedProof);
                     }
-                    let parent = if key_a.get_bit(height) {
-                        merge::<H>(height, &parent_key_a, &value_b, &value_a)
-                    } else {
-                        merge::<H>(height, &parent_key_a, &value_a, &value_b)
-                    };
-                    stack.push((height_u16 + 1, parent_key_a, parent));
+                    stack.push(height_a + 1);
                 }
-                // O : hash stack top item with n zero values
                 0x4F => {
-                    if stack.is_empty() {
-                        return Err(Error::CorruptedStack);
-                    }
-                    if program_index >= self.0.len() {
-                        return Err(Error::CorruptedProof);
-                    }
-                    let n = self.0[program_index];
-                    program_index += 1;
+                    let n = reader.read_u8()?;
                     let zero_count: u16 = if n == 0 { 256 } else { n as u16 };
-                    let (base_height, key, mut value) = stack.pop().unwrap();
-                    if base_height > 255 {
+                    let height = stack.last_mut().ok_or(Error::CorruptedStack)?;
+                    if *height + zero_count > 256 {
                         return Err(Error::CorruptedProof);
                     }
-                    let mut parent_key = key;
-                    let mut height_u16 = base_height;
-                    for idx in 0..zero_count {
-                        if base_height + idx > 255 {
-                            return Err(Error::CorruptedProof);
-                        }
-                        height_u16 = base_height + idx;
-                        let height = height_u16 as u8;
-                        parent_key = key.parent_path(height);
-                        value = if key.get_bit(height) {
-                            merge::<H>(height, &parent_key, &MergeValue::zero(), &value)
-                        } else {
-                            merge::<H>(height, &parent_key, &value, &MergeValue::zero())
-                        };
-                    }
-                    stack.push((height_u16 + 1, parent_key, value));
+                    *height += zero_count;
                 }
-                _ => return Err(Error::InvalidCode(code)),
+                code => return Err(Error::InvalidCode(code)),
+            }
+            if stack.len() > MAX_STACK_SIZE {
+                return Err(Error::CorruptedStack);
             }
-            debug_assert!(stack.len() <= MAX_STACK_SIZE);
-        }
-        if stack.len() != 1 {
-            return Err(Error::CorruptedStack);
-        }
-        if stack[0].0 != 256 {
-            return Err(Error::CorruptedProof);
         }
-        if leaf_index != leaves.len() {
+        if stack != [256] {
             return Err(Error::CorruptedProof);
         }
-        Ok(stack[0].2.hash::<H>())
+
+        Ok(MerkleProof::new(leaves_bitmap, merkle_path))
+    }
+
+    #[inline(always)]
+    pub fn compute_root<H: Hasher + Default>(
+        &self,
+        mut leaves: Vec<(H256, Option<H256>)>,
+    ) -> Result<H256> {
+        compute_root_with::<H>(&self.0, &mut leaves, &mut Vec::new(), MAX_STACK_SIZE)
     }
 
     #[inline(always)]
@@ -374,6 +1087,60 @@ impl CompiledMerkleProof {
         let calculated_root = self.compute_root::<H>(leaves)?;
         Ok(calculated_root == root)
     }
+
+    /// Same as `verify`, but tell why the proof does not match
+    /// instead of a bare `false` or error, see `VerifyOutcome`.
+    pub fn verify_detailed<H: Hasher + Default>(
+        &self,
+        root: H256,
+        leaves: Vec<(H256, Option<H256>)>,
+    ) -> VerifyOutcome {
+        // the number of leaves the program takes is the one of its source proof
+        match self.decompile().map(|proof| proof.leaves_count()) {
+            Ok(expected) if expected != leaves.len() => {
+                VerifyOutcome::LeafCountMismatch {
+                    expected,
+                    actual: leaves.len(),
+                }
+            }
+            Ok(_) => VerifyOutcome::of(root, self.compute_root::<H>(leaves)),
+            Err(e) => VerifyOutcome::Invalid(e),
+        }
+    }
+
+    /// Same as `verify`, with the size of the program, the number of leaves
+    /// and the depth of the stack bounded by `options`;
+    /// return LimitExceeded error if a bound is exceeded.
+    pub fn verify_with_options<H: Hasher + Default>(
+        &self,
+        root: H256,
+        mut leaves: Vec<(H256, Option<H256>)>,
+        options: &VerifyOptions,
+    ) -> Result<bool> {
+        options.check(Limit::ProofBytes, self.0.len())?;
+        options.check(Limit::Leaves, leaves.len())?;
+        compute_root_with::<H>(&self.0, &mut leaves, &mut Vec::new(), options.max_stack)
+            .map(|calculated_root| calculated_root == root)
+    }
+
+    /// Verify many `(proof, root, leaves)` sets in one pass,
+    /// the stack buffer is reused between them.
+    ///
+    /// One result per set, in the input order.
+    pub fn verify_batch<'a, H: Hasher + Default>(
+        items: impl IntoIterator<
+            Item = (&'a CompiledMerkleProof, H256, Vec<(H256, Option<H256>)>),
+        >,
+    ) -> Vec<Result<bool>> {
+        let mut stack = vec![];
+        items
+            .into_iter()
+            .map(|(proof, root, mut leaves)| {
+                compute_root_with::<H>(&proof.0, &mut leaves, &mut stack, MAX_STACK_SIZE)
+                    .map(|calculated_root| calculated_root == root)
+            })
+            .collect()
+    }
 }
 
 impl From<CompiledMerkleProof> for Vec<u8> {
@@ -382,3 +1149,404 @@ impl From<CompiledMerkleProof> for Vec<u8> {
         proof.0
     }
 }
+
+/// A compiled proof with the leaves it proves, sorted by key,
+/// see `MerkleProof::compile_with_values`.
+#[derive(Debug, Clone)]
+pub struct BoundMerkleProof {
+    proof: CompiledMerkleProof,
+    leaves: Vec<(H256, Option<H256>)>,
+}
+
+impl BoundMerkleProof {
+    /// The compiled program
+    #[inline(always)]
+    pub fn proof(&self) -> &CompiledMerkleProof {
+        &self.proof
+    }
+
+    /// The bound leaves, sorted by key
+    #[inline(always)]
+    pub fn leaves(&self) -> &[(H256, Option<H256>)] {
+        &self.leaves
+    }
+
+    /// Destruct the structure
+    #[inline(always)]
+    pub fn take(self) -> (CompiledMerkleProof, Vec<(H256, Option<H256>)>) {
+        (self.proof, self.leaves)
+    }
+
+    /// Compute root from the bound leaves
+    #[inline(always)]
+    pub fn compute_root<H: Hasher + Default>(&self) -> Result<H256> {
+        self.proof.compute_root::<H>(self.leaves.clone())
+    }
+
+    /// Verify the bound leaves against `root`
+    #[inline(always)]
+    pub fn verify<H: Hasher + Default>(&self, root: H256) -> Result<bool> {
+        self.proof.verify::<H>(root, self.leaves.clone())
+    }
+}
+
+// The human-readable form of `MerkleProof`
+#[derive(Serialize, Deserialize)]
+struct ProofJson {
+    leaves_bitmap: Vec<H256>,
+    merkle_path: Vec<NodeJson>,
+}
+
+// A path node of `ProofJson`: a hash, or `[base_node, zero_bits, zero_count]`
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum NodeJson {
+    Value(H256),
+    MergeWithZero(H256, H256, u8),
+}
+
+impl Serialize for MerkleProof {
+    fn serialize<S: Serializer>(&self, serializer: S) -> StdResult<S::Ok, S::Error> {
+        if !serializer.is_human_readable() {
+            return serializer.serialize_bytes(&self.to_bytes());
+        }
+        let merkle_path = self
+            .merkle_path
+            .iter()
+            .map(|node| match node {
+                MergeValue::Value(v) => NodeJson::Value(*v),
+                MergeValue::MergeWithZero {
+                    base_node,
+                    zero_bits,
+                    zero_count,
+                } => NodeJson::MergeWithZero(*base_node, *zero_bits, *zero_count),
+            })
+            .collect();
+        ProofJson {
+            leaves_bitmap: self.leaves_bitmap.clone(),
+            merkle_path,
+        }
+        .serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for MerkleProof {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> StdResult<Self, D::Error> {
+        if !deserializer.is_human_readable() {
+            let bytes = deserializer.deserialize_byte_buf(BytesVisitor)?;
+            return MerkleProof::from_bytes(&bytes).map_err(de::Error::custom);
+        }
+        let ProofJson {
+            leaves_bitmap,
+            merkle_path,
+        } = ProofJson::deserialize(deserializer)?;
+        let merkle_path = merkle_path
+            .into_iter()
+            .map(|node| match node {
+                NodeJson::Value(v) => MergeValue::Value(v),
+                NodeJson::MergeWithZero(base_node, zero_bits, zero_count) => {
+                    MergeValue::MergeWithZero {
+                        base_node,
+                        zero_bits,
+                        zero_count,
+                    }
+                }
+            })
+            .collect();
+        Ok(MerkleProof::new(leaves_bitmap, merkle_path))
+    }
+}
+
+impl Serialize for CompiledMerkleProof {
+    fn serialize<S: Serializer>(&self, serializer: S) -> StdResult<S::Ok, S::Error> {
+        if serializer.is_human_readable() {
+            serializer.serialize_str(&self.to_hex())
+        } else {
+            serializer.serialize_bytes(&self.0)
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for CompiledMerkleProof {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> StdResult<Self, D::Error> {
+        if deserializer.is_human_readable() {
+            let s = String::deserialize(deserializer)?;
+            CompiledMerkleProof::from_hex(&s).map_err(de::Error::custom)
+        } else {
+            let bytes = deserializer.deserialize_byte_buf(BytesVisitor)?;
+            CompiledMerkleProof::from_ckb_bytes(&bytes).map_err(de::Error::custom)
+        }
+    }
+}
+
+// A byte string, also accepted as a sequence of bytes
+pub(crate) struct BytesVisitor;
+
+impl<'de> de::Visitor<'de> for BytesVisitor {
+    type Value = Vec<u8>;
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("a byte string")
+    }
+
+    fn visit_bytes<E: de::Error>(self, v: &[u8]) -> StdResult<Vec<u8>, E> {
+        Ok(v.to_vec())
+    }
+
+    fn visit_byte_buf<E: de::Error>(self, v: Vec<u8>) -> StdResult<Vec<u8>, E> {
+        Ok(v)
+    }
+
+    fn visit_seq<A: de::SeqAccess<'de>>(self, mut seq: A) -> StdResult<Vec<u8>, A::Error> {
+        let mut bytes = Vec::with_capacity(seq.size_hint().unwrap_or(0).min(4096));
+        while let Some(b) = seq.next_element()? {
+            bytes.push(b);
+        }
+        Ok(bytes)
+    }
+}
+
+/// Split the keys strictly between `start` and `end` into aligned subtrees,
+/// as `(smallest key, height)` in ascending order;
+/// a subtree of height `h` holds the `2^h` keys that share all bits from `h` up.
+///
+/// Return EmptyRange error if there is no such key.
+pub(crate) fn range_subtrees(start: &H256, end: &H256) -> Result<Vec<(H256, u16)>> {
+    let (first, last) = match (next_key(start), prev_key(end)) {
+        (Some(first), Some(last)) if first <= last => (first, last),
+        _ => return Err(Error::EmptyRange),
+    };
+
+    let mut subtrees = vec![];
+    let mut current = first;
+    loop {
+        // grow the subtree while it stays aligned and inside the range
+        let mut height: u16 = 0;
+        let mut upper = current;
+        while height < 256 && !current.get_bit(height as u8) {
+            let mut next = upper;
+            next.set_bit(height as u8);
+            if next > last {
+                break;
+            }
+            upper = next;
+            height += 1;
+        }
+        subtrees.push((current, height));
+        match next_key(&upper) {
+            Some(k) if k <= last => current = k,
+            _ => return Ok(subtrees),
+        }
+    }
+}
+
+#[inline(always)]
+pub(crate) fn next_key(key: &H256) -> Option<H256> {
+    let mut key = *key;
+    for i in 0..=u8::MAX {
+        if key.get_bit(i) {
+            key.clear_bit(i);
+        } else {
+            key.set_bit(i);
+            return Some(key);
+        }
+    }
+    None
+}
+
+#[inline(always)]
+fn prev_key(key: &H256) -> Option<H256> {
+    let mut key = *key;
+    for i in 0..=u8::MAX {
+        if key.get_bit(i) {
+            key.clear_bit(i);
+            return Some(key);
+        } else {
+            key.set_bit(i);
+        }
+    }
+    None
+}
+
+// Run a compiled proof program, `stack` is a reusable working buffer.
+//
+// The program comes from untrusted peers: every operand read is bounds-checked,
+// the stack never grows beyond `max_stack`(capped at `MAX_STACK_SIZE`)
+// and no height goes past the root, malformed programs are reported
+// as errors rather than panics.
+fn compute_root_with<H: Hasher + Default>(
+    program: &[u8],
+    leaves: &mut [(H256, Option<H256>)],
+    stack: &mut Vec<(u16, H256, MergeValue)>,
+    max_stack: usize,
+) -> Result<H256> {
+    leaves.sort_unstable_by_key(|(k, _v)| *k);
+    stack.clear();
+    let mut reader = ByteReader::new(program);
+    let mut leaf_index = 0;
+    while !reader.is_empty() {
+        let offset = reader.pos;
+        let code = reader.read_u8()?;
+        let node = match code {
+            // L : push leaf value
+            0x4C => {
+                let (k, v) = *leaves.get(leaf_index).ok_or(Error::CorruptedStack)?;
+                leaf_index += 1;
+                (
+                    0,
+                    k,
+                    v.map(MergeValue::from_h256)
+                        .unwrap_or_else(MergeValue::zero),
+                )
+            }
+            // P : hash stack top item with sibling node in proof
+            // Q : hash stack top item with sibling node in proof,
+            // this is similar to P except that proof comes in using
+            // MergeWithZero format.
+            0x50 | 0x51 => {
+                let sibling_node = if code == 0x50 {
+                    MergeValue::from_h256(read_operand(&mut reader, ByteReader::read_h256)?)
+                } else {
+                    let zero_count = read_operand(&mut reader, ByteReader::read_u8)?;
+                    let base_node = read_operand(&mut reader, ByteReader::read_h256)?;
+                    let zero_bits = read_operand(&mut reader, ByteReader::read_h256)?;
+                    MergeValue::MergeWithZero {
+                        base_node,
+                        zero_bits,
+                        zero_count,
+                    }
+                };
+                let (height_u16, key, value) = stack.pop().ok_or(Error::CorruptedStack)?;
+                if height_u16 > 255 {
+                    return Err(Error::CorruptedProof);
+                }
+                let height = height_u16 as u8;
+                let parent_key = key.parent_path(height);
+                let parent = if key.get_bit(height) {
+                    merge::<H>(height, &parent_key, &sibling_node, &value)
+                } else {
+                    merge::<H>(height, &parent_key, &value, &sibling_node)
+                };
+                (height_u16 + 1, parent_key, parent)
+            }
+            // H : pop 2 items in stack hash them then push the result
+            0x48 => {
+                let (height_b, key_b, value_b) = stack.pop().ok_or(Error::CorruptedStack)?;
+                let (height_a, key_a, value_a) = stack.pop().ok_or(Error::CorruptedStack)?;
+                if height_a != height_b {
+                    return Err(Error::CorruptedProof);
+                }
+                if height_a > 255 {
+                    return Err(Error::CorruptedProof);
+                }
+                let height_u16 = height_a;
+                let height = height_u16 as u8;
+                let parent_key_a = key_a.parent_path(height);
+                let parent_key_b = key_b.parent_path(height);
+                if parent_key_a != parent_key_b {
+                    return Err(Error::CorruptedProof);
+                }
+                let parent = if key_a.get_bit(height) {
+                    merge::<H>(height, &parent_key_a, &value_b, &value_a)
+                } else {
+                    merge::<H>(height, &parent_key_a, &value_a, &value_b)
+                };
+                (height_u16 + 1, parent_key_a, parent)
+            }
+            // O : hash stack top item with n zero values
+            0x4F => {
+                let n = read_operand(&mut reader, ByteReader::read_u8)?;
+                let zero_count: u16 = if n == 0 { 256 } else { n as u16 };
+                let (base_height, key, mut value) =
+                    stack.pop().ok_or(Error::CorruptedStack)?;
+                if base_height + zero_count > 256 {
+                    return Err(Error::CorruptedProof);
+                }
+                let mut parent_key = key;
+                for height in base_height..base_height + zero_count {
+                    let height = height as u8;
+                    parent_key = key.parent_path(height);
+                    value = if key.get_bit(height) {
+                        merge::<H>(height, &parent_key, &MergeValue::zero(), &value)
+                    } else {
+                        merge::<H>(height, &parent_key, &value, &MergeValue::zero())
+                    };
+                }
+                (base_height + zero_count, parent_key, value)
+            }
+            _ => return Err(Error::UnknownOpcode { code, offset }),
+        };
+        if stack.len() >= MAX_STACK_SIZE {
+            return Err(Error::StackOverflow);
+        }
+        if stack.len() >= max_stack {
+            return Err(Error::LimitExceeded {
+                limit: Limit::Stack,
+                max: max_stack,
+                actual: stack.len() + 1,
+            });
+        }
+        stack.push(node);
+    }
+    match stack.as_slice() {
+        [(256, _, root)] if leaf_index == leaves.len() => Ok(root.hash::<H>()),
+        [_] => Err(Error::CorruptedProof),
+        _ => Err(Error::CorruptedStack),
+    }
+}
+
+// Read an operand of the opcode before it, a short read is a truncated program
+#[inline(always)]
+fn read_operand<'a, T>(
+    reader: &mut ByteReader<'a>,
+    read: impl FnOnce(&mut ByteReader<'a>) -> Result<T>,
+) -> Result<T> {
+    read(reader).map_err(|_| Error::TruncatedProof)
+}
+
+// A minimal cursor over untrusted proof bytes
+struct ByteReader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> ByteReader<'a> {
+    #[inline(always)]
+    fn new(bytes: &'a [u8]) -> Self {
+        ByteReader { bytes, pos: 0 }
+    }
+
+    #[inline(always)]
+    fn is_empty(&self) -> bool {
+        self.pos == self.bytes.len()
+    }
+
+    #[inline(always)]
+    fn read_slice(&mut self, n: usize) -> Result<&'a [u8]> {
+        if self.pos + n > self.bytes.len() {
+            return Err(Error::CorruptedProof);
+        }
+        let s = &self.bytes[self.pos..self.pos + n];
+        self.pos += n;
+        Ok(s)
+    }
+
+    #[inline(always)]
+    fn read_u8(&mut self) -> Result<u8> {
+        self.read_slice(1).map(|s| s[0])
+    }
+
+    #[inline(always)]
+    fn read_u32(&mut self) -> Result<u32> {
+        let mut buf = [0u8; 4];
+        buf.copy_from_slice(self.read_slice(4)?);
+        Ok(u32::from_le_bytes(buf))
+    }
+
+    #[inline(always)]
+    fn read_h256(&mut self) -> Result<H256> {
+        let mut buf = [0u8; 32];
+        buf.copy_from_slice(self.read_slice(32)?);
+        Ok(buf.into())
+    }
+}
@@ -0,0 +1,123 @@
+//!
+//! Operation counters of the trees, enabled by the `metrics` feature.
+//!
+//! The counters are process-wide and summed over all trees,
+//! read them with `snapshot` and clear them with `reset`.
+//!
+
+use std::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::Instant,
+};
+
+/// A point-in-time copy of the counters
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TreeMetrics {
+    /// Branches read from the store
+    pub branch_reads: u64,
+    /// Branch reads served by the cache of the top levels(`set_branch_cache`)
+    pub branch_cache_hits: u64,
+    /// Leaf hashes read from the store
+    pub leaf_reads: u64,
+    /// Leaf reads and unchanged updates served by the cached leaf hashes
+    pub leaf_hash_hits: u64,
+    /// Branches inserted or removed
+    pub branch_writes: u64,
+    /// Leaves inserted or removed
+    pub leaf_writes: u64,
+    /// Update batches applied, an update of `SparseMerkleTree2`
+    /// counts the one of its global tree as well
+    pub updates: u64,
+    /// Total time spent in `updates`, in nanoseconds
+    pub update_nanos: u64,
+    /// Merkle proofs generated
+    pub proofs: u64,
+    /// Total sibling nodes in the generated proofs
+    pub proof_siblings: u64,
+}
+
+#[derive(Default)]
+pub(crate) struct Counters {
+    pub(crate) branch_reads: AtomicU64,
+    pub(crate) branch_cache_hits: AtomicU64,
+    pub(crate) leaf_reads: AtomicU64,
+    pub(crate) leaf_hash_hits: AtomicU64,
+    pub(crate) branch_writes: AtomicU64,
+    pub(crate) leaf_writes: AtomicU64,
+    pub(crate) updates: AtomicU64,
+    pub(crate) update_nanos: AtomicU64,
+    pub(crate) proofs: AtomicU64,
+    pub(crate) proof_siblings: AtomicU64,
+}
+
+pub(crate) static COUNTERS: Counters = Counters {
+    branch_reads: AtomicU64::new(0),
+    branch_cache_hits: AtomicU64::new(0),
+    leaf_reads: AtomicU64::new(0),
+    leaf_hash_hits: AtomicU64::new(0),
+    branch_writes: AtomicU64::new(0),
+    leaf_writes: AtomicU64::new(0),
+    updates: AtomicU64::new(0),
+    update_nanos: AtomicU64::new(0),
+    proofs: AtomicU64::new(0),
+    proof_siblings: AtomicU64::new(0),
+};
+
+#[inline(always)]
+pub(crate) fn add(counter: &AtomicU64, n: u64) {
+    counter.fetch_add(n, Ordering::Relaxed);
+}
+
+/// Read the current counters
+pub fn snapshot() -> TreeMetrics {
+    let c = &COUNTERS;
+    let get = |counter: &AtomicU64| counter.load(Ordering::Relaxed);
+    TreeMetrics {
+        branch_reads: get(&c.branch_reads),
+        branch_cache_hits: get(&c.branch_cache_hits),
+        leaf_reads: get(&c.leaf_reads),
+        leaf_hash_hits: get(&c.leaf_hash_hits),
+        branch_writes: get(&c.branch_writes),
+        leaf_writes: get(&c.leaf_writes),
+        updates: get(&c.updates),
+        update_nanos: get(&c.update_nanos),
+        proofs: get(&c.proofs),
+        proof_siblings: get(&c.proof_siblings),
+    }
+}
+
+/// Clear all counters
+pub fn reset() {
+    let c = &COUNTERS;
+    for counter in [
+        &c.branch_reads,
+        &c.branch_cache_hits,
+        &c.leaf_reads,
+        &c.leaf_hash_hits,
+        &c.branch_writes,
+        &c.leaf_writes,
+        &c.updates,
+        &c.update_nanos,
+        &c.proofs,
+        &c.proof_siblings,
+    ] {
+        counter.store(0, Ordering::Relaxed);
+    }
+}
+
+// Counts an update and its latency when dropped
+pub(crate) struct UpdateTimer(Instant);
+
+impl UpdateTimer {
+    #[inline(always)]
+    pub(crate) fn start() -> Self {
+        UpdateTimer(Instant::now())
+    }
+}
+
+impl Drop for UpdateTimer {
+    fn drop(&mut self) {
+        add(&COUNTERS.updates, 1);
+        add(&COUNTERS.update_nanos, self.0.elapsed().as_nanos() as u64);
+    }
+}
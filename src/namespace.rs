@@ -0,0 +1,92 @@
+//!
+//! Keys grouped by namespace.
+//!
+//! A `NamespacedKey` is placed at the path whose highest 32 bits are the namespace,
+//! so all keys of a namespace sit in one subtree: they are found by a `KeyRange`,
+//! sort by namespace first, and an empty namespace is proved by `prove_empty_range`
+//! between the paths around it. The paths are plain `H256`s, proofs are the usual ones.
+//!
+
+use crate::{
+    error::Result,
+    lock::KeyRange,
+    traits::{Hasher, Key, Store, Value},
+    tree::SparseMerkleTree,
+    H256,
+};
+use std::ops::Bound;
+
+/// Number of the highest bits of a path taken by the namespace
+pub const NAMESPACE_BITS: u16 = 32;
+
+/// A key under a namespace
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct NamespacedKey {
+    pub namespace: u32,
+    /// only the lower 224 bits are kept in the path
+    pub key: H256,
+}
+
+impl NamespacedKey {
+    /// The highest 32 bits of `key` are dropped, see `hashed`
+    /// for keys that are not uniformly distributed.
+    #[inline(always)]
+    pub fn new(namespace: u32, key: H256) -> Self {
+        let mut bytes: [u8; 32] = key.into();
+        bytes[28..].fill(0);
+        NamespacedKey {
+            namespace,
+            key: bytes.into(),
+        }
+    }
+
+    /// The key of `raw_key` under `namespace`, `H::hash(raw_key)` in the lower bits
+    #[inline(always)]
+    pub fn hashed<H: Hasher>(namespace: u32, raw_key: &[u8]) -> Self {
+        Self::new(namespace, H::hash(raw_key))
+    }
+
+    /// Inverse of `to_path`
+    #[inline(always)]
+    pub fn from_path(path: &H256) -> Self {
+        let mut namespace = [0u8; 4];
+        namespace.copy_from_slice(&path.as_slice()[28..]);
+        Self::new(u32::from_le_bytes(namespace), *path)
+    }
+
+    /// All paths of `namespace`
+    #[inline(always)]
+    pub fn range(namespace: u32) -> KeyRange {
+        KeyRange::new(Self::new(namespace, H256::zero()).to_path(), NAMESPACE_BITS)
+    }
+}
+
+impl Key for NamespacedKey {
+    // the namespace in the highest bytes, little-endian like the integer keys,
+    // so paths sort by namespace first
+    #[inline(always)]
+    fn to_path(&self) -> H256 {
+        let mut bytes: [u8; 32] = self.key.into();
+        bytes[28..].copy_from_slice(&self.namespace.to_le_bytes());
+        bytes.into()
+    }
+}
+
+impl<H: Hasher, V: Value<H>, S: Store<V>, const DEPTH: usize>
+    SparseMerkleTree<H, V, S, DEPTH>
+{
+    /// All leaves whose paths are in `range`, sorted by key,
+    /// e.g. those of a namespace by `NamespacedKey::range`.
+    ///
+    /// The keys of a range are contiguous, the leaves are read
+    /// by `Store::iter_leaves_range`, and verified if `set_verify_reads` is on.
+    pub fn leaves_in(&self, range: &KeyRange) -> Result<Vec<(H256, V)>> {
+        let (first, last) = range.bounds();
+        let leaves: Vec<(H256, V)> = self
+            .store
+            .iter_leaves_range((Bound::Included(first), Bound::Included(last)))
+            .collect();
+        self.verify_leaves(leaves.iter().map(|(k, v)| (k, Some(v))))?;
+        Ok(leaves)
+    }
+}
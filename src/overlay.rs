@@ -0,0 +1,454 @@
+//!
+//! A store that stages writes in memory on top of another store.
+//!
+//! Reads see the staged writes first, `commit` flushes them to the base store
+//! and `discard` drops them, e.g. to compute candidate roots of a block
+//! without touching the canonical tree.
+//!
+
+use crate::{
+    error::{Error, Result},
+    traits::{Hasher, Store, Value},
+    tree::{BranchKey, BranchNode},
+    SparseMerkleTree, H256,
+};
+use ruc::*;
+use std::{
+    collections::BTreeMap,
+    ops::{Bound, RangeBounds},
+    result::Result as StdResult,
+};
+use vsdb::{BranchName, VersionName, Vs, VsMgmt};
+
+/// A `Store` buffering all branch/leaf/root writes in memory over `base`.
+///
+/// Version management(`VsMgmt`) is passed through to `base`,
+/// staged writes are not part of any version before `commit`.
+#[derive(Vs, Debug)]
+pub struct OverlayStore<S: VsMgmt, V> {
+    base: S,
+    staged: Staged<V>,
+}
+
+#[derive(Debug)]
+struct Staged<V> {
+    // `None` means removal
+    branches: BTreeMap<BranchKey, Option<BranchNode>>,
+    // `None` means removal, the hash is kept if given
+    leaves: BTreeMap<H256, Option<(V, Option<H256>)>>,
+    root: Option<H256>,
+    // sequence number of the first staged root, and the staged roots
+    root_history: Option<(u64, Vec<H256>)>,
+    // change of the number of leaves
+    leaves_delta: i64,
+}
+
+impl<V> Default for Staged<V> {
+    fn default() -> Self {
+        Staged {
+            branches: BTreeMap::new(),
+            leaves: BTreeMap::new(),
+            root: None,
+            root_history: None,
+            leaves_delta: 0,
+        }
+    }
+}
+
+// staged writes are not versioned
+impl<V> VsMgmt for Staged<V> {
+    vsdb::impl_vs_methods_nope!();
+}
+
+impl<S: VsMgmt, V> OverlayStore<S, V> {
+    #[inline(always)]
+    pub fn new(base: S) -> Self {
+        OverlayStore {
+            base,
+            staged: Staged::default(),
+        }
+    }
+
+    /// The base store, without the staged writes
+    #[inline(always)]
+    pub fn base(&self) -> &S {
+        &self.base
+    }
+
+    /// Whether there are staged writes
+    #[inline(always)]
+    pub fn is_dirty(&self) -> bool {
+        !self.staged.branches.is_empty()
+            || !self.staged.leaves.is_empty()
+            || self.staged.root.is_some()
+            || self.staged.root_history.is_some()
+    }
+
+    /// Drop all staged writes
+    #[inline(always)]
+    pub fn discard(&mut self) {
+        self.staged = Staged::default();
+    }
+
+    /// Drop all staged writes and take the base store back
+    #[inline(always)]
+    pub fn into_base(self) -> S {
+        self.base
+    }
+}
+
+impl<S: Store<V>, V: Clone> OverlayStore<S, V> {
+    /// Write all staged changes to the base store.
+    ///
+    /// The writes are not atomic, the base store may be left
+    /// partially written if one of them fails; the changes stay staged then,
+    /// so reads are unchanged and `commit` can be retried.
+    pub fn commit(&mut self) -> Result<()> {
+        let staged = &mut self.staged;
+        for (branch_key, branch) in staged.branches.iter() {
+            match branch {
+                Some(branch) => self.base.insert_branch(branch_key.clone(), *branch)?,
+                None => self.base.remove_branch(branch_key)?,
+            }
+        }
+        for (key, leaf) in staged.leaves.iter() {
+            match leaf {
+                Some((leaf, Some(hash))) => {
+                    self.base.insert_leaf_with_hash(*key, leaf.clone(), *hash)?
+                }
+                Some((leaf, None)) => self.base.insert_leaf(*key, leaf.clone())?,
+                None => self.base.remove_leaf(key)?,
+            }
+        }
+        // the count and the history are not idempotent,
+        // what is written of them is unstaged at once
+        self.base.add_leaves_count(staged.leaves_delta)?;
+        staged.leaves_delta = 0;
+        if let Some(root) = staged.root {
+            self.base.update_root(root)?;
+        }
+        if let Some((start, roots)) = staged.root_history.as_mut() {
+            for (i, root) in roots.iter().enumerate() {
+                if let Err(e) = self.base.push_root_history(*root) {
+                    roots.drain(..i);
+                    *start += i as u64;
+                    return Err(e);
+                }
+            }
+        }
+        self.discard();
+        Ok(())
+    }
+}
+
+impl<S: Store<V>, V: Clone> Store<V> for OverlayStore<S, V> {
+    #[inline(always)]
+    fn insert_branch(
+        &mut self,
+        node_key: BranchKey,
+        branch: BranchNode,
+    ) -> StdResult<(), Error> {
+        self.staged.branches.insert(node_key, Some(branch));
+        Ok(())
+    }
+
+    #[inline(always)]
+    fn remove_branch(&mut self, node_key: &BranchKey) -> StdResult<(), Error> {
+        self.staged.branches.insert(node_key.clone(), None);
+        Ok(())
+    }
+
+    #[inline(always)]
+    fn get_branch(
+        &self,
+        branch_key: &BranchKey,
+    ) -> StdResult<Option<BranchNode>, Error> {
+        match self.staged.branches.get(branch_key) {
+            Some(branch) => Ok(*branch),
+            None => self.base.get_branch(branch_key),
+        }
+    }
+
+    #[inline(always)]
+    fn get_branch_by_branch_version(
+        &self,
+        branch_key: &BranchKey,
+        br: BranchName,
+        ver: VersionName,
+    ) -> StdResult<Option<BranchNode>, Error> {
+        self.base.get_branch_by_branch_version(branch_key, br, ver)
+    }
+
+    #[inline(always)]
+    fn insert_leaf(&mut self, leaf_key: H256, leaf: V) -> StdResult<(), Error> {
+        self.staged.leaves.insert(leaf_key, Some((leaf, None)));
+        Ok(())
+    }
+
+    #[inline(always)]
+    fn insert_leaf_with_hash(
+        &mut self,
+        leaf_key: H256,
+        leaf: V,
+        leaf_hash: H256,
+    ) -> StdResult<(), Error> {
+        self.staged
+            .leaves
+            .insert(leaf_key, Some((leaf, Some(leaf_hash))));
+        Ok(())
+    }
+
+    #[inline(always)]
+    fn get_leaf_hash(&self, leaf_key: &H256) -> StdResult<Option<H256>, Error> {
+        match self.staged.leaves.get(leaf_key) {
+            Some(leaf) => Ok(leaf.as_ref().and_then(|(_, hash)| *hash)),
+            None => self.base.get_leaf_hash(leaf_key),
+        }
+    }
+
+    #[inline(always)]
+    fn remove_leaf(&mut self, leaf_key: &H256) -> StdResult<(), Error> {
+        self.staged.leaves.insert(*leaf_key, None);
+        Ok(())
+    }
+
+    #[inline(always)]
+    fn get_leaf(&self, leaf_key: &H256) -> StdResult<Option<V>, Error> {
+        match self.staged.leaves.get(leaf_key) {
+            Some(leaf) => Ok(leaf.as_ref().map(|(leaf, _)| leaf.clone())),
+            None => self.base.get_leaf(leaf_key),
+        }
+    }
+
+    #[inline(always)]
+    fn has_leaf(&self, leaf_key: &H256) -> StdResult<bool, Error> {
+        match self.staged.leaves.get(leaf_key) {
+            Some(leaf) => Ok(leaf.is_some()),
+            None => self.base.has_leaf(leaf_key),
+        }
+    }
+
+    // the leaves not staged are read from the base store in one batch
+    fn get_leaves(&self, leaf_keys: &[H256]) -> StdResult<Vec<Option<V>>, Error> {
+        let unstaged: Vec<H256> = leaf_keys
+            .iter()
+            .filter(|k| !self.staged.leaves.contains_key(k))
+            .copied()
+            .collect();
+        let mut base = self.base.get_leaves(&unstaged)?.into_iter();
+        Ok(leaf_keys
+            .iter()
+            .map(|k| match self.staged.leaves.get(k) {
+                Some(leaf) => leaf.as_ref().map(|(leaf, _)| leaf.clone()),
+                None => base.next().flatten(),
+            })
+            .collect())
+    }
+
+    #[inline(always)]
+    fn get_leaf_by_branch(
+        &self,
+        leaf_key: &H256,
+        br: BranchName,
+    ) -> StdResult<Option<V>, Error> {
+        self.base.get_leaf_by_branch(leaf_key, br)
+    }
+
+    #[inline(always)]
+    fn get_leaf_by_branch_version(
+        &self,
+        leaf_key: &H256,
+        br: BranchName,
+        ver: VersionName,
+    ) -> StdResult<Option<V>, Error> {
+        self.base.get_leaf_by_branch_version(leaf_key, br, ver)
+    }
+
+    #[inline(always)]
+    fn update_root(&mut self, new_root: H256) -> StdResult<(), Error> {
+        self.staged.root = Some(new_root);
+        Ok(())
+    }
+
+    #[inline(always)]
+    fn get_root(&self) -> StdResult<H256, Error> {
+        match self.staged.root {
+            Some(root) => Ok(root),
+            None => self.base.get_root(),
+        }
+    }
+
+    #[inline(always)]
+    fn get_root_by_branch_version(
+        &self,
+        br: BranchName,
+        ver: VersionName,
+    ) -> StdResult<H256, Error> {
+        self.base.get_root_by_branch_version(br, ver)
+    }
+
+    #[inline(always)]
+    fn get_root_by_branch(&self, br: BranchName) -> StdResult<H256, Error> {
+        self.base.get_root_by_branch(br)
+    }
+
+    fn push_root_history(&mut self, root: H256) -> StdResult<u64, Error> {
+        if self.staged.root_history.is_none() {
+            // the base store only exposes its history as an iterator
+            let start = self
+                .base
+                .iter_root_history((Bound::Unbounded, Bound::Unbounded))
+                .last()
+                .map(|(seq, _)| seq + 1)
+                .unwrap_or(0);
+            self.staged.root_history = Some((start, vec![]));
+        }
+        let (start, roots) = self.staged.root_history.as_mut().unwrap();
+        roots.push(root);
+        Ok(*start + roots.len() as u64 - 1)
+    }
+
+    #[inline(always)]
+    fn get_root_by_seq(&self, seq: u64) -> StdResult<Option<H256>, Error> {
+        match &self.staged.root_history {
+            Some((start, roots)) if seq >= *start => {
+                Ok(roots.get((seq - start) as usize).copied())
+            }
+            _ => self.base.get_root_by_seq(seq),
+        }
+    }
+
+    fn iter_root_history(
+        &self,
+        range: (Bound<u64>, Bound<u64>),
+    ) -> Box<dyn Iterator<Item = (u64, H256)> + '_> {
+        let staged = self
+            .staged
+            .root_history
+            .iter()
+            .flat_map(|(start, roots)| {
+                roots
+                    .iter()
+                    .enumerate()
+                    .map(move |(i, root)| (start + i as u64, *root))
+            })
+            .filter(move |(seq, _)| range.contains(seq));
+        Box::new(self.base.iter_root_history(range).chain(staged))
+    }
+
+    fn iter_branches(&self) -> Box<dyn Iterator<Item = (BranchKey, BranchNode)> + '_> {
+        let staged = &self.staged.branches;
+        let base = self
+            .base
+            .iter_branches()
+            .filter(move |(k, _)| !staged.contains_key(k));
+        let staged = staged
+            .iter()
+            .filter_map(|(k, branch)| branch.map(|branch| (k.clone(), branch)));
+        Box::new(base.chain(staged))
+    }
+
+    // staged writes take no space of the base store
+    #[inline(always)]
+    fn compact(&mut self) -> StdResult<u64, Error> {
+        self.base.compact()
+    }
+
+    // the base count, corrected by the staged batches
+    #[inline(always)]
+    fn leaves_count(&self) -> StdResult<u64, Error> {
+        let count = self.base.leaves_count()?;
+        Ok(count.saturating_add_signed(self.staged.leaves_delta))
+    }
+
+    #[inline(always)]
+    fn add_leaves_count(&mut self, delta: i64) -> StdResult<(), Error> {
+        self.staged.leaves_delta += delta;
+        Ok(())
+    }
+
+    fn iter_leaves<'a>(&'a self) -> Box<dyn Iterator<Item = (H256, V)> + 'a>
+    where
+        V: 'a,
+    {
+        let staged = &self.staged.leaves;
+        let base = self
+            .base
+            .iter_leaves()
+            .filter(move |(k, _)| !staged.contains_key(k));
+        let staged = staged
+            .iter()
+            .filter_map(|(k, leaf)| leaf.as_ref().map(|(leaf, _)| (*k, leaf.clone())));
+        Box::new(base.chain(staged))
+    }
+
+    // the staged leaves are merged into the sorted base ones
+    fn iter_leaves_range<'a>(
+        &'a self,
+        range: (Bound<H256>, Bound<H256>),
+    ) -> Box<dyn Iterator<Item = (H256, V)> + 'a>
+    where
+        V: 'a,
+    {
+        let staged = &self.staged.leaves;
+        let mut base = self
+            .base
+            .iter_leaves_range(range)
+            .filter(move |(k, _)| !staged.contains_key(k))
+            .peekable();
+        let mut staged = staged
+            .iter()
+            .filter(move |(k, _)| range.contains(*k))
+            .filter_map(|(k, leaf)| leaf.as_ref().map(|(leaf, _)| (*k, leaf.clone())))
+            .peekable();
+        Box::new(std::iter::from_fn(move || match (base.peek(), staged.peek()) {
+            (Some((b, _)), Some((s, _))) if b > s => staged.next(),
+            (Some(_), _) => base.next(),
+            (None, _) => staged.next(),
+        }))
+    }
+
+    #[inline(always)]
+    fn iter_leaves_by_branch_version<'a>(
+        &'a self,
+        br: BranchName,
+        ver: VersionName,
+    ) -> Box<dyn Iterator<Item = (H256, V)> + 'a>
+    where
+        V: 'a,
+    {
+        self.base.iter_leaves_by_branch_version(br, ver)
+    }
+}
+
+impl<H: Hasher, V: Value<H> + Clone, S: Store<V>, const DEPTH: usize>
+    SparseMerkleTree<H, V, S, DEPTH>
+{
+    /// Stage all following writes in memory, see `OverlayStore`
+    #[inline(always)]
+    pub fn into_overlay(self) -> SparseMerkleTree<H, V, OverlayStore<S, V>, DEPTH> {
+        let verify_reads = self.verify_reads;
+        let mut tree = SparseMerkleTree::new(OverlayStore::new(self.store));
+        tree.set_verify_reads(verify_reads);
+        tree
+    }
+}
+
+impl<H: Hasher, V: Value<H> + Clone, S: Store<V>, const DEPTH: usize>
+    SparseMerkleTree<H, V, OverlayStore<S, V>, DEPTH>
+{
+    /// Write the staged changes to the base store and return the tree over it
+    pub fn commit_overlay(mut self) -> Result<SparseMerkleTree<H, V, S, DEPTH>> {
+        self.store.commit()?;
+        Ok(self.discard_overlay())
+    }
+
+    /// Drop the staged changes and return the tree over the base store
+    #[inline(always)]
+    pub fn discard_overlay(self) -> SparseMerkleTree<H, V, S, DEPTH> {
+        let verify_reads = self.verify_reads;
+        let mut tree = SparseMerkleTree::new(self.store.into_base());
+        tree.set_verify_reads(verify_reads);
+        tree
+    }
+}
@@ -0,0 +1,112 @@
+//!
+//! A naive reference implementation, for differential testing.
+//!
+//! `ReferenceSmt` keeps the leaves in a `BTreeMap` and hashes the whole tree
+//! from scratch, level by level, on every `root` call: no stored branches,
+//! no incremental updates, only the `merge` rule itself is shared with the tree.
+//! Compare a tree against it after running the same writes on both.
+//!
+
+use crate::{
+    error::{Error, Result},
+    merge::{merge, MergeValue},
+    traits::{Hasher, Store, Value},
+    SparseMerkleTree, H256,
+};
+use std::{collections::BTreeMap, marker::PhantomData};
+
+/// A sparse merkle tree hashed from scratch over its leaves
+#[derive(Debug, Clone, Default)]
+pub struct ReferenceSmt<H> {
+    leaves: BTreeMap<H256, H256>,
+    phantom: PhantomData<fn() -> H>,
+}
+
+impl<H: Hasher> ReferenceSmt<H> {
+    /// The reference of the current leaves of `tree`
+    pub fn from_tree<V: Value<H>, S: Store<V>, const DEPTH: usize>(
+        tree: &SparseMerkleTree<H, V, S, DEPTH>,
+    ) -> Self {
+        let mut reference = Self::default();
+        for (k, v) in tree.store.iter_leaves() {
+            reference.update(k, v.to_h256());
+        }
+        reference
+    }
+
+    /// Set the value hash of a leaf, a zero value removes it
+    pub fn update(&mut self, key: H256, value: H256) {
+        if value.is_zero() {
+            self.leaves.remove(&key);
+        } else {
+            self.leaves.insert(key, value);
+        }
+    }
+
+    /// Value hash of a leaf
+    #[inline(always)]
+    pub fn get(&self, key: &H256) -> Option<H256> {
+        self.leaves.get(key).copied()
+    }
+
+    /// All leaves, sorted by key
+    #[inline(always)]
+    pub fn leaves(&self) -> &BTreeMap<H256, H256> {
+        &self.leaves
+    }
+
+    /// Merkle root, hashing all the leaves
+    pub fn root(&self) -> H256 {
+        let leaves: Vec<(H256, H256)> =
+            self.leaves.iter().map(|(k, v)| (*k, *v)).collect();
+        node::<H>(256, &leaves).hash::<H>()
+    }
+
+    /// Check the root of `tree` against the reference root,
+    /// return RootMismatch error otherwise.
+    pub fn compare_root<V: Value<H>, S: Store<V>, const DEPTH: usize>(
+        &self,
+        tree: &SparseMerkleTree<H, V, S, DEPTH>,
+    ) -> Result<()> {
+        check(self.root(), tree.try_root()?)
+    }
+
+    /// Check that the proof of `keys` generated by `tree` proves the reference
+    /// values of the keys against the reference root,
+    /// return RootMismatch error with the root it proves otherwise.
+    pub fn compare_proof<V: Value<H>, S: Store<V>, const DEPTH: usize>(
+        &self,
+        tree: &SparseMerkleTree<H, V, S, DEPTH>,
+        keys: &[H256],
+    ) -> Result<()> {
+        let proof = tree.merkle_proof(keys.to_vec())?;
+        let leaves = keys.iter().map(|k| (*k, self.get(k))).collect();
+        check(self.root(), proof.compute_root::<H>(leaves)?)
+    }
+}
+
+#[inline(always)]
+fn check(expected: H256, actual: H256) -> Result<()> {
+    if expected == actual {
+        Ok(())
+    } else {
+        Err(Error::RootMismatch { expected, actual })
+    }
+}
+
+// The node at `level` over the sorted `leaves` under it,
+// a leaf at level 0, the merge of the two halves above
+fn node<H: Hasher>(level: usize, leaves: &[(H256, H256)]) -> MergeValue {
+    match leaves {
+        [] => MergeValue::zero(),
+        [(_, value)] if level == 0 => MergeValue::from_h256(*value),
+        _ => {
+            let height = (level - 1) as u8;
+            // sorted by key, the left half(bit unset) comes first
+            let mid = leaves.partition_point(|(k, _)| !k.get_bit(height));
+            let left = node::<H>(level - 1, &leaves[..mid]);
+            let right = node::<H>(level - 1, &leaves[mid..]);
+            merge::<H>(height, &leaves[0].0.parent_path(height), &left, &right)
+        }
+    }
+}
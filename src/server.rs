@@ -0,0 +1,271 @@
+//!
+//! A minimal JSON-RPC 2.0 interface over HTTP, for serving proofs.
+//!
+//! Requests are `POST`ed as JSON-RPC objects with positional params,
+//! hashes are `0x`-prefixed hex and proofs are in the serde form of `MerkleProof`:
+//!
+//! - `root`: `[]`, the merkle root
+//! - `get`: `[key]`, the value of the leaf, `null` if absent
+//! - `prove`: `[[key, ..]]`, `{"root": root, "proof": proof, "leaves": leaves}`,
+//!   the leaves as `[key, value hash or null]` sorted by key, ready for `verify`
+//! - `verify`: `[root, proof, leaves]`, whether the proof of the leaves matches the root
+//!
+//! Every connection carries one request, the tree is locked for reading per request.
+//! The request line and headers are bounded by `MAX_HEADER_SIZE`, the body
+//! by `MAX_BODY_SIZE`; a client idle for `IO_TIMEOUT`, or whose request is not
+//! read within `REQUEST_TIMEOUT`, is dropped.
+//!
+
+use crate::{
+    error::{Error, Result},
+    merkle_proof::MerkleProof,
+    shared::SharedSmt,
+    traits::{Hasher, Store, Value},
+    H256,
+};
+use serde::Serialize;
+use serde_json::{json, Value as Json};
+use std::{
+    io::{self, BufRead, BufReader, Read, Take, Write},
+    net::{TcpListener, TcpStream},
+    time::{Duration, Instant},
+};
+
+/// Invalid JSON
+pub const PARSE_ERROR: i64 = -32700;
+/// Not a JSON-RPC request object
+pub const INVALID_REQUEST: i64 = -32600;
+/// Unknown method
+pub const METHOD_NOT_FOUND: i64 = -32601;
+/// Params of the wrong shape
+pub const INVALID_PARAMS: i64 = -32602;
+/// The tree or the proof returned an error
+pub const TREE_ERROR: i64 = -32000;
+
+/// Largest request body accepted, in bytes
+pub const MAX_BODY_SIZE: usize = 1 << 20;
+/// Largest request line and headers accepted, in bytes
+pub const MAX_HEADER_SIZE: u64 = 16 << 10;
+/// Read and write timeout of a connection
+pub const IO_TIMEOUT: Duration = Duration::from_secs(10);
+/// Longest time to read a whole request, however its bytes are spread
+pub const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Answer connections of `listener` one by one, until accepting fails;
+/// a slow client holds the loop for `REQUEST_TIMEOUT` at most.
+///
+/// Run it on several threads, each with a `try_clone` of the listener
+/// and a clone of the tree handle, to serve requests in parallel.
+pub fn serve<H, V, S, const DEPTH: usize>(
+    listener: &TcpListener,
+    tree: &SharedSmt<H, V, S, DEPTH>,
+) -> io::Result<()>
+where
+    H: Hasher,
+    V: Value<H> + Serialize,
+    S: Store<V>,
+{
+    loop {
+        let (stream, _) = listener.accept()?;
+        // a broken connection only concerns its own client
+        let _ = serve_connection(stream, tree);
+    }
+}
+
+/// Read one HTTP request from `stream` and write the response
+#[inline(always)]
+pub fn serve_connection<H, V, S, const DEPTH: usize>(
+    stream: TcpStream,
+    tree: &SharedSmt<H, V, S, DEPTH>,
+) -> io::Result<()>
+where
+    H: Hasher,
+    V: Value<H> + Serialize,
+    S: Store<V>,
+{
+    serve_connection_until(stream, tree, Instant::now() + REQUEST_TIMEOUT)
+}
+
+/// Same as `serve_connection`, the request must be read by `deadline`
+/// or the connection is dropped with a `TimedOut` error
+pub fn serve_connection_until<H, V, S, const DEPTH: usize>(
+    stream: TcpStream,
+    tree: &SharedSmt<H, V, S, DEPTH>,
+    deadline: Instant,
+) -> io::Result<()>
+where
+    H: Hasher,
+    V: Value<H> + Serialize,
+    S: Store<V>,
+{
+    stream.set_write_timeout(Some(IO_TIMEOUT))?;
+    let request = DeadlineStream {
+        stream: stream.try_clone()?,
+        deadline,
+    };
+    // the headers are read within `MAX_HEADER_SIZE`, the limit is raised for the body
+    let mut reader = BufReader::new(request.take(MAX_HEADER_SIZE));
+    let mut stream = stream;
+
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    let is_post = line.starts_with("POST ");
+    let mut len = None;
+    // a line cut off by the limit, rather than by the end of the stream
+    let cut_off = |line: &str, reader: &BufReader<Take<DeadlineStream>>| {
+        !line.ends_with('\n') && reader.get_ref().limit() == 0
+    };
+    let mut too_large = cut_off(&line, &reader);
+    while !too_large {
+        line.clear();
+        let n = reader.read_line(&mut line)?;
+        too_large = cut_off(&line, &reader);
+        if n == 0 || line.trim_end().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("content-length") {
+                len = value.trim().parse::<usize>().ok();
+            }
+        }
+    }
+
+    let (status, body) = match len {
+        _ if too_large => ("431 Request Header Fields Too Large", String::new()),
+        _ if !is_post => ("405 Method Not Allowed", String::new()),
+        None => ("411 Length Required", String::new()),
+        Some(len) if len > MAX_BODY_SIZE => ("413 Payload Too Large", String::new()),
+        Some(len) => {
+            reader.get_mut().set_limit(len as u64);
+            let mut body = vec![0u8; len];
+            reader.read_exact(&mut body)?;
+            ("200 OK", handle(tree, &String::from_utf8_lossy(&body)))
+        }
+    };
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    )?;
+    stream.flush()
+}
+
+// The read side of a connection, every read ends by `deadline`,
+// so a client can not hold it by sending a byte now and then
+struct DeadlineStream {
+    stream: TcpStream,
+    deadline: Instant,
+}
+
+impl Read for DeadlineStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let timed_out = || {
+            io::Error::new(io::ErrorKind::TimedOut, "the request is not read in time")
+        };
+        let left = self.deadline.saturating_duration_since(Instant::now());
+        if left.is_zero() {
+            return Err(timed_out());
+        }
+        self.stream.set_read_timeout(Some(left.min(IO_TIMEOUT)))?;
+        // an expired read timeout is reported as `WouldBlock` on some platforms
+        match self.stream.read(buf) {
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => Err(timed_out()),
+            res => res,
+        }
+    }
+}
+
+/// Answer a JSON-RPC request, the response is always a JSON-RPC response object
+pub fn handle<H, V, S, const DEPTH: usize>(
+    tree: &SharedSmt<H, V, S, DEPTH>,
+    request: &str,
+) -> String
+where
+    H: Hasher,
+    V: Value<H> + Serialize,
+    S: Store<V>,
+{
+    let request: Json = match serde_json::from_str(request) {
+        Ok(request) => request,
+        Err(e) => return response(Json::Null, Err((PARSE_ERROR, e.to_string()))),
+    };
+    let id = request.get("id").cloned().unwrap_or(Json::Null);
+    let (method, params) = match (request.get("method"), request.get("params")) {
+        (Some(Json::String(method)), params) => {
+            (method.as_str(), params.cloned().unwrap_or(json!([])))
+        }
+        _ => {
+            let msg = "missing method".to_owned();
+            return response(id, Err((INVALID_REQUEST, msg)));
+        }
+    };
+    response(id, call(tree, method, params))
+}
+
+type CallResult = core::result::Result<Json, (i64, String)>;
+
+fn response(id: Json, result: CallResult) -> String {
+    match result {
+        Ok(result) => json!({"jsonrpc": "2.0", "id": id, "result": result}),
+        Err((code, message)) => json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "error": {"code": code, "message": message},
+        }),
+    }
+    .to_string()
+}
+
+fn call<H, V, S, const DEPTH: usize>(
+    tree: &SharedSmt<H, V, S, DEPTH>,
+    method: &str,
+    params: Json,
+) -> CallResult
+where
+    H: Hasher,
+    V: Value<H> + Serialize,
+    S: Store<V>,
+{
+    match method {
+        "root" => tree.try_root().map(|root| json!(root)).map_err(tree_error),
+        "get" => {
+            let (key,): (H256,) = params_of(params)?;
+            tree.get(&key).map(|v| json!(v)).map_err(tree_error)
+        }
+        "prove" => {
+            let (mut keys,): (Vec<H256>,) = params_of(params)?;
+            keys.sort_unstable();
+            let tree = tree.read();
+            let root = tree.try_root().map_err(tree_error)?;
+            let leaves = keys
+                .iter()
+                .map(|k| Ok((*k, tree.get(k)?.map(|v| v.to_h256()))))
+                .collect::<Result<Vec<_>>>()
+                .map_err(tree_error)?;
+            let proof = tree.merkle_proof(keys).map_err(tree_error)?;
+            Ok(json!({"root": root, "proof": proof, "leaves": leaves}))
+        }
+        "verify" => {
+            let (root, proof, leaves): (H256, MerkleProof, Vec<(H256, Option<H256>)>) =
+                params_of(params)?;
+            proof
+                .verify::<H>(root, leaves)
+                .map(|valid| json!(valid))
+                .map_err(tree_error)
+        }
+        _ => Err((METHOD_NOT_FOUND, format!("unknown method {:?}", method))),
+    }
+}
+
+fn params_of<T: serde::de::DeserializeOwned>(
+    params: Json,
+) -> core::result::Result<T, (i64, String)> {
+    serde_json::from_value(params).map_err(|e| (INVALID_PARAMS, e.to_string()))
+}
+
+#[inline(always)]
+fn tree_error(e: Error) -> (i64, String) {
+    (TREE_ERROR, e.to_string())
+}
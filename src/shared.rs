@@ -0,0 +1,228 @@
+//!
+//! A tree handle shared between threads.
+//!
+//! Reads(`get`, `merkle_proof`, ...) run concurrently,
+//! a write holds the tree exclusively until it returns.
+//!
+//! Writers working on disjoint key ranges can lock their ranges with
+//! `write_range` and interleave their writes, a writer holds its range
+//! across many writes while the tree is locked only for each of them.
+//!
+
+use crate::{
+    error::{Error, Result},
+    lock::{KeyRange, LockOwner, RangeGuard, RangeLocks},
+    merkle_proof::MerkleProof,
+    traits::{Hasher, Store, Value},
+    tree::SparseMerkleTree,
+    H256,
+};
+use std::{
+    fmt,
+    sync::{Arc, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard},
+};
+use vsdb::VsMgmt;
+
+/// A cloneable handle to a `SparseMerkleTree` behind a read-write lock,
+/// all clones refer to the same tree.
+///
+/// Every clone is its own owner of the ranges locked with `write_range`,
+/// give each writer task its own clone.
+///
+/// A writer panicking does not poison the tree for the other handles,
+/// the tree is left as a failed write leaves it.
+#[derive(Debug)]
+pub struct SharedSmt<H, V, S: VsMgmt, const DEPTH: usize = 256> {
+    inner: Arc<RwLock<SparseMerkleTree<H, V, S, DEPTH>>>,
+    locks: Arc<RangeLocks>,
+    owner: LockOwner,
+}
+
+impl<H, V, S: VsMgmt, const DEPTH: usize> Clone for SharedSmt<H, V, S, DEPTH> {
+    #[inline(always)]
+    fn clone(&self) -> Self {
+        SharedSmt {
+            inner: Arc::clone(&self.inner),
+            locks: Arc::clone(&self.locks),
+            owner: self.locks.owner(),
+        }
+    }
+}
+
+impl<H: Hasher, V: Value<H>, S: Store<V>, const DEPTH: usize> SharedSmt<H, V, S, DEPTH> {
+    #[inline(always)]
+    pub fn new(tree: SparseMerkleTree<H, V, S, DEPTH>) -> Self {
+        let locks = RangeLocks::new();
+        SharedSmt {
+            inner: Arc::new(RwLock::new(tree)),
+            owner: locks.owner(),
+            locks: Arc::new(locks),
+        }
+    }
+
+    /// Lock the tree for reading, for the read APIs not wrapped here
+    #[inline(always)]
+    pub fn read(&self) -> RwLockReadGuard<'_, SparseMerkleTree<H, V, S, DEPTH>> {
+        self.inner.read().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Lock the tree for writing, for the write APIs not wrapped here
+    /// or several writes in a row
+    #[inline(always)]
+    pub fn write(&self) -> RwLockWriteGuard<'_, SparseMerkleTree<H, V, S, DEPTH>> {
+        self.inner.write().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Lock all keys sharing the highest `bits` bits with `prefix` for writing,
+    /// blocks while an overlapping range is held by another handle,
+    /// fails with `Error::Deadlock` if waiting would close a wait cycle.
+    ///
+    /// The range is advisory: the writes of plain `update`/`remove`
+    /// are not checked against it.
+    pub fn write_range(
+        &self,
+        prefix: H256,
+        bits: u16,
+    ) -> Result<RangeWriter<'_, H, V, S, DEPTH>> {
+        let guard = self.locks.lock_range(self.owner, prefix, bits)?;
+        Ok(RangeWriter { tree: self, guard })
+    }
+
+    /// Like `write_range`, but returns `None` instead of blocking
+    pub fn try_write_range(
+        &self,
+        prefix: H256,
+        bits: u16,
+    ) -> Option<RangeWriter<'_, H, V, S, DEPTH>> {
+        let guard = self.locks.try_lock_range(self.owner, prefix, bits)?;
+        Some(RangeWriter { tree: self, guard })
+    }
+
+    /// Take the tree back, return `None` if other handles are still alive
+    #[inline(always)]
+    pub fn into_inner(self) -> Option<SparseMerkleTree<H, V, S, DEPTH>> {
+        Arc::try_unwrap(self.inner)
+            .ok()
+            .map(|lock| lock.into_inner().unwrap_or_else(PoisonError::into_inner))
+    }
+
+    /// Merkle root
+    #[inline(always)]
+    pub fn root(&self) -> H256 {
+        self.read().root()
+    }
+
+    /// Merkle root, or the error of the store
+    #[inline(always)]
+    pub fn try_root(&self) -> Result<H256> {
+        self.read().try_root()
+    }
+
+    /// Get value of a leaf
+    #[inline(always)]
+    pub fn get(&self, key: &H256) -> Result<Option<V>> {
+        self.read().get(key)
+    }
+
+    /// Values of several leaves, in the order of `keys`
+    #[inline(always)]
+    pub fn get_many(&self, keys: &[H256]) -> Result<Vec<Option<V>>> {
+        self.read().get_many(keys)
+    }
+
+    /// Whether a leaf exists, without decoding its value
+    #[inline(always)]
+    pub fn contains_key(&self, key: &H256) -> Result<bool> {
+        self.read().contains_key(key)
+    }
+
+    /// Generate merkle proof
+    #[inline(always)]
+    pub fn merkle_proof(&self, keys: Vec<H256>) -> Result<MerkleProof> {
+        self.read().merkle_proof(keys)
+    }
+
+    /// Update a leaf, return new merkle root
+    #[inline(always)]
+    pub fn update(&self, key: H256, value: V) -> Result<H256> {
+        self.write().update(key, value)
+    }
+
+    /// Update multiple leaves at once
+    #[inline(always)]
+    pub fn update_all(&self, leaves: Vec<(H256, V)>) -> Result<H256> {
+        self.write().update_all(leaves)
+    }
+
+    /// Remove a leaf, return new merkle root and the removed value
+    #[inline(always)]
+    pub fn remove(&self, key: H256) -> Result<(H256, Option<V>)> {
+        self.write().remove(key)
+    }
+}
+
+/// Writes within a locked key range, see `SharedSmt::write_range`,
+/// the range is unlocked when the writer is dropped
+pub struct RangeWriter<'a, H, V, S: VsMgmt, const DEPTH: usize> {
+    tree: &'a SharedSmt<H, V, S, DEPTH>,
+    guard: RangeGuard<'a>,
+}
+
+impl<H: Hasher, V: Value<H>, S: Store<V>, const DEPTH: usize>
+    RangeWriter<'_, H, V, S, DEPTH>
+{
+    /// The locked range
+    #[inline(always)]
+    pub fn range(&self) -> &KeyRange {
+        self.guard.range()
+    }
+
+    #[inline(always)]
+    fn check(&self, key: &H256) -> Result<()> {
+        if self.range().contains(key) {
+            Ok(())
+        } else {
+            Err(Error::KeyNotLocked(*key))
+        }
+    }
+
+    /// Get value of a leaf in the range
+    #[inline(always)]
+    pub fn get(&self, key: &H256) -> Result<Option<V>> {
+        self.check(key)?;
+        self.tree.get(key)
+    }
+
+    /// Update a leaf in the range, return new merkle root
+    #[inline(always)]
+    pub fn update(&self, key: H256, value: V) -> Result<H256> {
+        self.check(&key)?;
+        self.tree.update(key, value)
+    }
+
+    /// Update multiple leaves in the range at once,
+    /// nothing is written if any of them is out of the range
+    pub fn update_all(&self, leaves: Vec<(H256, V)>) -> Result<H256> {
+        for (key, _) in leaves.iter() {
+            self.check(key)?;
+        }
+        self.tree.update_all(leaves)
+    }
+
+    /// Remove a leaf in the range, return new merkle root and the removed value
+    #[inline(always)]
+    pub fn remove(&self, key: H256) -> Result<(H256, Option<V>)> {
+        self.check(&key)?;
+        self.tree.remove(key)
+    }
+}
+
+impl<H, V, S: VsMgmt, const DEPTH: usize> fmt::Debug
+    for RangeWriter<'_, H, V, S, DEPTH>
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RangeWriter")
+            .field("range", self.guard.range())
+            .finish()
+    }
+}
@@ -0,0 +1,136 @@
+//!
+//! Snapshot export / import of a whole tree.
+//!
+//! Layout(all integers are little-endian):
+//! `b"XSMT" | format version(u8) | root(32) | leaves count(u64) | leaves`,
+//! each leaf is encoded as `key(32) | value length(u32) | value bytes`,
+//! leaves are sorted by key.
+//!
+
+use crate::{
+    error::{Error, Result},
+    merge::MergeValue,
+    traits::{Hasher, Store, Value},
+    tree::merge_leaves,
+    SparseMerkleTree, H256,
+};
+use std::io::{Read, Write};
+use vsdb::ValueEnDe;
+
+const MAGIC: &[u8; 4] = b"XSMT";
+const FORMAT_VERSION: u8 = 1;
+
+impl<H: Hasher, V: Value<H> + ValueEnDe, S: Store<V>, const DEPTH: usize>
+    SparseMerkleTree<H, V, S, DEPTH>
+{
+    /// Serialize all leaves and the root into `writer`,
+    /// return the number of exported leaves.
+    pub fn export_snapshot(&self, mut writer: impl Write) -> Result<u64> {
+        let mut leaves = self.store.iter_leaves().collect::<Vec<_>>();
+        leaves.sort_unstable_by_key(|(k, _)| *k);
+
+        let mut w = |bytes: &[u8]| writer.write_all(bytes).map_err(snapshot_err);
+        w(MAGIC)?;
+        w(&[FORMAT_VERSION])?;
+        w(self.store.get_root()?.as_slice())?;
+        w(&(leaves.len() as u64).to_le_bytes())?;
+        for (k, v) in leaves.iter() {
+            let v = v.encode();
+            w(k.as_slice())?;
+            w(&(v.len() as u32).to_le_bytes())?;
+            w(&v)?;
+        }
+
+        Ok(leaves.len() as u64)
+    }
+
+    /// Rebuild the tree from a snapshot produced by `export_snapshot`,
+    /// the tree must be empty; return the restored root.
+    ///
+    /// The root is recomputed and checked before anything is written.
+    pub fn import_snapshot(&mut self, mut reader: impl Read) -> Result<H256> {
+        if !self.try_is_empty()? {
+            return Err(Error::Snapshot("target tree is not empty".to_owned()));
+        }
+
+        let mut magic = [0u8; 4];
+        read_exact(&mut reader, &mut magic)?;
+        if &magic != MAGIC {
+            return Err(Error::Snapshot("invalid magic bytes".to_owned()));
+        }
+        let mut version = [0u8; 1];
+        read_exact(&mut reader, &mut version)?;
+        if version[0] != FORMAT_VERSION {
+            return Err(Error::Snapshot(format!(
+                "unsupported format version: {}",
+                version[0]
+            )));
+        }
+        let root = read_h256(&mut reader)?;
+        let mut count = [0u8; 8];
+        read_exact(&mut reader, &mut count)?;
+        let count = u64::from_le_bytes(count);
+
+        let mut leaves: Vec<(H256, V)> = Vec::new();
+        for _ in 0..count {
+            let key = read_h256(&mut reader)?;
+            let mut len = [0u8; 4];
+            read_exact(&mut reader, &mut len)?;
+            let buf = read_vec(&mut reader, u32::from_le_bytes(len))?;
+            let value = V::decode(&buf).map_err(|e| Error::Snapshot(e.to_string()))?;
+            if leaves.last().is_some_and(|(k, _)| *k >= key) {
+                return Err(Error::Snapshot("leaves are not sorted".to_owned()));
+            }
+            leaves.push((key, value));
+        }
+
+        let nodes = leaves
+            .iter()
+            .map(|(k, v)| (*k, MergeValue::from_h256(v.to_h256())))
+            .filter(|(_, v)| !v.is_zero())
+            .collect();
+        let computed_root = merge_leaves::<H>(DEPTH, nodes, |_, _| Ok(()))?;
+        if computed_root != root {
+            return Err(Error::Snapshot(format!(
+                "root mismatch, expected {:?} actual {:?}",
+                root, computed_root
+            )));
+        }
+
+        self.update_all(leaves)
+    }
+}
+
+#[inline(always)]
+fn snapshot_err(e: std::io::Error) -> Error {
+    Error::Snapshot(e.to_string())
+}
+
+#[inline(always)]
+fn read_exact(reader: &mut impl Read, buf: &mut [u8]) -> Result<()> {
+    reader.read_exact(buf).map_err(snapshot_err)
+}
+
+// the length is untrusted, the buffer only grows with the bytes actually read
+fn read_vec(reader: &mut impl Read, len: u32) -> Result<Vec<u8>> {
+    let mut buf = Vec::new();
+    reader
+        .take(len as u64)
+        .read_to_end(&mut buf)
+        .map_err(snapshot_err)?;
+    if buf.len() != len as usize {
+        return Err(Error::Snapshot(format!(
+            "truncated value, expected {} bytes actual {}",
+            len,
+            buf.len()
+        )));
+    }
+    Ok(buf)
+}
+
+#[inline(always)]
+fn read_h256(reader: &mut impl Read) -> Result<H256> {
+    let mut buf = [0u8; 32];
+    read_exact(reader, &mut buf)?;
+    Ok(buf.into())
+}
@@ -0,0 +1,109 @@
+//!
+//! Stateless execution against a witness.
+//!
+//! A `WitnessTree` holds the leaves proved by a compiled proof instead of a store,
+//! reads and writes of those leaves yield the same roots as the full tree would,
+//! e.g. to re-execute a block with only its witness.
+//!
+
+use crate::{
+    error::{Error, Result},
+    merkle_proof::CompiledMerkleProof,
+    traits::Hasher,
+    H256,
+};
+use std::{collections::BTreeMap, marker::PhantomData};
+
+/// The leaves of a witness, with the root they are proved against
+#[derive(Debug, Clone)]
+pub struct WitnessTree<H> {
+    root: H256,
+    proof: CompiledMerkleProof,
+    leaves: BTreeMap<H256, Option<H256>>,
+    phantom: PhantomData<fn() -> H>,
+}
+
+impl<H: Hasher + Default> WitnessTree<H> {
+    /// Build from a witness: `proof` of `leaves` against `root`,
+    /// return `Error::RootMismatch` if the proof does not match `root`.
+    pub fn new(
+        root: H256,
+        proof: CompiledMerkleProof,
+        leaves: Vec<(H256, Option<H256>)>,
+    ) -> Result<Self> {
+        let leaves: BTreeMap<H256, Option<H256>> = leaves
+            .into_iter()
+            .map(|(k, v)| (k, v.filter(|v| !v.is_zero())))
+            .collect();
+        let actual = compute_root::<H>(&proof, &leaves)?;
+        if actual != root {
+            return Err(Error::RootMismatch {
+                expected: root,
+                actual,
+            });
+        }
+        Ok(WitnessTree {
+            root,
+            proof,
+            leaves,
+            phantom: PhantomData,
+        })
+    }
+
+    /// Current root, after the updates so far
+    #[inline(always)]
+    pub fn root(&self) -> H256 {
+        self.root
+    }
+
+    /// The leaves of the witness, with their current values
+    #[inline(always)]
+    pub fn leaves(&self) -> &BTreeMap<H256, Option<H256>> {
+        &self.leaves
+    }
+
+    /// Value of `key`, `None` if it's absent from the tree,
+    /// return `Error::UnprovedKeys` if it's outside of the witness.
+    pub fn get(&self, key: &H256) -> Result<Option<H256>> {
+        self.leaves
+            .get(key)
+            .copied()
+            .ok_or_else(|| Error::UnprovedKeys(vec![*key]))
+    }
+
+    /// Update a leaf, a zero value removes it,
+    /// return the new root.
+    #[inline(always)]
+    pub fn update(&mut self, key: H256, value: H256) -> Result<H256> {
+        self.update_all(vec![(key, value)])
+    }
+
+    /// Update several leaves at once, return the new root.
+    ///
+    /// Nothing is changed if any of the keys is outside of the witness.
+    pub fn update_all(&mut self, leaves: Vec<(H256, H256)>) -> Result<H256> {
+        let unproved: Vec<H256> = leaves
+            .iter()
+            .map(|(k, _)| *k)
+            .filter(|k| !self.leaves.contains_key(k))
+            .collect();
+        if !unproved.is_empty() {
+            return Err(Error::UnprovedKeys(unproved));
+        }
+
+        let mut updated = self.leaves.clone();
+        for (key, value) in leaves {
+            updated.insert(key, Some(value).filter(|v| !v.is_zero()));
+        }
+        self.root = compute_root::<H>(&self.proof, &updated)?;
+        self.leaves = updated;
+        Ok(self.root)
+    }
+}
+
+fn compute_root<H: Hasher + Default>(
+    proof: &CompiledMerkleProof,
+    leaves: &BTreeMap<H256, Option<H256>>,
+) -> Result<H256> {
+    proof.compute_root::<H>(leaves.iter().map(|(k, v)| (*k, *v)).collect())
+}
@@ -0,0 +1,281 @@
+//!
+//! Fast sync of a whole tree in chunks of contiguous leaves.
+//!
+//! `export_chunks` cuts the leaves, sorted by key, into chunks of a fixed size.
+//! Each chunk covers the keys from right after the last key of the chunk before
+//! up to its own last key(the last chunk up to the largest key),
+//! and carries one proof of its leaves and of the emptiness of the rest of its range.
+//! `ChunkImporter` checks every chunk against the trusted root as it arrives, in order,
+//! and writes its leaves, so a peer never has to trust a whole download at once.
+//!
+
+use crate::{
+    error::{Error, Result},
+    merkle_proof::{next_key, MerkleProof},
+    traits::{Hasher, Store, Value},
+    tree::{leaf_hash, LeafIter},
+    SparseMerkleTree, H256,
+};
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, iter::Peekable};
+use vsdb::VsMgmt;
+
+/// Leaves of a key range with the proof that they are all the leaves in it
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Chunk<V> {
+    start: H256,
+    end: H256,
+    leaves: Vec<(H256, V)>,
+    proof: MerkleProof,
+}
+
+impl<V> Chunk<V> {
+    /// The first key of the range
+    #[inline(always)]
+    pub fn start(&self) -> H256 {
+        self.start
+    }
+
+    /// The last key of the range, included
+    #[inline(always)]
+    pub fn end(&self) -> H256 {
+        self.end
+    }
+
+    /// The leaves in the range, sorted by key
+    #[inline(always)]
+    pub fn leaves(&self) -> &[(H256, V)] {
+        &self.leaves
+    }
+
+    /// The proof of the leaves and of the range boundaries
+    #[inline(always)]
+    pub fn proof(&self) -> &MerkleProof {
+        &self.proof
+    }
+
+    /// Check that the leaves are all the leaves between `start` and `end`
+    /// in the tree of `root`.
+    pub fn verify<H: Hasher>(&self, root: H256) -> Result<bool>
+    where
+        V: Value<H>,
+    {
+        if self.start > self.end {
+            return Ok(false);
+        }
+        let mut keys = BTreeMap::new();
+        keys.insert(self.start, None);
+        keys.insert(self.end, None);
+        let mut last = None;
+        for (k, v) in self.leaves.iter() {
+            let hash = leaf_hash(Some(v));
+            if *k < self.start
+                || *k > self.end
+                || last.is_some_and(|last| last >= *k)
+                || hash.is_zero()
+            {
+                return Ok(false);
+            }
+            last = Some(*k);
+            keys.insert(*k, Some(hash));
+        }
+
+        let proved: Vec<H256> = keys.keys().copied().collect();
+        if !siblings_outside(&self.proof, &proved, &self.start, &self.end) {
+            return Ok(false);
+        }
+        self.proof
+            .clone()
+            .verify::<H>(root, keys.into_iter().collect())
+    }
+}
+
+// Whether every non-zero sibling of the proof of the sorted `keys`
+// lies outside `start..=end`, both of which are in `keys`.
+//
+// Such a sibling holds none of `keys`, so it is either inside the range or outside it,
+// and one inside could hide leaves left out of the chunk.
+fn siblings_outside(proof: &MerkleProof, keys: &[H256], start: &H256, end: &H256) -> bool {
+    if proof.leaves_count() != keys.len() {
+        return false;
+    }
+    for (i, (key, bitmap)) in keys.iter().zip(proof.leaves_bitmap()).enumerate() {
+        // the same heights as walked by `MerkleProof::compile`
+        let top = keys
+            .get(i + 1)
+            .map(|next| key.fork_height(next) as u16)
+            .unwrap_or(256);
+        for height in (0..top).map(|h| h as u8) {
+            if !bitmap.get_bit(height) {
+                continue;
+            }
+            let mut first = key.parent_path(height);
+            if !key.get_bit(height) {
+                first.set_bit(height);
+            }
+            let mut last = first;
+            for h in 0..height {
+                last.set_bit(h);
+            }
+            // a sibling holding other keys is merged from them, not read from the proof
+            let j = keys.partition_point(|k| *k < first);
+            if keys.get(j).is_some_and(|k| *k <= last) {
+                continue;
+            }
+            if first > *start && last < *end {
+                return false;
+            }
+        }
+    }
+    true
+}
+
+// The largest key of a `depth`-level tree
+#[inline(always)]
+fn max_key(depth: usize) -> H256 {
+    let mut key = H256::zero();
+    for h in 0..depth {
+        key.set_bit(h as u8);
+    }
+    key
+}
+
+impl<H: Hasher, V: Value<H>, S: Store<V>, const DEPTH: usize>
+    SparseMerkleTree<H, V, S, DEPTH>
+{
+    /// Stream the tree as chunks of `chunk_size` leaves(the last one may hold fewer),
+    /// see `ChunkImporter` for the receiving side.
+    ///
+    /// An empty tree gives a single chunk without leaves.
+    pub fn export_chunks(&self, chunk_size: usize) -> Result<ChunkIter<'_, H, V, S, DEPTH>> {
+        if chunk_size == 0 {
+            return Err(Error::Sync("chunk size must not be zero".to_owned()));
+        }
+        Ok(ChunkIter {
+            tree: self,
+            leaves: self.export_leaves_sorted()?.peekable(),
+            chunk_size,
+            next: Some(H256::zero()),
+        })
+    }
+}
+
+/// Iterator of `SparseMerkleTree::export_chunks`, it ends after yielding an error
+pub struct ChunkIter<'a, H: Hasher, V: Value<H>, S: Store<V>, const DEPTH: usize> {
+    tree: &'a SparseMerkleTree<H, V, S, DEPTH>,
+    leaves: Peekable<LeafIter<'a, H, V, S, DEPTH>>,
+    chunk_size: usize,
+    // start of the next chunk, `None` after the last one
+    next: Option<H256>,
+}
+
+impl<H: Hasher, V: Value<H>, S: Store<V>, const DEPTH: usize> ChunkIter<'_, H, V, S, DEPTH> {
+    fn chunk(&mut self, start: H256) -> Result<Chunk<V>> {
+        let mut leaves = Vec::with_capacity(self.chunk_size);
+        while leaves.len() < self.chunk_size {
+            match self.leaves.next() {
+                Some(leaf) => leaves.push(leaf?),
+                None => break,
+            }
+        }
+        let end = match (self.leaves.peek(), leaves.last()) {
+            (Some(_), Some((last, _))) => *last,
+            _ => max_key(DEPTH),
+        };
+
+        let mut keys: Vec<H256> = leaves.iter().map(|(k, _)| *k).collect();
+        keys.push(start);
+        keys.push(end);
+        keys.sort_unstable();
+        keys.dedup();
+        let proof = self.tree.merkle_proof(keys)?;
+
+        self.next = if end == max_key(DEPTH) {
+            None
+        } else {
+            next_key(&end)
+        };
+        Ok(Chunk {
+            start,
+            end,
+            leaves,
+            proof,
+        })
+    }
+}
+
+impl<H: Hasher, V: Value<H>, S: Store<V>, const DEPTH: usize> Iterator
+    for ChunkIter<'_, H, V, S, DEPTH>
+{
+    type Item = Result<Chunk<V>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let start = self.next.take()?;
+        Some(self.chunk(start))
+    }
+}
+
+/// Rebuild a tree from the chunks of `export_chunks`, checking each against a trusted root
+pub struct ChunkImporter<'a, H, V, S: VsMgmt, const DEPTH: usize = 256> {
+    tree: &'a mut SparseMerkleTree<H, V, S, DEPTH>,
+    root: H256,
+    // start of the expected chunk, `None` once the last one is imported
+    next: Option<H256>,
+}
+
+impl<'a, H: Hasher, V: Value<H>, S: Store<V>, const DEPTH: usize>
+    ChunkImporter<'a, H, V, S, DEPTH>
+{
+    /// Import into `tree`, which must be empty, the tree of `root`
+    pub fn new(tree: &'a mut SparseMerkleTree<H, V, S, DEPTH>, root: H256) -> Result<Self> {
+        if !tree.try_is_empty()? {
+            return Err(Error::TreeNotEmpty);
+        }
+        Ok(ChunkImporter {
+            tree,
+            root,
+            next: Some(H256::zero()),
+        })
+    }
+
+    /// Check the chunk and write its leaves, chunks must come in order;
+    /// return Sync error for a chunk out of order or with an invalid proof,
+    /// nothing is written then.
+    pub fn import(&mut self, chunk: Chunk<V>) -> Result<()> {
+        let next = self
+            .next
+            .ok_or_else(|| Error::Sync("all chunks are imported".to_owned()))?;
+        if chunk.start != next || chunk.end > max_key(DEPTH) {
+            return Err(Error::Sync(format!(
+                "unexpected chunk range: {:?}..={:?}",
+                chunk.start, chunk.end
+            )));
+        }
+        if !chunk.verify::<H>(self.root)? {
+            return Err(Error::Sync(format!(
+                "invalid chunk proof: {:?}..={:?}",
+                chunk.start, chunk.end
+            )));
+        }
+
+        self.next = if chunk.end == max_key(DEPTH) {
+            None
+        } else {
+            next_key(&chunk.end)
+        };
+        let root = self.tree.update_all(chunk.leaves)?;
+        if self.next.is_none() && root != self.root {
+            return Err(Error::RootMismatch {
+                expected: self.root,
+                actual: root,
+            });
+        }
+        Ok(())
+    }
+
+    /// Whether the last chunk has been imported, the tree then has the trusted root
+    #[inline(always)]
+    pub fn is_complete(&self) -> bool {
+        self.next.is_none()
+    }
+}
@@ -0,0 +1,117 @@
+//!
+//! Proptest strategies and assertions for testing code built on the tree.
+//!
+//! Enabled by the `testing` feature, e.g. as a dev-dependency:
+//! `xsmt = { version = "*", features = ["testing"] }`.
+//!
+
+use crate::{
+    traits::{Hasher, Store, Value},
+    SparseMerkleTree, H256,
+};
+use proptest::{collection, prelude::*};
+use std::collections::BTreeMap;
+
+/// Any `H256`, zero included
+pub fn h256() -> impl Strategy<Value = H256> {
+    any::<[u8; 32]>().prop_map(H256::from)
+}
+
+/// Any non-zero `H256`, e.g. a leaf value that is not a removal
+pub fn non_zero_h256() -> impl Strategy<Value = H256> {
+    h256().prop_filter("zero", |h| !h.is_zero())
+}
+
+/// `min..=max` leaves with distinct keys and non-zero values, sorted by key
+pub fn leaves(min: usize, max: usize) -> impl Strategy<Value = Vec<(H256, H256)>> {
+    collection::btree_map(h256(), non_zero_h256(), min..=max)
+        .prop_map(|leaves| leaves.into_iter().collect())
+}
+
+/// A write of a random update sequence
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Op {
+    Update(H256, H256),
+    Remove(H256),
+}
+
+impl Op {
+    /// Apply the write to `tree`, return the new root
+    pub fn apply<
+        H: Hasher,
+        V: Value<H> + From<H256>,
+        S: Store<V>,
+        const DEPTH: usize,
+    >(
+        &self,
+        tree: &mut SparseMerkleTree<H, V, S, DEPTH>,
+    ) -> crate::error::Result<H256> {
+        match self {
+            Op::Update(k, v) => tree.update(*k, V::from(*v)),
+            Op::Remove(k) => tree.remove(*k).map(|(root, _)| root),
+        }
+    }
+}
+
+/// `1..=max` writes on keys drawn from `keys` random ones,
+/// so that keys are written over and removed again.
+pub fn ops(keys: usize, max: usize) -> impl Strategy<Value = Vec<Op>> {
+    collection::vec(h256(), 1..=keys.max(1)).prop_flat_map(move |pool| {
+        let op = (
+            prop::sample::select(pool),
+            prop::option::weighted(0.75, non_zero_h256()),
+        )
+            .prop_map(|(k, v)| match v {
+                Some(v) => Op::Update(k, v),
+                None => Op::Remove(k),
+            });
+        collection::vec(op, 1..=max.max(1))
+    })
+}
+
+/// The leaves `ops` leave behind, sorted by key
+pub fn ops_result(ops: &[Op]) -> Vec<(H256, H256)> {
+    let mut leaves = BTreeMap::new();
+    for op in ops {
+        match op {
+            Op::Update(k, v) => leaves.insert(*k, *v),
+            Op::Remove(k) => leaves.remove(k),
+        };
+    }
+    leaves.into_iter().collect()
+}
+
+/// Assert that the proof of `keys`, plain and compiled,
+/// verifies the current values of the keys against the root of `tree`.
+///
+/// Panics with the failing step otherwise.
+pub fn assert_proofs_roundtrip<
+    H: Hasher,
+    V: Value<H>,
+    S: Store<V>,
+    const DEPTH: usize,
+>(
+    tree: &SparseMerkleTree<H, V, S, DEPTH>,
+    keys: &[H256],
+) {
+    let root = tree.try_root().expect("root");
+    let leaves: Vec<(H256, Option<H256>)> = keys
+        .iter()
+        .map(|k| (*k, tree.get(k).expect("get").map(|v| v.to_h256())))
+        .collect();
+    let proof = tree.merkle_proof(keys.to_vec()).expect("merkle proof");
+    assert!(
+        proof
+            .clone()
+            .verify::<H>(root, leaves.clone())
+            .expect("verify"),
+        "proof of {:?} does not verify",
+        keys
+    );
+    let compiled = proof.compile(keys.to_vec()).expect("compile proof");
+    assert!(
+        compiled.verify::<H>(root, leaves).expect("compiled verify"),
+        "compiled proof of {:?} does not verify",
+        keys
+    );
+}
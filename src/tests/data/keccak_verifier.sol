@@ -0,0 +1,260 @@
+// SPDX-License-Identifier: GPL-3.0
+// Generated by xsmt, do not edit.
+pragma solidity ^0.8.0;
+
+/// Verifier of xsmt compiled merkle proofs, hashing with keccak256.
+library SmtVerifier {
+    struct Node {
+        // `MergeWithZero` if true, `Value` otherwise
+        bool merged;
+        // the value, or the base node of `MergeWithZero`
+        bytes32 value;
+        bytes32 zeroBits;
+        uint8 zeroCount;
+    }
+
+    struct Item {
+        uint256 height;
+        bytes32 key;
+        Node node;
+    }
+
+    uint256 private constant MAX_STACK_SIZE = 257;
+
+    function verify(
+        bytes32 root,
+        bytes memory proof,
+        bytes32[] memory keys,
+        bytes32[] memory values
+    ) internal pure returns (bool) {
+        return computeRoot(proof, keys, values) == root;
+    }
+
+    /// Leaves must be sorted by key in the order of xsmt `H256`,
+    /// which compares the last byte first.
+    function computeRoot(
+        bytes memory proof,
+        bytes32[] memory keys,
+        bytes32[] memory values
+    ) internal pure returns (bytes32) {
+        require(keys.length == values.length, "SMT: leaves mismatch");
+        for (uint256 i = 1; i < keys.length; i++) {
+            require(!lessThan(keys[i], keys[i - 1]), "SMT: unsorted leaves");
+        }
+
+        Item[] memory stack = new Item[](MAX_STACK_SIZE);
+        uint256 sp = 0;
+        uint256 pc = 0;
+        uint256 leafIndex = 0;
+        while (pc < proof.length) {
+            uint8 code = uint8(proof[pc]);
+            pc += 1;
+            if (code == 0x4C) {
+                // L : push leaf value
+                require(leafIndex < keys.length, "SMT: corrupted stack");
+                require(sp < MAX_STACK_SIZE, "SMT: corrupted stack");
+                stack[sp] = Item(0, keys[leafIndex], Node(false, values[leafIndex], 0, 0));
+                sp += 1;
+                leafIndex += 1;
+            } else if (code == 0x50 || code == 0x51) {
+                // P / Q : hash stack top item with sibling node in proof
+                require(sp > 0, "SMT: corrupted stack");
+                pc = mergeSibling(stack[sp - 1], proof, pc, code == 0x51);
+            } else if (code == 0x48) {
+                // H : pop 2 items in stack hash them then push the result
+                require(sp >= 2, "SMT: corrupted stack");
+                mergePair(stack[sp - 2], stack[sp - 1]);
+                sp -= 1;
+            } else if (code == 0x4F) {
+                // O : hash stack top item with n zero values
+                require(sp > 0, "SMT: corrupted stack");
+                pc = mergeZeros(stack[sp - 1], proof, pc);
+            } else {
+                revert("SMT: invalid code");
+            }
+        }
+        require(sp == 1, "SMT: corrupted stack");
+        require(stack[0].height == 256, "SMT: corrupted proof");
+        require(leafIndex == keys.length, "SMT: corrupted proof");
+        return hashNode(stack[0].node);
+    }
+
+    // `item` is updated in place, return the new program counter
+    function mergeSibling(
+        Item memory item,
+        bytes memory proof,
+        uint256 pc,
+        bool merged
+    ) private pure returns (uint256) {
+        Node memory sibling;
+        if (merged) {
+            require(pc + 65 <= proof.length, "SMT: corrupted proof");
+            sibling = Node(
+                true,
+                readBytes32(proof, pc + 1),
+                readBytes32(proof, pc + 33),
+                uint8(proof[pc])
+            );
+            pc += 65;
+        } else {
+            require(pc + 32 <= proof.length, "SMT: corrupted proof");
+            sibling = Node(false, readBytes32(proof, pc), 0, 0);
+            pc += 32;
+        }
+        require(item.height <= 255, "SMT: corrupted proof");
+        bytes32 parentKey = parentPath(item.key, item.height);
+        if (getBit(item.key, item.height)) {
+            item.node = merge(item.height, parentKey, sibling, item.node);
+        } else {
+            item.node = merge(item.height, parentKey, item.node, sibling);
+        }
+        item.height += 1;
+        item.key = parentKey;
+        return pc;
+    }
+
+    // merge `b` into `a`
+    function mergePair(Item memory a, Item memory b) private pure {
+        require(a.height == b.height, "SMT: corrupted proof");
+        require(a.height <= 255, "SMT: corrupted proof");
+        bytes32 parentKey = parentPath(a.key, a.height);
+        require(parentKey == parentPath(b.key, b.height), "SMT: corrupted proof");
+        if (getBit(a.key, a.height)) {
+            a.node = merge(a.height, parentKey, b.node, a.node);
+        } else {
+            a.node = merge(a.height, parentKey, a.node, b.node);
+        }
+        a.height += 1;
+        a.key = parentKey;
+    }
+
+    // `item` is updated in place, return the new program counter
+    function mergeZeros(
+        Item memory item,
+        bytes memory proof,
+        uint256 pc
+    ) private pure returns (uint256) {
+        require(pc < proof.length, "SMT: corrupted proof");
+        uint256 n = uint8(proof[pc]);
+        if (n == 0) {
+            n = 256;
+        }
+        uint256 top = item.height + n;
+        require(top <= 256, "SMT: corrupted proof");
+        bytes32 key = item.key;
+        Node memory zero;
+        for (uint256 height = item.height; height < top; height++) {
+            item.key = parentPath(key, height);
+            if (getBit(key, height)) {
+                item.node = merge(height, item.key, zero, item.node);
+            } else {
+                item.node = merge(height, item.key, item.node, zero);
+            }
+        }
+        item.height = top;
+        return pc + 1;
+    }
+
+    function hashNode(Node memory node) private pure returns (bytes32) {
+        if (!node.merged) {
+            return node.value;
+        }
+        return keccak256(
+            abi.encodePacked(uint8(2), node.value, node.zeroBits, node.zeroCount)
+        );
+    }
+
+    function isZero(Node memory node) private pure returns (bool) {
+        return !node.merged && node.value == bytes32(0);
+    }
+
+    function merge(
+        uint256 height,
+        bytes32 nodeKey,
+        Node memory lhs,
+        Node memory rhs
+    ) private pure returns (Node memory) {
+        bool lhsZero = isZero(lhs);
+        bool rhsZero = isZero(rhs);
+        if (lhsZero && rhsZero) {
+            return Node(false, 0, 0, 0);
+        }
+        if (lhsZero) {
+            return mergeWithZero(height, nodeKey, rhs, true);
+        }
+        if (rhsZero) {
+            return mergeWithZero(height, nodeKey, lhs, false);
+        }
+        bytes32 value = keccak256(
+            abi.encodePacked(uint8(1), uint8(height), nodeKey, hashNode(lhs), hashNode(rhs))
+        );
+        return Node(false, value, 0, 0);
+    }
+
+    function mergeWithZero(
+        uint256 height,
+        bytes32 nodeKey,
+        Node memory node,
+        bool setBit
+    ) private pure returns (Node memory) {
+        if (!node.merged) {
+            bytes32 baseNode = keccak256(abi.encodePacked(uint8(height), nodeKey, node.value));
+            bytes32 bits = setBit ? withBit(bytes32(0), height) : bytes32(0);
+            return Node(true, baseNode, bits, 1);
+        }
+        bytes32 zeroBits = setBit ? withBit(node.zeroBits, height) : node.zeroBits;
+        unchecked {
+            return Node(true, node.value, zeroBits, node.zeroCount + 1);
+        }
+    }
+
+    // bit `i` of a `H256` is bit `i % 8` of its byte `i / 8`
+    function bitShift(uint256 i) private pure returns (uint256) {
+        return (31 - i / 8) * 8 + (i % 8);
+    }
+
+    function getBit(bytes32 x, uint256 i) private pure returns (bool) {
+        return (uint256(x) >> bitShift(i)) & 1 == 1;
+    }
+
+    function withBit(bytes32 x, uint256 i) private pure returns (bytes32) {
+        return bytes32(uint256(x) | (1 << bitShift(i)));
+    }
+
+    // keep the bits from `height + 1` up
+    function parentPath(bytes32 key, uint256 height) private pure returns (bytes32) {
+        if (height == 255) {
+            return bytes32(0);
+        }
+        uint256 start = height + 1;
+        uint256 startByte = start / 8;
+        uint256 v = uint256(key);
+        if (startByte > 0) {
+            v &= type(uint256).max >> (startByte * 8);
+        }
+        uint256 remain = start % 8;
+        if (remain > 0) {
+            v &= ~((uint256(0xff) >> (8 - remain)) << ((31 - startByte) * 8));
+        }
+        return bytes32(v);
+    }
+
+    function lessThan(bytes32 a, bytes32 b) private pure returns (bool) {
+        for (uint256 i = 32; i > 0; i--) {
+            if (a[i - 1] != b[i - 1]) {
+                return uint8(a[i - 1]) < uint8(b[i - 1]);
+            }
+        }
+        return false;
+    }
+
+    function readBytes32(bytes memory data, uint256 offset)
+        private
+        pure
+        returns (bytes32 result)
+    {
+        assembly {
+            result := mload(add(add(data, 32), offset))
+        }
+    }
+}
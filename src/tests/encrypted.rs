@@ -0,0 +1,152 @@
+use crate::{
+    blake3_hasher::Blake3Hasher,
+    encrypted::{EncryptedStore, Sealed},
+    error::{Error, StoreTarget},
+    traits::Store,
+    DefaultStore, SparseMerkleTree, VsSmt, H256,
+};
+use vsdb::{VersionName, VsMgmt};
+
+type EncryptedSmt =
+    SparseMerkleTree<Blake3Hasher, H256, EncryptedStore<DefaultStore<Sealed>>>;
+
+const KEY0: [u8; 32] = [7u8; 32];
+const KEY1: [u8; 32] = [9u8; 32];
+
+fn encrypted_tree(seal_branches: bool) -> EncryptedSmt {
+    let store = EncryptedStore::new(DefaultStore::default(), 0, &KEY0, seal_branches);
+    SparseMerkleTree::new(store.unwrap())
+}
+
+fn leaves(range: std::ops::Range<u8>) -> Vec<(H256, H256)> {
+    range.map(|i| ([i; 32].into(), [i + 100; 32].into())).collect()
+}
+
+fn sorted_leaves(tree: &EncryptedSmt) -> Vec<(H256, H256)> {
+    let mut values: Vec<(H256, H256)> = tree.store().iter_leaves().collect();
+    values.sort_unstable();
+    values
+}
+
+#[test]
+fn test_encrypted_store() {
+    let mut plain = VsSmt::<H256>::default();
+    let mut tree = encrypted_tree(false);
+    plain.update_all(leaves(1..20)).unwrap();
+    tree.update_all(leaves(1..20)).unwrap();
+    assert_eq!(tree.root(), plain.root());
+    assert_eq!(tree.len().unwrap(), 19);
+
+    let key: H256 = [5u8; 32].into();
+    assert_eq!(tree.get(&key).unwrap(), Some([105u8; 32].into()));
+    let proof = tree.merkle_proof(vec![key]).unwrap();
+    assert!(proof
+        .verify::<Blake3Hasher>(tree.root(), vec![(key, Some([105u8; 32].into()))])
+        .unwrap());
+
+    // the inner store only holds ciphertexts
+    let inner = tree.store().inner();
+    let sealed = inner.get_leaf(&key).unwrap().unwrap();
+    assert_eq!(sealed.key_id(), 0);
+    let bytes = sealed.to_bytes();
+    assert!(!bytes.windows(32).any(|w| w == [105u8; 32]));
+    assert_eq!(Sealed::from_bytes(&bytes).unwrap(), sealed);
+    assert!(inner.get_leaf_hash(&key).unwrap().is_none());
+    assert_eq!(sorted_leaves(&tree), leaves(1..20));
+
+    // a sealed value is bound to its key, and opened by the key it is sealed with
+    let mut inner = tree.store.into_inner();
+    let other: H256 = [6u8; 32].into();
+    inner.insert_leaf(other, sealed).unwrap();
+    let store = EncryptedStore::new(inner, 0, &KEY0, false).unwrap();
+    let err = Store::<H256>::get_leaf(&store, &other).unwrap_err();
+    assert!(
+        matches!(err, Error::Store { target: StoreTarget::Leaf(k), .. } if k == other)
+    );
+    assert!(Store::<H256>::get_leaf(&store, &key).is_ok());
+    // iterators skip the leaves failing to open
+    let opened: Vec<(H256, H256)> = Store::<H256>::iter_leaves(&store).collect();
+    assert_eq!(opened.len(), 18);
+    assert!(opened.iter().all(|(k, _)| *k != other));
+    let store = EncryptedStore::new(store.into_inner(), 0, &KEY1, false).unwrap();
+    assert!(Store::<H256>::get_leaf(&store, &key).is_err());
+}
+
+#[test]
+fn test_encrypted_store_sealed_branches() {
+    let mut plain = VsSmt::<H256>::default();
+    let mut tree = encrypted_tree(true);
+    assert!(tree.store().seals_branches());
+    plain.update_all(leaves(1..20)).unwrap();
+    tree.update_all(leaves(1..20)).unwrap();
+    assert_eq!(tree.root(), plain.root());
+
+    // the inner store holds no branch, so no leaf hash, which is the value for H256
+    assert_eq!(tree.store().inner().iter_branches().count(), 0);
+    let mut branches: Vec<_> = Store::<H256>::iter_branches(tree.store()).collect();
+    let mut expected: Vec<_> = plain.store().iter_branches().collect();
+    branches.sort_unstable_by(|a, b| a.0.cmp(&b.0));
+    expected.sort_unstable_by(|a, b| a.0.cmp(&b.0));
+    assert_eq!(branches, expected);
+
+    let key: H256 = [5u8; 32].into();
+    let proof = tree.merkle_proof(vec![key]).unwrap();
+    assert!(proof
+        .verify::<Blake3Hasher>(tree.root(), vec![(key, Some([105u8; 32].into()))])
+        .unwrap());
+    tree.remove(key).unwrap();
+    plain.remove(key).unwrap();
+    assert_eq!(tree.root(), plain.root());
+}
+
+#[test]
+fn test_encrypted_store_rotation() {
+    let mut tree = encrypted_tree(true);
+    tree.update_all(leaves(1..10)).unwrap();
+    let root = tree.root();
+
+    tree.store_mut().rotate(1, &KEY1);
+    assert_eq!(tree.store().current_key_id(), 1);
+    tree.update([10u8; 32].into(), [110u8; 32].into()).unwrap();
+    let key_id = |tree: &EncryptedSmt, i: u8| {
+        let sealed = tree.store().inner().get_leaf(&[i; 32].into()).unwrap();
+        sealed.unwrap().key_id()
+    };
+    assert_eq!(key_id(&tree, 1), 0);
+    assert_eq!(key_id(&tree, 10), 1);
+    assert_eq!(tree.get(&[1u8; 32].into()).unwrap(), Some([101u8; 32].into()));
+
+    assert!(tree.store_mut().reseal().unwrap() > 9);
+    assert_eq!(key_id(&tree, 1), 1);
+    assert_eq!(tree.store_mut().reseal().unwrap(), 0);
+
+    assert!(matches!(tree.store_mut().remove_key(1), Err(Error::Encryption(_))));
+    tree.store_mut().remove_key(0).unwrap();
+    assert!(matches!(tree.store_mut().remove_key(0), Err(Error::Encryption(_))));
+    assert_eq!(sorted_leaves(&tree), leaves(1..11));
+    assert_ne!(tree.root(), root);
+    tree.remove([10u8; 32].into()).unwrap();
+    assert_eq!(tree.root(), root);
+}
+
+#[test]
+fn test_encrypted_store_key_in_use() {
+    let mut tree = encrypted_tree(false);
+    tree.update_all(leaves(1..10)).unwrap();
+    tree.version_create(VersionName(b"a")).unwrap();
+    tree.store_mut().rotate(1, &KEY1);
+    assert_eq!(tree.store_mut().reseal().unwrap(), 9);
+
+    // the first version still holds values sealed with key 0
+    assert!(matches!(tree.store_mut().remove_key(0), Err(Error::Encryption(_))));
+    let br = tree.store().branch_get_default();
+    let old: Vec<(H256, H256)> = tree
+        .store()
+        .iter_leaves_by_branch_version(br.as_deref(), VersionName(b""))
+        .collect();
+    assert_eq!(old.len(), 9);
+
+    tree.prune_versions_before(VersionName(b"a")).unwrap();
+    tree.store_mut().remove_key(0).unwrap();
+    assert_eq!(sorted_leaves(&tree), leaves(1..10));
+}
@@ -0,0 +1,93 @@
+use crate::{
+    error::Error,
+    ledger::{verify_accounts, Account, Ledger, Transfer},
+    H256,
+};
+
+fn addr(i: u8) -> H256 {
+    [i; 32].into()
+}
+
+fn transfer(from: u8, to: u8, amount: u128, nonce: u64) -> Transfer {
+    Transfer {
+        from: addr(from),
+        to: addr(to),
+        amount,
+        nonce,
+    }
+}
+
+#[test]
+fn test_commit_block() {
+    let mut ledger = Ledger::genesis(vec![(addr(1), 100)]).unwrap();
+    let genesis_root = ledger.state_root();
+
+    let header = ledger
+        .commit_block(vec![transfer(1, 2, 30, 0), transfer(2, 3, 10, 0)])
+        .unwrap();
+    assert_eq!(header.height, 1);
+    assert_eq!(header.state_root, ledger.state_root());
+    assert_eq!(
+        ledger.account(&addr(1)).unwrap(),
+        Account {
+            nonce: 1,
+            balance: 70
+        }
+    );
+    assert_eq!(
+        ledger.account(&addr(2)).unwrap(),
+        Account {
+            nonce: 1,
+            balance: 20
+        }
+    );
+
+    // replayed nonce, the valid transfer before it is not applied either
+    assert!(matches!(
+        ledger.commit_block(vec![transfer(3, 1, 1, 0), transfer(1, 2, 1, 0)]),
+        Err(Error::Ledger(_))
+    ));
+    // insufficient balance
+    assert!(matches!(
+        ledger.commit_block(vec![transfer(3, 1, 11, 0)]),
+        Err(Error::Ledger(_))
+    ));
+    assert_eq!(ledger.height(), 1);
+    assert_eq!(ledger.state_root(), header.state_root);
+    assert_eq!(ledger.state_root_at(0).unwrap(), genesis_root);
+    assert_eq!(ledger.state_root_at(1).unwrap(), header.state_root);
+
+    let proof = ledger.prove_accounts_at(0, vec![addr(1), addr(2)]).unwrap();
+    assert!(verify_accounts(
+        genesis_root,
+        proof,
+        &[
+            (
+                addr(1),
+                Account {
+                    nonce: 0,
+                    balance: 100
+                }
+            ),
+            (addr(2), Account::default())
+        ]
+    )
+    .unwrap());
+}
+
+#[test]
+fn test_state_sync() {
+    let mut ledger = Ledger::genesis(vec![(addr(1), 100), (addr(2), 100)]).unwrap();
+    ledger.commit_block(vec![transfer(1, 3, 40, 0)]).unwrap();
+
+    let mut state = vec![];
+    assert_eq!(ledger.export_state(&mut state).unwrap(), 3);
+    let mut peer = Ledger::from_state(ledger.height(), &state[..]).unwrap();
+    assert_eq!(peer.state_root(), ledger.state_root());
+
+    let block = vec![transfer(3, 2, 5, 0)];
+    assert_eq!(
+        peer.commit_block(block.clone()).unwrap(),
+        ledger.commit_block(block).unwrap()
+    );
+}
@@ -0,0 +1,96 @@
+use crate::{error::Error, lock::KeyRange, RangeLocks, H256};
+use std::{
+    sync::{mpsc, Arc, Barrier},
+    thread,
+    time::Duration,
+};
+
+fn prefix(b: u8) -> H256 {
+    let mut buf = [0u8; 32];
+    buf[31] = b;
+    buf.into()
+}
+
+#[test]
+fn test_key_range_overlaps() {
+    let a = KeyRange::new(prefix(0b1000_0000), 1);
+    let b = KeyRange::new(prefix(0b1100_0000), 2);
+    let c = KeyRange::new(prefix(0b0100_0000), 2);
+    assert!(a.overlaps(&b));
+    assert!(b.overlaps(&a));
+    assert!(!a.overlaps(&c));
+    assert!(!b.overlaps(&c));
+    assert!(a.contains(&prefix(0b1011_1111)));
+    assert!(!a.contains(&prefix(0b0011_1111)));
+    assert!(KeyRange::new(H256::zero(), 0).overlaps(&c));
+}
+
+#[test]
+fn test_lock_disjoint_and_overlapping_ranges() {
+    let locks = Arc::new(RangeLocks::new());
+    let me = locks.owner();
+
+    let g1 = locks.lock_range(me, prefix(0b1000_0000), 1).unwrap();
+    let g2 = locks.lock_range(me, prefix(0b0000_0000), 1).unwrap();
+    let other = locks.owner();
+    assert!(locks.try_lock_range(other, prefix(0b1100_0000), 2).is_none());
+
+    let (tx, rx) = mpsc::channel();
+    let l = Arc::clone(&locks);
+    let h = thread::spawn(move || {
+        let _g = l.lock_range(other, prefix(0b1100_0000), 2).unwrap();
+        tx.send(()).unwrap();
+    });
+    assert!(rx.recv_timeout(Duration::from_millis(100)).is_err());
+    drop(g1);
+    rx.recv_timeout(Duration::from_secs(5)).unwrap();
+    h.join().unwrap();
+    drop(g2);
+}
+
+#[test]
+fn test_lock_deadlock_detection() {
+    let locks = Arc::new(RangeLocks::new());
+    let barrier = Arc::new(Barrier::new(2));
+    let (a, b) = (locks.owner(), locks.owner());
+
+    let l = Arc::clone(&locks);
+    let bar = Arc::clone(&barrier);
+    let h = thread::spawn(move || {
+        let _g = l.lock_range(b, prefix(0b0000_0000), 1).unwrap();
+        bar.wait();
+        // blocks until `a` gives up its range
+        let _g2 = l.lock_range(b, prefix(0b1000_0000), 1).unwrap();
+    });
+
+    let g = locks.lock_range(a, prefix(0b1000_0000), 1).unwrap();
+    barrier.wait();
+    thread::sleep(Duration::from_millis(100));
+    assert_eq!(
+        locks.lock_range(a, prefix(0b0000_0000), 1).unwrap_err(),
+        Error::Deadlock
+    );
+    drop(g);
+    h.join().unwrap();
+}
+
+#[test]
+fn test_lock_owner_is_not_the_thread() {
+    let locks = RangeLocks::new();
+    let (a, b) = (locks.owner(), locks.owner());
+    let g = locks.lock_range(a, prefix(0b1000_0000), 1).unwrap();
+
+    // another owner on the same thread must wait
+    assert!(locks.try_lock_range(b, prefix(0b1100_0000), 2).is_none());
+    // the owner waiting for itself is a deadlock
+    assert_eq!(
+        locks.lock_range(a, prefix(0b1100_0000), 2).unwrap_err(),
+        Error::Deadlock
+    );
+
+    // a guard can be released from another thread
+    thread::scope(|s| {
+        s.spawn(move || drop(g));
+    });
+    assert!(locks.try_lock_range(b, prefix(0b1100_0000), 2).is_some());
+}
@@ -1,2 +1,15 @@
+#[cfg(feature = "encryption")]
+mod encrypted;
+#[cfg(feature = "ledger")]
+mod ledger;
+mod lock;
+mod namespace;
+mod overlay;
+#[cfg(feature = "server")]
+mod server;
+mod shared;
+#[cfg(feature = "keccak")]
+mod solidity;
+mod stateless;
 mod tree;
 mod tree2;
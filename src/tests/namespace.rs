@@ -0,0 +1,125 @@
+use crate::{blake3_hasher::Blake3Hasher, traits::Key, NamespacedKey, VsSmt, H256};
+
+#[allow(clippy::upper_case_acronyms)]
+type SMT = VsSmt<H256>;
+
+fn nk(namespace: u32, n: u8) -> NamespacedKey {
+    NamespacedKey::hashed::<Blake3Hasher>(namespace, &[n])
+}
+
+#[test]
+fn test_namespaced_key_path() {
+    let key = nk(7, 1);
+    let path = key.to_path();
+    assert_eq!(NamespacedKey::from_path(&path), key);
+    assert_eq!(&path.as_slice()[28..], &7u32.to_le_bytes());
+    assert_eq!(&path.as_slice()[..28], &key.key.as_slice()[..28]);
+
+    // the highest bits of the key are dropped
+    let full: H256 = [0xff; 32].into();
+    assert_eq!(NamespacedKey::new(7, full).to_path(), {
+        let mut bytes = [0xff; 32];
+        bytes[28..].copy_from_slice(&7u32.to_le_bytes());
+        H256::from(bytes)
+    });
+
+    // paths sort by namespace first, whatever the keys
+    let mut keys: Vec<NamespacedKey> = [3, 1, 0x100, 2]
+        .iter()
+        .flat_map(|ns| (0u8..4).map(move |n| nk(*ns, n)))
+        .collect();
+    keys.sort_unstable_by_key(|k| k.to_path());
+    let namespaces: Vec<u32> = keys.iter().map(|k| k.namespace).collect();
+    assert!(namespaces.windows(2).all(|w| w[0] <= w[1]));
+
+    assert!(NamespacedKey::range(3).contains(&nk(3, 9).to_path()));
+    assert!(!NamespacedKey::range(3).contains(&nk(4, 9).to_path()));
+    assert!(!NamespacedKey::range(3).contains(&nk(0x103, 9).to_path()));
+
+    // the keys of a range are the ones between its bounds
+    let (first, last) = NamespacedKey::range(3).bounds();
+    assert_eq!(first, NamespacedKey::new(3, H256::zero()).to_path());
+    assert_eq!(last, NamespacedKey::new(3, [0xff; 32].into()).to_path());
+}
+
+#[test]
+fn test_iter_leaves_range() {
+    use crate::traits::Store;
+    use rand::Rng;
+    use std::ops::Bound;
+
+    let mut tree = SMT::default();
+    let leaves: Vec<(H256, H256)> = (1u8..20)
+        .rev()
+        .map(|i| (H256::from_u64(i as u64), [i; 32].into()))
+        .collect();
+    tree.update_all(leaves).unwrap();
+
+    let keys = |range: (Bound<H256>, Bound<H256>)| -> Vec<u8> {
+        tree.store()
+            .iter_leaves_range(range)
+            .map(|(k, _)| k.as_slice()[0])
+            .collect()
+    };
+    let (lo, hi) = (H256::from_u64(5), H256::from_u64(9));
+    assert_eq!(keys((Bound::Included(lo), Bound::Excluded(hi))), vec![5, 6, 7, 8]);
+    assert_eq!(keys((Bound::Excluded(lo), Bound::Included(hi))), vec![6, 7, 8, 9]);
+    assert_eq!(keys((Bound::Unbounded, Bound::Unbounded)), (1..20).collect::<Vec<_>>());
+
+    // the order of the index is the one of `Ord`, also before it is complete
+    let mut rng = rand::thread_rng();
+    let leaves: Vec<(H256, H256)> = (0..50)
+        .map(|_| (rng.gen::<[u8; 32]>().into(), [1u8; 32].into()))
+        .collect();
+    tree.update_all(leaves).unwrap();
+    let mut sorted: Vec<H256> = tree.store().iter_leaves().map(|(k, _)| k).collect();
+    sorted.sort_unstable();
+    let range = (Bound::Excluded(sorted[10]), Bound::Included(sorted[40]));
+    let ranged = |tree: &SMT| -> Vec<H256> {
+        tree.store().iter_leaves_range(range).map(|(k, _)| k).collect()
+    };
+    assert_eq!(ranged(&tree), sorted[11..=40]);
+    tree.store_mut().reset_leaves_count();
+    assert_eq!(ranged(&tree), sorted[11..=40]);
+}
+
+#[test]
+fn test_namespace_leaves_and_proofs() {
+    let mut tree = SMT::default();
+    let leaves: Vec<(H256, H256)> = [1u32, 3, 4]
+        .iter()
+        .flat_map(|ns| (0u8..5).map(move |n| (nk(*ns, n).to_path(), [n + 1; 32].into())))
+        .collect();
+    tree.update_all(leaves.clone()).unwrap();
+    let root = tree.root();
+
+    let found = tree.leaves_in(&NamespacedKey::range(3)).unwrap();
+    let mut expected: Vec<(H256, H256)> = leaves
+        .iter()
+        .filter(|(k, _)| NamespacedKey::from_path(k).namespace == 3)
+        .copied()
+        .collect();
+    expected.sort_unstable();
+    assert_eq!(found.len(), 5);
+    assert_eq!(found, expected);
+    assert!(tree.leaves_in(&NamespacedKey::range(2)).unwrap().is_empty());
+    tree.set_verify_reads(true);
+    assert_eq!(tree.leaves_in(&NamespacedKey::range(3)).unwrap(), expected);
+
+    // namespaced paths are proved like any other
+    let key = nk(4, 2).to_path();
+    let proof = tree.merkle_proof(vec![key]).unwrap();
+    assert!(proof
+        .verify::<Blake3Hasher>(root, vec![(key, Some([3u8; 32].into()))])
+        .unwrap());
+
+    // namespace 2 is empty, between the last path of 1 and the first of 3
+    let start = NamespacedKey::new(1, [0xff; 32].into()).to_path();
+    let end = NamespacedKey::new(3, H256::zero()).to_path();
+    let proof = tree.prove_empty_range(&start, &end).unwrap();
+    assert!(proof
+        .verify_empty_range::<Blake3Hasher>(root, &start, &end)
+        .unwrap());
+    let end = NamespacedKey::new(4, H256::zero()).to_path();
+    assert!(tree.prove_empty_range(&start, &end).is_err());
+}
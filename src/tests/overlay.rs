@@ -0,0 +1,235 @@
+use crate::{
+    blake3_hasher::Blake3Hasher,
+    error::{Error, StoreOp, StoreTarget},
+    traits::Store,
+    tree::{BranchKey, BranchNode},
+    DefaultStore, OverlayStore, VsSmt, H256,
+};
+use std::{cell::Cell, ops::Bound, rc::Rc};
+
+#[allow(clippy::upper_case_acronyms)]
+type SMT = VsSmt<H256>;
+
+fn leaves(range: std::ops::Range<u8>) -> Vec<(H256, H256)> {
+    range.map(|i| ([i; 32].into(), [i; 32].into())).collect()
+}
+
+#[test]
+fn test_overlay_discard() {
+    let mut tree = SMT::default();
+    tree.update_all(leaves(1..10)).unwrap();
+    let root = tree.root();
+    let history = tree.root_history(..);
+
+    let mut overlay = tree.into_overlay();
+    overlay.update_all(leaves(5..20)).unwrap();
+    let (_, removed) = overlay.remove([1u8; 32].into()).unwrap();
+    assert_eq!(removed, Some([1u8; 32].into()));
+    assert_ne!(overlay.root(), root);
+    assert_eq!(
+        overlay.get(&[15u8; 32].into()).unwrap(),
+        Some([15u8; 32].into())
+    );
+    assert_eq!(overlay.root_history(..).len(), history.len() + 2);
+
+    let tree = overlay.discard_overlay();
+    assert_eq!(tree.root(), root);
+    assert_eq!(tree.root_history(..), history);
+    assert_eq!(tree.get(&[1u8; 32].into()).unwrap(), Some([1u8; 32].into()));
+    assert_eq!(tree.get(&[15u8; 32].into()).unwrap(), None);
+    assert!(tree.verify_integrity().unwrap().is_ok());
+}
+
+#[test]
+fn test_overlay_commit() {
+    let mut tree = SMT::default();
+    tree.update_all(leaves(1..10)).unwrap();
+
+    let mut overlay = tree.into_overlay();
+    overlay.update_all(leaves(5..20)).unwrap();
+    overlay.remove([1u8; 32].into()).unwrap();
+    let root = overlay.root();
+    let history = overlay.root_history(..);
+    let proof = overlay.merkle_proof(vec![[1u8; 32].into()]).unwrap();
+
+    let tree = overlay.commit_overlay().unwrap();
+    assert_eq!(tree.root(), root);
+    assert_eq!(tree.root_history(..), history);
+    assert!(tree.verify_integrity().unwrap().is_ok());
+    assert_eq!(proof, tree.merkle_proof(vec![[1u8; 32].into()]).unwrap());
+
+    // the same tree written directly
+    let mut expected = SMT::default();
+    expected.update_all(leaves(2..20)).unwrap();
+    assert_eq!(tree.root(), expected.root());
+}
+
+#[test]
+fn test_overlay_store() {
+    let mut base = crate::DefaultStore::<H256>::default();
+    base.insert_leaf([1u8; 32].into(), [1u8; 32].into())
+        .unwrap();
+    let mut store = OverlayStore::new(base);
+    assert!(!store.is_dirty());
+
+    store.remove_leaf(&[1u8; 32].into()).unwrap();
+    store
+        .insert_leaf([2u8; 32].into(), [2u8; 32].into())
+        .unwrap();
+    assert!(store.is_dirty());
+    assert!(!store.has_leaf(&[1u8; 32].into()).unwrap());
+    assert_eq!(
+        store.iter_leaves().collect::<Vec<_>>(),
+        vec![([2u8; 32].into(), [2u8; 32].into())]
+    );
+    assert!(store.base().has_leaf(&[1u8; 32].into()).unwrap());
+
+    store.discard();
+    assert!(!store.is_dirty());
+    assert_eq!(
+        store.get_leaf(&[1u8; 32].into()).unwrap(),
+        Some([1u8; 32].into())
+    );
+    assert_eq!(store.get_leaf(&[2u8; 32].into()).unwrap(), None);
+
+    // an overlay tree proves the same as the base tree
+    let mut tree = SMT::default();
+    tree.update_all(leaves(1..10)).unwrap();
+    let root = tree.root();
+    let overlay = tree.into_overlay();
+    let proof = overlay.merkle_proof(vec![[3u8; 32].into()]).unwrap();
+    assert!(proof
+        .verify::<Blake3Hasher>(root, vec![([3u8; 32].into(), Some([3u8; 32].into()))])
+        .unwrap());
+}
+
+#[test]
+fn test_overlay_leaves_range() {
+    let mut tree = SMT::default();
+    tree.update_all(leaves(1..10)).unwrap();
+    let mut overlay = tree.into_overlay();
+    overlay.update_all(leaves(8..12)).unwrap();
+    overlay.remove([3u8; 32].into()).unwrap();
+    overlay.update([5u8; 32].into(), [55u8; 32].into()).unwrap();
+
+    let range = (
+        Bound::Included([2u8; 32].into()),
+        Bound::Excluded([10u8; 32].into()),
+    );
+    let mut expected = leaves(2..10);
+    expected.retain(|(k, _)| *k != [3u8; 32].into());
+    expected[2].1 = [55u8; 32].into();
+    let staged: Vec<_> = overlay.store().iter_leaves_range(range).collect();
+    assert_eq!(staged, expected);
+
+    let tree = overlay.commit_overlay().unwrap();
+    let committed: Vec<_> = tree.store().iter_leaves_range(range).collect();
+    assert_eq!(committed, expected);
+}
+
+// A `DefaultStore` whose `push_root_history` fails once `pushes` runs out
+struct Flaky {
+    store: DefaultStore<H256>,
+    pushes: Rc<Cell<u32>>,
+}
+
+impl vsdb::VsMgmt for Flaky {
+    vsdb::impl_vs_methods_nope!();
+}
+
+impl Store<H256> for Flaky {
+    fn insert_branch(&mut self, key: BranchKey, branch: BranchNode) -> Result<(), Error> {
+        self.store.insert_branch(key, branch)
+    }
+    fn remove_branch(&mut self, key: &BranchKey) -> Result<(), Error> {
+        self.store.remove_branch(key)
+    }
+    fn get_branch(&self, key: &BranchKey) -> Result<Option<BranchNode>, Error> {
+        self.store.get_branch(key)
+    }
+    fn insert_leaf(&mut self, key: H256, leaf: H256) -> Result<(), Error> {
+        self.store.insert_leaf(key, leaf)
+    }
+    fn remove_leaf(&mut self, key: &H256) -> Result<(), Error> {
+        self.store.remove_leaf(key)
+    }
+    fn get_leaf(&self, key: &H256) -> Result<Option<H256>, Error> {
+        self.store.get_leaf(key)
+    }
+    fn get_leaf_by_branch(
+        &self,
+        key: &H256,
+        br: vsdb::BranchName,
+    ) -> Result<Option<H256>, Error> {
+        self.store.get_leaf_by_branch(key, br)
+    }
+    fn get_leaf_by_branch_version(
+        &self,
+        key: &H256,
+        br: vsdb::BranchName,
+        ver: vsdb::VersionName,
+    ) -> Result<Option<H256>, Error> {
+        self.store.get_leaf_by_branch_version(key, br, ver)
+    }
+    fn update_root(&mut self, root: H256) -> Result<(), Error> {
+        self.store.update_root(root)
+    }
+    fn get_root(&self) -> Result<H256, Error> {
+        self.store.get_root()
+    }
+    fn leaves_count(&self) -> Result<u64, Error> {
+        self.store.leaves_count()
+    }
+    fn add_leaves_count(&mut self, delta: i64) -> Result<(), Error> {
+        self.store.add_leaves_count(delta)
+    }
+    fn push_root_history(&mut self, root: H256) -> Result<u64, Error> {
+        match self.pushes.get() {
+            0 => Err(Error::store(StoreOp::Insert, StoreTarget::Root, "full")),
+            n => {
+                self.pushes.set(n - 1);
+                self.store.push_root_history(root)
+            }
+        }
+    }
+    fn iter_root_history(
+        &self,
+        range: (Bound<u64>, Bound<u64>),
+    ) -> Box<dyn Iterator<Item = (u64, H256)> + '_> {
+        self.store.iter_root_history(range)
+    }
+}
+
+#[test]
+fn test_overlay_commit_retry() {
+    let pushes = Rc::new(Cell::new(1));
+    let mut store = OverlayStore::new(Flaky {
+        store: DefaultStore::default(),
+        pushes: pushes.clone(),
+    });
+    let (k1, k2): (H256, H256) = ([1u8; 32].into(), [2u8; 32].into());
+    store.insert_leaf(k1, k1).unwrap();
+    store.insert_leaf(k2, k2).unwrap();
+    store.add_leaves_count(2).unwrap();
+    store.update_root(k2).unwrap();
+    store.push_root_history(k1).unwrap();
+    store.push_root_history(k2).unwrap();
+
+    // the second root is not recorded, nothing is unstaged
+    assert!(store.commit().is_err());
+    assert!(store.is_dirty());
+    assert_eq!(store.get_leaf(&k2).unwrap(), Some(k2));
+    assert_eq!(store.leaves_count().unwrap(), 2);
+    let all = (Bound::Unbounded, Bound::Unbounded);
+    let history = vec![(0, k1), (1, k2)];
+    assert_eq!(store.iter_root_history(all).collect::<Vec<_>>(), history);
+
+    // a retry writes the rest once
+    pushes.set(u32::MAX);
+    store.commit().unwrap();
+    assert!(!store.is_dirty());
+    assert_eq!(store.base().leaves_count().unwrap(), 2);
+    assert_eq!(store.base().get_root().unwrap(), k2);
+    let committed: Vec<_> = store.base().iter_root_history(all).collect();
+    assert_eq!(committed, history);
+}
@@ -0,0 +1,140 @@
+use crate::{
+    blake3_hasher::Blake3Hasher,
+    server::{
+        self, INVALID_PARAMS, MAX_HEADER_SIZE, METHOD_NOT_FOUND, PARSE_ERROR, TREE_ERROR,
+    },
+    SharedSmt, VsSmt, H256,
+};
+use serde_json::{json, Value as Json};
+use std::{
+    io::{ErrorKind, Read, Write},
+    net::{TcpListener, TcpStream},
+    thread,
+    time::{Duration, Instant},
+};
+
+fn call(tree: &SharedSmt<Blake3Hasher, H256, crate::DefaultStore<H256>>, req: Json) -> Json {
+    serde_json::from_str(&server::handle(tree, &req.to_string())).unwrap()
+}
+
+#[test]
+fn test_server_methods() {
+    let tree = SharedSmt::new(VsSmt::<H256>::default());
+    let leaves: Vec<(H256, H256)> = (1u8..10)
+        .map(|i| ([i; 32].into(), [i; 32].into()))
+        .collect();
+    let root = tree.update_all(leaves).unwrap();
+
+    let res = call(&tree, json!({"jsonrpc": "2.0", "id": 1, "method": "root"}));
+    assert_eq!(res["id"], json!(1));
+    assert_eq!(res["result"], json!(root));
+
+    let key = H256::from([3u8; 32]);
+    let res = call(&tree, json!({"id": 2, "method": "get", "params": [key]}));
+    assert_eq!(res["result"], json!(key));
+    let absent = H256::from([20u8; 32]);
+    let res = call(&tree, json!({"id": 3, "method": "get", "params": [absent]}));
+    assert_eq!(res["result"], Json::Null);
+
+    // the result of `prove` is checked by `verify` as it is
+    let res = call(
+        &tree,
+        json!({"id": 4, "method": "prove", "params": [[absent, key]]}),
+    );
+    let proved = &res["result"];
+    assert_eq!(proved["root"], json!(root));
+    assert_eq!(proved["leaves"], json!([[key, key], [absent, Json::Null]]));
+    let params = json!([proved["root"], proved["proof"], proved["leaves"]]);
+    let res = call(&tree, json!({"id": 5, "method": "verify", "params": params}));
+    assert_eq!(res["result"], json!(true));
+    let params = json!([proved["root"], proved["proof"], [[key, key], [absent, key]]]);
+    let res = call(&tree, json!({"id": 6, "method": "verify", "params": params}));
+    assert_eq!(res["result"], json!(false));
+
+    let code = |res: Json| res["error"]["code"].as_i64().unwrap();
+    assert_eq!(code(call(&tree, json!({"id": 7, "method": "put"}))), METHOD_NOT_FOUND);
+    assert_eq!(
+        code(call(&tree, json!({"id": 8, "method": "get", "params": ["0x12"]}))),
+        INVALID_PARAMS
+    );
+    assert_eq!(
+        code(call(&tree, json!({"id": 9, "method": "prove", "params": [[key, key]]}))),
+        TREE_ERROR
+    );
+    let res: Json = serde_json::from_str(&server::handle(&tree, "{")).unwrap();
+    assert_eq!(code(res), PARSE_ERROR);
+}
+
+#[test]
+fn test_server_http() {
+    let tree = SharedSmt::new(VsSmt::<H256>::default());
+    let root = tree.update([1u8; 32].into(), [1u8; 32].into()).unwrap();
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let handle = {
+        let tree = tree.clone();
+        thread::spawn(move || {
+            for _ in 0..3 {
+                let (stream, _) = listener.accept().unwrap();
+                server::serve_connection(stream, &tree).unwrap();
+            }
+        })
+    };
+
+    let body = json!({"jsonrpc": "2.0", "id": 1, "method": "root"}).to_string();
+    let mut stream = TcpStream::connect(addr).unwrap();
+    write!(
+        stream,
+        "POST / HTTP/1.1\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+        body.len(),
+        body
+    )
+    .unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+    let (_, body) = response.split_once("\r\n\r\n").unwrap();
+    let res: Json = serde_json::from_str(body).unwrap();
+    assert_eq!(res["result"], json!(root));
+
+    let mut stream = TcpStream::connect(addr).unwrap();
+    write!(stream, "GET / HTTP/1.1\r\n\r\n").unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    assert!(response.starts_with("HTTP/1.1 405 "));
+
+    // a request line that never ends
+    let mut stream = TcpStream::connect(addr).unwrap();
+    let line = vec![b'a'; MAX_HEADER_SIZE as usize];
+    stream.write_all(&line).unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    assert!(response.starts_with("HTTP/1.1 431 "));
+
+    handle.join().unwrap();
+}
+
+#[test]
+fn test_server_deadline() {
+    let tree = SharedSmt::new(VsSmt::<H256>::default());
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let handle = thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        let deadline = Instant::now() + Duration::from_millis(300);
+        server::serve_connection_until(stream, &tree, deadline)
+    });
+
+    // a header trickled byte by byte, each read is quick but the request never ends
+    let mut stream = TcpStream::connect(addr).unwrap();
+    stream.write_all(b"POST / HTTP/1.1\r\nX-Slow: ").unwrap();
+    for _ in 0..20 {
+        thread::sleep(Duration::from_millis(50));
+        if stream.write_all(b"a").is_err() {
+            break;
+        }
+    }
+    let err = handle.join().unwrap().unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::TimedOut);
+}
@@ -0,0 +1,165 @@
+use crate::{
+    blake3_hasher::Blake3Hasher, error::Error, FrozenSmt, SharedSmt, VsSmt, VsSmt2, H256,
+};
+use std::{
+    sync::{Arc, Barrier},
+    thread,
+};
+use vsdb::VersionName;
+
+fn assert_send_sync<T: Send + Sync>() {}
+
+#[test]
+fn test_tree_is_send_sync() {
+    assert_send_sync::<VsSmt<H256>>();
+    assert_send_sync::<VsSmt2<[u8; 16], H256>>();
+    assert_send_sync::<SharedSmt<Blake3Hasher, H256, crate::DefaultStore<H256>>>();
+    assert_send_sync::<FrozenSmt<Blake3Hasher, H256, crate::DefaultStore<H256>>>();
+}
+
+#[test]
+fn test_shared_reads_during_writes() {
+    let shared = SharedSmt::new(VsSmt::<H256>::default());
+    let k0: H256 = [0u8; 32].into();
+    shared.update(k0, [1u8; 32].into()).unwrap();
+
+    let n = 50u8;
+    let barrier = Arc::new(Barrier::new(4));
+    let readers = (0..3)
+        .map(|_| {
+            let shared = shared.clone();
+            let barrier = Arc::clone(&barrier);
+            thread::spawn(move || {
+                barrier.wait();
+                for _ in 0..n {
+                    // every read sees a consistent tree
+                    let tree = shared.read();
+                    let proof = tree.merkle_proof(vec![k0]).unwrap();
+                    assert!(proof
+                        .verify::<Blake3Hasher>(
+                            tree.root(),
+                            vec![(k0, Some([1u8; 32].into()))]
+                        )
+                        .unwrap());
+                }
+            })
+        })
+        .collect::<Vec<_>>();
+
+    barrier.wait();
+    for i in 1..=n {
+        shared.update([i; 32].into(), [i; 32].into()).unwrap();
+    }
+    for r in readers {
+        r.join().unwrap();
+    }
+
+    assert_eq!(shared.get(&[n; 32].into()).unwrap(), Some([n; 32].into()));
+    let root = shared.root();
+    assert_eq!(shared.into_inner().unwrap().root(), root);
+}
+
+#[test]
+fn test_shared_poisoned() {
+    let shared = SharedSmt::new(VsSmt::<H256>::default());
+    let root = shared.update([1u8; 32].into(), [1u8; 32].into()).unwrap();
+
+    // a writer panics while holding the tree
+    let writer = shared.clone();
+    let panicked = thread::spawn(move || {
+        let _tree = writer.write();
+        panic!("writer panicked");
+    })
+    .join();
+    assert!(panicked.is_err());
+
+    // the other handles keep working
+    assert_eq!(shared.root(), root);
+    assert_ne!(shared.update([2u8; 32].into(), [2u8; 32].into()).unwrap(), root);
+    assert_eq!(shared.get(&[2u8; 32].into()).unwrap(), Some([2u8; 32].into()));
+    assert!(shared.into_inner().is_some());
+}
+
+#[test]
+fn test_shared_write_range() {
+    let shared = SharedSmt::new(VsSmt::<H256>::default());
+    let low = |i: u8| -> H256 { [i; 32].into() };
+    let high = |i: u8| -> H256 { [i | 0x80; 32].into() };
+
+    let n = 30u8;
+    let barrier = Arc::new(Barrier::new(2));
+    let writers = [low, high]
+        .into_iter()
+        .map(|key| {
+            let shared = shared.clone();
+            let barrier = Arc::clone(&barrier);
+            thread::spawn(move || {
+                let writer = shared.write_range(key(0), 1).unwrap();
+                let other: H256 = [key(0).as_slice()[0] ^ 0x80; 32].into();
+                barrier.wait();
+                // both writers hold their halves at the same time
+                assert!(shared.try_write_range(other, 1).is_none());
+                for i in 1..=n {
+                    writer.update(key(i), [i; 32].into()).unwrap();
+                }
+                assert_eq!(
+                    writer.update(other, [1u8; 32].into()).unwrap_err(),
+                    Error::KeyNotLocked(other)
+                );
+                barrier.wait();
+            })
+        })
+        .collect::<Vec<_>>();
+    for w in writers {
+        w.join().unwrap();
+    }
+
+    let mut tree = VsSmt::<H256>::default();
+    for i in 1..=n {
+        tree.update(low(i), [i; 32].into()).unwrap();
+        tree.update(high(i), [i; 32].into()).unwrap();
+    }
+    assert_eq!(shared.root(), tree.root());
+    assert!(shared.try_write_range(H256::zero(), 0).is_some());
+}
+
+#[test]
+fn test_frozen_view() {
+    let mut tree = VsSmt::<H256>::default();
+    let k1: H256 = [1u8; 32].into();
+    let k2: H256 = [2u8; 32].into();
+    tree.update(k1, [1u8; 32].into()).unwrap();
+    let root = tree.root();
+
+    let frozen = tree.freeze(b"frozen").unwrap();
+    assert_eq!(frozen.version().0, b"");
+    assert_eq!(tree.list_checkpoints().unwrap().pop().unwrap(), b"frozen");
+    tree.update(k2, [2u8; 32].into()).unwrap();
+    tree.remove(k1).unwrap();
+    assert_ne!(tree.root(), root);
+
+    let frozen2 = frozen.clone();
+    let reader = thread::spawn(move || {
+        assert_eq!(frozen2.root(), root);
+        assert_eq!(frozen2.get(&k1).unwrap(), Some([1u8; 32].into()));
+        assert_eq!(frozen2.get(&k2).unwrap(), None);
+    });
+    reader.join().unwrap();
+
+    let proof = frozen.merkle_proof(vec![k1, k2]).unwrap();
+    assert!(proof
+        .verify::<Blake3Hasher>(root, vec![(k1, Some([1u8; 32].into())), (k2, None)])
+        .unwrap());
+
+    // a later freeze sees the writes in between, the first one is unchanged;
+    // both are gone with their versions
+    let frozen3 = tree.freeze(b"frozen2").unwrap();
+    assert_eq!(frozen3.root(), tree.root());
+    assert_eq!(frozen3.get(&k2).unwrap(), Some([2u8; 32].into()));
+    assert_eq!(frozen.root(), root);
+
+    tree.prune_versions_before(VersionName(b"frozen2")).unwrap();
+    assert!(matches!(frozen.try_root(), Err(Error::VersionNotFound(_))));
+    assert!(matches!(frozen.get(&k1), Err(Error::VersionNotFound(_))));
+    assert!(matches!(frozen3.get(&k2), Err(Error::VersionNotFound(_))));
+}
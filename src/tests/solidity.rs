@@ -0,0 +1,57 @@
+use crate::{
+    keccak_hasher::KeccakHasher, solidity::keccak_verifier, DefaultStore, Hasher,
+    SparseMerkleTree, H256,
+};
+
+#[allow(clippy::upper_case_acronyms)]
+type SMT = SparseMerkleTree<KeccakHasher, H256, DefaultStore<H256>>;
+
+#[test]
+fn test_keccak_hasher() {
+    // keccak256("")
+    let expected: [u8; 32] = [
+        0xc5, 0xd2, 0x46, 0x01, 0x86, 0xf7, 0x23, 0x3c, 0x92, 0x7e, 0x7d, 0xb2, 0xdc,
+        0xc7, 0x03, 0xc0, 0xe5, 0x00, 0xb6, 0x53, 0xca, 0x82, 0x27, 0x3b, 0x7b, 0xfa,
+        0xd8, 0x04, 0x5d, 0x85, 0xa4, 0x70,
+    ];
+    assert_eq!(KeccakHasher::hash(&[]), expected.into());
+    assert_eq!(KeccakHasher::default().finish(), expected.into());
+
+    let leaves: Vec<(H256, H256)> = (1u8..20)
+        .map(|i| ([i; 32].into(), [i; 32].into()))
+        .collect();
+    let mut tree = SMT::default();
+    tree.update_all(leaves.clone()).unwrap();
+    let keys: Vec<H256> = leaves.iter().step_by(4).map(|(k, _)| *k).collect();
+    let proof = tree
+        .merkle_proof(keys.clone())
+        .unwrap()
+        .compile(keys)
+        .unwrap();
+    assert!(proof
+        .verify::<KeccakHasher>(
+            tree.root(),
+            leaves
+                .iter()
+                .step_by(4)
+                .map(|(k, v)| (*k, Some(*v)))
+                .collect()
+        )
+        .unwrap());
+}
+
+#[test]
+fn test_keccak_verifier() {
+    let code = keccak_verifier("SmtVerifier");
+    assert!(code.contains("library SmtVerifier {"));
+    assert!(!code.contains("__LIBRARY__"));
+
+    // the generated code must only change on purpose,
+    // update the golden file along with the template
+    let golden = include_str!("data/keccak_verifier.sol");
+    for (i, (line, expected)) in code.lines().zip(golden.lines()).enumerate() {
+        assert_eq!(line, expected, "line {} differs from the golden file", i + 1);
+    }
+    assert_eq!(code.lines().count(), golden.lines().count());
+    assert_eq!(code, golden);
+}
@@ -0,0 +1,58 @@
+use crate::{blake3_hasher::Blake3Hasher, error::Error, VsSmt, WitnessTree, H256};
+
+#[allow(clippy::upper_case_acronyms)]
+type SMT = VsSmt<H256>;
+
+#[test]
+fn test_witness_tree() {
+    let leaves: Vec<(H256, H256)> = (1u8..20)
+        .map(|i| ([i; 32].into(), [i; 32].into()))
+        .collect();
+    let mut tree = SMT::default();
+    tree.update_all(leaves).unwrap();
+
+    let keys: Vec<H256> = vec![[3u8; 32].into(), [7u8; 32].into(), [42u8; 32].into()];
+    let (proof, values) = tree.merkle_proof_with_values(keys.clone()).unwrap();
+    let proof = proof.compile(keys).unwrap();
+    let mut witness =
+        WitnessTree::<Blake3Hasher>::new(tree.root(), proof.clone(), values.clone())
+            .unwrap();
+    assert_eq!(witness.get(&[3u8; 32].into()), Ok(Some([3u8; 32].into())));
+    assert_eq!(witness.get(&[42u8; 32].into()), Ok(None));
+    assert_eq!(
+        witness.get(&[5u8; 32].into()),
+        Err(Error::UnprovedKeys(vec![[5u8; 32].into()]))
+    );
+
+    // the same writes on the witness and on the tree
+    let root = witness
+        .update([42u8; 32].into(), [0xaa; 32].into())
+        .unwrap();
+    assert_eq!(
+        root,
+        tree.update([42u8; 32].into(), [0xaa; 32].into()).unwrap()
+    );
+    let root = witness.update([7u8; 32].into(), H256::zero()).unwrap();
+    assert_eq!(
+        (root, Some([7u8; 32].into())),
+        tree.remove([7u8; 32].into()).unwrap()
+    );
+    assert_eq!(witness.root(), tree.root());
+    assert_eq!(witness.get(&[7u8; 32].into()), Ok(None));
+
+    // untouched on a key outside of the witness
+    assert_eq!(
+        witness.update_all(vec![
+            ([3u8; 32].into(), [1u8; 32].into()),
+            ([5u8; 32].into(), [1u8; 32].into()),
+        ]),
+        Err(Error::UnprovedKeys(vec![[5u8; 32].into()]))
+    );
+    assert_eq!(witness.root(), tree.root());
+    assert_eq!(witness.get(&[3u8; 32].into()), Ok(Some([3u8; 32].into())));
+
+    assert!(matches!(
+        WitnessTree::<Blake3Hasher>::new(H256::zero(), proof, values),
+        Err(Error::RootMismatch { .. })
+    ));
+}
@@ -1,5 +1,9 @@
 use crate::{
-    blake3_hasher::Blake3Hasher, error::Error, merge::MergeValue, MerkleProof, VsSmt, *,
+    blake3_hasher::Blake3Hasher,
+    error::Error,
+    merge::MergeValue,
+    tree::{BranchKey, BranchNode},
+    MerkleProof, VsSmt, *,
 };
 use proptest::prelude::*;
 use rand::prelude::{Rng, SliceRandom};
@@ -7,6 +11,18 @@ use rand::prelude::{Rng, SliceRandom};
 #[allow(clippy::upper_case_acronyms)]
 type SMT = VsSmt<H256>;
 
+// leaves and branches of the store, sorted by key;
+// vsdb maps compare by their instance prefixes, not their contents
+type StoreContents = (Vec<(H256, H256)>, Vec<(BranchKey, BranchNode)>);
+
+fn store_contents(tree: &SMT) -> StoreContents {
+    let mut leaves: Vec<_> = tree.store().iter_leaves().collect();
+    let mut branches: Vec<_> = tree.store().iter_branches().collect();
+    leaves.sort_unstable_by_key(|(k, _)| *k);
+    branches.sort_unstable_by(|(a, _), (b, _)| a.cmp(b));
+    (leaves, branches)
+}
+
 #[test]
 fn test_default_root() {
     let mut tree = SMT::default();
@@ -146,7 +162,7 @@ fn test_zero_value_donot_change_store() {
     tree.update(key, value).unwrap();
     assert_ne!(tree.root(), H256::zero());
     let root = tree.root();
-    let store = tree.store().clone();
+    let contents = store_contents(&tree);
 
     // insert a zero value leaf
     let key = [
@@ -161,8 +177,7 @@ fn test_zero_value_donot_change_store() {
     .into();
     tree.update(key, value).unwrap();
     assert_eq!(tree.root(), root);
-    assert_eq!(tree.store().leaves_map(), store.leaves_map());
-    assert_eq!(tree.store().branches_map(), store.branches_map());
+    assert_eq!(store_contents(&tree), contents);
 }
 
 #[test]
@@ -181,7 +196,7 @@ fn test_delete_a_leaf() {
     tree.update(key, value).unwrap();
     assert_ne!(tree.root(), H256::zero());
     let root = tree.root();
-    let store = tree.store().clone();
+    let contents = store_contents(&tree);
 
     // insert a leaf
     let key = [
@@ -200,8 +215,7 @@ fn test_delete_a_leaf() {
     // delete a leaf
     tree.update(key, H256::zero()).unwrap();
     assert_eq!(tree.root(), root);
-    assert_eq!(tree.store().leaves_map(), store.leaves_map());
-    assert_eq!(tree.store().branches_map(), store.branches_map());
+    assert_eq!(store_contents(&tree), contents);
 }
 
 #[test]
@@ -268,14 +282,17 @@ fn test_update(key: H256, value: H256) {
 }
 
 fn test_update_tree_store(key: H256, value: H256, value2: H256) {
+    // a single leaf is a run of merges with zero under the top branch,
+    // which is the only one stored
+    const EXPECTED_BRANCHES_LEN: usize = 1;
     const EXPECTED_LEAVES_LEN: usize = 1;
 
     let mut tree = SMT::default();
     tree.update(key, value).expect("update");
-    assert_eq!(tree.store().branches_map().len(), 256);
+    assert_eq!(tree.store().branches_map().len(), EXPECTED_BRANCHES_LEN);
     assert_eq!(tree.store().leaves_map().len(), EXPECTED_LEAVES_LEN);
     tree.update(key, value2).expect("update");
-    assert_eq!(tree.store().branches_map().len(), 256);
+    assert_eq!(tree.store().branches_map().len(), EXPECTED_BRANCHES_LEN);
     assert_eq!(tree.store().leaves_map().len(), EXPECTED_LEAVES_LEN);
     assert_eq!(tree.get(&key).unwrap().unwrap(), value2);
 }
@@ -393,7 +410,7 @@ proptest! {
     fn test_h256_copy_bits(start: u8) {
         let one: H256 = [255u8; 32].into();
         let target = one.copy_bits(start);
-        for i in start..=core::u8::MAX {
+        for i in start..=u8::MAX {
             assert_eq!(one.get_bit(i), target.get_bit(i));
         }
         for i in 0..start {
@@ -643,10 +660,222 @@ proptest! {
 }
 
 fn parse_h256(s: &str) -> H256 {
-    let data = hex::decode(s).unwrap();
-    let mut inner = [0u8; 32];
-    inner.copy_from_slice(&data);
-    H256::from(inner)
+    s.parse().unwrap()
+}
+
+#[test]
+fn test_h256_paths() {
+    let a = H256::from(0b1011u128);
+    let b = H256::from(0b0011u128);
+    assert_eq!(a.fork_height(&b), 3);
+    assert_eq!(a.common_prefix_len(&b), 252);
+    assert_eq!(a.common_prefix_len(&a), 256);
+    assert_eq!(a.fork_height(&a), 0);
+    assert_eq!(H256::zero().common_prefix_len(&H256::from(1u128)), 255);
+
+    // siblings at the fork height share the parent
+    assert_eq!(a.parent_path(3), b.parent_path(3));
+    assert_ne!(a.parent_path(2), b.parent_path(2));
+    assert_eq!(a.parent_path(u8::MAX), H256::zero());
+    assert_eq!(a.parent_path(0), H256::from(0b1010u128));
+
+    let bits: Vec<bool> = a.bits().collect();
+    assert_eq!(bits.len(), 256);
+    assert_eq!(a.bits().len(), 256);
+    assert_eq!(
+        a.bits().rev().take(4).collect::<Vec<_>>(),
+        [true, true, false, true]
+    );
+    assert!(bits[..252].iter().all(|b| !b));
+    for (i, bit) in a.bits().enumerate() {
+        assert_eq!(bit, a.is_right(255 - i as u8));
+    }
+    // the common prefix is where the bits are the same
+    let same = a.bits().zip(b.bits()).take_while(|(x, y)| x == y).count();
+    assert_eq!(same as u16, a.common_prefix_len(&b));
+}
+
+proptest! {
+    #[test]
+    fn test_h256_limbs(key: [u8; 32], key2: [u8; 32], start: u8) {
+        let (a, b): (H256, H256) = (key.into(), key2.into());
+        // the same as walking the bits one by one
+        let fork = (0..=u8::MAX).rev().find(|h| a.get_bit(*h) != b.get_bit(*h));
+        prop_assert_eq!(a.fork_height(&b), fork.unwrap_or(0));
+        prop_assert_eq!(a.cmp(&b), a.bits().cmp(b.bits()));
+        let target = a.copy_bits(start);
+        for i in 0..=u8::MAX {
+            prop_assert_eq!(target.get_bit(i), i >= start && a.get_bit(i));
+        }
+    }
+}
+
+#[test]
+fn test_h256_numeric() {
+    assert_eq!(H256::from_u64(7), 7u64.to_path());
+    assert_eq!(H256::from(u128::MAX), u128::MAX.to_path());
+    assert!(H256::from_u64(1) < H256::from_u64(256));
+    assert!(H256::from(u64::MAX as u128) < H256::from(1u128 << 64));
+
+    let h = H256::from(0x0102u128);
+    let be = h.to_be_bytes();
+    assert_eq!(be[30..], [1, 2]);
+    assert!(be[..30].iter().all(|b| *b == 0));
+    assert_eq!(H256::from_be_bytes(be), h);
+
+    let bytes = [9u8; 40];
+    assert_eq!(H256::try_from(&bytes[..32]).unwrap(), [9u8; 32].into());
+    assert_eq!(
+        H256::try_from(&bytes[..]).unwrap_err(),
+        Error::InvalidLength {
+            expected: 32,
+            actual: 40
+        }
+    );
+}
+
+#[test]
+fn test_hn_widths() {
+    let a: H160 = [1u8; 20].into();
+    let b = H512::from([0xFFu8; 64]);
+    assert_eq!(a.to_string(), format!("0x{}", "01".repeat(20)));
+    assert_eq!(a.to_string().parse::<H160>().unwrap(), a);
+    assert_eq!(b.to_string().parse::<H512>().unwrap(), b);
+    assert!(a.to_string().parse::<H256>().is_err());
+    assert_eq!(
+        H160::try_from(&[0u8; 32][..]).unwrap_err(),
+        Error::InvalidLength {
+            expected: 20,
+            actual: 32
+        }
+    );
+    assert!(H512::default().is_zero());
+    assert_eq!(format!("{:?}", H160::zero()), format!("H160({:?})", [0u8; 20]));
+
+    // the highest byte is the last one, whatever the width
+    let mut low = [0u8; 20];
+    low[0] = 0xFF;
+    let mut high = [0u8; 20];
+    high[19] = 1;
+    assert!(H160::from(low) < H160::from(high));
+
+    // the serde forms are the same as those of `H256`
+    let json = serde_json::to_string(&a).unwrap();
+    assert_eq!(serde_json::from_str::<H160>(&json).unwrap(), a);
+    let bytes = vsdb::ValueEnDe::encode(&b);
+    assert_eq!(<H512 as vsdb::ValueEnDe>::decode(&bytes).unwrap(), b);
+    let h: H256 = [7u8; 32].into();
+    assert_eq!(
+        &vsdb::ValueEnDe::encode(&h)[..],
+        &vsdb::ValueEnDe::encode(&[7u8; 32])[..]
+    );
+}
+
+#[test]
+fn test_hn_conversions() {
+    let n = 0x0102_0304_0506_0708_090a_0b0c_0d0e_0f10u128;
+    let h = H256::from(n);
+    let narrow = H160::from(h);
+    assert_eq!(H256::from(narrow), h);
+    assert_eq!(H256::from(H512::from(h)), h);
+    assert_eq!(H512::from(h).resize::<16>(), HN::from(n.to_le_bytes()));
+
+    // narrowing drops the highest bytes
+    let mut wide = [0u8; 64];
+    wide[0] = 1;
+    wide[63] = 1;
+    assert_eq!(H256::from(H512::from(wide)), H256::from(1u128));
+    let mut high = [0u8; 32];
+    high[31] = 1;
+    assert!(H160::from(H256::from(high)).is_zero());
+}
+
+#[test]
+fn test_narrow_hasher() {
+    type Smt160 = crate::SparseMerkleTree<
+        NarrowHasher<Blake3Hasher, 20>,
+        H256,
+        DefaultStore<H256>,
+    >;
+
+    let d20: H160 = <Blake3Hasher as Digest<20>>::digest(b"xsmt");
+    let d64: H512 = <Blake3Hasher as Digest<64>>::digest(b"xsmt");
+    assert_eq!(d20.as_slice(), &d64.as_slice()[..20]);
+    assert_eq!(
+        H256::from(d64),
+        <Blake3Hasher as crate::traits::Hasher>::hash(b"xsmt")
+    );
+
+    let mut tree = Smt160::default();
+    let leaves: Vec<(H256, H256)> =
+        (1..20u8).map(|i| ([i; 32].into(), [i; 32].into())).collect();
+    tree.update_all(leaves.clone()).unwrap();
+    let root = tree.root();
+    assert_eq!(H256::from(H160::from(root)), root);
+
+    let keys: Vec<H256> = leaves.iter().map(|(k, _)| *k).collect();
+    let proof = tree.merkle_proof(keys).unwrap();
+    let leaves: Vec<_> = leaves.into_iter().map(|(k, v)| (k, Some(v))).collect();
+    assert!(proof
+        .clone()
+        .verify::<NarrowHasher<Blake3Hasher, 20>>(root, leaves.clone())
+        .unwrap());
+    assert!(!proof.verify::<Blake3Hasher>(root, leaves).unwrap());
+}
+
+#[test]
+fn test_h256_key_of() {
+    use crate::domain::{DomainTag, TaggedHasher};
+
+    struct Accounts;
+    impl DomainTag for Accounts {
+        const TAG: &'static [u8] = b"accounts";
+    }
+
+    let key = H256::key_of::<Blake3Hasher>(b"accounts", b"alice");
+    assert_eq!(key, H256::key_of::<Blake3Hasher>(b"accounts", b"alice"));
+    assert_eq!(
+        key,
+        <TaggedHasher<Blake3Hasher, Accounts> as Hasher>::hash(b"alice")
+    );
+    assert_ne!(key, H256::key_of::<Blake3Hasher>(b"balances", b"alice"));
+    assert_ne!(key, H256::key_of::<Blake3Hasher>(b"", b"accountsalice"));
+    // the namespace can not swallow the head of the key
+    assert_ne!(
+        H256::key_of::<Blake3Hasher>(b"ab", b"c"),
+        H256::key_of::<Blake3Hasher>(b"a", b"bc")
+    );
+}
+
+#[test]
+fn test_h256_hex() {
+    use vsdb::ValueEnDe;
+
+    let mut bytes = [0u8; 32];
+    bytes[0] = 0xab;
+    bytes[31] = 0x01;
+    let h = H256::from(bytes);
+    let s = format!("0xab{}01", "0".repeat(60));
+    assert_eq!(h.to_string(), s);
+    assert_eq!(format!("{:x}", h), s[2..]);
+    assert_eq!(s.parse::<H256>().unwrap(), h);
+    assert_eq!(s[2..].to_uppercase().parse::<H256>().unwrap(), h);
+    assert!(matches!("0x12".parse::<H256>(), Err(Error::ParseH256(_))));
+    assert!(matches!(
+        s.replace('a', "g").parse::<H256>(),
+        Err(Error::ParseH256(_))
+    ));
+
+    // hex in human-readable formats, byte arrays are still accepted
+    let json = serde_json::to_string(&h).unwrap();
+    assert_eq!(json, format!("\"{}\"", s));
+    assert_eq!(serde_json::from_str::<H256>(&json).unwrap(), h);
+    let array = serde_json::to_string(&bytes).unwrap();
+    assert_eq!(serde_json::from_str::<H256>(&array).unwrap(), h);
+
+    // the stored encoding is unchanged
+    assert_eq!(h.encode(), bytes.encode());
+    assert_eq!(<H256 as ValueEnDe>::decode(&h.encode()).unwrap(), h);
 }
 
 #[test]
@@ -902,3 +1131,3359 @@ fn test_max_stack_size() {
             .expect("verify")
     );
 }
+
+#[test]
+fn test_decompile_proof() {
+    let mut rng = rand::thread_rng();
+    let mut tree = SMT::default();
+    let leaves: Vec<(H256, H256)> = (0..50)
+        .map(|_| (rng.gen::<[u8; 32]>().into(), rng.gen::<[u8; 32]>().into()))
+        .collect();
+    tree.update_all(leaves.clone()).unwrap();
+
+    let mut proved: Vec<(H256, Option<H256>)> = leaves
+        .iter()
+        .take(10)
+        .map(|(k, v)| (*k, Some(*v)))
+        .collect();
+    proved.push((rng.gen::<[u8; 32]>().into(), None));
+    let keys: Vec<H256> = proved.iter().map(|(k, _)| *k).collect();
+    let proof = tree.merkle_proof(keys.clone()).unwrap();
+    let compiled = proof.clone().compile(keys.clone()).unwrap();
+
+    let decompiled = compiled.decompile().unwrap();
+    assert_eq!(decompiled.merkle_path(), proof.merkle_path());
+    assert_eq!(decompiled.leaves_count(), proof.leaves_count());
+    for (a, b) in decompiled.leaves_bitmap().iter().zip(proof.leaves_bitmap()) {
+        // only bits the program uses are kept
+        for h in 0..=u8::MAX {
+            assert!(!a.get_bit(h) || b.get_bit(h));
+        }
+    }
+    assert_eq!(decompiled.clone().compile(keys).unwrap().0, compiled.0);
+    assert!(decompiled
+        .verify::<Blake3Hasher>(tree.root(), proved)
+        .unwrap());
+
+    assert_eq!(
+        CompiledMerkleProof(vec![]).decompile().unwrap_err(),
+        Error::EmptyProof
+    );
+    assert!(CompiledMerkleProof(compiled.0[1..].to_vec())
+        .decompile()
+        .is_err());
+    let truncated = compiled.0[..compiled.0.len() - 1].to_vec();
+    assert!(CompiledMerkleProof(truncated).decompile().is_err());
+}
+
+#[test]
+fn test_compile_validation() {
+    let mut tree = SMT::default();
+    let leaves: Vec<(H256, H256)> = (1u8..10)
+        .map(|i| ([i; 32].into(), [i; 32].into()))
+        .collect();
+    tree.update_all(leaves).unwrap();
+    let k = |i: u8| -> H256 { [i; 32].into() };
+
+    let proof = tree.merkle_proof(vec![k(1), k(2)]).unwrap();
+    assert!(proof.clone().compile(vec![k(2), k(1)]).is_ok());
+    assert_eq!(
+        proof.clone().compile(vec![k(1)]).unwrap_err(),
+        Error::IncorrectNumberOfLeaves {
+            expected: 2,
+            actual: 1
+        }
+    );
+    assert_eq!(
+        proof.clone().compile(vec![k(1), k(1)]).unwrap_err(),
+        Error::DuplicatedKeys(k(1))
+    );
+    assert_eq!(
+        proof.compile(vec![k(1), k(5)]).unwrap_err(),
+        Error::KeyMismatch {
+            key: k(5),
+            height: 251
+        }
+    );
+    let proof = tree.merkle_proof(vec![k(1), k(4)]).unwrap();
+    assert_eq!(
+        proof.compile(vec![k(1), k(2)]).unwrap_err(),
+        Error::UnusedSiblings(1)
+    );
+
+    // the leaves are bound in key order
+    let proof = tree.merkle_proof(vec![k(3), k(20)]).unwrap();
+    let bound = proof
+        .clone()
+        .compile_with_values(vec![(k(20), None), (k(3), Some(k(3)))])
+        .unwrap();
+    assert_eq!(bound.leaves(), &[(k(3), Some(k(3))), (k(20), None)]);
+    assert!(bound.verify::<Blake3Hasher>(tree.root()).unwrap());
+    assert_eq!(bound.compute_root::<Blake3Hasher>().unwrap(), tree.root());
+    let bound = proof
+        .compile_with_values(vec![(k(3), None), (k(20), None)])
+        .unwrap();
+    assert!(!bound.verify::<Blake3Hasher>(tree.root()).unwrap());
+}
+
+#[test]
+fn test_proof_compressed_bytes() {
+    let mut tree = SMT::default();
+    let leaves: Vec<(H256, H256)> = (0u8..20)
+        .map(|i| ([i; 32].into(), [i.wrapping_add(1); 32].into()))
+        .collect();
+    tree.update_all(leaves.clone()).unwrap();
+    let keys: Vec<H256> = leaves.iter().map(|(k, _)| *k).collect();
+    let proof = tree.merkle_proof(keys.clone()).unwrap();
+
+    assert_eq!(MerkleProof::from_bytes(&proof.to_bytes()).unwrap(), proof);
+    assert!(MerkleProof::from_bytes(&proof.to_bytes()[1..]).is_err());
+    assert_eq!(proof.serialized_size(), proof.to_bytes().len());
+    assert_eq!(proof.proof_len(), proof.merkle_path().len());
+    let single = tree.merkle_proof(vec![keys[0]]).unwrap();
+    assert_eq!(single.serialized_size(), single.to_bytes().len());
+    let compiled = proof.clone().compile(keys).unwrap();
+    assert_eq!(compiled.serialized_size(), compiled.0.len());
+
+    let codecs = [
+        Codec::None,
+        #[cfg(feature = "zstd")]
+        Codec::Zstd {
+            level: 3,
+            dict: None,
+        },
+        #[cfg(feature = "snappy")]
+        Codec::Snappy,
+    ];
+
+    for codec in codecs.iter() {
+        let bytes = proof.to_compressed_bytes(codec).unwrap();
+        let decoded = MerkleProof::from_compressed_bytes(codec, &bytes).unwrap();
+        assert_eq!(decoded, proof);
+        assert!(
+            decoded
+                .verify::<Blake3Hasher>(
+                    tree.root(),
+                    leaves.iter().map(|(k, v)| (*k, Some(*v))).collect()
+                )
+                .unwrap()
+        );
+    }
+}
+
+#[cfg(feature = "zstd")]
+#[test]
+fn test_train_zstd_dict() {
+    let mut tree = SMT::default();
+    let leaves: Vec<(H256, H256)> = (0u8..200)
+        .map(|i| ([i; 32].into(), [i.wrapping_add(1); 32].into()))
+        .collect();
+    tree.update_all(leaves.clone()).unwrap();
+    let samples: Vec<MerkleProof> = leaves
+        .iter()
+        .map(|(k, _)| tree.merkle_proof(vec![*k]).unwrap())
+        .collect();
+    let dict = crate::compression::train_zstd_dict(&samples, 4096).unwrap();
+    assert!(!dict.is_empty() && dict.len() <= 4096);
+
+    let codec = Codec::Zstd {
+        level: 3,
+        dict: Some(dict),
+    };
+    for proof in samples.iter().take(10) {
+        let bytes = proof.to_compressed_bytes(&codec).unwrap();
+        assert_eq!(&MerkleProof::from_compressed_bytes(&codec, &bytes).unwrap(), proof);
+    }
+    // a payload compressed with a dictionary needs it
+    let bytes = samples[0].to_compressed_bytes(&codec).unwrap();
+    let plain = Codec::Zstd {
+        level: 3,
+        dict: None,
+    };
+    assert!(MerkleProof::from_compressed_bytes(&plain, &bytes).is_err());
+}
+
+#[cfg(feature = "snappy")]
+#[test]
+fn test_snappy_size_limit() {
+    // tag, then a header claiming 1 GiB of output
+    let bomb = [2u8, 0x80, 0x80, 0x80, 0x80, 0x04, 0];
+    assert!(matches!(
+        Codec::Snappy.decompress(&bomb),
+        Err(Error::Compression(_))
+    ));
+}
+
+#[test]
+fn test_verify_integrity() {
+    let mut tree = SMT::default();
+    let leaves: Vec<(H256, H256)> = (1u8..10)
+        .map(|i| ([i; 32].into(), [i; 32].into()))
+        .collect();
+    tree.update_all(leaves).unwrap();
+
+    let report = tree.verify_integrity().unwrap();
+    assert!(report.is_ok());
+    assert_eq!(report.computed_root, tree.root());
+
+    // corrupt a branch on the path of an existing leaf
+    let key: H256 = [1u8; 32].into();
+    let height = key.fork_height(&[2u8; 32].into());
+    let branch_key = BranchKey::new(height, key.parent_path(height));
+    let mut branch = tree.store().get_branch(&branch_key).unwrap().unwrap();
+    branch.right = MergeValue::from_h256([7u8; 32].into());
+    tree.store_mut()
+        .insert_branch(branch_key.clone(), branch)
+        .unwrap();
+
+    // leave a branch behind that no leaf leads to
+    let dangling = BranchKey::new(3, H256::zero());
+    tree.store_mut()
+        .insert_branch(
+            dangling.clone(),
+            BranchNode {
+                left: MergeValue::from_h256([9u8; 32].into()),
+                right: MergeValue::zero(),
+            },
+        )
+        .unwrap();
+
+    let report = tree.verify_integrity().unwrap();
+    assert!(!report.is_ok());
+    assert_eq!(report.stored_root, report.computed_root);
+    assert_eq!(report.mismatched_branches, vec![branch_key]);
+    assert_eq!(report.dangling_branches, vec![dangling]);
+}
+
+#[test]
+fn test_compact() {
+    use vsdb::{VersionName, VsMgmt};
+
+    let mut tree = SMT::default();
+    let mut rng = rand::thread_rng();
+    let mut keys: Vec<H256> = Vec::new();
+    for ver in [b"a", b"b", b"c", b"d"] {
+        tree.version_create(VersionName(ver)).unwrap();
+        let leaves: Vec<(H256, H256)> = (0..20)
+            .map(|_| (rng.gen::<[u8; 32]>().into(), rng.gen::<[u8; 32]>().into()))
+            .collect();
+        keys.extend(leaves.iter().map(|(k, _)| *k));
+        tree.update_all(leaves).unwrap();
+        tree.remove_all(keys.choose_multiple(&mut rng, 5).copied().collect())
+            .unwrap();
+    }
+    let root = tree.root();
+    let values: Vec<Option<H256>> = keys.iter().map(|k| tree.get(k).unwrap()).collect();
+
+    let dangling = BranchKey::new(3, H256::zero());
+    tree.store_mut()
+        .insert_branch(
+            dangling.clone(),
+            BranchNode {
+                left: MergeValue::from_h256([9u8; 32].into()),
+                right: MergeValue::zero(),
+            },
+        )
+        .unwrap();
+
+    let report = tree.compact(Some(2)).unwrap();
+    assert_eq!(report.dangling_branches, 1);
+    assert!(report.merged_versions > 0);
+    assert!(!tree.version_exists(VersionName(b"a")));
+    assert!(tree.version_exists(VersionName(b"d")));
+    assert_eq!(tree.store().get_branch(&dangling).unwrap(), None);
+    assert_eq!(tree.root(), root);
+    for (k, v) in keys.iter().zip(values) {
+        assert_eq!(tree.get(k).unwrap(), v);
+    }
+    assert!(tree.verify_integrity().unwrap().is_ok());
+
+    // nothing left to do
+    let report = tree.compact(None).unwrap();
+    assert_eq!((report.dangling_branches, report.merged_versions), (0, 0));
+
+    // a corrupted store is left as it is
+    let key = keys[0];
+    tree.store_mut().insert_leaf(key, [0xffu8; 32].into()).unwrap();
+    assert!(matches!(
+        tree.compact(None).unwrap_err(),
+        Error::RootMismatch { .. }
+    ));
+}
+
+#[test]
+fn test_snapshot_export_import() {
+    let mut tree = SMT::default();
+    let leaves: Vec<(H256, H256)> = (1u8..30)
+        .map(|i| ([i; 32].into(), [i.wrapping_mul(7); 32].into()))
+        .collect();
+    tree.update_all(leaves.clone()).unwrap();
+
+    let mut buf = vec![];
+    assert_eq!(tree.export_snapshot(&mut buf).unwrap(), leaves.len() as u64);
+
+    let mut tree2 = SMT::default();
+    assert_eq!(tree2.import_snapshot(&buf[..]).unwrap(), tree.root());
+    for (k, v) in leaves.iter() {
+        assert_eq!(tree2.get(k).unwrap(), Some(*v));
+    }
+
+    // not empty any more
+    assert!(matches!(
+        tree2.import_snapshot(&buf[..]),
+        Err(Error::Snapshot(_))
+    ));
+
+    // tampered root
+    let mut bad = buf.clone();
+    bad[5] ^= 1;
+    let mut tree3 = SMT::default();
+    assert!(matches!(
+        tree3.import_snapshot(&bad[..]),
+        Err(Error::Snapshot(_))
+    ));
+    assert!(tree3.is_empty());
+
+    // truncated
+    assert!(tree3.import_snapshot(&buf[..buf.len() - 1]).is_err());
+
+    // a value length beyond the snapshot
+    let mut bad = buf.clone();
+    let len_at = 4 + 1 + 32 + 8 + 32;
+    bad[len_at..len_at + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+    assert!(matches!(
+        tree3.import_snapshot(&bad[..]),
+        Err(Error::Snapshot(_))
+    ));
+}
+
+#[test]
+fn test_checkpoint() {
+    let mut tree = SMT::default();
+    let leaves: Vec<(H256, H256)> = (1u8..30)
+        .map(|i| ([i; 32].into(), [i.wrapping_mul(3); 32].into()))
+        .collect();
+    tree.update_all(leaves.clone()).unwrap();
+    let root = tree.root();
+
+    let absent: H256 = [40u8; 32].into();
+    let keys = vec![leaves[9].0, absent, leaves[2].0];
+    let checkpoint = Checkpoint::capture(&tree, keys).unwrap();
+    assert_eq!(checkpoint.root(), root);
+    assert!(checkpoint.verify::<Blake3Hasher>().unwrap());
+
+    let bytes = checkpoint.to_bytes();
+    let opened = Checkpoint::<H256>::open::<Blake3Hasher>(&bytes, root).unwrap();
+    assert_eq!(
+        opened,
+        vec![
+            (leaves[2].0, Some(leaves[2].1)),
+            (leaves[9].0, Some(leaves[9].1)),
+            (absent, None),
+        ]
+    );
+
+    // captured at another root
+    let other: H256 = [1u8; 32].into();
+    assert_eq!(
+        Checkpoint::<H256>::open::<Blake3Hasher>(&bytes, other).unwrap_err(),
+        Error::RootMismatch {
+            expected: other,
+            actual: root
+        }
+    );
+
+    // a tampered value no longer matches the root
+    let mut bad = bytes.clone();
+    let len_at = 4 + 1 + 32 + 4 + 32 + 1;
+    let len = u32::from_le_bytes(bad[len_at..len_at + 4].try_into().unwrap()) as usize;
+    bad[len_at + 4 + len - 1] ^= 1;
+    let tampered = Checkpoint::<H256>::from_bytes(&bad).unwrap();
+    assert!(!tampered.verify::<Blake3Hasher>().unwrap());
+    assert!(matches!(
+        Checkpoint::<H256>::open::<Blake3Hasher>(&bad, root),
+        Err(Error::RootMismatch { .. })
+    ));
+
+    // a value length beyond the blob is rejected before allocating
+    let mut bad = bytes.clone();
+    bad[len_at..len_at + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+    assert!(matches!(
+        Checkpoint::<H256>::from_bytes(&bad),
+        Err(Error::Checkpoint(_))
+    ));
+
+    let mut bad = bytes.clone();
+    bad[0] = b'Y';
+    assert!(matches!(
+        Checkpoint::<H256>::from_bytes(&bad),
+        Err(Error::Checkpoint(_))
+    ));
+    assert!(matches!(
+        Checkpoint::<H256>::from_bytes(&bytes[..bytes.len() - 1]),
+        Err(Error::Checkpoint(_))
+    ));
+    let mut long = bytes.clone();
+    long.push(0);
+    assert!(matches!(
+        Checkpoint::<H256>::from_bytes(&long),
+        Err(Error::Checkpoint(_))
+    ));
+}
+
+#[test]
+fn test_prove_subtree() {
+    let mut rng = rand::thread_rng();
+    let mut leaves: Vec<(H256, H256)> = (0..200)
+        .map(|_| (rng.gen::<[u8; 32]>().into(), rng.gen::<[u8; 32]>().into()))
+        .collect();
+    // a dense corner, so low subtrees hold several leaves
+    leaves.extend((0u64..50).map(|i| (H256::from_u64(i * 3), [i as u8 + 1; 32].into())));
+    let mut tree = SMT::default();
+    tree.set_verify_reads(true);
+    tree.update_all(leaves.clone()).unwrap();
+    let root = tree.root();
+
+    let subtree_leaves = |proof: &SubtreeProof| -> Vec<(H256, H256)> {
+        leaves
+            .iter()
+            .filter(|(k, _)| proof.contains(k))
+            .cloned()
+            .collect()
+    };
+    let mut keys: Vec<H256> = leaves.iter().step_by(25).map(|(k, _)| *k).collect();
+    keys.push(rng.gen::<[u8; 32]>().into());
+    keys.push(H256::from_u64(1));
+    for key in keys {
+        for height in [0u16, 1, 2, 5, 8, 100, 200, 250, 255, 256] {
+            let proof = tree.prove_subtree(&key, height).unwrap();
+            assert!(proof.contains(&key));
+            assert!(proof.verify::<Blake3Hasher>(root).unwrap());
+            let inside = subtree_leaves(&proof);
+            assert_eq!(proof.node().is_zero(), inside.is_empty());
+            assert!(proof
+                .verify_leaves::<Blake3Hasher>(root, inside.clone())
+                .unwrap());
+            if let Some((k, _)) = inside.first() {
+                // a missing or changed leaf is caught
+                assert!(!proof
+                    .verify_leaves::<Blake3Hasher>(root, inside[1..].to_vec())
+                    .unwrap());
+                let mut changed = inside.clone();
+                changed[0].1 = [0xEEu8; 32].into();
+                assert!(!proof
+                    .verify_leaves::<Blake3Hasher>(root, changed)
+                    .unwrap());
+                assert!(proof.verify::<Blake3Hasher>(*k).is_ok_and(|v| !v));
+            }
+            if height < 256 {
+                let mut outside = proof.key();
+                if outside.get_bit(height as u8) {
+                    outside.clear_bit(height as u8);
+                } else {
+                    outside.set_bit(height as u8);
+                }
+                assert_eq!(
+                    proof.verify_leaves::<Blake3Hasher>(root, vec![(outside, outside)]),
+                    Err(Error::UnprovedKeys(vec![outside]))
+                );
+            }
+        }
+    }
+    assert_eq!(
+        tree.prove_subtree(&H256::zero(), 257),
+        Err(Error::InvalidHeight(257))
+    );
+
+    let empty = SMT::default();
+    let proof = empty.prove_subtree(&H256::zero(), 10).unwrap();
+    assert!(proof.node().is_zero());
+    assert!(proof.verify::<Blake3Hasher>(H256::zero()).unwrap());
+
+    // heights above the top of a shallow tree
+    let leaves: Vec<(H256, H256)> = (0u64..30)
+        .map(|i| (H256::from_u64(i * 5), [i as u8 + 1; 32].into()))
+        .collect();
+    let mut tree =
+        SparseMerkleTree::<Blake3Hasher, H256, DefaultStore<H256>, 64>::default();
+    tree.update_all(leaves.clone()).unwrap();
+    for height in [0u16, 3, 63, 64, 65, 256] {
+        let proof = tree.prove_subtree(&H256::from_u64(10), height).unwrap();
+        let inside = leaves
+            .iter()
+            .filter(|(k, _)| proof.contains(k))
+            .cloned()
+            .collect();
+        assert!(proof
+            .verify_leaves::<Blake3Hasher>(tree.root(), inside)
+            .unwrap());
+    }
+}
+
+#[test]
+fn test_verify_reads() {
+    let mut tree = SMT::default();
+    tree.set_verify_reads(true);
+    let leaves: Vec<(H256, H256)> = (1u8..10)
+        .map(|i| ([i; 32].into(), [i; 32].into()))
+        .collect();
+    tree.update_all(leaves.clone()).unwrap();
+    tree.update([42u8; 32].into(), [42u8; 32].into()).unwrap();
+    let (_, removed) = tree.remove([42u8; 32].into()).unwrap();
+    assert_eq!(removed, Some([42u8; 32].into()));
+    let keys: Vec<H256> = leaves.iter().map(|(k, _)| *k).collect();
+    let proof = tree.merkle_proof(keys).unwrap();
+    assert!(
+        proof
+            .verify::<Blake3Hasher>(
+                tree.root(),
+                leaves.iter().map(|(k, v)| (*k, Some(*v))).collect()
+            )
+            .unwrap()
+    );
+    assert_eq!(tree.get(&[42u8; 32].into()).unwrap(), None);
+
+    // bit-rot in a leaf
+    let key: H256 = [1u8; 32].into();
+    tree.store_mut()
+        .insert_leaf(key, [0xffu8; 32].into())
+        .unwrap();
+    assert_eq!(
+        tree.get(&key).unwrap_err(),
+        Error::CorruptedNode { key, height: 0 }
+    );
+
+    // reads are trusted by default
+    tree.set_verify_reads(false);
+    assert_eq!(tree.get(&key).unwrap(), Some([0xffu8; 32].into()));
+    tree.set_verify_reads(true);
+
+    // bit-rot in a branch, off the path of the key
+    let height = key.fork_height(&[2u8; 32].into());
+    let branch_key = BranchKey::new(height, key.parent_path(height));
+    let mut branch = tree.store().get_branch(&branch_key).unwrap().unwrap();
+    assert!(!key.is_right(height));
+    branch.right = MergeValue::from_h256([0xffu8; 32].into());
+    tree.store_mut()
+        .insert_branch(branch_key.clone(), branch)
+        .unwrap();
+    assert_eq!(
+        tree.update(key, [1u8; 32].into()).unwrap_err(),
+        Error::CorruptedNode {
+            key: branch_key.node_key,
+            height
+        }
+    );
+}
+
+#[test]
+fn test_branch_cache() {
+    use vsdb::{VersionName, VsMgmt};
+
+    let mut rng = rand::thread_rng();
+    let mut plain = SMT::default();
+    let mut cached = SMT::default();
+    cached.set_branch_cache(8);
+
+    let mut keys: Vec<H256> = Vec::new();
+    for round in 0..20u8 {
+        if round == 10 {
+            plain.version_create(VersionName(b"a")).unwrap();
+            cached.version_create(VersionName(b"a")).unwrap();
+        }
+        let mut batch: Vec<(H256, H256)> = (0..rng.gen_range(1..20))
+            .map(|_| (rng.gen::<[u8; 32]>().into(), rng.gen::<[u8; 32]>().into()))
+            .collect();
+        // delete some existing leaves
+        for k in keys.choose_multiple(&mut rng, 3) {
+            batch.push((*k, H256::zero()));
+        }
+        keys.extend(batch.iter().map(|(k, _)| *k));
+        keys.sort_unstable();
+        keys.dedup();
+        let root = plain.update_all(batch.clone()).unwrap();
+        assert_eq!(cached.update_all(batch).unwrap(), root);
+        let k = *keys.choose(&mut rng).unwrap();
+        assert_eq!(
+            cached.update(k, [round; 32].into()).unwrap(),
+            plain.update(k, [round; 32].into()).unwrap()
+        );
+
+        let proved: Vec<H256> = keys.choose_multiple(&mut rng, 5).copied().collect();
+        assert_eq!(
+            cached.merkle_proof(proved.clone()).unwrap(),
+            plain.merkle_proof(proved).unwrap()
+        );
+    }
+
+    // a rollback changes the root behind the cache
+    plain.version_pop().unwrap();
+    cached.version_pop().unwrap();
+    assert_eq!(cached.root(), plain.root());
+    let batch: Vec<(H256, H256)> =
+        keys.iter().step_by(2).map(|k| (*k, H256::zero())).collect();
+    assert_eq!(
+        cached.update_all(batch.clone()).unwrap(),
+        plain.update_all(batch).unwrap()
+    );
+    assert_eq!(
+        cached.merkle_proof(keys.clone()).unwrap(),
+        plain.merkle_proof(keys).unwrap()
+    );
+}
+
+#[test]
+fn test_diff_versions() {
+    use vsdb::{common::INITIAL_BRANCH_NAME, VersionName, VsMgmt};
+
+    let k1: H256 = [1u8; 32].into();
+    let k2: H256 = [2u8; 32].into();
+    let k3: H256 = [3u8; 32].into();
+    let v = |n: u8| -> H256 { [n; 32].into() };
+
+    let mut tree = SMT::default();
+    tree.version_create(VersionName(b"a")).unwrap();
+    tree.update_all(vec![(k1, v(1)), (k2, v(2))]).unwrap();
+
+    tree.version_create(VersionName(b"b")).unwrap();
+    tree.update(k2, v(20)).unwrap();
+    tree.update(k3, v(3)).unwrap();
+    tree.update(k1, H256::zero()).unwrap();
+
+    let diff = tree
+        .diff_versions(INITIAL_BRANCH_NAME, VersionName(b"a"), VersionName(b"b"))
+        .unwrap();
+    assert_eq!(
+        diff,
+        vec![
+            (k1, Some(v(1)), None),
+            (k2, Some(v(2)), Some(v(20))),
+            (k3, None, Some(v(3))),
+        ]
+    );
+
+    assert!(tree
+        .diff_versions(INITIAL_BRANCH_NAME, VersionName(b"b"), VersionName(b"b"))
+        .unwrap()
+        .is_empty());
+
+    assert!(matches!(
+        tree.diff_versions(INITIAL_BRANCH_NAME, VersionName(b"a"), VersionName(b"x")),
+        Err(Error::VersionNotFound(_))
+    ));
+}
+
+#[test]
+fn test_diff_trees() {
+    use std::collections::BTreeMap;
+
+    let mut rng = rand::thread_rng();
+    let leaves: Vec<(H256, H256)> = (0..200)
+        .map(|_| (rng.gen::<[u8; 32]>().into(), rng.gen::<[u8; 32]>().into()))
+        .collect();
+    let mut a = SMT::default();
+    a.update_all(leaves.clone()).unwrap();
+    let mut b = SMT::default();
+    b.update_all(leaves.clone()).unwrap();
+    b.set_verify_reads(true);
+    assert_eq!(a.diff(&b).unwrap().count(), 0);
+
+    // update, remove and insert a few keys of one tree
+    let mut expected = BTreeMap::new();
+    for (k, v) in leaves.choose_multiple(&mut rng, 10) {
+        let new: H256 = rng.gen::<[u8; 32]>().into();
+        b.update(*k, new).unwrap();
+        expected.insert(*k, (Some(*v), Some(new)));
+    }
+    let removed: Vec<_> = leaves
+        .iter()
+        .filter(|(k, _)| !expected.contains_key(k))
+        .take(5)
+        .collect();
+    for (k, v) in removed {
+        b.remove(*k).unwrap();
+        expected.insert(*k, (Some(*v), None));
+    }
+    for _ in 0..5 {
+        let (k, v): (H256, H256) =
+            (rng.gen::<[u8; 32]>().into(), rng.gen::<[u8; 32]>().into());
+        b.update(k, v).unwrap();
+        expected.insert(k, (None, Some(v)));
+    }
+    let expected: Vec<_> = expected.into_iter().map(|(k, (l, r))| (k, l, r)).collect();
+    let diff = a.diff(&b).unwrap().collect::<Result<Vec<_>, _>>().unwrap();
+    assert_eq!(diff, expected);
+    let reversed = b.diff(&a).unwrap().collect::<Result<Vec<_>, _>>().unwrap();
+    assert_eq!(
+        reversed,
+        expected
+            .iter()
+            .map(|(k, l, r)| (*k, *r, *l))
+            .collect::<Vec<_>>()
+    );
+
+    // against an empty tree every leaf differs
+    let empty = SMT::default();
+    assert_eq!(a.diff(&empty).unwrap().count(), leaves.len());
+
+    // shallow trees
+    type Smt64 = SparseMerkleTree<Blake3Hasher, H256, DefaultStore<H256>, 64>;
+    let mut c = Smt64::default();
+    let mut d = Smt64::default();
+    c.update_all((1u64..50).map(|i| (H256::from_u64(i), [1u8; 32].into())).collect())
+        .unwrap();
+    d.update_all((2u64..51).map(|i| (H256::from_u64(i), [1u8; 32].into())).collect())
+        .unwrap();
+    let diff = c.diff(&d).unwrap().collect::<Result<Vec<_>, _>>().unwrap();
+    assert_eq!(
+        diff,
+        vec![
+            (H256::from_u64(1), Some([1u8; 32].into()), None),
+            (H256::from_u64(50), None, Some([1u8; 32].into())),
+        ]
+    );
+}
+
+#[test]
+fn test_merge_from() {
+    let v = |n: u8| -> H256 { [n; 32].into() };
+    let k = |n: u8| -> H256 { [n; 32].into() };
+
+    let mut ours = SMT::default();
+    ours.update_all(vec![(k(1), v(1)), (k(2), v(2)), (k(3), v(3))])
+        .unwrap();
+    let mut theirs = SMT::default();
+    theirs
+        .update_all(vec![(k(2), v(2)), (k(3), v(30)), (k(4), v(4))])
+        .unwrap();
+
+    // the larger value wins
+    let root = ours
+        .merge_from(&theirs, |_, a, b| if a > b { *a } else { *b })
+        .unwrap();
+    let mut expected = SMT::default();
+    expected
+        .update_all(vec![(k(1), v(1)), (k(2), v(2)), (k(3), v(30)), (k(4), v(4))])
+        .unwrap();
+    assert_eq!(root, expected.root());
+    assert_eq!(ours.diff(&expected).unwrap().count(), 0);
+
+    // merging again changes nothing, the resolver is only called on conflicts
+    let root = ours
+        .merge_from(&theirs, |_, _, _| panic!("no conflict"))
+        .unwrap();
+    assert_eq!(root, expected.root());
+
+    // a deletion from the resolver removes the leaf
+    theirs.update(k(1), v(10)).unwrap();
+    ours.merge_from(&theirs, |_, _, _| H256::zero()).unwrap();
+    assert_eq!(ours.get(&k(1)).unwrap(), None);
+    assert_eq!(ours.get(&k(4)).unwrap(), Some(v(4)));
+}
+
+#[test]
+fn test_split_by() {
+    let leaves: Vec<(H256, H256)> = (1u8..40)
+        .map(|i| ([i; 32].into(), [i; 32].into()))
+        .collect();
+    let mut tree = SMT::default();
+    let root = tree.update_all(leaves.clone()).unwrap();
+
+    let (odd, even) = tree.split_by(|k, _| k.as_slice()[0] % 2 == 1).unwrap();
+    let (expected_odd, expected_even): (Vec<_>, Vec<_>) =
+        leaves.iter().partition(|(k, _)| k.as_slice()[0] % 2 == 1);
+    assert_eq!(
+        odd.root(),
+        compute_root_from_leaves::<Blake3Hasher, H256>(expected_odd)
+    );
+    assert_eq!(
+        even.root(),
+        compute_root_from_leaves::<Blake3Hasher, H256>(expected_even)
+    );
+    assert_eq!(odd.get(&[1u8; 32].into()).unwrap(), Some([1u8; 32].into()));
+    assert_eq!(odd.get(&[2u8; 32].into()).unwrap(), None);
+    assert!(even.verify_integrity().unwrap().is_ok());
+
+    // the source is untouched, and merging the parts back gives it again
+    assert_eq!(tree.root(), root);
+    let mut merged = odd;
+    merged.merge_from(&even, |_, a, _| *a).unwrap();
+    assert_eq!(merged.root(), root);
+}
+
+#[test]
+fn test_export_leaves_sorted() {
+    let mut rng = rand::thread_rng();
+    let mut leaves: Vec<(H256, H256)> = (0..300)
+        .map(|_| (rng.gen::<[u8; 32]>().into(), rng.gen::<[u8; 32]>().into()))
+        .collect();
+    let mut tree = SMT::default();
+    assert_eq!(tree.export_leaves_sorted().unwrap().count(), 0);
+    tree.update(leaves[0].0, leaves[0].1).unwrap();
+    let exported: Vec<_> = tree.export_leaves_sorted().unwrap().collect();
+    assert_eq!(exported, vec![Ok(leaves[0])]);
+    let root = tree.update_all(leaves.clone()).unwrap();
+    tree.set_verify_reads(true);
+
+    leaves.sort_unstable_by_key(|(k, _)| *k);
+    let exported = tree
+        .export_leaves_sorted()
+        .unwrap()
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+    assert_eq!(exported, leaves);
+
+    // the stream feeds a bulk build on the other side
+    let mut copy = SMT::default();
+    let stream = tree.export_leaves_sorted().unwrap().map(|leaf| leaf.unwrap());
+    assert_eq!(copy.build_from_sorted(stream).unwrap(), root);
+
+    type Smt64 = SparseMerkleTree<Blake3Hasher, H256, DefaultStore<H256>, 64>;
+    let mut shallow = Smt64::default();
+    let leaves: Vec<(H256, H256)> = [3u64, 1, 2, u64::MAX]
+        .iter()
+        .map(|i| (H256::from_u64(*i), [1u8; 32].into()))
+        .collect();
+    shallow.update_all(leaves).unwrap();
+    let keys: Vec<H256> = shallow
+        .export_leaves_sorted()
+        .unwrap()
+        .map(|leaf| leaf.unwrap().0)
+        .collect();
+    assert_eq!(
+        keys,
+        [1u64, 2, 3, u64::MAX].map(H256::from_u64).to_vec()
+    );
+}
+
+#[test]
+fn test_chunked_sync() {
+    use crate::sync::{Chunk, ChunkImporter};
+
+    let mut rng = rand::thread_rng();
+    let leaves: Vec<(H256, H256)> = (0..300)
+        .map(|_| (rng.gen::<[u8; 32]>().into(), rng.gen::<[u8; 32]>().into()))
+        .collect();
+    let mut tree = SMT::default();
+    let root = tree.update_all(leaves).unwrap();
+
+    let chunks = tree
+        .export_chunks(64)
+        .unwrap()
+        .collect::<Result<Vec<Chunk<H256>>, _>>()
+        .unwrap();
+    assert_eq!(chunks.len(), 5);
+    assert_eq!(chunks[4].leaves().len(), 300 - 4 * 64);
+    assert!(chunks.iter().all(|c| c.verify::<Blake3Hasher>(root).unwrap()));
+
+    let mut copy = SMT::default();
+    let mut importer = ChunkImporter::new(&mut copy, root).unwrap();
+    // chunks must come in order
+    assert!(matches!(importer.import(chunks[1].clone()), Err(Error::Sync(_))));
+    for chunk in chunks.iter().cloned() {
+        assert!(!importer.is_complete());
+        importer.import(chunk).unwrap();
+    }
+    assert!(importer.is_complete());
+    assert_eq!(copy.root(), root);
+
+    // a chunk leaving out a leaf is rejected, even with a genuine proof of the rest
+    let chunk = &chunks[2];
+    let mut forged: Vec<(H256, H256)> = chunk.leaves().to_vec();
+    forged.remove(10);
+    let mut keys: Vec<H256> = forged.iter().map(|(k, _)| *k).collect();
+    keys.extend([chunk.start(), chunk.end()]);
+    keys.sort_unstable();
+    keys.dedup();
+    let forged: Chunk<H256> = serde_json::from_value(serde_json::json!({
+        "start": chunk.start(),
+        "end": chunk.end(),
+        "leaves": forged,
+        "proof": tree.merkle_proof(keys).unwrap(),
+    }))
+    .unwrap();
+    assert!(!forged.verify::<Blake3Hasher>(root).unwrap());
+    let mut other = SMT::default();
+    let mut importer = ChunkImporter::new(&mut other, root).unwrap();
+    importer.import(chunks[0].clone()).unwrap();
+    importer.import(chunks[1].clone()).unwrap();
+    assert!(matches!(importer.import(forged), Err(Error::Sync(_))));
+
+    // an empty tree is a single empty chunk
+    let empty = SMT::default();
+    let chunks = empty
+        .export_chunks(64)
+        .unwrap()
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+    assert_eq!(chunks.len(), 1);
+    let mut copy = SMT::default();
+    let mut importer = ChunkImporter::new(&mut copy, H256::zero()).unwrap();
+    importer.import(chunks[0].clone()).unwrap();
+    assert!(importer.is_complete());
+
+    // shallow trees end at their own largest key
+    type Smt64 = SparseMerkleTree<Blake3Hasher, H256, DefaultStore<H256>, 64>;
+    let mut shallow = Smt64::default();
+    let root = shallow
+        .update_all((1u64..20).map(|i| (H256::from_u64(i * 7), [1u8; 32].into())).collect())
+        .unwrap();
+    let mut copy = Smt64::default();
+    let mut importer = ChunkImporter::new(&mut copy, root).unwrap();
+    for chunk in shallow.export_chunks(8).unwrap() {
+        importer.import(chunk.unwrap()).unwrap();
+    }
+    assert!(importer.is_complete());
+    assert_eq!(copy.root(), root);
+}
+
+#[test]
+fn test_merkle_proof_by_branch_version() {
+    use vsdb::{common::INITIAL_BRANCH_NAME, VersionName, VsMgmt};
+
+    let leaves: Vec<(H256, H256)> = (1u8..20)
+        .map(|i| ([i; 32].into(), [i.wrapping_mul(3); 32].into()))
+        .collect();
+    let keys: Vec<H256> = leaves.iter().step_by(4).map(|(k, _)| *k).collect();
+
+    let mut tree = SMT::default();
+    tree.version_create(VersionName(b"a")).unwrap();
+    tree.update_all(leaves.clone()).unwrap();
+    let root_a = tree.root();
+
+    tree.version_create(VersionName(b"b")).unwrap();
+    for (k, _) in leaves.iter().skip(1).step_by(2) {
+        tree.update(*k, [0xffu8; 32].into()).unwrap();
+    }
+    assert_ne!(tree.root(), root_a);
+
+    let proof = tree
+        .merkle_proof_by_branch_version(
+            keys.clone(),
+            INITIAL_BRANCH_NAME,
+            VersionName(b"a"),
+        )
+        .unwrap();
+    let old_leaves: Vec<(H256, Option<H256>)> = leaves
+        .iter()
+        .step_by(4)
+        .map(|(k, v)| (*k, Some(*v)))
+        .collect();
+    assert!(proof
+        .clone()
+        .verify::<Blake3Hasher>(root_a, old_leaves.clone())
+        .unwrap());
+    assert!(!proof
+        .verify::<Blake3Hasher>(tree.root(), old_leaves)
+        .unwrap());
+
+    assert!(matches!(
+        tree.merkle_proof_by_branch_version(
+            keys,
+            INITIAL_BRANCH_NAME,
+            VersionName(b"x")
+        ),
+        Err(Error::VersionNotFound(_))
+    ));
+}
+
+#[test]
+fn test_value_deletion() {
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+    struct Balance {
+        owner: u64,
+        amount: u64,
+    }
+
+    // a zero amount is a deletion, though its hash is not zero
+    impl<H: Hasher> Value<H> for Balance {
+        fn to_h256(&self) -> H256 {
+            H::hash(&[self.owner.to_le_bytes(), self.amount.to_le_bytes()].concat())
+        }
+
+        fn is_deletion(&self) -> bool {
+            self.amount == 0
+        }
+    }
+
+    let key: H256 = [1u8; 32].into();
+    let mut tree = VsSmt::<Balance>::default();
+    tree.update(key, Balance { owner: 1, amount: 5 }).unwrap();
+    assert!(!tree.is_empty());
+    tree.update(key, <Balance as Value<Blake3Hasher>>::zero())
+        .unwrap();
+    assert!(tree.is_empty());
+    assert_eq!(tree.get(&key).unwrap(), None);
+
+    // any deletion removes the leaf, in batches as well
+    tree.update_all(vec![
+        (key, Balance { owner: 1, amount: 5 }),
+        ([2u8; 32].into(), Balance { owner: 2, amount: 0 }),
+    ])
+    .unwrap();
+    let root = tree.root();
+    let (_, report) = tree
+        .update_all_with_report(vec![(key, Balance { owner: 1, amount: 0 })])
+        .unwrap();
+    assert_eq!(report.count(LeafChange::Deleted), 1);
+    assert_ne!(tree.root(), root);
+    assert!(tree.is_empty());
+    assert_eq!(
+        compute_root_from_leaves::<Blake3Hasher, _>(vec![(
+            key,
+            Balance { owner: 1, amount: 0 }
+        )]),
+        H256::zero()
+    );
+
+    // values hashed to zero stay deletions by default
+    assert!(<H256 as Value<Blake3Hasher>>::is_deletion(&H256::zero()));
+    assert_eq!(<H256 as Value<Blake3Hasher>>::zero(), H256::zero());
+}
+
+#[test]
+fn test_update_strict() {
+    let mut tree = SMT::default();
+    let key: H256 = [1u8; 32].into();
+    let root = tree.update_strict(key, [2u8; 32].into()).unwrap();
+    assert_eq!(
+        tree.update_strict(key, H256::zero()).unwrap_err(),
+        Error::ImplicitDelete(key)
+    );
+    assert_eq!(tree.get(&key).unwrap(), Some([2u8; 32].into()));
+
+    // nothing of a rejected batch is written
+    let leaves = vec![([3u8; 32].into(), [3u8; 32].into()), (key, H256::zero())];
+    assert_eq!(
+        tree.update_all_strict(leaves).unwrap_err(),
+        Error::ImplicitDelete(key)
+    );
+    assert_eq!(tree.root(), root);
+    assert_eq!(tree.get(&[3u8; 32].into()).unwrap(), None);
+
+    // explicit removal still works
+    tree.remove(key).unwrap();
+    assert!(tree.is_empty());
+}
+
+#[test]
+fn test_update_all_bounded() {
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+    struct Small(Vec<u8>);
+
+    impl<H: Hasher> Value<H> for Small {
+        fn to_h256(&self) -> H256 {
+            if self.0.is_empty() {
+                H256::zero()
+            } else {
+                H::hash(&self.0)
+            }
+        }
+    }
+
+    impl BoundedValue for Small {
+        const MAX_ENCODED_LEN: usize = 8;
+    }
+
+    let mut tree =
+        SparseMerkleTree::<Blake3Hasher, Small, DefaultStore<Small>>::default();
+    let k1: H256 = [1u8; 32].into();
+    let k2: H256 = [2u8; 32].into();
+
+    tree.update_all_bounded(vec![(k1, Small(vec![1, 2, 3]))])
+        .unwrap();
+    let root = tree.root();
+
+    assert!(matches!(
+        tree.update_all_bounded(vec![(k2, Small(vec![1])), (k1, Small(vec![7; 16]))]),
+        Err(Error::ValueTooLarge { max: 8, .. })
+    ));
+    assert_eq!(tree.root(), root);
+    assert_eq!(tree.get(&k2).unwrap(), None);
+
+    // the store enforces the bound itself
+    let leaves = vec![(k2, Small(vec![1]), None), (k1, Small(vec![7; 16]), None)];
+    assert!(matches!(
+        tree.store_mut().insert_leaves_bounded(leaves, 8),
+        Err(Error::ValueTooLarge { max: 8, .. })
+    ));
+    assert_eq!(tree.store().get_leaf(&k2).unwrap(), None);
+
+    // unchanged values are not written, a value too large still fails
+    tree.update_all_bounded(vec![(k1, Small(vec![1, 2, 3]))])
+        .unwrap();
+    assert!(tree.update_all_bounded(vec![(k2, Small(vec![7; 16]))]).is_err());
+
+    assert!(H256::from([0xffu8; 32]).encode_bounded().is_ok());
+}
+
+#[test]
+fn test_root_by_branch_version() {
+    use vsdb::{common::INITIAL_BRANCH_NAME, VersionName, VsMgmt};
+
+    let mut tree = SMT::default();
+    tree.version_create(VersionName(b"a")).unwrap();
+    assert_eq!(
+        tree.root_by_branch_version(INITIAL_BRANCH_NAME, VersionName(b"a"))
+            .unwrap(),
+        H256::zero()
+    );
+
+    tree.update([1u8; 32].into(), [1u8; 32].into()).unwrap();
+    let root_a = tree.root();
+    tree.version_create(VersionName(b"b")).unwrap();
+    tree.update([2u8; 32].into(), [2u8; 32].into()).unwrap();
+    let root_b = tree.root();
+    assert_ne!(root_a, root_b);
+
+    assert_eq!(
+        tree.root_by_branch_version(INITIAL_BRANCH_NAME, VersionName(b"a"))
+            .unwrap(),
+        root_a
+    );
+    assert_eq!(
+        tree.root_by_branch_version(INITIAL_BRANCH_NAME, VersionName(b"b"))
+            .unwrap(),
+        root_b
+    );
+    assert!(matches!(
+        tree.root_by_branch_version(INITIAL_BRANCH_NAME, VersionName(b"x")),
+        Err(Error::VersionNotFound(_))
+    ));
+}
+
+#[test]
+fn test_rollback_to() {
+    use vsdb::{VersionName, VsMgmt};
+
+    let k1: H256 = [1u8; 32].into();
+    let k2: H256 = [2u8; 32].into();
+
+    let mut tree = SMT::default();
+    tree.version_create(VersionName(b"a")).unwrap();
+    tree.update(k1, [1u8; 32].into()).unwrap();
+    let root_a = tree.root();
+
+    tree.version_create(VersionName(b"b")).unwrap();
+    tree.update(k1, [11u8; 32].into()).unwrap();
+    tree.update(k2, [2u8; 32].into()).unwrap();
+    tree.version_create(VersionName(b"c")).unwrap();
+    tree.remove(k1).unwrap();
+
+    assert!(matches!(
+        tree.rollback_to(VersionName(b"x")),
+        Err(Error::VersionNotFound(_))
+    ));
+    assert_eq!(tree.get(&k2).unwrap(), Some([2u8; 32].into()));
+
+    tree.rollback_to(VersionName(b"a")).unwrap();
+    assert_eq!(tree.root(), root_a);
+    assert_eq!(tree.get(&k1).unwrap(), Some([1u8; 32].into()));
+    assert_eq!(tree.get(&k2).unwrap(), None);
+    assert!(!tree.version_exists(VersionName(b"b")));
+    assert!(tree.verify_integrity().unwrap().is_ok());
+
+    tree.version_create(VersionName(b"d")).unwrap();
+    tree.update(k2, [3u8; 32].into()).unwrap();
+    assert_eq!(tree.get(&k2).unwrap(), Some([3u8; 32].into()));
+}
+
+#[test]
+fn test_try_root() {
+    let mut tree = SMT::default();
+    assert!(tree.try_is_empty().unwrap());
+    assert_eq!(tree.try_root().unwrap(), H256::zero());
+
+    let root = tree.update([1u8; 32].into(), [1u8; 32].into()).unwrap();
+    assert!(!tree.try_is_empty().unwrap());
+    assert_eq!(tree.try_root().unwrap(), root);
+    assert_eq!(tree.root(), root);
+}
+
+#[test]
+fn test_view_at() {
+    use vsdb::{common::INITIAL_BRANCH_NAME, VersionName, VsMgmt};
+
+    let k1: H256 = [1u8; 32].into();
+    let k2: H256 = [2u8; 32].into();
+    let (br, ver_a) = (INITIAL_BRANCH_NAME, VersionName(b"a"));
+
+    let mut tree = SMT::default();
+    tree.version_create(ver_a).unwrap();
+    tree.update(k1, [1u8; 32].into()).unwrap();
+    let root_a = tree.root();
+    tree.version_create(VersionName(b"b")).unwrap();
+    tree.update(k1, [11u8; 32].into()).unwrap();
+    tree.update(k2, [2u8; 32].into()).unwrap();
+
+    let view = tree.at(br, ver_a).unwrap();
+    assert_eq!(view.root(), root_a);
+    assert_eq!(view.get(&k1).unwrap(), Some([1u8; 32].into()));
+    assert_eq!(view.get(&k2).unwrap(), None);
+    let proof = view.merkle_proof(vec![k1, k2]).unwrap();
+    assert!(proof
+        .verify::<Blake3Hasher>(
+            view.root(),
+            vec![(k1, Some([1u8; 32].into())), (k2, None)]
+        )
+        .unwrap());
+
+    // the newest version is the current state
+    let view = tree.at(br, VersionName(b"b")).unwrap();
+    assert_eq!(view.root(), tree.root());
+    assert_eq!(view.get(&k2).unwrap(), Some([2u8; 32].into()));
+
+    assert_eq!(
+        tree.at(br, VersionName(b"x")).unwrap_err(),
+        Error::VersionNotFound(b"x".to_vec())
+    );
+}
+
+#[test]
+fn test_checkpoint_restore() {
+    let k1: H256 = [1u8; 32].into();
+    let k2: H256 = [2u8; 32].into();
+
+    let mut tree = SMT::default();
+    tree.checkpoint("genesis").unwrap();
+    tree.update(k1, [1u8; 32].into()).unwrap();
+    let root1 = tree.root();
+
+    tree.checkpoint("block-1").unwrap();
+    tree.update(k2, [2u8; 32].into()).unwrap();
+    tree.update(k1, [11u8; 32].into()).unwrap();
+    tree.checkpoint(b"block-2").unwrap();
+    tree.remove(k2).unwrap();
+
+    assert!(tree.checkpoint("block-1").is_err());
+    let names = tree.list_checkpoints().unwrap();
+    assert_eq!(
+        names[names.len() - 3..],
+        [b"genesis".to_vec(), b"block-1".to_vec(), b"block-2".to_vec()]
+    );
+
+    // back to the state when `block-1` was taken
+    tree.restore("block-1").unwrap();
+    assert_eq!(tree.root(), root1);
+    assert_eq!(tree.get(&k1).unwrap(), Some([1u8; 32].into()));
+    assert_eq!(tree.get(&k2).unwrap(), None);
+    assert_eq!(tree.list_checkpoints().unwrap().last().unwrap(), b"block-1");
+    assert!(tree.verify_integrity().unwrap().is_ok());
+
+    // `block-1` takes the following writes again
+    tree.update(k2, [22u8; 32].into()).unwrap();
+    tree.restore("block-1").unwrap();
+    assert_eq!(tree.root(), root1);
+
+    tree.restore("genesis").unwrap();
+    assert!(tree.is_empty());
+    // names of dropped checkpoints can be taken again
+    tree.checkpoint("block-1").unwrap();
+    assert_eq!(
+        tree.restore("block-2").unwrap_err(),
+        Error::VersionNotFound(b"block-2".to_vec())
+    );
+}
+
+#[test]
+fn test_prune_versions_before() {
+    use vsdb::{common::INITIAL_BRANCH_NAME, VersionName, VsMgmt};
+
+    let k1: H256 = [1u8; 32].into();
+    let k2: H256 = [2u8; 32].into();
+
+    let mut tree = SMT::default();
+    tree.version_create(VersionName(b"a")).unwrap();
+    tree.update(k1, [1u8; 32].into()).unwrap();
+    tree.version_create(VersionName(b"b")).unwrap();
+    tree.update(k2, [2u8; 32].into()).unwrap();
+    let root_b = tree.root();
+    tree.version_create(VersionName(b"c")).unwrap();
+    tree.update(k1, [11u8; 32].into()).unwrap();
+    let root_c = tree.root();
+
+    assert_eq!(
+        tree.prune_versions_before(VersionName(b"x")).unwrap_err(),
+        Error::VersionNotFound(b"x".to_vec())
+    );
+    tree.prune_versions_before(VersionName(b"b")).unwrap();
+    assert!(!tree.version_exists(VersionName(b"a")));
+    assert!(tree.version_exists(VersionName(b"b")));
+    assert_eq!(tree.root(), root_c);
+
+    // `b` now carries the changes of `a`
+    let (br, ver) = (INITIAL_BRANCH_NAME, VersionName(b"b"));
+    assert_eq!(tree.root_by_branch_version(br, ver).unwrap(), root_b);
+    assert_eq!(
+        tree.get_by_branch_version(&k1, br, ver).unwrap(),
+        Some([1u8; 32].into())
+    );
+    assert_eq!(tree.root_history(..).len(), 3);
+    assert!(tree.verify_integrity().unwrap().is_ok());
+
+    tree.rollback_to(VersionName(b"b")).unwrap();
+    assert_eq!(tree.root(), root_b);
+}
+
+#[test]
+fn test_root_history() {
+    use vsdb::{VersionName, VsMgmt};
+
+    let k1: H256 = [1u8; 32].into();
+    let k2: H256 = [2u8; 32].into();
+
+    let mut tree = SMT::default();
+    assert!(tree.root_history(..).is_empty());
+
+    tree.version_create(VersionName(b"a")).unwrap();
+    let r0 = tree.update(k1, [1u8; 32].into()).unwrap();
+    let r1 = tree.update_all(vec![(k2, [2u8; 32].into())]).unwrap();
+    // nothing is written for an unchanged leaf
+    tree.update(k1, [1u8; 32].into()).unwrap();
+
+    tree.version_create(VersionName(b"b")).unwrap();
+    let (r2, removed) = tree.remove(k1).unwrap();
+    assert_eq!(removed, Some([1u8; 32].into()));
+    assert_eq!(tree.root_history(..), vec![(0, r0), (1, r1), (2, r2)]);
+    assert_eq!(tree.root_history(1..), vec![(1, r1), (2, r2)]);
+    assert_eq!(tree.root_history(..=1), vec![(0, r0), (1, r1)]);
+    assert_eq!(tree.root_at_seq(2).unwrap(), Some(tree.root()));
+    assert_eq!(tree.root_at_seq(3).unwrap(), None);
+
+    // the history follows the versions
+    tree.rollback_to(VersionName(b"a")).unwrap();
+    assert_eq!(tree.root_history(..), vec![(0, r0), (1, r1)]);
+    let (r2, _) = tree.remove(k2).unwrap();
+    assert_eq!(tree.root_at_seq(2).unwrap(), Some(r2));
+    // nothing to remove
+    assert_eq!(tree.remove(k2).unwrap(), (r2, None));
+}
+
+#[test]
+fn test_remove_prefix() {
+    let mut rng = rand::thread_rng();
+    let mut tree = SMT::default();
+    let leaves: Vec<(H256, H256)> = (0..200)
+        .map(|i| {
+            let mut k = rng.gen::<[u8; 32]>();
+            k[0] = i as u8 % 4;
+            k[1] = 0xab;
+            (k.into(), rng.gen::<[u8; 32]>().into())
+        })
+        .collect();
+    tree.update_all(leaves.clone()).unwrap();
+
+    let root = tree.root();
+    assert_eq!(tree.remove_prefix(&[9]).unwrap(), root);
+
+    let mut expected = SMT::default();
+    let kept: Vec<(H256, H256)> = leaves
+        .iter()
+        .filter(|(k, _)| k.as_slice()[0] != 2)
+        .copied()
+        .collect();
+    expected.update_all(kept.clone()).unwrap();
+    assert_eq!(tree.remove_prefix(&[2, 0xab]).unwrap(), expected.root());
+    assert_eq!(tree.root(), expected.root());
+    for (k, v) in leaves.iter() {
+        let value = tree.get(k).unwrap();
+        assert_eq!(value, (k.as_slice()[0] != 2).then_some(*v));
+    }
+    assert!(tree.verify_integrity().unwrap().is_ok());
+
+    // an empty prefix removes everything
+    assert_eq!(tree.remove_prefix(&[]).unwrap(), H256::zero());
+    assert!(tree.is_empty());
+}
+
+#[test]
+fn test_deferred_root() {
+    let mut rng = rand::thread_rng();
+    let mut eager = SMT::default();
+    let mut deferred = DeferredSmt::new(SMT::default());
+    assert_eq!(deferred.root().unwrap(), H256::zero());
+
+    let mut keys: Vec<H256> = Vec::new();
+    for _ in 0..5 {
+        for _ in 0..20 {
+            let k: H256 = rng.gen::<[u8; 32]>().into();
+            let v: H256 = rng.gen::<[u8; 32]>().into();
+            eager.update(k, v).unwrap();
+            deferred.update(k, v);
+            keys.push(k);
+        }
+        // removals and rewrites of dirty and committed keys
+        for k in keys.choose_multiple(&mut rng, 5) {
+            eager.remove(*k).unwrap();
+            deferred.remove(*k);
+            assert_eq!(deferred.get(k).unwrap(), None);
+        }
+        let k = *keys.choose(&mut rng).unwrap();
+        eager.update(k, [7u8; 32].into()).unwrap();
+        deferred.update(k, [7u8; 32].into());
+        assert_eq!(deferred.get(&k).unwrap(), Some([7u8; 32].into()));
+
+        // the root is computed without writing
+        let committed = deferred.tree().root();
+        assert_eq!(deferred.root().unwrap(), eager.root());
+        assert_eq!(deferred.tree().root(), committed);
+        assert!(deferred.is_dirty());
+
+        assert_eq!(deferred.commit_root().unwrap(), eager.root());
+        assert!(!deferred.is_dirty());
+        assert_eq!(deferred.root().unwrap(), eager.root());
+    }
+
+    deferred.update([1u8; 32].into(), [1u8; 32].into());
+    deferred.discard();
+    assert_eq!(deferred.root().unwrap(), eager.root());
+    let tree = deferred.into_inner().unwrap();
+    keys.sort_unstable();
+    keys.dedup();
+    assert_eq!(
+        tree.merkle_proof(keys.clone()).unwrap(),
+        eager.merkle_proof(keys).unwrap()
+    );
+}
+
+#[test]
+fn test_transaction() {
+    let leaves: Vec<(H256, H256)> = (1u8..10)
+        .map(|i| ([i; 32].into(), [i; 32].into()))
+        .collect();
+    let mut tree = SMT::default();
+    tree.update_all(leaves.clone()).unwrap();
+    let root = tree.root();
+
+    let k1: H256 = [1u8; 32].into();
+    let k2: H256 = [2u8; 32].into();
+    let k42: H256 = [42u8; 32].into();
+
+    let mut txn = tree.transaction();
+    txn.update(k42, [42u8; 32].into());
+    txn.remove(k1);
+    txn.update(k2, [20u8; 32].into());
+    txn.update(k2, H256::zero());
+    assert_eq!(txn.len(), 3);
+    assert_eq!(txn.get(&k42).unwrap(), Some([42u8; 32].into()));
+    assert_eq!(txn.get(&k1).unwrap(), None);
+    assert_eq!(txn.get(&k2).unwrap(), None);
+    assert_eq!(txn.get(&[3u8; 32].into()).unwrap(), Some([3u8; 32].into()));
+    txn.abort();
+    assert_eq!(tree.root(), root);
+    assert_eq!(tree.get(&k42).unwrap(), None);
+
+    let mut txn = tree.transaction();
+    txn.update(k42, [42u8; 32].into());
+    txn.remove(k1);
+    txn.update(k2, H256::zero());
+    let new_root = txn.commit().unwrap();
+    assert_eq!(new_root, tree.root());
+
+    let mut expected = SMT::default();
+    expected.update_all(leaves.clone()).unwrap();
+    expected.remove_all(vec![k1, k2]).unwrap();
+    expected.update(k42, [42u8; 32].into()).unwrap();
+    assert_eq!(new_root, expected.root());
+    assert!(tree.verify_integrity().unwrap().is_ok());
+
+    // a failed commit writes nothing
+    tree.set_verify_reads(true);
+    let height = k42.fork_height(&[3u8; 32].into());
+    let branch_key = BranchKey::new(height, k42.parent_path(height));
+    let mut branch = tree.store().get_branch(&branch_key).unwrap().unwrap();
+    branch.right = MergeValue::from_h256([0xffu8; 32].into());
+    tree.store_mut()
+        .insert_branch(branch_key.clone(), branch)
+        .unwrap();
+    let mut txn = tree.transaction();
+    txn.update(k42, [43u8; 32].into());
+    assert!(matches!(
+        txn.commit(),
+        Err(Error::CorruptedNode { height: h, .. }) if h == height
+    ));
+    assert_eq!(tree.root(), new_root);
+    tree.set_verify_reads(false);
+    assert_eq!(tree.get(&k42).unwrap(), Some([42u8; 32].into()));
+}
+
+#[test]
+fn test_blake3_domains() {
+    use crate::blake3_hasher::{Blake3Domain, DomainBlake3Hasher};
+
+    struct Keyed;
+    impl Blake3Domain for Keyed {
+        fn hasher() -> Blake3Hasher {
+            Blake3Hasher::keyed(&[7u8; 32])
+        }
+    }
+    struct Derived;
+    impl Blake3Domain for Derived {
+        fn hasher() -> Blake3Hasher {
+            Blake3Hasher::derive_key("xsmt tests domain")
+        }
+    }
+    type KeyedSmt =
+        SparseMerkleTree<DomainBlake3Hasher<Keyed>, H256, DefaultStore<H256>>;
+    type DerivedSmt =
+        SparseMerkleTree<DomainBlake3Hasher<Derived>, H256, DefaultStore<H256>>;
+
+    assert_eq!(
+        DomainBlake3Hasher::<Keyed>::hash(b"xsmt"),
+        H256::from(*blake3::keyed_hash(&[7u8; 32], b"xsmt").as_bytes())
+    );
+    let mut derived = [0u8; 32];
+    derived.copy_from_slice(&blake3::derive_key("xsmt tests domain", b"xsmt"));
+    assert_eq!(DomainBlake3Hasher::<Derived>::hash(b"xsmt"), derived.into());
+
+    let leaves: Vec<(H256, H256)> = (1u8..10)
+        .map(|i| ([i; 32].into(), [i; 32].into()))
+        .collect();
+    let mut plain = SMT::default();
+    let mut keyed = KeyedSmt::default();
+    let mut derived = DerivedSmt::default();
+    plain.update_all(leaves.clone()).unwrap();
+    keyed.update_all(leaves.clone()).unwrap();
+    derived.update_all(leaves).unwrap();
+    assert_ne!(keyed.root(), plain.root());
+    assert_ne!(keyed.root(), derived.root());
+    assert_ne!(derived.root(), plain.root());
+
+    // a proof of one domain does not verify in another
+    let key: H256 = [3u8; 32].into();
+    let leaf = vec![(key, Some(key))];
+    let proof = keyed.merkle_proof(vec![key]).unwrap();
+    assert!(proof
+        .clone()
+        .verify::<DomainBlake3Hasher<Keyed>>(keyed.root(), leaf.clone())
+        .unwrap());
+    assert!(!proof
+        .clone()
+        .verify::<Blake3Hasher>(keyed.root(), leaf.clone())
+        .unwrap());
+    assert!(!proof
+        .verify::<DomainBlake3Hasher<Derived>>(keyed.root(), leaf)
+        .unwrap());
+}
+
+#[test]
+fn test_merkle_proof_duplicated_keys() {
+    let mut tree = SMT::default();
+    tree.update_all(
+        (1u8..10)
+            .map(|i| ([i; 32].into(), [i; 32].into()))
+            .collect(),
+    )
+    .unwrap();
+    let keys: Vec<H256> = vec![[3u8; 32].into(), [5u8; 32].into(), [3u8; 32].into()];
+    assert_eq!(
+        tree.merkle_proof(keys),
+        Err(Error::DuplicatedKeys([3u8; 32].into()))
+    );
+    // also on an empty tree
+    assert_eq!(
+        SMT::default().merkle_proof(vec![[7u8; 32].into(), [7u8; 32].into()]),
+        Err(Error::DuplicatedKeys([7u8; 32].into()))
+    );
+}
+
+#[test]
+fn test_domain_tags() {
+    use crate::domain::{DomainTag, TaggedHasher};
+
+    struct Accounts;
+    impl DomainTag for Accounts {
+        const TAG: &'static [u8] = b"accounts";
+    }
+    struct Storage;
+    impl DomainTag for Storage {
+        const TAG: &'static [u8] = b"storage";
+    }
+    type AccountsHasher = TaggedHasher<Blake3Hasher, Accounts>;
+    type StorageHasher = TaggedHasher<Blake3Hasher, Storage>;
+
+    let mut tagged = b"\x08\0\0\0accounts".to_vec();
+    tagged.extend_from_slice(b"xsmt");
+    assert_eq!(AccountsHasher::hash(b"xsmt"), Blake3Hasher::hash(&tagged));
+    // the streaming and the one-shot hashing agree
+    let mut hasher = AccountsHasher::default();
+    for b in b"xsmt" {
+        hasher.write_byte(*b);
+    }
+    assert_eq!(hasher.finish(), AccountsHasher::hash(b"xsmt"));
+
+    let leaves: Vec<(H256, H256)> = (1u8..10)
+        .map(|i| ([i; 32].into(), [i; 32].into()))
+        .collect();
+    let mut accounts =
+        SparseMerkleTree::<AccountsHasher, H256, DefaultStore<H256>>::default();
+    let mut storage =
+        SparseMerkleTree::<StorageHasher, H256, DefaultStore<H256>>::default();
+    let mut plain = SMT::default();
+    accounts.update_all(leaves.clone()).unwrap();
+    storage.update_all(leaves.clone()).unwrap();
+    plain.update_all(leaves).unwrap();
+    assert_ne!(accounts.root(), storage.root());
+    assert_ne!(accounts.root(), plain.root());
+
+    let key: H256 = [3u8; 32].into();
+    let leaf = vec![(key, Some(key))];
+    let proof = accounts.merkle_proof(vec![key]).unwrap();
+    assert!(proof
+        .clone()
+        .verify::<AccountsHasher>(accounts.root(), leaf.clone())
+        .unwrap());
+    assert!(!proof
+        .verify::<StorageHasher>(accounts.root(), leaf)
+        .unwrap());
+}
+
+#[test]
+fn test_prefixed_hashing() {
+    use crate::{
+        domain::Prefixed,
+        merge::{hash_base_node, LEAF_PREFIX, NODE_PREFIX},
+    };
+
+    type PrefixedHasher = Prefixed<Blake3Hasher>;
+
+    let key: H256 = [3u8; 32].into();
+    let mut node = vec![NODE_PREFIX, 0];
+    node.extend_from_slice(key.as_slice());
+    node.extend_from_slice(key.as_slice());
+    assert_eq!(
+        hash_base_node::<PrefixedHasher>(0, &key, &key),
+        Blake3Hasher::hash(&node)
+    );
+    assert_eq!(
+        hash_base_node::<Blake3Hasher>(0, &key, &key),
+        Blake3Hasher::hash(&node[1..])
+    );
+    // leaf values are hashed by `Hasher::hash`, prefixed by `LEAF_PREFIX`
+    assert_eq!(
+        PrefixedHasher::hash(b"xsmt"),
+        Blake3Hasher::hash(&[&[LEAF_PREFIX][..], b"xsmt"].concat())
+    );
+    // a leaf value can not be crafted to hash as a node
+    assert_ne!(
+        PrefixedHasher::hash(&node[1..]),
+        hash_base_node::<PrefixedHasher>(0, &key, &key)
+    );
+
+    let leaves: Vec<(H256, H256)> = (1u8..10)
+        .map(|i| ([i; 32].into(), [i; 32].into()))
+        .collect();
+    let mut prefixed =
+        SparseMerkleTree::<PrefixedHasher, H256, DefaultStore<H256>>::default();
+    let mut plain = SMT::default();
+    prefixed.update_all(leaves.clone()).unwrap();
+    plain.update_all(leaves).unwrap();
+    assert_ne!(prefixed.root(), plain.root());
+
+    let leaf = vec![(key, Some(key))];
+    let proof = prefixed.merkle_proof(vec![key]).unwrap();
+    assert!(proof
+        .clone()
+        .verify::<PrefixedHasher>(prefixed.root(), leaf.clone())
+        .unwrap());
+    assert!(!proof.verify::<Blake3Hasher>(prefixed.root(), leaf).unwrap());
+}
+
+#[test]
+fn test_write_by_branch() {
+    use vsdb::{common::INITIAL_BRANCH_NAME, BranchName, VersionName, VsMgmt};
+
+    let leaves: Vec<(H256, H256)> = (1u8..10)
+        .map(|i| ([i; 32].into(), [i; 32].into()))
+        .collect();
+    let k1: H256 = [1u8; 32].into();
+    let k42: H256 = [42u8; 32].into();
+    let spec = BranchName(b"spec");
+
+    let mut tree = SMT::default();
+    tree.version_create(VersionName(b"a")).unwrap();
+    tree.update_all(leaves.clone()).unwrap();
+    let root = tree.root();
+    tree.branch_create(spec, VersionName(b"spec-1"), false).unwrap();
+    assert_eq!(tree.root_by_branch(spec).unwrap(), root);
+
+    // a speculative chain, the default branch keeps its own writes
+    let spec_root = tree.update_by_branch(spec, k42, [42u8; 32].into()).unwrap();
+    assert_eq!(tree.root_by_branch(spec).unwrap(), spec_root);
+    let (spec_root, removed) = tree.remove_by_branch(spec, k1).unwrap();
+    assert_eq!(removed, Some([1u8; 32].into()));
+    assert_eq!(tree.root(), root);
+    assert_eq!(tree.branch_get_default().0, INITIAL_BRANCH_NAME.0);
+    tree.update([2u8; 32].into(), [20u8; 32].into()).unwrap();
+
+    let mut expected = SMT::default();
+    expected.update_all(leaves.clone()).unwrap();
+    expected.remove(k1).unwrap();
+    expected.update(k42, [42u8; 32].into()).unwrap();
+    assert_eq!(spec_root, expected.root());
+    assert_eq!(tree.root_by_branch(spec).unwrap(), spec_root);
+    assert_eq!(
+        tree.get_by_branch(&k42, spec).unwrap(),
+        Some([42u8; 32].into())
+    );
+    assert_eq!(tree.get(&k42).unwrap(), None);
+    assert_eq!(
+        tree.update_all_by_branch(INITIAL_BRANCH_NAME, vec![]).unwrap(),
+        tree.root()
+    );
+
+    // a missing branch fails and leaves the default branch in place
+    assert!(tree
+        .update_by_branch(BranchName(b"x"), k42, [42u8; 32].into())
+        .is_err());
+    assert_eq!(tree.branch_get_default().0, INITIAL_BRANCH_NAME.0);
+    assert_eq!(tree.get(&k42).unwrap(), None);
+}
+
+#[test]
+fn test_fork() {
+    use vsdb::{common::INITIAL_BRANCH_NAME, BranchName, VersionName};
+
+    let main = INITIAL_BRANCH_NAME;
+    let fork = BranchName(b"fork");
+    let mut tree = SMT::default();
+    tree.update([1u8; 32].into(), [1u8; 32].into()).unwrap();
+    let root = tree.root();
+
+    // dropped
+    tree.fork(fork, VersionName(b"f1")).unwrap();
+    tree.update([2u8; 32].into(), [2u8; 32].into()).unwrap();
+    assert_ne!(tree.root(), root);
+    assert_eq!(tree.get_by_branch(&[2u8; 32].into(), main).unwrap(), None);
+    tree.drop_fork(fork, main).unwrap();
+    assert_eq!(tree.root(), root);
+    assert_eq!(tree.get(&[2u8; 32].into()).unwrap(), None);
+
+    // merged
+    tree.fork(fork, VersionName(b"f2")).unwrap();
+    tree.update([3u8; 32].into(), [3u8; 32].into()).unwrap();
+    tree.remove([1u8; 32].into()).unwrap();
+    let forked_root = tree.root();
+    assert_eq!(
+        tree.get_by_branch(&[1u8; 32].into(), main).unwrap(),
+        Some([1u8; 32].into())
+    );
+    tree.merge_fork(fork, main).unwrap();
+    assert_eq!(tree.root(), forked_root);
+    assert_eq!(tree.get(&[1u8; 32].into()).unwrap(), None);
+    assert!(tree.verify_integrity().unwrap().is_ok());
+}
+
+#[test]
+fn test_get_many() {
+    let leaves: Vec<(H256, H256)> = (1u8..10)
+        .map(|i| ([i; 32].into(), [i; 32].into()))
+        .collect();
+    let mut tree = SMT::default();
+    tree.update_all(leaves).unwrap();
+
+    let keys: Vec<H256> = vec![[5u8; 32].into(), [42u8; 32].into(), [2u8; 32].into()];
+    let expected = vec![Some([5u8; 32].into()), None, Some([2u8; 32].into())];
+    assert_eq!(tree.get_many(&keys).unwrap(), expected);
+    assert!(tree.get_many(&[]).unwrap().is_empty());
+    tree.set_verify_reads(true);
+    assert_eq!(tree.get_many(&keys).unwrap(), expected);
+
+    // staged leaves are read from the overlay, the others from the base store
+    let mut tree = tree.into_overlay();
+    tree.update([42u8; 32].into(), [42u8; 32].into()).unwrap();
+    tree.remove([2u8; 32].into()).unwrap();
+    let keys: Vec<H256> = [5u8, 42, 2, 7, 5].iter().map(|i| [*i; 32].into()).collect();
+    let expected: Vec<Option<H256>> = [Some(5u8), Some(42), None, Some(7), Some(5)]
+        .iter()
+        .map(|i| i.map(|i| [i; 32].into()))
+        .collect();
+    assert_eq!(tree.get_many(&keys).unwrap(), expected);
+}
+
+#[test]
+fn test_build_from_sorted() {
+    let mut rng = rand::thread_rng();
+    for n in [0usize, 1, 2, 3, 10, 100, 500] {
+        let mut leaves: Vec<(H256, H256)> = (0..n)
+            .map(|_| (rng.gen::<[u8; 32]>().into(), rng.gen::<[u8; 32]>().into()))
+            .collect();
+        leaves.sort_by_key(|(k, _)| *k);
+        // zero values are skipped
+        if n > 5 {
+            leaves[3].1 = H256::zero();
+        }
+
+        let mut expected = SMT::default();
+        expected.update_all(leaves.clone()).unwrap();
+        let mut tree = SMT::default();
+        let root = tree.build_from_sorted(leaves.clone()).unwrap();
+        assert_eq!(root, expected.root());
+        assert_eq!(tree.root(), root);
+        assert!(tree.verify_integrity().unwrap().is_ok());
+        assert_eq!(
+            tree.store().branches_map().len(),
+            expected.store().branches_map().len()
+        );
+        assert_eq!(
+            tree.store().leaves_map().len(),
+            expected.store().leaves_map().len()
+        );
+    }
+
+    // keys in a shallow tree
+    let leaves: Vec<(H256, H256)> = (0u64..50)
+        .map(|i| (H256::from_u64(i * 7), [i as u8 + 1; 32].into()))
+        .collect();
+    let mut expected =
+        SparseMerkleTree::<Blake3Hasher, H256, DefaultStore<H256>, 64>::default();
+    expected.update_all(leaves.clone()).unwrap();
+    let mut tree =
+        SparseMerkleTree::<Blake3Hasher, H256, DefaultStore<H256>, 64>::default();
+    assert_eq!(tree.build_from_sorted(leaves).unwrap(), expected.root());
+    assert!(tree.verify_integrity().unwrap().is_ok());
+
+    let mut tree = SMT::default();
+    assert_eq!(
+        tree.build_from_sorted(vec![
+            ([2u8; 32].into(), [2u8; 32].into()),
+            ([1u8; 32].into(), [1u8; 32].into()),
+        ]),
+        Err(Error::UnsortedKeys([1u8; 32].into()))
+    );
+    let mut tree = SMT::default();
+    tree.build_from_sorted(vec![([3u8; 32].into(), [3u8; 32].into())])
+        .unwrap();
+    assert_eq!(
+        tree.build_from_sorted(vec![([4u8; 32].into(), [4u8; 32].into())]),
+        Err(Error::TreeNotEmpty)
+    );
+}
+
+#[test]
+fn test_compute_root_from_leaves() {
+    let mut rng = rand::thread_rng();
+    assert_eq!(
+        compute_root_from_leaves::<Blake3Hasher, H256>(vec![]),
+        H256::zero()
+    );
+
+    let mut leaves: Vec<(H256, H256)> = (0..100)
+        .map(|_| (rng.gen::<[u8; 32]>().into(), rng.gen::<[u8; 32]>().into()))
+        .collect();
+    // a later leaf wins, a zero value is left out
+    leaves.push((leaves[0].0, [7u8; 32].into()));
+    leaves.push((leaves[1].0, H256::zero()));
+    leaves.push(([3u8; 32].into(), H256::zero()));
+
+    let mut tree = SMT::default();
+    tree.update_all(leaves.clone()).unwrap();
+    assert_eq!(
+        compute_root_from_leaves::<Blake3Hasher, H256>(leaves.clone()),
+        tree.root()
+    );
+    leaves.reverse();
+    assert_ne!(
+        compute_root_from_leaves::<Blake3Hasher, H256>(leaves),
+        tree.root()
+    );
+}
+
+#[test]
+fn test_data_dir() {
+    use crate::{
+        default_store,
+        error::{StoreError, StoreOp, StoreTarget},
+    };
+    use std::error::Error as _;
+
+    // vsdb takes its directory once per process
+    let dir = default_store::data_dir();
+    let _ = default_store::set_data_dir(&dir);
+    let err = default_store::set_data_dir(&dir).unwrap_err();
+    assert!(matches!(
+        &err,
+        Error::Store {
+            op: StoreOp::SetDataDir,
+            target: StoreTarget::DataDir(d),
+            ..
+        } if *d == dir
+    ));
+    assert!(err.source().is_some());
+
+    // the error of a backend is kept as the source
+    let io = std::io::Error::new(std::io::ErrorKind::NotFound, "no such dir");
+    let err = Error::Store {
+        op: StoreOp::SetDataDir,
+        target: StoreTarget::DataDir(dir.clone()),
+        source: StoreError::new(io),
+    };
+    let source = err.source().unwrap();
+    assert_eq!(source.to_string(), "no such dir");
+    let io = source.downcast_ref::<std::io::Error>().unwrap();
+    assert_eq!(io.kind(), std::io::ErrorKind::NotFound);
+    assert_eq!(err.clone(), err);
+
+    // stores of the same process do not share data
+    let mut tree = SMT::default();
+    let mut tree2 = SMT::default();
+    tree.update([1u8; 32].into(), [1u8; 32].into()).unwrap();
+    tree2.update([1u8; 32].into(), [2u8; 32].into()).unwrap();
+    assert_eq!(tree.get(&[1u8; 32].into()).unwrap(), Some([1u8; 32].into()));
+    assert_eq!(
+        tree2.get(&[1u8; 32].into()).unwrap(),
+        Some([2u8; 32].into())
+    );
+}
+
+#[test]
+fn test_batch_report() {
+    let mut tree = SMT::default();
+    tree.update_all(vec![
+        ([1u8; 32].into(), [1u8; 32].into()),
+        ([2u8; 32].into(), [2u8; 32].into()),
+        ([3u8; 32].into(), [3u8; 32].into()),
+    ])
+    .unwrap();
+
+    let mut expected = SMT::default();
+    expected
+        .update_all(tree.store().iter_leaves().collect())
+        .unwrap();
+    let leaves: Vec<(H256, H256)> = vec![
+        ([1u8; 32].into(), [1u8; 32].into()),
+        ([2u8; 32].into(), [0xaa; 32].into()),
+        ([3u8; 32].into(), H256::zero()),
+        ([4u8; 32].into(), [0xbb; 32].into()),
+        ([5u8; 32].into(), H256::zero()),
+        ([4u8; 32].into(), [4u8; 32].into()),
+    ];
+    let (root, report) = tree.update_all_with_report(leaves.clone()).unwrap();
+    assert_eq!(
+        report.changes,
+        vec![
+            ([1u8; 32].into(), LeafChange::Unchanged),
+            ([2u8; 32].into(), LeafChange::Updated),
+            ([3u8; 32].into(), LeafChange::Deleted),
+            ([4u8; 32].into(), LeafChange::Inserted),
+            ([5u8; 32].into(), LeafChange::Unchanged),
+        ]
+    );
+    assert_eq!(report.duplicates, 1);
+    assert_eq!(report.count(LeafChange::Unchanged), 2);
+    assert_eq!(root, tree.root());
+    assert_eq!(root, expected.update_all(leaves).unwrap());
+    assert_eq!(tree.get(&[4u8; 32].into()).unwrap(), Some([4u8; 32].into()));
+
+    let keys: Vec<H256> = vec![[2u8; 32].into(), [3u8; 32].into(), [2u8; 32].into()];
+    let (root, report) = tree.remove_all_with_report(keys).unwrap();
+    assert_eq!(
+        report,
+        BatchReport {
+            changes: vec![
+                ([2u8; 32].into(), LeafChange::Deleted),
+                ([3u8; 32].into(), LeafChange::Unchanged),
+            ],
+            duplicates: 1,
+        }
+    );
+    assert_eq!(root, tree.root());
+    assert_eq!(tree.get(&[2u8; 32].into()).unwrap(), None);
+}
+
+#[test]
+fn test_contains_key() {
+    let mut tree = SMT::default();
+    let k1: H256 = [1u8; 32].into();
+    let k2: H256 = [2u8; 32].into();
+    assert!(!tree.contains_key(&k1).unwrap());
+    tree.update(k1, [1u8; 32].into()).unwrap();
+    assert!(tree.contains_key(&k1).unwrap());
+    assert!(!tree.contains_key(&k2).unwrap());
+
+    // a zero value deletes the leaf
+    tree.update(k1, H256::zero()).unwrap();
+    assert!(!tree.contains_key(&k1).unwrap());
+
+    tree.update(k2, [2u8; 32].into()).unwrap();
+    tree.set_verify_reads(true);
+    assert!(tree.contains_key(&k2).unwrap());
+    assert!(!tree.contains_key(&k1).unwrap());
+}
+
+#[test]
+fn test_merkle_proof_with_values() {
+    let leaves: Vec<(H256, H256)> = (1u8..10)
+        .map(|i| ([i; 32].into(), [i; 32].into()))
+        .collect();
+    let mut tree = SMT::default();
+    tree.update_all(leaves).unwrap();
+
+    let keys: Vec<H256> = vec![[5u8; 32].into(), [42u8; 32].into(), [2u8; 32].into()];
+    let (proof, values) = tree.merkle_proof_with_values(keys).unwrap();
+    assert_eq!(
+        values,
+        vec![
+            ([2u8; 32].into(), Some([2u8; 32].into())),
+            ([5u8; 32].into(), Some([5u8; 32].into())),
+            ([42u8; 32].into(), None),
+        ]
+    );
+    assert!(proof.verify::<Blake3Hasher>(tree.root(), values).unwrap());
+}
+
+#[test]
+fn test_compute_new_root() {
+    let leaves: Vec<(H256, H256)> = (1u8..20)
+        .map(|i| ([i; 32].into(), [i; 32].into()))
+        .collect();
+    let mut tree = SMT::default();
+    tree.update_all(leaves).unwrap();
+
+    let keys: Vec<H256> = vec![[3u8; 32].into(), [7u8; 32].into(), [42u8; 32].into()];
+    let (proof, old_leaves) = tree.merkle_proof_with_values(keys).unwrap();
+    assert!(proof
+        .clone()
+        .verify::<Blake3Hasher>(tree.root(), old_leaves.clone())
+        .unwrap());
+
+    // [3; 32] keeps its value
+    let new_leaves = vec![
+        ([7u8; 32].into(), None),
+        ([42u8; 32].into(), Some([0xaa; 32].into())),
+    ];
+    let new_root = proof
+        .clone()
+        .compute_new_root::<Blake3Hasher>(old_leaves.clone(), new_leaves)
+        .unwrap();
+    tree.remove([7u8; 32].into()).unwrap();
+    tree.update([42u8; 32].into(), [0xaa; 32].into()).unwrap();
+    assert_eq!(new_root, tree.root());
+
+    let outside = vec![([5u8; 32].into(), None)];
+    assert_eq!(
+        proof.compute_new_root::<Blake3Hasher>(old_leaves, outside),
+        Err(Error::UnprovedKeys(vec![[5u8; 32].into()]))
+    );
+}
+
+#[test]
+fn test_proof_apply_update() {
+    let leaves: Vec<(H256, H256)> = (1u8..20)
+        .map(|i| ([i; 32].into(), [i; 32].into()))
+        .collect();
+    let mut tree = SMT::default();
+    tree.update_all(leaves).unwrap();
+
+    let keys: Vec<H256> = vec![[3u8; 32].into(), [7u8; 32].into(), [42u8; 32].into()];
+    let proof = tree.merkle_proof(keys.clone()).unwrap();
+
+    // update, remove and insert proved keys
+    let changed: Vec<(H256, H256)> = vec![
+        ([3u8; 32].into(), [0xaa; 32].into()),
+        ([7u8; 32].into(), H256::zero()),
+        ([42u8; 32].into(), [42u8; 32].into()),
+    ];
+    tree.update_all(changed.clone()).unwrap();
+    let changed_keys: Vec<H256> = changed.iter().map(|(k, _)| *k).collect();
+    let proof = proof.apply_update(&keys, &changed_keys).unwrap();
+    assert_eq!(
+        proof.merkle_path(),
+        tree.merkle_proof(keys.clone()).unwrap().merkle_path()
+    );
+    let values = changed
+        .iter()
+        .map(|(k, v)| (*k, Some(*v).filter(|v| !v.is_zero())))
+        .collect();
+    assert!(proof
+        .clone()
+        .verify::<Blake3Hasher>(tree.root(), values)
+        .unwrap());
+
+    let outside: Vec<H256> = vec![[5u8; 32].into()];
+    assert_eq!(
+        proof.apply_update(&keys, &outside),
+        Err(Error::UnprovedKeys(vec![[5u8; 32].into()]))
+    );
+    assert!(matches!(
+        proof.apply_update(&keys[1..], &changed_keys[1..]),
+        Err(Error::IncorrectNumberOfLeaves { .. })
+    ));
+}
+
+#[test]
+fn test_verify_subset() {
+    let leaves: Vec<(H256, H256)> = (1u8..10)
+        .map(|i| ([i; 32].into(), [i; 32].into()))
+        .collect();
+    let mut tree = SMT::default();
+    tree.update_all(leaves).unwrap();
+    let root = tree.root();
+
+    let keys: Vec<H256> = vec![[2u8; 32].into(), [5u8; 32].into(), [42u8; 32].into()];
+    let (proof, values) = tree.merkle_proof_with_values(keys.clone()).unwrap();
+    let subset = vec![([5u8; 32].into(), Some([5u8; 32].into()))];
+
+    // the rest is taken from the embedded values
+    assert!(proof
+        .clone()
+        .verify_subset::<Blake3Hasher>(root, &keys, subset.clone(), &values)
+        .unwrap());
+    let wrong = vec![([5u8; 32].into(), Some([6u8; 32].into()))];
+    assert!(!proof
+        .clone()
+        .verify_subset::<Blake3Hasher>(root, &keys, wrong, &values)
+        .unwrap());
+
+    // the required keys are listed instead of a count mismatch
+    assert_eq!(
+        proof.clone().verify_subset::<Blake3Hasher>(
+            root,
+            &keys,
+            subset.clone(),
+            &values[..1]
+        ),
+        Err(Error::MissingLeaves(vec![[42u8; 32].into()]))
+    );
+    let outside = vec![([7u8; 32].into(), Some([7u8; 32].into()))];
+    assert_eq!(
+        proof.verify_subset::<Blake3Hasher>(root, &keys, outside, &values),
+        Err(Error::UnprovedKeys(vec![[7u8; 32].into()]))
+    );
+}
+
+#[test]
+fn test_proof_wire_format() {
+    use vsdb::ValueEnDe;
+
+    let leaves: Vec<(H256, H256)> = (1u8..20)
+        .map(|i| ([i; 32].into(), [i; 32].into()))
+        .collect();
+    let mut tree = SMT::default();
+    tree.update_all(leaves.clone()).unwrap();
+    let keys: Vec<H256> = leaves.iter().step_by(4).map(|(k, _)| *k).collect();
+    let values: Vec<(H256, Option<H256>)> =
+        leaves.iter().step_by(4).map(|(k, v)| (*k, Some(*v))).collect();
+    let proof = tree.merkle_proof(keys.clone()).unwrap();
+    assert!(proof
+        .merkle_path()
+        .iter()
+        .any(|node| matches!(node, MergeValue::MergeWithZero { .. })));
+
+    // hashes in hex, runs of merges with zero as compact arrays
+    let json: serde_json::Value = serde_json::to_value(&proof).unwrap();
+    assert_eq!(
+        json["leaves_bitmap"][0],
+        serde_json::json!(proof.leaves_bitmap()[0].to_string())
+    );
+    for (node, wire) in proof
+        .merkle_path()
+        .iter()
+        .zip(json["merkle_path"].as_array().unwrap())
+    {
+        match node {
+            MergeValue::Value(v) => assert_eq!(wire, &serde_json::json!(v.to_string())),
+            MergeValue::MergeWithZero {
+                base_node,
+                zero_bits,
+                zero_count,
+            } => assert_eq!(
+                wire,
+                &serde_json::json!([
+                    base_node.to_string(),
+                    zero_bits.to_string(),
+                    zero_count
+                ])
+            ),
+        }
+    }
+    let decoded: MerkleProof = serde_json::from_value(json).unwrap();
+    assert_eq!(decoded, proof);
+
+    // byte string of `to_bytes` in other formats
+    assert_eq!(<MerkleProof as ValueEnDe>::decode(&proof.encode()).unwrap(), proof);
+    assert_eq!(MerkleProof::from_hex(&proof.to_hex()).unwrap(), proof);
+    assert_eq!(MerkleProof::from_hex(&proof.to_hex()[2..]).unwrap(), proof);
+    assert!(matches!(
+        MerkleProof::from_hex("0x123"),
+        Err(Error::ParseHex(_))
+    ));
+    assert!(matches!(
+        MerkleProof::from_hex("0xzz"),
+        Err(Error::ParseHex(_))
+    ));
+
+    let compiled = proof.compile(keys).unwrap();
+    let json = serde_json::to_string(&compiled).unwrap();
+    assert_eq!(json, format!("\"{}\"", compiled.to_hex()));
+    let decoded: CompiledMerkleProof = serde_json::from_str(&json).unwrap();
+    assert_eq!(decoded.0, compiled.0);
+    assert!(decoded
+        .verify::<Blake3Hasher>(tree.root(), values.clone())
+        .unwrap());
+    let decoded = <CompiledMerkleProof as ValueEnDe>::decode(&compiled.encode()).unwrap();
+    assert_eq!(decoded.0, compiled.0);
+    assert_eq!(
+        CompiledMerkleProof::from_hex(&compiled.to_hex()).unwrap().0,
+        compiled.0
+    );
+    // the program is checked
+    assert!(serde_json::from_str::<CompiledMerkleProof>("\"0x4c\"").is_err());
+    assert_eq!(
+        CompiledMerkleProof::from_hex("0x").unwrap_err(),
+        Error::EmptyProof
+    );
+}
+
+#[test]
+fn test_compiled_proof_vm_errors() {
+    let key: H256 = [1u8; 32].into();
+    let leaf = vec![(key, Some(H256::from([1u8; 32])))];
+    let run = |program: Vec<u8>, leaves: Vec<(H256, Option<H256>)>| {
+        CompiledMerkleProof(program).compute_root::<Blake3Hasher>(leaves)
+    };
+
+    assert_eq!(
+        run(vec![0x4C, 0x4F, 0x00, 0x53], leaf.clone()),
+        Err(Error::UnknownOpcode {
+            code: 0x53,
+            offset: 3
+        })
+    );
+    for program in [
+        vec![0x4C, 0x4F],
+        vec![0x4C, 0x50, 0x01, 0x02],
+        vec![0x4C, 0x51, 0x01],
+        vec![0x4C, 0x51, 0x01, 0x00, 0x00],
+    ] {
+        assert_eq!(run(program, leaf.clone()), Err(Error::TruncatedProof));
+    }
+    assert_eq!(run(vec![0x48], leaf.clone()), Err(Error::CorruptedStack));
+    assert_eq!(run(vec![], leaf.clone()), Err(Error::CorruptedStack));
+    assert_eq!(
+        run(vec![0x4C, 0x4F, 0x00, 0x4F, 0x01], leaf.clone()),
+        Err(Error::CorruptedProof)
+    );
+
+    // every pushed leaf stays on the stack
+    let leaves: Vec<(H256, Option<H256>)> = (0u64..300)
+        .map(|i| (H256::from_u64(i), Some(H256::from_u64(i + 1))))
+        .collect();
+    assert_eq!(run(vec![0x4C; 300], leaves), Err(Error::StackOverflow));
+
+    // duplicated keys never merge, and are rejected before compiling
+    let proof = MerkleProof::new(vec![H256::zero(); 300], vec![]);
+    assert_eq!(
+        proof.compute_root::<Blake3Hasher>(vec![(key, None); 300]),
+        Err(Error::DuplicatedKeys(key))
+    );
+
+    // random programs are rejected without panicking
+    let mut rng = rand::thread_rng();
+    let opcodes = [0x4C, 0x50, 0x51, 0x48, 0x4F];
+    for _ in 0..2000 {
+        let len = rng.gen_range(0..80);
+        let program: Vec<u8> = (0..len)
+            .map(|_| {
+                if rng.gen_bool(0.7) {
+                    *opcodes.choose(&mut rng).unwrap()
+                } else {
+                    rng.gen()
+                }
+            })
+            .collect();
+        let _ = run(program, leaf.clone());
+    }
+}
+
+#[test]
+fn test_verify_options() {
+    use crate::error::Limit;
+
+    let leaves: Vec<(H256, H256)> = (1u8..50)
+        .map(|i| ([i; 32].into(), [i; 32].into()))
+        .collect();
+    let mut tree = SMT::default();
+    tree.update_all(leaves.clone()).unwrap();
+    let root = tree.root();
+    let proved: Vec<(H256, Option<H256>)> =
+        leaves.iter().step_by(5).map(|(k, v)| (*k, Some(*v))).collect();
+    let keys: Vec<H256> = proved.iter().map(|(k, _)| *k).collect();
+    let proof = tree.merkle_proof(keys.clone()).unwrap();
+    let compiled = proof.clone().compile(keys).unwrap();
+
+    let options = VerifyOptions::default();
+    assert!(proof
+        .clone()
+        .verify_with_options::<Blake3Hasher>(root, proved.clone(), &options)
+        .unwrap());
+    assert!(compiled
+        .verify_with_options::<Blake3Hasher>(root, proved.clone(), &options)
+        .unwrap());
+
+    let options = VerifyOptions {
+        max_proof_bytes: compiled.0.len(),
+        max_leaves: proved.len(),
+        ..Default::default()
+    };
+    assert!(compiled
+        .verify_with_options::<Blake3Hasher>(root, proved.clone(), &options)
+        .unwrap());
+
+    let options = VerifyOptions {
+        max_proof_bytes: 100,
+        ..Default::default()
+    };
+    assert_eq!(
+        proof
+            .clone()
+            .verify_with_options::<Blake3Hasher>(root, proved.clone(), &options),
+        Err(Error::LimitExceeded {
+            limit: Limit::ProofBytes,
+            max: 100,
+            actual: proof.serialized_size()
+        })
+    );
+    assert_eq!(
+        compiled.verify_with_options::<Blake3Hasher>(root, proved.clone(), &options),
+        Err(Error::LimitExceeded {
+            limit: Limit::ProofBytes,
+            max: 100,
+            actual: compiled.0.len()
+        })
+    );
+
+    let options = VerifyOptions {
+        max_leaves: 3,
+        ..Default::default()
+    };
+    assert_eq!(
+        compiled.verify_with_options::<Blake3Hasher>(root, proved.clone(), &options),
+        Err(Error::LimitExceeded {
+            limit: Limit::Leaves,
+            max: 3,
+            actual: proved.len()
+        })
+    );
+
+    // two leaves are on the stack before they merge
+    let options = VerifyOptions {
+        max_stack: 1,
+        ..Default::default()
+    };
+    assert_eq!(
+        proof.verify_with_options::<Blake3Hasher>(root, proved.clone(), &options),
+        Err(Error::LimitExceeded {
+            limit: Limit::Stack,
+            max: 1,
+            actual: 2
+        })
+    );
+}
+
+#[test]
+fn test_ckb_compiled_proof_bytes() {
+    // a lone leaf merges with 256 zeros: `L O(0)`
+    let tree = SMT::default();
+    let key: H256 = [1u8; 32].into();
+    let proof = tree
+        .merkle_proof(vec![key])
+        .unwrap()
+        .compile(vec![key])
+        .unwrap();
+    assert_eq!(proof.to_ckb_bytes(), vec![0x4C, 0x4F, 0x00]);
+
+    let leaves: Vec<(H256, H256)> = (1u8..20)
+        .map(|i| ([i; 32].into(), [i; 32].into()))
+        .collect();
+    let mut tree = SMT::default();
+    tree.update_all(leaves.clone()).unwrap();
+    let keys: Vec<H256> = leaves.iter().step_by(3).map(|(k, _)| *k).collect();
+    let proof = tree
+        .merkle_proof(keys.clone())
+        .unwrap()
+        .compile(keys.clone())
+        .unwrap();
+    let bytes = proof.to_ckb_bytes();
+    let parsed = CompiledMerkleProof::from_ckb_bytes(&bytes).unwrap();
+    assert_eq!(parsed.0, proof.0);
+    assert!(parsed
+        .verify::<Blake3Hasher>(
+            tree.root(),
+            leaves
+                .iter()
+                .step_by(3)
+                .map(|(k, v)| (*k, Some(*v)))
+                .collect()
+        )
+        .unwrap());
+
+    assert_eq!(
+        CompiledMerkleProof::from_ckb_bytes(&[]).unwrap_err(),
+        Error::EmptyProof
+    );
+    assert_eq!(
+        CompiledMerkleProof::from_ckb_bytes(&bytes[..bytes.len() - 1]).unwrap_err(),
+        Error::CorruptedProof
+    );
+    assert_eq!(
+        CompiledMerkleProof::from_ckb_bytes(&[0x4C, 0x4F]).unwrap_err(),
+        Error::CorruptedProof
+    );
+    assert_eq!(
+        CompiledMerkleProof::from_ckb_bytes(&[0x4F, 0x00]).unwrap_err(),
+        Error::CorruptedStack
+    );
+    assert_eq!(
+        CompiledMerkleProof::from_ckb_bytes(&[0x4C, 0x4F, 0x00, 0x4F, 0x01])
+            .unwrap_err(),
+        Error::CorruptedProof
+    );
+    assert_eq!(
+        CompiledMerkleProof::from_ckb_bytes(&[0x4C, 0x52]).unwrap_err(),
+        Error::InvalidCode(0x52)
+    );
+}
+
+#[test]
+fn test_prove_absence() {
+    let leaves: Vec<(H256, H256)> = (1u8..20)
+        .map(|i| ([i; 32].into(), [i; 32].into()))
+        .collect();
+    let mut tree = SMT::default();
+    tree.update_all(leaves.clone()).unwrap();
+
+    let absent: Vec<H256> = (20u8..25).map(|i| [i; 32].into()).collect();
+    let proof = tree.prove_absence(absent.clone()).unwrap();
+    assert!(proof
+        .clone()
+        .verify_absence::<Blake3Hasher>(tree.root(), absent.clone())
+        .unwrap());
+    assert!(!proof
+        .verify_absence::<Blake3Hasher>(H256::zero(), absent.clone())
+        .unwrap());
+
+    // a present key can not be proved absent
+    let mut keys = absent.clone();
+    keys.push(leaves[3].0);
+    assert_eq!(
+        tree.prove_absence(keys.clone()).unwrap_err(),
+        Error::KeyExists(leaves[3].0)
+    );
+    let proof = tree.merkle_proof(keys.clone()).unwrap();
+    assert!(!proof
+        .verify_absence::<Blake3Hasher>(tree.root(), keys)
+        .unwrap());
+
+    assert_eq!(tree.prove_absence(vec![]).unwrap_err(), Error::EmptyKeys);
+}
+
+#[test]
+fn test_prove_empty_range() {
+    let key = |n: u64| -> H256 {
+        let mut bytes = [0u8; 32];
+        bytes[..8].copy_from_slice(&n.to_le_bytes());
+        bytes.into()
+    };
+    let mut tree = SMT::default();
+    tree.update_all(vec![
+        (key(10), [1u8; 32].into()),
+        (key(20), [2u8; 32].into()),
+        (key(30), [3u8; 32].into()),
+    ])
+    .unwrap();
+    let root = tree.root();
+
+    let max: H256 = [0xff; 32].into();
+    for (start, end) in [(key(10), key(20)), (H256::zero(), key(10)), (key(30), max)] {
+        let proof = tree.prove_empty_range(&start, &end).unwrap();
+        assert!(proof
+            .clone()
+            .verify_empty_range::<Blake3Hasher>(root, &start, &end)
+            .unwrap());
+        assert!(!proof
+            .verify_empty_range::<Blake3Hasher>(H256::zero(), &start, &end)
+            .unwrap());
+    }
+
+    assert_eq!(
+        tree.prove_empty_range(&key(9), &key(21)).unwrap_err(),
+        Error::KeyExists(key(10))
+    );
+    assert_eq!(
+        tree.prove_empty_range(&key(11), &max).unwrap_err(),
+        Error::KeyExists(key(20))
+    );
+    assert_eq!(
+        tree.prove_empty_range(&key(10), &key(11)).unwrap_err(),
+        Error::EmptyRange
+    );
+    assert_eq!(
+        tree.prove_empty_range(&key(20), &key(10)).unwrap_err(),
+        Error::EmptyRange
+    );
+
+    // a proof over the subtrees of a non-empty range must not pass
+    let keys = crate::merkle_proof::range_subtrees(&key(5), &key(25))
+        .unwrap()
+        .into_iter()
+        .map(|(k, _)| k)
+        .collect();
+    let proof = tree.merkle_proof(keys).unwrap();
+    assert!(!proof
+        .verify_empty_range::<Blake3Hasher>(root, &key(5), &key(25))
+        .unwrap());
+}
+
+#[test]
+fn test_compile_clustered_keys() {
+    let key = |n: u64| -> H256 {
+        let mut bytes = [0u8; 32];
+        bytes[..8].copy_from_slice(&n.to_le_bytes());
+        bytes.into()
+    };
+    let leaves: Vec<(H256, H256)> =
+        (0..1000).map(|i| (key(i), [1u8; 32].into())).collect();
+    let mut tree = SMT::default();
+    tree.update_all(leaves.clone()).unwrap();
+
+    let compiled_len = |keys: Vec<H256>| {
+        let proof = tree.merkle_proof(keys.clone()).unwrap();
+        let compiled = proof.compile(keys.clone()).unwrap();
+        assert!(compiled
+            .verify::<Blake3Hasher>(
+                tree.root(),
+                keys.iter().map(|k| (*k, Some([1u8; 32].into()))).collect()
+            )
+            .unwrap());
+        compiled.0.len()
+    };
+    // siblings shared by the keys are emitted only once
+    let single = compiled_len(vec![key(300)]);
+    let clustered = compiled_len((300..400).map(key).collect());
+    assert!(clustered < 2 * single, "{} vs {}", clustered, single);
+}
+
+#[test]
+fn test_tree_depth() {
+    type Smt64 = SparseMerkleTree<Blake3Hasher, H256, DefaultStore<H256>, 64>;
+    let key = |n: u64| -> H256 {
+        let mut bytes = [0u8; 32];
+        bytes[..8].copy_from_slice(&n.to_le_bytes());
+        bytes.into()
+    };
+    let leaves: Vec<(H256, H256)> = (0..50)
+        .map(|i| (key(i * 7919), [(i % 200) as u8 + 1; 32].into()))
+        .collect();
+
+    let mut full = SMT::default();
+    let mut short = Smt64::default();
+    full.update_all(leaves[..40].to_vec()).unwrap();
+    short.update_all(leaves[..40].to_vec()).unwrap();
+    for (k, v) in leaves[40..].iter() {
+        full.update(*k, *v).unwrap();
+        short.update(*k, *v).unwrap();
+    }
+    full.remove(leaves[3].0).unwrap();
+    short.remove(leaves[3].0).unwrap();
+
+    // same root, branches and proofs as the full-depth tree,
+    // only the forks and the top branch are stored at any depth
+    assert_eq!(short.root(), full.root());
+    assert_eq!(
+        short.store().branches_map().len(),
+        full.store().branches_map().len()
+    );
+    let keys = vec![leaves[0].0, leaves[3].0, key(u64::MAX)];
+    let proof = short.merkle_proof(keys.clone()).unwrap();
+    assert_eq!(proof, full.merkle_proof(keys.clone()).unwrap());
+    assert!(proof
+        .verify::<Blake3Hasher>(
+            short.root(),
+            keys.iter().map(|k| (*k, short.get(k).unwrap())).collect()
+        )
+        .unwrap());
+    assert!(short.verify_integrity().unwrap().is_ok());
+    short.set_verify_reads(true);
+    assert_eq!(short.get(&leaves[0].0).unwrap(), Some(leaves[0].1));
+    short.merkle_proof(vec![leaves[1].0]).unwrap();
+    assert_eq!(
+        short.prove_empty_range(&key(2), &key(7919)).unwrap(),
+        full.prove_empty_range(&key(2), &key(7919)).unwrap()
+    );
+
+    let mut buf = vec![];
+    full.export_snapshot(&mut buf).unwrap();
+    let mut restored = Smt64::default();
+    assert_eq!(restored.import_snapshot(&buf[..]).unwrap(), full.root());
+
+    // keys must fit in the lowest 64 bits
+    let mut long_key = key(1);
+    long_key.set_bit(64);
+    let err = Error::KeyOutOfRange(long_key);
+    assert_eq!(short.update(long_key, [1u8; 32].into()).unwrap_err(), err);
+    assert_eq!(short.remove(long_key).unwrap_err(), err);
+    assert_eq!(
+        short
+            .update_all(vec![
+                (key(1), [1u8; 32].into()),
+                (long_key, [1u8; 32].into())
+            ])
+            .unwrap_err(),
+        err
+    );
+    assert_eq!(short.merkle_proof(vec![long_key]).unwrap_err(), err);
+    assert_eq!(short.root(), full.root());
+}
+
+#[test]
+fn test_key_trait() {
+    type Smt64 = SparseMerkleTree<Blake3Hasher, H256, DefaultStore<H256>, 64>;
+    let mut tree = Smt64::default();
+    let mut plain = SMT::default();
+    for i in [3u64, 1, 4, 1, 5, 9, 2, 6, u64::MAX] {
+        tree.update_by_key(&i, [(i % 200) as u8 + 1; 32].into())
+            .unwrap();
+        plain
+            .update(i.to_path(), [(i % 200) as u8 + 1; 32].into())
+            .unwrap();
+    }
+    tree.remove_by_key(&4u64).unwrap();
+    plain.remove(4u64.to_path()).unwrap();
+    assert_eq!(tree.root(), plain.root());
+    assert_eq!(tree.get_by_key(&9u64).unwrap(), Some([10u8; 32].into()));
+    assert_eq!(tree.get_by_key(&4u64).unwrap(), None);
+
+    // integer order is kept
+    assert!(1u64.to_path() < 2u64.to_path());
+    assert!(255u64.to_path() < 256u64.to_path());
+    assert!(u32::MAX.to_path() < (u32::MAX as u64 + 1).to_path());
+    assert_eq!(7u64.fork_height(&6u64), 0);
+    assert_eq!(1u8.to_path(), 1u128.to_path());
+
+    let keys = [9u64, 4];
+    let proof = tree.merkle_proof_by_keys(&keys).unwrap();
+    assert!(proof
+        .verify::<Blake3Hasher>(
+            tree.root(),
+            vec![
+                (9u64.to_path(), Some([10u8; 32].into())),
+                (4u64.to_path(), None)
+            ]
+        )
+        .unwrap());
+
+    // a 128-bit key does not fit a 64-level tree
+    let addr = u128::MAX;
+    assert_eq!(
+        tree.update_by_key(&addr, [1u8; 32].into()).unwrap_err(),
+        Error::KeyOutOfRange(addr.to_path())
+    );
+    plain.update_by_key(&addr, [1u8; 32].into()).unwrap();
+    assert_eq!(
+        plain.get(&H256::from(addr)).unwrap(),
+        Some([1u8; 32].into())
+    );
+}
+
+#[test]
+fn test_verify_batch() {
+    let leaves: Vec<(H256, H256)> = (1u8..30)
+        .map(|i| ([i; 32].into(), [i; 32].into()))
+        .collect();
+    let mut tree = SMT::default();
+    tree.update_all(leaves.clone()).unwrap();
+    let root = tree.root();
+
+    let sets: Vec<Vec<(H256, Option<H256>)>> = (1..5)
+        .map(|n| {
+            leaves
+                .iter()
+                .step_by(n)
+                .map(|(k, v)| (*k, Some(*v)))
+                .collect()
+        })
+        .collect();
+    let proofs: Vec<MerkleProof> = sets
+        .iter()
+        .map(|set| {
+            tree.merkle_proof(set.iter().map(|(k, _)| *k).collect())
+                .unwrap()
+        })
+        .collect();
+
+    let mut wrong = sets[1].clone();
+    wrong[0].1 = Some([0xff; 32].into());
+    let items = vec![
+        (&proofs[0], root, sets[0].clone()),
+        (&proofs[1], root, wrong.clone()),
+        (&proofs[2], H256::zero(), sets[2].clone()),
+        (&proofs[3], root, vec![]),
+        (&proofs[3], root, sets[3].clone()),
+    ];
+    let expected: Vec<std::result::Result<bool, Error>> = items
+        .iter()
+        .map(|(proof, root, leaves)| {
+            (*proof)
+                .clone()
+                .verify::<Blake3Hasher>(*root, leaves.clone())
+        })
+        .collect();
+    let results = MerkleProof::verify_batch::<Blake3Hasher>(items);
+    assert_eq!(results, expected);
+    assert_eq!(
+        results,
+        vec![
+            Ok(true),
+            Ok(false),
+            Ok(false),
+            Err(Error::EmptyKeys),
+            Ok(true)
+        ]
+    );
+
+    let compiled: Vec<CompiledMerkleProof> = proofs
+        .iter()
+        .zip(sets.iter())
+        .map(|(proof, set)| {
+            proof
+                .clone()
+                .compile(set.iter().map(|(k, _)| *k).collect())
+                .unwrap()
+        })
+        .collect();
+    let corrupted = CompiledMerkleProof(vec![0x4C, 0x52]);
+    let results = CompiledMerkleProof::verify_batch::<Blake3Hasher>(vec![
+        (&compiled[0], root, sets[0].clone()),
+        (&compiled[1], root, wrong),
+        (&corrupted, root, sets[2].clone()),
+        (&compiled[3], root, sets[3].clone()),
+    ]);
+    assert_eq!(
+        results,
+        vec![
+            Ok(true),
+            Ok(false),
+            Err(Error::UnknownOpcode {
+                code: 0x52,
+                offset: 1
+            }),
+            Ok(true)
+        ]
+    );
+}
+
+#[cfg(feature = "bytes-value")]
+#[test]
+fn test_bytes_value() {
+    let mut tree = VsSmt::<Vec<u8>>::default();
+    tree.update([1u8; 32].into(), b"hello".to_vec()).unwrap();
+    assert_eq!(
+        <Vec<u8> as Value<Blake3Hasher>>::to_h256(&b"hello".to_vec()),
+        Blake3Hasher::hash(b"hello")
+    );
+    let proof = tree.merkle_proof(vec![[1u8; 32].into()]).unwrap();
+    assert!(proof
+        .verify::<Blake3Hasher>(
+            tree.root(),
+            vec![([1u8; 32].into(), Some(Blake3Hasher::hash(b"hello")))]
+        )
+        .unwrap());
+
+    // the same bytes give the same leaf, whatever the container
+    let mut tree2 = VsSmt::<String>::default();
+    tree2.update([1u8; 32].into(), "hello".to_owned()).unwrap();
+    assert_eq!(tree2.root(), tree.root());
+    let mut tree3 = VsSmt::<[u8; 5]>::default();
+    tree3.update([1u8; 32].into(), *b"hello").unwrap();
+    assert_eq!(tree3.root(), tree.root());
+
+    // empty bytes remove the leaf
+    tree.update([1u8; 32].into(), vec![]).unwrap();
+    assert!(tree.is_empty());
+
+    // H256 values are kept as they are
+    assert_eq!(
+        <H256 as Value<Blake3Hasher>>::to_h256(&[7u8; 32].into()),
+        [7u8; 32].into()
+    );
+}
+
+#[cfg(feature = "int-value")]
+#[test]
+fn test_int_value() {
+    let key: H256 = [1u8; 32].into();
+    let mut tree = VsSmt::<u64>::default();
+    tree.update(key, 100).unwrap();
+    assert_eq!(
+        <u64 as Value<Blake3Hasher>>::to_h256(&100),
+        H256::from_u64(100)
+    );
+    let proof = tree.merkle_proof(vec![key]).unwrap();
+    assert!(proof
+        .verify::<Blake3Hasher>(tree.root(), vec![(key, Some(H256::from_u64(100)))])
+        .unwrap());
+
+    // the same number gives the same leaf, whatever the width
+    let mut tree2 = VsSmt::<u128>::default();
+    tree2.update(key, 100).unwrap();
+    assert_eq!(tree2.root(), tree.root());
+
+    // a zero balance removes the leaf
+    tree.update(key, 0).unwrap();
+    assert!(tree.is_empty());
+    assert_eq!(tree.get(&key).unwrap(), None);
+
+    let mut tree3 = VsSmt::<LowBytes<8>>::default();
+    tree3.update(key, 100u64.to_le_bytes().into()).unwrap();
+    assert_eq!(tree3.root(), tree2.root());
+    assert_eq!(tree3.get(&key).unwrap(), Some(100u64.to_le_bytes().into()));
+    tree3.update(key, LowBytes::default()).unwrap();
+    assert!(tree3.is_empty());
+}
+
+#[test]
+fn test_leaf_hash_cache() {
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+    struct Hashed(u64);
+
+    impl<H: Hasher> Value<H> for Hashed {
+        fn to_h256(&self) -> H256 {
+            if self.0 == 0 {
+                H256::zero()
+            } else {
+                H::hash(&self.0.to_le_bytes())
+            }
+        }
+    }
+
+    let hash = |v: u64| <Hashed as Value<Blake3Hasher>>::to_h256(&Hashed(v));
+    let mut tree = VsSmt::<Hashed>::default();
+    let key: H256 = [1u8; 32].into();
+    let root = tree.update(key, Hashed(2)).unwrap();
+    assert_eq!(tree.store().get_leaf_hash(&key).unwrap(), Some(hash(2)));
+
+    // an identical hash neither reads nor writes any branch
+    let branch_key = BranchKey::new(u8::MAX, H256::zero());
+    tree.store_mut().remove_branch(&branch_key).unwrap();
+    assert_eq!(tree.update(key, Hashed(2)).unwrap(), root);
+    assert_eq!(tree.update_all(vec![(key, Hashed(2))]).unwrap(), root);
+    assert!(tree.store().get_branch(&branch_key).unwrap().is_none());
+
+    let mut tree = VsSmt::<Hashed>::default();
+    tree.update(key, Hashed(2)).unwrap();
+    tree.update_all(vec![(key, Hashed(3))]).unwrap();
+    assert_eq!(tree.store().get_leaf_hash(&key).unwrap(), Some(hash(3)));
+    let root = tree.update(key, Hashed(2)).unwrap();
+
+    // a plain insert drops the cached hash
+    tree.store_mut().insert_leaf(key, Hashed(3)).unwrap();
+    assert_eq!(tree.store().get_leaf_hash(&key).unwrap(), None);
+    assert_eq!(tree.update(key, Hashed(2)).unwrap(), root);
+
+    // verified reads hash the leaf, a stale cached hash does not hide bit-rot
+    tree.store_mut()
+        .insert_leaf_with_hash(key, Hashed(3), hash(2))
+        .unwrap();
+    tree.set_verify_reads(true);
+    assert_eq!(tree.update(key, Hashed(2)).unwrap(), root);
+    assert_eq!(tree.get(&key).unwrap(), Some(Hashed(2)));
+    tree.set_verify_reads(false);
+
+    tree.remove(key).unwrap();
+    assert_eq!(tree.store().get_leaf_hash(&key).unwrap(), None);
+    assert!(tree.is_empty());
+
+    // `H256` values are their own hashes, nothing is cached for them,
+    // yet an identical value is still skipped
+    let mut tree = SMT::default();
+    let root = tree.update(key, [2u8; 32].into()).unwrap();
+    assert_eq!(tree.store().get_leaf_hash(&key).unwrap(), None);
+    tree.store_mut().remove_branch(&branch_key).unwrap();
+    assert_eq!(tree.update(key, [2u8; 32].into()).unwrap(), root);
+    assert!(tree.store().get_branch(&branch_key).unwrap().is_none());
+}
+
+#[test]
+fn test_fork_only_branches() {
+    let mut rng = rand::thread_rng();
+    let mut keys: Vec<H256> = (0..100).map(|_| rng.gen::<[u8; 32]>().into()).collect();
+    // neighbors that fork right above the leaves
+    for i in 0..10 {
+        let mut k = keys[i];
+        if k.get_bit(0) {
+            k.clear_bit(0);
+        } else {
+            k.set_bit(0);
+        }
+        keys.push(k);
+    }
+    let value = |k: &H256| -> H256 {
+        let mut v = *k;
+        v.set_bit(255);
+        v
+    };
+
+    let mut tree = SMT::default();
+    tree.set_verify_reads(true);
+    tree.update_all(keys[..50].iter().map(|k| (*k, value(k))).collect())
+        .unwrap();
+    for k in keys[50..].iter() {
+        tree.update(*k, value(k)).unwrap();
+    }
+    for k in keys.iter().step_by(3) {
+        tree.remove(*k).unwrap();
+    }
+    tree.remove_all(keys.iter().skip(1).step_by(7).copied().collect())
+        .unwrap();
+
+    let mut leaves = tree.store().leaves_map().iter().collect::<Vec<_>>();
+    leaves.sort_unstable();
+    let mut expected = SMT::default();
+    expected.update_all(leaves.clone()).unwrap();
+    assert_eq!(tree.root(), expected.root());
+    assert!(tree.verify_integrity().unwrap().is_ok());
+
+    // a tree of n leaves forks n - 1 times, plus the top branch
+    assert!(tree.store().branches_map().len() <= leaves.len());
+
+    // removed keys and random ones are absent
+    for k in keys.iter() {
+        let absent: H256 = rng.gen::<[u8; 32]>().into();
+        let proof = tree.merkle_proof(vec![*k, absent]).unwrap();
+        assert_eq!(proof, expected.merkle_proof(vec![*k, absent]).unwrap());
+        let mut proved = vec![(*k, tree.get(k).unwrap()), (absent, None)];
+        proved.sort_unstable_by_key(|(k, _)| *k);
+        assert!(proof.verify::<Blake3Hasher>(tree.root(), proved).unwrap());
+    }
+
+    for k in keys.iter() {
+        tree.remove(*k).unwrap();
+    }
+    assert!(tree.is_empty());
+    assert_eq!(tree.store().branches_map().len(), 0);
+}
+
+#[cfg(feature = "borsh")]
+#[test]
+fn test_borsh_roundtrip() {
+    let leaves: Vec<(H256, H256)> = (1u8..20)
+        .map(|i| ([i; 32].into(), [i; 32].into()))
+        .collect();
+    let mut tree = SMT::default();
+    tree.update_all(leaves.clone()).unwrap();
+    let keys: Vec<H256> = leaves.iter().step_by(5).map(|(k, _)| *k).collect();
+
+    let proof = tree.merkle_proof(keys.clone()).unwrap();
+    let bytes = borsh::to_vec(&proof).unwrap();
+    let decoded: MerkleProof = borsh::from_slice(&bytes).unwrap();
+    assert_eq!(decoded, proof);
+
+    let compiled = proof.compile(keys.clone()).unwrap();
+    let bytes = borsh::to_vec(&compiled).unwrap();
+    let decoded: CompiledMerkleProof = borsh::from_slice(&bytes).unwrap();
+    assert_eq!(decoded.0, compiled.0);
+
+    let key = keys[0];
+    let bytes = borsh::to_vec(&key).unwrap();
+    assert_eq!(bytes, key.as_slice());
+    assert_eq!(borsh::from_slice::<H256>(&bytes).unwrap(), key);
+
+    let height = key.fork_height(&keys[1]);
+    let branch_key = BranchKey::new(height, key.parent_path(height));
+    let branch = tree.store().get_branch(&branch_key).unwrap().unwrap();
+    let bytes = borsh::to_vec(&branch).unwrap();
+    assert_eq!(borsh::from_slice::<BranchNode>(&bytes).unwrap(), branch);
+}
+
+#[cfg(feature = "reference-impl")]
+#[test]
+fn test_reference_impl() {
+    use crate::reference::ReferenceSmt;
+
+    let mut rng = rand::thread_rng();
+    let mut tree = SMT::default();
+    let mut reference = ReferenceSmt::<Blake3Hasher>::default();
+    assert_eq!(reference.root(), tree.root());
+
+    let mut keys: Vec<H256> = vec![];
+    for i in 0..200 {
+        let key: H256 = if i % 4 == 3 {
+            // write over or remove a known key
+            *keys.choose(&mut rng).unwrap()
+        } else {
+            rng.gen::<[u8; 32]>().into()
+        };
+        let value: H256 = if i % 5 == 4 {
+            H256::zero()
+        } else {
+            rng.gen::<[u8; 32]>().into()
+        };
+        tree.update(key, value).unwrap();
+        reference.update(key, value);
+        keys.push(key);
+    }
+    reference.compare_root(&tree).unwrap();
+    keys.sort();
+    keys.dedup();
+    reference.compare_proof(&tree, &keys).unwrap();
+    reference.compare_proof(&tree, &keys[..3]).unwrap();
+    assert_eq!(ReferenceSmt::from_tree(&tree).leaves(), reference.leaves());
+
+    // a diverging leaf is reported, also by the proofs of other keys
+    reference.update(keys[1], [9u8; 32].into());
+    assert!(matches!(
+        reference.compare_root(&tree),
+        Err(Error::RootMismatch { .. })
+    ));
+    assert!(matches!(
+        reference.compare_proof(&tree, &keys[..1]),
+        Err(Error::RootMismatch { .. })
+    ));
+}
+
+#[cfg(feature = "testing")]
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(32))]
+    #[test]
+    fn test_testing_strategies(ops in crate::testing::ops(10, 50)) {
+        let mut tree = SMT::default();
+        for op in &ops {
+            op.apply(&mut tree).unwrap();
+        }
+        let leaves = crate::testing::ops_result(&ops);
+        let mut expected = SMT::default();
+        expected.update_all(leaves.clone()).unwrap();
+        prop_assert_eq!(tree.root(), expected.root());
+
+        let mut keys: Vec<H256> = ops
+            .iter()
+            .map(|op| match op {
+                crate::testing::Op::Update(k, _) | crate::testing::Op::Remove(k) => *k,
+            })
+            .collect();
+        keys.sort();
+        keys.dedup();
+        crate::testing::assert_proofs_roundtrip(&tree, &keys);
+    }
+}
+
+#[cfg(feature = "metrics")]
+#[test]
+fn test_metrics() {
+    use crate::metrics;
+
+    let k1: H256 = [1u8; 32].into();
+    let k2: H256 = [2u8; 32].into();
+    let mut tree = SMT::default();
+
+    // counters are shared with the tests running in parallel
+    let before = metrics::snapshot();
+    tree.update_all(vec![(k1, [1u8; 32].into()), (k2, [2u8; 32].into())])
+        .unwrap();
+    tree.update(k1, [1u8; 32].into()).unwrap();
+    let proof = tree.merkle_proof(vec![k1]).unwrap();
+    let after = metrics::snapshot();
+
+    assert!(after.updates >= before.updates + 2);
+    assert!(after.leaf_writes >= before.leaf_writes + 2);
+    assert!(after.branch_writes > before.branch_writes);
+    assert!(after.proofs > before.proofs);
+    assert!(
+        after.proof_siblings >= before.proof_siblings + proof.merkle_path().len() as u64
+    );
+    assert!(after.branch_reads > before.branch_reads);
+
+    // branches served by the cache are counted apart from the store reads
+    let mut cached = SMT::default();
+    cached.set_branch_cache(256);
+    cached
+        .update_all(vec![(k1, [1u8; 32].into()), (k2, [2u8; 32].into())])
+        .unwrap();
+    let before = metrics::snapshot();
+    cached.merkle_proof(vec![k1]).unwrap();
+    assert!(metrics::snapshot().branch_cache_hits > before.branch_cache_hits);
+
+    // `H256` values cache no hash, hashed ones do
+    #[cfg(feature = "bytes-value")]
+    {
+        let mut tree = VsSmt::<Vec<u8>>::default();
+        let before = metrics::snapshot();
+        tree.update(k1, vec![1]).unwrap();
+        tree.update(k1, vec![1]).unwrap();
+        assert!(metrics::snapshot().leaf_hash_hits > before.leaf_hash_hits);
+    }
+}
+
+#[test]
+fn test_verify_detailed() {
+    let mut tree = SMT::default();
+    let leaves: Vec<(H256, H256)> = (1u8..10)
+        .map(|i| ([i; 32].into(), [i; 32].into()))
+        .collect();
+    tree.update_all(leaves).unwrap();
+    let root = tree.root();
+
+    let (k1, k2, k5): (H256, H256, H256) =
+        ([1u8; 32].into(), [2u8; 32].into(), [5u8; 32].into());
+    let proof = tree.merkle_proof(vec![k1, k2]).unwrap();
+    let compiled = proof.clone().compile(vec![k1, k2]).unwrap();
+    let valid = vec![(k1, Some(k1)), (k2, Some(k2))];
+    let wrong_value = vec![(k1, Some(k1)), (k2, Some(k5))];
+    let wrong_key = vec![(k1, Some(k1)), (k5, Some(k5))];
+    let computed = compiled.compute_root::<Blake3Hasher>(wrong_value.clone()).unwrap();
+
+    for outcome in [
+        proof.clone().verify_detailed::<Blake3Hasher>(root, valid.clone()),
+        compiled.verify_detailed::<Blake3Hasher>(root, valid.clone()),
+    ] {
+        assert!(outcome.is_valid());
+    }
+    for outcome in [
+        proof.clone().verify_detailed::<Blake3Hasher>(root, wrong_value.clone()),
+        compiled.verify_detailed::<Blake3Hasher>(root, wrong_value),
+    ] {
+        assert_eq!(
+            outcome,
+            VerifyOutcome::RootMismatch {
+                expected: root,
+                computed
+            }
+        );
+    }
+    for outcome in [
+        proof.clone().verify_detailed::<Blake3Hasher>(root, valid[..1].to_vec()),
+        compiled.verify_detailed::<Blake3Hasher>(root, valid[..1].to_vec()),
+    ] {
+        assert_eq!(
+            outcome,
+            VerifyOutcome::LeafCountMismatch {
+                expected: 2,
+                actual: 1
+            }
+        );
+    }
+    for outcome in [
+        proof.verify_detailed::<Blake3Hasher>(root, wrong_key.clone()),
+        compiled.verify_detailed::<Blake3Hasher>(root, wrong_key),
+    ] {
+        assert_eq!(outcome, VerifyOutcome::KeyMismatch);
+    }
+
+    let outcome =
+        CompiledMerkleProof(vec![0xFF]).verify_detailed::<Blake3Hasher>(root, valid);
+    assert!(matches!(outcome, VerifyOutcome::Invalid(_)));
+}
+
+#[test]
+fn test_branch_compact_bytes() {
+    let mut rng = rand::thread_rng();
+    let mut tree = SMT::default();
+    let leaves: Vec<(H256, H256)> = (0..50)
+        .map(|_| (rng.gen::<[u8; 32]>().into(), rng.gen::<[u8; 32]>().into()))
+        .collect();
+    tree.update_all(leaves).unwrap();
+    let branches: Vec<(BranchKey, BranchNode)> = tree.store().iter_branches().collect();
+    assert!(!branches.is_empty());
+    for (_, branch) in branches {
+        let bytes = branch.to_compact_bytes();
+        assert!(bytes.len() < vsdb::ValueEnDe::encode(&branch).len());
+        assert_eq!(BranchNode::from_compact_bytes(&bytes).unwrap(), branch);
+    }
+
+    // a zero child takes no bytes
+    let mut zero_bits = H256::zero();
+    zero_bits.set_bit(200);
+    let branch = BranchNode {
+        left: MergeValue::zero(),
+        right: MergeValue::MergeWithZero {
+            base_node: [7u8; 32].into(),
+            zero_bits,
+            zero_count: 3,
+        },
+    };
+    let bytes = branch.to_compact_bytes();
+    assert_eq!(bytes.len(), 1 + 1 + 32 + 2 + 1);
+    assert_eq!(BranchNode::from_compact_bytes(&bytes).unwrap(), branch);
+
+    assert_eq!(
+        BranchNode::from_compact_bytes(&[0x03]).unwrap_err(),
+        Error::InvalidCode(3)
+    );
+    assert_eq!(
+        BranchNode::from_compact_bytes(&bytes[..bytes.len() - 1]).unwrap_err(),
+        Error::InvalidLength {
+            expected: bytes.len(),
+            actual: bytes.len() - 1
+        }
+    );
+    assert_eq!(
+        BranchNode::from_compact_bytes(&[0x00, 0x00]).unwrap_err(),
+        Error::InvalidLength {
+            expected: 1,
+            actual: 2
+        }
+    );
+}
+
+#[test]
+fn test_branch_legacy_encoding() {
+    use crate::default_store::CompactBranch;
+    use vsdb::ValueEnDe;
+
+    let mut rng = rand::thread_rng();
+    let mut tree = SMT::default();
+    let leaves: Vec<(H256, H256)> = (0..50)
+        .map(|_| (rng.gen::<[u8; 32]>().into(), rng.gen::<[u8; 32]>().into()))
+        .collect();
+    tree.update_all(leaves).unwrap();
+    for (_, branch) in tree.store().iter_branches() {
+        // as stored by 3.0.7 and earlier
+        let legacy = branch.encode();
+        assert_eq!(CompactBranch::decode(&legacy).unwrap().0, branch);
+        let compact = CompactBranch(branch).encode();
+        assert_eq!(CompactBranch::decode(&compact).unwrap().0, branch);
+    }
+    assert!(CompactBranch::decode(&0u64.encode()).is_err());
+}
+
+#[test]
+fn test_len() {
+    let k = |i: u8| -> H256 { [i; 32].into() };
+    let mut tree = SMT::default();
+    assert_eq!(tree.len().unwrap(), 0);
+    assert!(tree.is_empty_exact().unwrap());
+
+    tree.update_all((1u8..10).map(|i| (k(i), k(i))).collect())
+        .unwrap();
+    assert_eq!(tree.len().unwrap(), 9);
+    // an update of an existing leaf does not count
+    tree.update(k(1), k(11)).unwrap();
+    assert_eq!(tree.len().unwrap(), 9);
+    // neither does a removal of an absent one
+    tree.remove(k(20)).unwrap();
+    tree.update(k(2), H256::zero()).unwrap();
+    assert_eq!(tree.len().unwrap(), 8);
+    assert!(!tree.is_empty_exact().unwrap());
+
+    // staged writes are counted before they are committed
+    let mut tree = tree.into_overlay();
+    tree.update(k(20), k(20)).unwrap();
+    tree.remove(k(3)).unwrap();
+    tree.remove(k(4)).unwrap();
+    assert_eq!(tree.len().unwrap(), 7);
+    let mut tree = tree.commit_overlay().unwrap();
+    assert_eq!(tree.len().unwrap(), 7);
+    assert_eq!(tree.len().unwrap(), tree.store().iter_leaves().count());
+
+    // the counter follows the versions of the store
+    tree.checkpoint("full").unwrap();
+    let keys: Vec<H256> = tree.store().iter_leaves().map(|(k, _)| k).collect();
+    for key in keys {
+        tree.remove(key).unwrap();
+    }
+    assert!(tree.is_empty_exact().unwrap());
+    tree.restore("full").unwrap();
+    assert_eq!(tree.len().unwrap(), 7);
+
+    // an unset counter, as in a store of 3.0.7 and earlier, is counted once
+    tree.store_mut().reset_leaves_count();
+    assert_eq!(tree.len().unwrap(), 7);
+    vsdb::VsMgmt::version_create(&mut tree, vsdb::VersionName(b"unset")).unwrap();
+    tree.update(k(30), k(30)).unwrap();
+    tree.remove(k(5)).unwrap();
+    tree.remove(k(6)).unwrap();
+    assert_eq!(tree.len().unwrap(), 6);
+    assert_eq!(tree.len().unwrap(), tree.store().iter_leaves().count());
+}
+
+// A store with only the required methods, all others are the defaults
+#[derive(Default)]
+struct MinimalStore {
+    branches: std::collections::BTreeMap<BranchKey, BranchNode>,
+    leaves: std::collections::BTreeMap<H256, H256>,
+    root: H256,
+}
+
+impl vsdb::VsMgmt for MinimalStore {
+    vsdb::impl_vs_methods_nope!();
+}
+
+impl traits::Store<H256> for MinimalStore {
+    fn insert_branch(
+        &mut self,
+        key: BranchKey,
+        branch: BranchNode,
+    ) -> Result<(), Error> {
+        self.branches.insert(key, branch);
+        Ok(())
+    }
+    fn remove_branch(&mut self, key: &BranchKey) -> Result<(), Error> {
+        self.branches.remove(key);
+        Ok(())
+    }
+    fn get_branch(&self, key: &BranchKey) -> Result<Option<BranchNode>, Error> {
+        Ok(self.branches.get(key).copied())
+    }
+    fn insert_leaf(&mut self, key: H256, leaf: H256) -> Result<(), Error> {
+        self.leaves.insert(key, leaf);
+        Ok(())
+    }
+    fn remove_leaf(&mut self, key: &H256) -> Result<(), Error> {
+        self.leaves.remove(key);
+        Ok(())
+    }
+    fn get_leaf(&self, key: &H256) -> Result<Option<H256>, Error> {
+        Ok(self.leaves.get(key).copied())
+    }
+    fn get_leaf_by_branch(
+        &self,
+        key: &H256,
+        _: vsdb::BranchName,
+    ) -> Result<Option<H256>, Error> {
+        self.get_leaf(key)
+    }
+    fn get_leaf_by_branch_version(
+        &self,
+        key: &H256,
+        _: vsdb::BranchName,
+        _: vsdb::VersionName,
+    ) -> Result<Option<H256>, Error> {
+        self.get_leaf(key)
+    }
+    fn update_root(&mut self, root: H256) -> Result<(), Error> {
+        self.root = root;
+        Ok(())
+    }
+    fn get_root(&self) -> Result<H256, Error> {
+        Ok(self.root)
+    }
+}
+
+#[test]
+fn test_store_defaults() {
+    let leaves: Vec<(H256, H256)> = (1u8..10)
+        .map(|i| ([i; 32].into(), [i; 32].into()))
+        .collect();
+    let mut tree: SparseMerkleTree<Blake3Hasher, H256, MinimalStore> =
+        SparseMerkleTree::new(MinimalStore::default());
+    let root = tree.update_all(leaves.clone()).unwrap();
+    assert_eq!(root, compute_root_from_leaves::<Blake3Hasher, H256>(leaves.clone()));
+    assert_eq!(tree.get(&leaves[3].0).unwrap(), Some(leaves[3].1));
+    let proof = tree.merkle_proof(vec![leaves[3].0]).unwrap();
+    assert!(proof
+        .verify::<Blake3Hasher>(root, vec![(leaves[3].0, Some(leaves[3].1))])
+        .unwrap());
+
+    // no history and no versions
+    assert!(tree.root_history(..).is_empty());
+    assert_eq!(tree.root_at_seq(0).unwrap(), None);
+    let (br, ver) = (vsdb::BranchName(b"main"), vsdb::VersionName(b"a"));
+    assert!(tree.root_by_branch_version(br, ver).is_err());
+}
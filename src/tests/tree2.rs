@@ -63,12 +63,45 @@ fn test_default_merkle_proof() {
         }
     );
 
-    // FIXME: makes room for leaves
-    // let proof = MerkleProof::new(vec![Vec::new()], Default::default());
-    // let root = proof
-    //     .compute_root::<Blake3Hasher>(vec![([42u8; 32].into(), [42u8; 32].into())])
-    //     .expect("compute root");
-    // assert_ne!(root, H256::zero());
+    let proof = MerkleProof::empty(&[[42u8; 32].into()]);
+    let root = proof
+        .compute_root::<Blake3Hasher>(vec![([42u8; 32].into(), Some([42u8; 32].into()))])
+        .expect("compute root");
+    assert_ne!(root, H256::zero());
+}
+
+#[test]
+fn test_empty_tree_proof() {
+    let tree = SMT::default();
+    for n in [1u8, 2, 5, 32] {
+        let keys: Vec<H256> = (0..n).map(|i| [i; 32].into()).collect();
+        let proof = tree.merkle_proof(&XID, keys.clone()).expect("merkle proof");
+        assert_eq!(proof, MerkleProof::empty(&keys));
+        assert!(proof
+            .clone()
+            .verify::<Blake3Hasher>(
+                tree.root(&XID),
+                keys.iter().map(|k| (*k, None)).collect()
+            )
+            .unwrap());
+        assert!(proof
+            .verify_absence::<Blake3Hasher>(tree.root(&XID), keys.clone())
+            .unwrap());
+
+        // the same root as a tree holding the leaves
+        let leaves: Vec<(H256, H256)> =
+            keys.iter().map(|k| (*k, [n; 32].into())).collect();
+        let mut tree = SMT::default();
+        tree.update_all(&XID, leaves.clone()).unwrap();
+        assert_eq!(
+            MerkleProof::empty(&keys)
+                .compute_root::<Blake3Hasher>(
+                    leaves.into_iter().map(|(k, v)| (k, Some(v))).collect()
+                )
+                .unwrap(),
+            tree.root(&XID)
+        );
+    }
 }
 
 #[test]
@@ -259,10 +292,7 @@ fn new_smt(pairs: Vec<(H256, H256)>) -> SMT {
 }
 
 fn parse_h256(s: &str) -> H256 {
-    let data = hex::decode(s).unwrap();
-    let mut inner = [0u8; 32];
-    inner.copy_from_slice(&data);
-    H256::from(inner)
+    s.parse().unwrap()
 }
 
 #[test]
@@ -518,3 +548,615 @@ fn test_max_stack_size() {
             .expect("verify")
     );
 }
+
+#[test]
+fn test_verify_reads() {
+    let mut tree = SMT::default();
+    tree.set_verify_reads(true);
+    let leaves: Vec<(H256, H256)> = (1u8..10)
+        .map(|i| ([i; 32].into(), [i; 32].into()))
+        .collect();
+    tree.update_all(&XID, leaves.clone()).unwrap();
+    tree.update_all(&XID1, leaves.clone()).unwrap();
+    let (_, removed) = tree.remove(&XID1, [1u8; 32].into()).unwrap();
+    assert_eq!(removed, Some([1u8; 32].into()));
+    assert_eq!(
+        tree.get(&XID, &[1u8; 32].into()).unwrap(),
+        Some([1u8; 32].into())
+    );
+    assert_eq!(tree.get(&XID1, &[1u8; 32].into()).unwrap(), None);
+    let keys: Vec<H256> = leaves.iter().map(|(k, _)| *k).collect();
+    let proof = tree.merkle_proof(&XID, keys).unwrap();
+    assert!(
+        proof
+            .verify::<Blake3Hasher>(
+                tree.root(&XID),
+                leaves.iter().map(|(k, v)| (*k, Some(*v))).collect()
+            )
+            .unwrap()
+    );
+}
+
+#[test]
+fn test_promote_to_smt2() {
+    let leaves: Vec<(H256, H256)> = (1u8..30)
+        .map(|i| ([i; 32].into(), [i.wrapping_mul(7); 32].into()))
+        .collect();
+    let mut tree = VsSmt::<H256>::default();
+    tree.update_all(leaves.clone()).unwrap();
+    let root = tree.root();
+
+    let mut tree2: SMT = promote_to_smt2(
+        tree,
+        &XID1,
+        DefaultStore::default(),
+        DefaultStore2::default(),
+    )
+    .unwrap();
+    assert_eq!(tree2.root(&XID1), root);
+    assert!(tree2.is_empty(&XID));
+    for (k, v) in leaves.iter() {
+        assert_eq!(tree2.get(&XID1, k).unwrap(), Some(*v));
+    }
+
+    let mut expected = SMT::default();
+    expected.update_all(&XID1, leaves.clone()).unwrap();
+    assert_eq!(tree2.xroot(), expected.xroot());
+
+    let proof = tree2.merkle_proof(&XID1, vec![leaves[3].0]).unwrap();
+    assert!(proof
+        .verify::<Blake3Hasher>(root, vec![(leaves[3].0, Some(leaves[3].1))])
+        .unwrap());
+
+    let k: H256 = [99u8; 32].into();
+    let v: H256 = [98u8; 32].into();
+    assert_eq!(
+        tree2.update(&XID1, k, v).unwrap(),
+        expected.update(&XID1, k, v).unwrap()
+    );
+}
+
+#[test]
+fn test_promote_to_existing_xid() {
+    use crate::error::{StoreOp, StoreTarget};
+    use vsdb::KeyEnDe;
+
+    let mut tree = VsSmt::<H256>::default();
+    tree.update([1u8; 32].into(), [1u8; 32].into()).unwrap();
+
+    let mut store2 = DefaultStore2::default();
+    store2.update_root(&XID1, [1u8; 32].into()).unwrap();
+    let err = promote_to_smt2::<_, _, _, _, _, DefaultStore2<Xid, H256>>(
+        tree,
+        &XID1,
+        DefaultStore::default(),
+        store2,
+    )
+    .map(|_| ())
+    .unwrap_err();
+    assert!(matches!(
+        err,
+        Error::Store {
+            op: StoreOp::Insert,
+            target: StoreTarget::Xid(xid),
+            ..
+        } if xid == XID1.encode().to_vec()
+    ));
+}
+
+#[test]
+fn test_root_by_branch_version() {
+    use vsdb::{common::INITIAL_BRANCH_NAME, VersionName, VsMgmt};
+
+    let mut tree = SMT::default();
+    tree.version_create(VersionName(b"a")).unwrap();
+    tree.update(&XID1, [1u8; 32].into(), [1u8; 32].into())
+        .unwrap();
+    let root_a = tree.root(&XID1);
+    let xroot_a = tree.xroot();
+
+    tree.version_create(VersionName(b"b")).unwrap();
+    tree.update(&XID1, [2u8; 32].into(), [2u8; 32].into())
+        .unwrap();
+    tree.update(&XID2, [2u8; 32].into(), [2u8; 32].into())
+        .unwrap();
+
+    let ver_a = VersionName(b"a");
+    assert_eq!(
+        tree.root_by_branch_version(&XID1, INITIAL_BRANCH_NAME, ver_a)
+            .unwrap(),
+        root_a
+    );
+    assert_eq!(
+        tree.root_by_branch_version(&XID2, INITIAL_BRANCH_NAME, ver_a)
+            .unwrap(),
+        H256::zero()
+    );
+    assert_eq!(
+        tree.xroot_by_branch_version(INITIAL_BRANCH_NAME, ver_a)
+            .unwrap(),
+        xroot_a
+    );
+    assert_eq!(
+        tree.root_by_branch_version(&XID1, INITIAL_BRANCH_NAME, VersionName(b"b"))
+            .unwrap(),
+        tree.root(&XID1)
+    );
+}
+
+#[test]
+fn test_merkle_proof_by_branch_version() {
+    use vsdb::{common::INITIAL_BRANCH_NAME, VersionName, VsMgmt};
+
+    let k1: H256 = [1u8; 32].into();
+    let k2: H256 = [2u8; 32].into();
+    let (br, ver_a) = (INITIAL_BRANCH_NAME, VersionName(b"a"));
+
+    let mut tree = SMT::default();
+    tree.version_create(ver_a).unwrap();
+    tree.update(&XID1, k1, [1u8; 32].into()).unwrap();
+    tree.update(&XID2, k2, [2u8; 32].into()).unwrap();
+    let root_a = tree.root(&XID1);
+    let xroot_a = tree.xroot();
+
+    tree.version_create(VersionName(b"b")).unwrap();
+    tree.update(&XID1, k1, [11u8; 32].into()).unwrap();
+    tree.update(&XID1, k2, [12u8; 32].into()).unwrap();
+    assert_eq!(
+        tree.get_by_branch_version(&XID1, &k1, br, ver_a).unwrap(),
+        Some([1u8; 32].into())
+    );
+
+    let old_leaves = vec![(k1, Some([1u8; 32].into())), (k2, None)];
+    let proof = tree
+        .merkle_proof_by_branch_version(&XID1, vec![k1, k2], br, ver_a)
+        .unwrap();
+    assert!(proof
+        .clone()
+        .verify::<Blake3Hasher>(root_a, old_leaves.clone())
+        .unwrap());
+    assert!(!proof
+        .verify::<Blake3Hasher>(tree.root(&XID1), old_leaves.clone())
+        .unwrap());
+
+    let proof = tree
+        .xmerkle_proof_by_branch_version(&XID1, vec![k1, k2], br, ver_a)
+        .unwrap();
+    assert_eq!(proof.root(), root_a);
+    assert!(proof
+        .verify::<Blake3Hasher, _>(xroot_a, &XID1, old_leaves)
+        .unwrap());
+
+    assert_eq!(
+        tree.merkle_proof_by_branch_version(&XID1, vec![k1], br, VersionName(b"x"))
+            .unwrap_err(),
+        Error::VersionNotFound(b"x".to_vec())
+    );
+}
+
+#[test]
+fn test_merkle_proof_with_values() {
+    let mut tree = SMT::default();
+    tree.update(&XID1, [1u8; 32].into(), [1u8; 32].into())
+        .unwrap();
+    tree.update(&XID2, [2u8; 32].into(), [2u8; 32].into())
+        .unwrap();
+
+    let keys: Vec<H256> = vec![[2u8; 32].into(), [1u8; 32].into()];
+    let (proof, values) = tree.merkle_proof_with_values(&XID1, keys).unwrap();
+    assert_eq!(
+        values,
+        vec![
+            ([1u8; 32].into(), Some([1u8; 32].into())),
+            ([2u8; 32].into(), None),
+        ]
+    );
+    assert!(proof
+        .verify::<Blake3Hasher>(tree.root(&XID1), values)
+        .unwrap());
+}
+
+#[test]
+fn test_prove_absence() {
+    let mut tree = SMT::default();
+    tree.update(&XID1, [1u8; 32].into(), [1u8; 32].into())
+        .unwrap();
+    tree.update(&XID2, [2u8; 32].into(), [2u8; 32].into())
+        .unwrap();
+
+    let keys: Vec<H256> = vec![[2u8; 32].into(), [3u8; 32].into()];
+    let proof = tree.prove_absence(&XID1, keys.clone()).unwrap();
+    assert!(proof
+        .verify_absence::<Blake3Hasher>(tree.root(&XID1), keys.clone())
+        .unwrap());
+
+    assert_eq!(
+        tree.prove_absence(&XID2, keys).unwrap_err(),
+        Error::KeyExists([2u8; 32].into())
+    );
+}
+
+#[test]
+fn test_prove_empty_range() {
+    let mut tree = SMT::default();
+    tree.update(&XID1, [1u8; 32].into(), [1u8; 32].into())
+        .unwrap();
+    tree.update(&XID2, [2u8; 32].into(), [2u8; 32].into())
+        .unwrap();
+
+    let start: H256 = [0u8; 32].into();
+    let end: H256 = [2u8; 32].into();
+    let proof = tree.prove_empty_range(&XID2, &start, &end).unwrap();
+    assert!(proof
+        .verify_empty_range::<Blake3Hasher>(tree.root(&XID2), &start, &end)
+        .unwrap());
+
+    let end: H256 = [3u8; 32].into();
+
+    assert_eq!(
+        tree.prove_empty_range(&XID1, &start, &end).unwrap_err(),
+        Error::KeyExists([1u8; 32].into())
+    );
+}
+
+#[test]
+fn test_key_trait() {
+    let mut tree = SMT::default();
+    tree.update_by_key(&XID1, &7u64, [7u8; 32].into()).unwrap();
+    assert_eq!(
+        tree.get(&XID1, &7u64.to_path()).unwrap(),
+        Some([7u8; 32].into())
+    );
+    assert_eq!(
+        tree.get_by_key(&XID1, &7u64).unwrap(),
+        Some([7u8; 32].into())
+    );
+    assert_eq!(tree.get_by_key(&XID2, &7u64).unwrap(), None);
+
+    let proof = tree.merkle_proof_by_keys(&XID1, &[7u64]).unwrap();
+    assert!(proof
+        .verify::<Blake3Hasher>(
+            tree.root(&XID1),
+            vec![(7u64.to_path(), Some([7u8; 32].into()))]
+        )
+        .unwrap());
+
+    tree.remove_by_key(&XID1, &7u64).unwrap();
+    assert!(tree.is_empty(&XID1));
+}
+
+#[test]
+fn test_xroot_history() {
+    let mut tree = SMT::default();
+    tree.update(&XID1, [1u8; 32].into(), [1u8; 32].into())
+        .unwrap();
+    let x0 = tree.xroot();
+    tree.update(&XID2, [2u8; 32].into(), [2u8; 32].into())
+        .unwrap();
+    let x1 = tree.xroot();
+    assert_eq!(tree.xroot_history(..), vec![(0, x0), (1, x1)]);
+    assert_eq!(tree.xroot_at_seq(1).unwrap(), Some(x1));
+}
+
+#[test]
+fn test_batch_report() {
+    let mut tree = SMT::default();
+    tree.update(&XID, [1u8; 32].into(), [1u8; 32].into())
+        .unwrap();
+    let (root, report) = tree
+        .update_all_with_report(
+            &XID,
+            vec![
+                ([1u8; 32].into(), [2u8; 32].into()),
+                ([2u8; 32].into(), [2u8; 32].into()),
+            ],
+        )
+        .unwrap();
+    assert_eq!(root, tree.root(&XID));
+    assert_eq!(report.count(LeafChange::Updated), 1);
+    assert_eq!(report.count(LeafChange::Inserted), 1);
+
+    // other xids are untouched
+    let (_, report) = tree
+        .remove_all_with_report(&XID1, vec![[1u8; 32].into()])
+        .unwrap();
+    assert_eq!(
+        report.changes,
+        vec![([1u8; 32].into(), LeafChange::Unchanged)]
+    );
+    let (_, report) = tree
+        .remove_all_with_report(&XID, vec![[1u8; 32].into()])
+        .unwrap();
+    assert_eq!(
+        report.changes,
+        vec![([1u8; 32].into(), LeafChange::Deleted)]
+    );
+}
+
+#[test]
+fn test_contains_key() {
+    let mut tree = SMT::default();
+    let key: H256 = [1u8; 32].into();
+    tree.update(&XID1, key, [1u8; 32].into()).unwrap();
+    assert!(tree.contains_key(&XID1, &key).unwrap());
+    assert!(!tree.contains_key(&XID2, &key).unwrap());
+    tree.remove(&XID1, key).unwrap();
+    assert!(!tree.contains_key(&XID1, &key).unwrap());
+}
+
+#[test]
+fn test_get_many() {
+    let mut tree = SMT::default();
+    let k1: H256 = [1u8; 32].into();
+    let k2: H256 = [2u8; 32].into();
+    tree.update(&XID1, k1, [1u8; 32].into()).unwrap();
+    tree.update(&XID2, k2, [2u8; 32].into()).unwrap();
+    assert_eq!(
+        tree.get_many(&XID1, &[k2, k1]).unwrap(),
+        vec![None, Some([1u8; 32].into())]
+    );
+}
+
+#[test]
+fn test_xids() {
+    let mut tree = SMT::default();
+    assert!(tree.xids().is_empty());
+
+    tree.update(&XID1, [1u8; 32].into(), [1u8; 32].into())
+        .unwrap();
+    tree.update(&XID2, [2u8; 32].into(), [2u8; 32].into())
+        .unwrap();
+    let mut xids = tree.xids();
+    xids.sort_unstable();
+    assert_eq!(xids, vec![XID1, XID2]);
+
+    // emptied subtrees are not listed
+    tree.remove(&XID1, [1u8; 32].into()).unwrap();
+    assert_eq!(tree.xids(), vec![XID2]);
+    tree.remove_x(&XID2).unwrap();
+    assert!(tree.xids().is_empty());
+}
+
+#[test]
+fn test_xmerkle_proof() {
+    let k1: H256 = [1u8; 32].into();
+    let k2: H256 = [2u8; 32].into();
+    let v1: H256 = [11u8; 32].into();
+
+    let mut tree = SMT::default();
+    tree.update(&XID1, k1, v1).unwrap();
+    tree.update(&XID2, k2, [22u8; 32].into()).unwrap();
+    let xroot = tree.xroot();
+
+    let proof = tree.xmerkle_proof(&XID1, vec![k1, k2]).unwrap();
+    assert_eq!(proof.root(), tree.root(&XID1));
+    assert!(proof
+        .clone()
+        .verify::<Blake3Hasher, _>(xroot, &XID1, vec![(k1, Some(v1)), (k2, None)])
+        .unwrap());
+    // bound to both the xid and the leaves
+    assert!(!proof
+        .clone()
+        .verify::<Blake3Hasher, _>(xroot, &XID2, vec![(k1, Some(v1)), (k2, None)])
+        .unwrap());
+    assert!(!proof
+        .verify::<Blake3Hasher, _>(xroot, &XID1, vec![(k1, None), (k2, None)])
+        .unwrap());
+
+    // an empty subtree
+    let proof = tree.xmerkle_proof(&XID, vec![k1]).unwrap();
+    assert!(proof.root().is_zero());
+    assert!(proof
+        .verify::<Blake3Hasher, _>(xroot, &XID, vec![(k1, None)])
+        .unwrap());
+}
+
+#[test]
+fn test_merkle_proof_multi_x() {
+    let k1: H256 = [1u8; 32].into();
+    let k2: H256 = [2u8; 32].into();
+    let v1: H256 = [11u8; 32].into();
+    let v2: H256 = [22u8; 32].into();
+
+    let mut tree = SMT::default();
+    tree.update(&XID1, k1, v1).unwrap();
+    tree.update(&XID2, k2, v2).unwrap();
+    tree.update(&XID2, k1, v1).unwrap();
+    let xroot = tree.xroot();
+
+    // an empty subtree is proved absent
+    let proof = tree
+        .merkle_proof_multi_x(vec![
+            (XID2, vec![k2, k1]),
+            (XID1, vec![k1]),
+            (XID, vec![k1]),
+        ])
+        .unwrap();
+    assert_eq!(
+        proof.roots(),
+        vec![tree.root(&XID2), tree.root(&XID1), H256::zero()]
+    );
+    let leaves = vec![
+        (XID2, vec![(k2, Some(v2)), (k1, Some(v1))]),
+        (XID1, vec![(k1, Some(v1))]),
+        (XID, vec![(k1, None)]),
+    ];
+    assert!(proof
+        .clone()
+        .verify::<Blake3Hasher, _>(xroot, leaves.clone())
+        .unwrap());
+
+    // bound to every xid and every leaf
+    let mut swapped = leaves.clone();
+    swapped[0].0 = XID1;
+    swapped[1].0 = XID2;
+    assert!(!proof
+        .clone()
+        .verify::<Blake3Hasher, _>(xroot, swapped)
+        .unwrap());
+    let mut changed = leaves.clone();
+    changed[1].1 = vec![(k1, Some(v2))];
+    assert!(!proof
+        .clone()
+        .verify::<Blake3Hasher, _>(xroot, changed)
+        .unwrap());
+    assert_eq!(
+        proof
+            .verify::<Blake3Hasher, _>(xroot, leaves[..2].to_vec())
+            .unwrap_err(),
+        Error::IncorrectNumberOfLeaves {
+            expected: 3,
+            actual: 2
+        }
+    );
+
+    assert!(matches!(
+        tree.merkle_proof_multi_x(vec![(XID1, vec![k1]), (XID1, vec![k2])])
+            .unwrap_err(),
+        Error::DuplicatedKeys(_)
+    ));
+}
+
+#[test]
+fn test_update_all_multi() {
+    let mut rng = rand::thread_rng();
+    let mut gen_pairs = |n| -> Vec<(H256, H256)> {
+        (0..n)
+            .map(|_| (rng.gen::<[u8; 32]>().into(), rng.gen::<[u8; 32]>().into()))
+            .collect()
+    };
+    let (p1, p2, p3) = (gen_pairs(20), gen_pairs(20), gen_pairs(5));
+    // overwrite and delete some leaves of XID1 in a later batch
+    let mut p4 = vec![(p1[0].0, [7u8; 32].into()), (p1[1].0, H256::zero())];
+    p4.extend(p3.clone());
+
+    let mut expected = SMT::default();
+    expected.update_all(&XID1, p1.clone()).unwrap();
+    expected.update_all(&XID2, p2.clone()).unwrap();
+    expected.update_all(&XID1, p4.clone()).unwrap();
+
+    let mut tree = SMT::default();
+    let roots = tree
+        .update_all_multi(vec![(XID1, p1), (XID2, p2), (XID1, p4), (XID, vec![])])
+        .unwrap();
+    assert_eq!(
+        roots,
+        vec![
+            (XID1, expected.root(&XID1)),
+            (XID2, expected.root(&XID2)),
+            (XID, H256::zero())
+        ]
+    );
+    assert_eq!(tree.xroot(), expected.xroot());
+    assert_eq!(tree.xroot_history(..).len(), 1);
+    assert_eq!(tree.get(&XID1, &p3[0].0).unwrap(), Some(p3[0].1));
+}
+
+#[test]
+fn test_clone_x() {
+    let mut rng = rand::thread_rng();
+    let pairs: Vec<(H256, H256)> = (0..50)
+        .map(|_| (rng.gen::<[u8; 32]>().into(), rng.gen::<[u8; 32]>().into()))
+        .collect();
+
+    let mut tree = SMT::default();
+    tree.update_all(&XID1, pairs.clone()).unwrap();
+    tree.update(&XID2, [2u8; 32].into(), [2u8; 32].into())
+        .unwrap();
+
+    // an existing subtree is replaced
+    tree.clone_x(&XID1, &XID2).unwrap();
+    assert_eq!(tree.root(&XID2), tree.root(&XID1));
+    assert_eq!(tree.get(&XID2, &[2u8; 32].into()).unwrap(), None);
+    for (k, v) in pairs.iter() {
+        assert_eq!(tree.get(&XID2, k).unwrap(), Some(*v));
+    }
+    assert_eq!(tree.len(&XID2).unwrap(), pairs.len());
+    // a subtree copied onto itself is left as it is
+    tree.clone_x(&XID1, &XID1).unwrap();
+    assert_eq!(tree.len(&XID1).unwrap(), pairs.len());
+
+    // same global root as building the copy from scratch
+    let mut expected = SMT::default();
+    expected.update_all(&XID1, pairs.clone()).unwrap();
+    expected.update_all(&XID2, pairs.clone()).unwrap();
+    assert_eq!(tree.xroot(), expected.xroot());
+
+    // the copy evolves independently
+    let k = pairs[0].0;
+    tree.update(&XID2, k, [9u8; 32].into()).unwrap();
+    assert_eq!(tree.get(&XID1, &k).unwrap(), Some(pairs[0].1));
+    let proof = tree.merkle_proof(&XID1, vec![k]).unwrap();
+    assert!(proof
+        .verify::<Blake3Hasher>(tree.root(&XID1), vec![(k, Some(pairs[0].1))])
+        .unwrap());
+
+    // cloning an empty subtree empties the destination
+    tree.clone_x(&XID, &XID2).unwrap();
+    assert!(tree.is_empty(&XID2));
+    assert_eq!(tree.xids(), vec![XID1]);
+}
+
+#[test]
+fn test_len() {
+    let k = |i: u8| -> H256 { [i; 32].into() };
+    let mut tree = SMT::default();
+    assert!(tree.is_empty_exact(&XID1).unwrap());
+
+    tree.update_all(&XID1, (1u8..10).map(|i| (k(i), k(i))).collect())
+        .unwrap();
+    tree.update(&XID2, k(1), k(1)).unwrap();
+    tree.update(&XID1, k(1), k(11)).unwrap();
+    assert_eq!(tree.remove(&XID1, k(2)).unwrap().1, Some(k(2)));
+    assert_eq!(tree.remove(&XID1, k(2)).unwrap().1, None);
+    assert_eq!(tree.len(&XID1).unwrap(), 8);
+    assert_eq!(tree.len(&XID2).unwrap(), 1);
+
+    tree.clone_x(&XID1, &XID2).unwrap();
+    assert_eq!(tree.len(&XID2).unwrap(), 8);
+    tree.remove_x(&XID1).unwrap();
+    assert!(tree.is_empty_exact(&XID1).unwrap());
+    assert_eq!(tree.len(&XID2).unwrap(), 8);
+}
+
+#[test]
+fn test_leaf_hash_cache() {
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+    struct Hashed(u64);
+
+    impl<H: Hasher> Value<H> for Hashed {
+        fn to_h256(&self) -> H256 {
+            if self.0 == 0 {
+                H256::zero()
+            } else {
+                H::hash(&self.0.to_le_bytes())
+            }
+        }
+    }
+
+    let key: H256 = [1u8; 32].into();
+    let hash = <Hashed as Value<Blake3Hasher>>::to_h256(&Hashed(2));
+    let mut tree = VsSmt2::<Xid, Hashed>::default();
+    let root = tree.update(&XID1, key, Hashed(2)).unwrap();
+    assert_eq!(tree.store().get_leaf_hash(&XID1, &key).unwrap(), Some(hash));
+    assert_eq!(tree.store().get_leaf_hash(&XID2, &key).unwrap(), None);
+
+    // an identical cached hash writes nothing, the leaf is not read
+    tree.store_mut().insert_leaf(&XID1, key, Hashed(4)).unwrap();
+    assert_eq!(tree.store().get_leaf_hash(&XID1, &key).unwrap(), None);
+    tree.store_mut()
+        .insert_leaf_with_hash(&XID1, key, Hashed(4), hash)
+        .unwrap();
+    assert_eq!(tree.update(&XID1, key, Hashed(2)).unwrap(), root);
+    assert_eq!(tree.get(&XID1, &key).unwrap(), Some(Hashed(4)));
+
+    // verified reads hash the leaf instead
+    tree.set_verify_reads(true);
+    assert_eq!(tree.update(&XID1, key, Hashed(2)).unwrap(), root);
+    assert_eq!(tree.get(&XID1, &key).unwrap(), Some(Hashed(2)));
+
+    tree.remove_x(&XID1).unwrap();
+    assert_eq!(tree.store().get_leaf_hash(&XID1, &key).unwrap(), None);
+}
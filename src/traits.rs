@@ -1,65 +1,325 @@
 use crate::{
-    error::Error,
+    error::{Error, StoreOp, StoreTarget},
     tree::{BranchKey, BranchNode},
-    H256,
+    H256, HN,
 };
-use std::result::Result as StdResult;
-use vsdb::{BranchName, VersionName, VsMgmt};
+use std::{
+    ops::{Bound, RangeBounds},
+    result::Result as StdResult,
+};
+use vsdb::{common::RawBytes, BranchName, KeyEnDe, ValueEnDe, VersionName, VsMgmt};
 
 /// Trait for customize hash function
 pub trait Hasher: Default {
+    /// Hash leaf values and tree nodes with distinct prefixes,
+    /// `merge::LEAF_PREFIX` and `merge::NODE_PREFIX`: the tree writes
+    /// `NODE_PREFIX` first into the hasher of a node, and `hash` must start
+    /// its input with `LEAF_PREFIX`.
+    ///
+    /// It changes all roots, so it is off unless turned on,
+    /// e.g. by `domain::Prefixed`.
+    const PREFIXED: bool = false;
+
     fn write_h256(&mut self, h: &H256);
     fn write_byte(&mut self, b: u8);
     fn finish(self) -> H256;
     fn hash(bytes: &[u8]) -> H256;
 }
 
+/// A hash function with a digest of `N` bytes,
+/// e.g. for the 20 or 64-byte commitments of other systems.
+///
+/// The tree is built on a `Hasher`, see `h256::NarrowHasher`
+/// to build it on a digest of up to 32 bytes.
+pub trait Digest<const N: usize>: Default {
+    fn update(&mut self, bytes: &[u8]);
+    fn finalize(self) -> HN<N>;
+
+    #[inline(always)]
+    fn digest(bytes: &[u8]) -> HN<N> {
+        let mut d = Self::default();
+        d.update(bytes);
+        d.finalize()
+    }
+}
+
 /// Trait for define value structures
 pub trait Value<H> {
+    /// Whether stores cache the hash of this value next to it(see
+    /// `Store::insert_leaf_with_hash`), off for values whose `to_h256`
+    /// costs no more than reading a cached hash, e.g. `H256` itself.
+    const CACHE_HASH: bool = true;
+
     fn to_h256(&self) -> H256;
+
+    /// The value removing a leaf when written, `Default::default()` unless overridden;
+    /// it must be a deletion by `is_deletion`.
+    #[inline(always)]
+    fn zero() -> Self
+    where
+        Self: Sized + Default,
+    {
+        Self::default()
+    }
+
+    /// Whether writing this value removes the leaf instead of storing it,
+    /// by default whether its hash is zero.
+    ///
+    /// A value hashed to zero is always a deletion, whatever this returns.
+    #[inline(always)]
+    fn is_deletion(&self) -> bool {
+        self.to_h256().is_zero()
+    }
 }
 
 impl<H> Value<H> for H256 {
+    const CACHE_HASH: bool = false;
+
     fn to_h256(&self) -> H256 {
         *self
     }
 }
 
+#[cfg(feature = "pt11")]
 impl<H> Value<H> for pt11::H256 {
+    const CACHE_HASH: bool = false;
+
     fn to_h256(&self) -> H256 {
         <H256 as Value<H>>::to_h256(&H256::from(self))
     }
 }
 
+#[cfg(feature = "pt11")]
 impl<H> Value<H> for pt11::H160 {
+    const CACHE_HASH: bool = false;
+
     fn to_h256(&self) -> H256 {
         <H256 as Value<H>>::to_h256(&H256::from(self))
     }
 }
 
+#[cfg(feature = "pt10")]
 impl<H> Value<H> for pt10::H256 {
+    const CACHE_HASH: bool = false;
+
     fn to_h256(&self) -> H256 {
         <H256 as Value<H>>::to_h256(&H256::from(self))
     }
 }
 
+#[cfg(feature = "pt10")]
 impl<H> Value<H> for pt10::H160 {
+    const CACHE_HASH: bool = false;
+
     fn to_h256(&self) -> H256 {
         <H256 as Value<H>>::to_h256(&H256::from(self))
     }
 }
 
+/// Trait for leaf keys, a key is placed at the path given by `to_path`.
+///
+/// Integers sit in the lowest bytes(little-endian), so their order is kept
+/// and they fit a tree whose `DEPTH` is their bit width.
+pub trait Key {
+    fn to_path(&self) -> H256;
+
+    #[inline(always)]
+    fn get_bit(&self, i: u8) -> bool {
+        self.to_path().get_bit(i)
+    }
+
+    #[inline(always)]
+    fn parent_path(&self, height: u8) -> H256 {
+        self.to_path().parent_path(height)
+    }
+
+    #[inline(always)]
+    fn fork_height(&self, other: &Self) -> u8 {
+        self.to_path().fork_height(&other.to_path())
+    }
+}
+
+impl Key for H256 {
+    #[inline(always)]
+    fn to_path(&self) -> H256 {
+        *self
+    }
+}
+
+impl Key for [u8; 32] {
+    #[inline(always)]
+    fn to_path(&self) -> H256 {
+        H256::from(self)
+    }
+}
+
+macro_rules! impl_key_for_int {
+    ($($t: ty),+) => {
+        $(
+            impl Key for $t {
+                #[inline(always)]
+                fn to_path(&self) -> H256 {
+                    let mut bytes = [0u8; 32];
+                    bytes[..core::mem::size_of::<$t>()]
+                        .copy_from_slice(&self.to_le_bytes());
+                    bytes.into()
+                }
+            }
+        )+
+    };
+}
+
+impl_key_for_int!(u8, u16, u32, u64, u128);
+
+#[cfg(feature = "pt11")]
+impl Key for pt11::H256 {
+    #[inline(always)]
+    fn to_path(&self) -> H256 {
+        H256::from(self)
+    }
+}
+
+#[cfg(feature = "pt11")]
+impl Key for pt11::H160 {
+    #[inline(always)]
+    fn to_path(&self) -> H256 {
+        H256::from(self)
+    }
+}
+
+#[cfg(feature = "pt10")]
+impl Key for pt10::H256 {
+    #[inline(always)]
+    fn to_path(&self) -> H256 {
+        H256::from(self)
+    }
+}
+
+#[cfg(feature = "pt10")]
+impl Key for pt10::H160 {
+    #[inline(always)]
+    fn to_path(&self) -> H256 {
+        H256::from(self)
+    }
+}
+
+/// Trait for values with an upper bound on their encoded size,
+/// so bulk writes can size buffers up front and reject oversized values early.
+pub trait BoundedValue: ValueEnDe {
+    /// Maximum length of the `ValueEnDe::encode` output, in bytes.
+    const MAX_ENCODED_LEN: usize;
+
+    /// Checked at compile time when the bound is used,
+    /// lengths are stored as `u32` in snapshots and buffers.
+    #[doc(hidden)]
+    const ASSERT_MAX_ENCODED_LEN: () = assert!(
+        Self::MAX_ENCODED_LEN > 0 && Self::MAX_ENCODED_LEN <= u32::MAX as usize,
+        "MAX_ENCODED_LEN must be in 1..=u32::MAX"
+    );
+
+    /// Encode the value, reject it if the result exceeds `MAX_ENCODED_LEN`.
+    fn encode_bounded(&self) -> StdResult<RawBytes, Error> {
+        #[allow(clippy::let_unit_value)]
+        let () = Self::ASSERT_MAX_ENCODED_LEN;
+        check_encoded_len(self, Self::MAX_ENCODED_LEN)
+    }
+}
+
+// Encode `v`, return ValueTooLarge error if the result exceeds `max` bytes
+fn check_encoded_len<V: ValueEnDe>(v: &V, max: usize) -> StdResult<RawBytes, Error> {
+    let bytes = v.encode();
+    if bytes.len() > max {
+        return Err(Error::ValueTooLarge {
+            len: bytes.len(),
+            max,
+        });
+    }
+    Ok(bytes)
+}
+
+impl BoundedValue for H256 {
+    // msgpack: 3-byte array header and at most 2 bytes per element
+    const MAX_ENCODED_LEN: usize = 67;
+}
+
 // impl<T: ValueEn, H: Hasher> Value<H> for T {
 //     fn to_h256(&self) -> H256 {
 //         H::hash(&self.encode_value()[..])
 //     }
 // }
 
-// impl<T: AsRef<[u8]>, H: Hasher> Value<H> for T {
-//     fn to_h256(&self) -> H256 {
-//         H::hash(self.as_ref())
-//     }
-// }
+// A blanket `impl<T: AsRef<[u8]>, H: Hasher> Value<H> for T` would also cover `H256`,
+// whose value must stay as it is(a zero value removes the leaf),
+// so the byte-like std types are listed one by one instead.
+// Empty bytes(the default) remove the leaf.
+#[cfg(feature = "bytes-value")]
+macro_rules! impl_value_for_bytes {
+    ($($t: ty),+) => {
+        $(
+            impl<H: Hasher> Value<H> for $t {
+                #[inline(always)]
+                fn to_h256(&self) -> H256 {
+                    H::hash(AsRef::<[u8]>::as_ref(self))
+                }
+
+                #[inline(always)]
+                fn is_deletion(&self) -> bool {
+                    AsRef::<[u8]>::as_ref(self).is_empty()
+                }
+            }
+        )+
+    };
+}
+
+#[cfg(feature = "bytes-value")]
+impl_value_for_bytes!(Vec<u8>, Box<[u8]>, String);
+
+// An all-zero array(the default) removes the leaf, as with `LowBytes`.
+#[cfg(feature = "bytes-value")]
+impl<H: Hasher, const N: usize> Value<H> for [u8; N] {
+    #[inline(always)]
+    fn to_h256(&self) -> H256 {
+        H::hash(&self[..])
+    }
+
+    #[inline(always)]
+    fn is_deletion(&self) -> bool {
+        self.iter().all(|b| *b == 0)
+    }
+}
+
+// Integers are kept as they are, in the lowest bytes of the leaf(little-endian,
+// the same layout as `H256::from_u64`), so no hashing is involved
+// and a zero balance is a zero leaf, i.e. storing `0` removes the key.
+#[cfg(feature = "int-value")]
+macro_rules! impl_value_for_int {
+    ($($t: ty),+) => {
+        $(
+            impl<H> Value<H> for $t {
+                const CACHE_HASH: bool = false;
+
+                #[inline(always)]
+                fn to_h256(&self) -> H256 {
+                    H256::from(*self as u128)
+                }
+            }
+        )+
+    };
+}
+
+#[cfg(feature = "int-value")]
+impl_value_for_int!(u64, u128);
+
+#[cfg(feature = "int-value")]
+impl<H, const N: usize> Value<H> for crate::h256::LowBytes<N> {
+    const CACHE_HASH: bool = false;
+
+    #[inline(always)]
+    fn to_h256(&self) -> H256 {
+        const { assert!(N <= 32, "a `LowBytes` value holds at most 32 bytes") };
+        HN::from(self.0).resize()
+    }
+}
 
 /// Trait for customize backend storage
 pub trait Store<V>: VsMgmt {
@@ -71,10 +331,110 @@ pub trait Store<V>: VsMgmt {
     fn remove_branch(&mut self, node_key: &BranchKey) -> StdResult<(), Error>;
     fn get_branch(&self, branch_key: &BranchKey)
     -> StdResult<Option<BranchNode>, Error>;
+    /// The default implementation keeps no versions and returns an error.
+    fn get_branch_by_branch_version(
+        &self,
+        branch_key: &BranchKey,
+        br: BranchName,
+        ver: VersionName,
+    ) -> StdResult<Option<BranchNode>, Error> {
+        let _ = (br, ver);
+        Err(Error::store(
+            StoreOp::Get,
+            StoreTarget::Branch(branch_key.clone()),
+            "the store keeps no versions",
+        ))
+    }
 
     fn insert_leaf(&mut self, leaf_key: H256, leaf: V) -> StdResult<(), Error>;
+    /// Store a leaf together with its hash(`Value::to_h256`),
+    /// the default implementation does not cache the hash.
+    fn insert_leaf_with_hash(
+        &mut self,
+        leaf_key: H256,
+        leaf: V,
+        leaf_hash: H256,
+    ) -> StdResult<(), Error> {
+        let _ = leaf_hash;
+        self.insert_leaf(leaf_key, leaf)
+    }
+    /// The hash cached by `insert_leaf_with_hash`, `None` if not cached.
+    /// A plain `insert_leaf` or `remove_leaf` must drop the cached hash.
+    fn get_leaf_hash(&self, leaf_key: &H256) -> StdResult<Option<H256>, Error> {
+        let _ = leaf_key;
+        Ok(None)
+    }
+    /// Store leaves, with their hashes if given(see `insert_leaf_with_hash`),
+    /// nothing is written and ValueTooLarge error is returned
+    /// if the encoding of a leaf is longer than `max_len` bytes.
+    /// The default implementation measures every leaf before the inserts.
+    fn insert_leaves_bounded(
+        &mut self,
+        leaves: Vec<(H256, V, Option<H256>)>,
+        max_len: usize,
+    ) -> StdResult<(), Error>
+    where
+        V: ValueEnDe,
+    {
+        for (_, leaf, _) in leaves.iter() {
+            check_encoded_len(leaf, max_len)?;
+        }
+        for (leaf_key, leaf, leaf_hash) in leaves {
+            match leaf_hash {
+                Some(leaf_hash) => {
+                    self.insert_leaf_with_hash(leaf_key, leaf, leaf_hash)?
+                }
+                None => self.insert_leaf(leaf_key, leaf)?,
+            }
+        }
+        Ok(())
+    }
     fn remove_leaf(&mut self, leaf_key: &H256) -> StdResult<(), Error>;
+    /// Remove a leaf and return its value, the default implementation reads it
+    /// before `remove_leaf`, stores that can do both at once should override it.
+    fn take_leaf(&mut self, leaf_key: &H256) -> StdResult<Option<V>, Error> {
+        let leaf = self.get_leaf(leaf_key)?;
+        self.remove_leaf(leaf_key)?;
+        Ok(leaf)
+    }
+    /// Remove all leaves whose key bytes start with `prefix`, return their keys.
+    /// The default implementation scans `iter_leaves`,
+    /// stores that keep leaves ordered by key should override it.
+    fn remove_leaves_by_prefix(&mut self, prefix: &[u8]) -> StdResult<Vec<H256>, Error> {
+        let keys: Vec<H256> = self
+            .iter_leaves()
+            .map(|(k, _)| k)
+            .filter(|k| k.as_slice().starts_with(prefix))
+            .collect();
+        for k in keys.iter() {
+            self.remove_leaf(k)?;
+        }
+        Ok(keys)
+    }
     fn get_leaf(&self, leaf_key: &H256) -> StdResult<Option<V>, Error>;
+    /// Whether the leaf exists, stores should override it
+    /// to skip decoding the value.
+    fn has_leaf(&self, leaf_key: &H256) -> StdResult<bool, Error> {
+        Ok(self.get_leaf(leaf_key)?.is_some())
+    }
+    /// Number of leaves, the default implementation scans `iter_leaves`,
+    /// stores should override it to keep a counter, see `add_leaves_count`.
+    fn leaves_count(&self) -> StdResult<u64, Error> {
+        Ok(self.iter_leaves().count() as u64)
+    }
+    /// Add `delta` to the number of leaves, called by the tree once per batch,
+    /// after its leaves are written; `insert_leaf` and `remove_leaf` do not count.
+    /// The default implementation does nothing.
+    fn add_leaves_count(&mut self, delta: i64) -> StdResult<(), Error> {
+        let _ = delta;
+        Ok(())
+    }
+    /// Values of several leaves, in the order of `leaf_keys`.
+    /// The default implementation reads them one by one,
+    /// stores that support batched reads should override it.
+    fn get_leaves(&self, leaf_keys: &[H256]) -> StdResult<Vec<Option<V>>, Error> {
+        leaf_keys.iter().map(|k| self.get_leaf(k)).collect()
+    }
     fn get_leaf_by_branch(
         &self,
         leaf_key: &H256,
@@ -89,6 +449,106 @@ pub trait Store<V>: VsMgmt {
 
     fn update_root(&mut self, new_root: H256) -> StdResult<(), Error>;
     fn get_root(&self) -> StdResult<H256, Error>;
+    /// The default implementation keeps no versions and returns an error.
+    fn get_root_by_branch_version(
+        &self,
+        br: BranchName,
+        ver: VersionName,
+    ) -> StdResult<H256, Error> {
+        let _ = (br, ver);
+        Err(Error::store(
+            StoreOp::Get,
+            StoreTarget::Root,
+            "the store keeps no versions",
+        ))
+    }
+    /// The root at the head of a branch, the default implementation
+    /// reads it at the newest version of the branch.
+    fn get_root_by_branch(&self, br: BranchName) -> StdResult<H256, Error> {
+        let versions = self.version_list_by_branch(br).map_err(|e| {
+            Error::store(StoreOp::Get, StoreTarget::VsBranch(br.0.to_vec()), e)
+        })?;
+        match versions.last() {
+            Some(ver) => self.get_root_by_branch_version(br, ver.as_deref()),
+            None => Ok(H256::zero()),
+        }
+    }
+
+    /// Append a root to the root history, return its sequence number,
+    /// which is one more than that of the last recorded root(or 0).
+    /// The default implementation keeps no history, records nothing and returns 0.
+    fn push_root_history(&mut self, root: H256) -> StdResult<u64, Error> {
+        let _ = root;
+        Ok(0)
+    }
+    /// The default implementation keeps no history and returns `None`.
+    fn get_root_by_seq(&self, seq: u64) -> StdResult<Option<H256>, Error> {
+        let _ = seq;
+        Ok(None)
+    }
+    /// Iterate recorded roots with sequence numbers in `range`, in order.
+    /// The default implementation keeps no history and yields nothing.
+    fn iter_root_history(
+        &self,
+        range: (Bound<u64>, Bound<u64>),
+    ) -> Box<dyn Iterator<Item = (u64, H256)> + '_> {
+        let _ = range;
+        Box::new(std::iter::empty())
+    }
+
+    /// Iterate all branches, in no particular order.
+    /// The default implementation yields nothing, stores should override it
+    /// for `verify_integrity` to see their branches.
+    fn iter_branches(&self) -> Box<dyn Iterator<Item = (BranchKey, BranchNode)> + '_> {
+        Box::new(std::iter::empty())
+    }
+    /// Iterate all leaves, in no particular order.
+    /// The default implementation yields nothing, stores should override it,
+    /// the defaults of the other leaf scans(e.g. `leaves_count`) are built on it.
+    fn iter_leaves<'a>(&'a self) -> Box<dyn Iterator<Item = (H256, V)> + 'a>
+    where
+        V: 'a,
+    {
+        Box::new(std::iter::empty())
+    }
+    /// Iterate the leaves with keys in `range`, sorted by key.
+    /// The default implementation scans and sorts `iter_leaves`,
+    /// stores that keep leaves ordered by key should override it.
+    fn iter_leaves_range<'a>(
+        &'a self,
+        range: (Bound<H256>, Bound<H256>),
+    ) -> Box<dyn Iterator<Item = (H256, V)> + 'a>
+    where
+        V: 'a,
+    {
+        let mut leaves: Vec<(H256, V)> = self
+            .iter_leaves()
+            .filter(|(k, _)| range.contains(k))
+            .collect();
+        leaves.sort_unstable_by_key(|(k, _)| *k);
+        Box::new(leaves.into_iter())
+    }
+    /// Iterate all leaves of a specified version, in no particular order.
+    /// The default implementation yields nothing, stores keeping versions
+    /// should override it for `diff_versions`.
+    fn iter_leaves_by_branch_version<'a>(
+        &'a self,
+        br: BranchName,
+        ver: VersionName,
+    ) -> Box<dyn Iterator<Item = (H256, V)> + 'a>
+    where
+        V: 'a,
+    {
+        let _ = (br, ver);
+        Box::new(std::iter::empty())
+    }
+
+    /// Reclaim the space of data no version or branch can reach anymore,
+    /// return the number of bytes freed if the store can tell, 0 otherwise.
+    /// The default implementation does nothing.
+    fn compact(&mut self) -> StdResult<u64, Error> {
+        Ok(0)
+    }
 }
 
 /// Trait for customize backend storage,
@@ -106,10 +566,109 @@ pub trait Store2<X, V>: VsMgmt {
         xid: &X,
         branch_key: &BranchKey,
     ) -> StdResult<Option<BranchNode>, Error>;
+    /// The default implementation keeps no versions and returns an error.
+    fn get_branch_by_branch_version(
+        &self,
+        xid: &X,
+        branch_key: &BranchKey,
+        br: BranchName,
+        ver: VersionName,
+    ) -> StdResult<Option<BranchNode>, Error> {
+        let _ = (xid, br, ver);
+        Err(Error::store(
+            StoreOp::Get,
+            StoreTarget::Branch(branch_key.clone()),
+            "the store keeps no versions",
+        ))
+    }
 
     fn insert_leaf(&mut self, xid: &X, leaf_key: H256, leaf: V) -> StdResult<(), Error>;
+    /// Store a leaf together with its hash(`Value::to_h256`),
+    /// the default implementation does not cache the hash.
+    fn insert_leaf_with_hash(
+        &mut self,
+        xid: &X,
+        leaf_key: H256,
+        leaf: V,
+        leaf_hash: H256,
+    ) -> StdResult<(), Error> {
+        let _ = leaf_hash;
+        self.insert_leaf(xid, leaf_key, leaf)
+    }
+    /// The hash cached by `insert_leaf_with_hash`, `None` if not cached.
+    /// A plain `insert_leaf` or `remove_leaf` must drop the cached hash.
+    fn get_leaf_hash(&self, xid: &X, leaf_key: &H256) -> StdResult<Option<H256>, Error> {
+        let _ = (xid, leaf_key);
+        Ok(None)
+    }
+    /// Store leaves, with their hashes if given(see `insert_leaf_with_hash`),
+    /// nothing is written and ValueTooLarge error is returned
+    /// if the encoding of a leaf is longer than `max_len` bytes.
+    /// The default implementation measures every leaf before the inserts.
+    fn insert_leaves_bounded(
+        &mut self,
+        xid: &X,
+        leaves: Vec<(H256, V, Option<H256>)>,
+        max_len: usize,
+    ) -> StdResult<(), Error>
+    where
+        V: ValueEnDe,
+    {
+        for (_, leaf, _) in leaves.iter() {
+            check_encoded_len(leaf, max_len)?;
+        }
+        for (leaf_key, leaf, leaf_hash) in leaves {
+            match leaf_hash {
+                Some(leaf_hash) => {
+                    self.insert_leaf_with_hash(xid, leaf_key, leaf, leaf_hash)?
+                }
+                None => self.insert_leaf(xid, leaf_key, leaf)?,
+            }
+        }
+        Ok(())
+    }
     fn remove_leaf(&mut self, xid: &X, leaf_key: &H256) -> StdResult<(), Error>;
+    /// Remove a leaf and return its value, the default implementation reads it
+    /// before `remove_leaf`, stores that can do both at once should override it.
+    fn take_leaf(&mut self, xid: &X, leaf_key: &H256) -> StdResult<Option<V>, Error> {
+        let leaf = self.get_leaf(xid, leaf_key)?;
+        self.remove_leaf(xid, leaf_key)?;
+        Ok(leaf)
+    }
     fn get_leaf(&self, xid: &X, leaf_key: &H256) -> StdResult<Option<V>, Error>;
+    /// Whether the leaf exists, stores should override it
+    /// to skip decoding the value.
+    fn has_leaf(&self, xid: &X, leaf_key: &H256) -> StdResult<bool, Error> {
+        Ok(self.get_leaf(xid, leaf_key)?.is_some())
+    }
+    /// Number of leaves under the xid, see `add_leaves_count`.
+    /// The default implementation keeps no counter and returns an error.
+    fn leaves_count(&self, xid: &X) -> StdResult<u64, Error> {
+        let _ = xid;
+        Err(Error::store(
+            StoreOp::Get,
+            StoreTarget::LeavesCount,
+            "the store keeps no leaf count",
+        ))
+    }
+    /// Add `delta` to the number of leaves under the xid, called by the tree
+    /// once per batch, after its leaves are written;
+    /// `insert_leaf` and `remove_leaf` do not count, `remove_x` and `clone_x` do.
+    /// The default implementation does nothing.
+    fn add_leaves_count(&mut self, xid: &X, delta: i64) -> StdResult<(), Error> {
+        let _ = (xid, delta);
+        Ok(())
+    }
+    /// Values of several leaves, in the order of `leaf_keys`.
+    /// The default implementation reads them one by one,
+    /// stores that support batched reads should override it.
+    fn get_leaves(
+        &self,
+        xid: &X,
+        leaf_keys: &[H256],
+    ) -> StdResult<Vec<Option<V>>, Error> {
+        leaf_keys.iter().map(|k| self.get_leaf(xid, k)).collect()
+    }
     fn get_leaf_by_branch(
         &self,
         xid: &X,
@@ -126,7 +685,45 @@ pub trait Store2<X, V>: VsMgmt {
 
     // Remove all data under the xid(top-level key).
     fn remove_x(&mut self, xid: &X) -> StdResult<(), Error>;
+    /// Iterate all xids(top-level keys) whose subtree is not empty,
+    /// in no particular order.
+    /// The default implementation yields nothing, stores should override it.
+    fn iter_x<'a>(&'a self) -> Box<dyn Iterator<Item = X> + 'a>
+    where
+        X: 'a,
+    {
+        Box::new(std::iter::empty())
+    }
+    /// Copy all data(leaves, branches and root) under `src` to `dst`,
+    /// the existing data under `dst` is replaced.
+    /// The default implementation can not list the data of an xid
+    /// and returns an error.
+    fn clone_x(&mut self, src: &X, dst: &X) -> StdResult<(), Error>
+    where
+        X: KeyEnDe,
+    {
+        let _ = src;
+        Err(Error::store(
+            StoreOp::Insert,
+            StoreTarget::Xid(KeyEnDe::encode(dst).into()),
+            "the store can not copy a subtree",
+        ))
+    }
 
     fn update_root(&mut self, xid: &X, new_root: H256) -> StdResult<(), Error>;
     fn get_root(&self, xid: &X) -> StdResult<H256, Error>;
+    /// The default implementation keeps no versions and returns an error.
+    fn get_root_by_branch_version(
+        &self,
+        xid: &X,
+        br: BranchName,
+        ver: VersionName,
+    ) -> StdResult<H256, Error> {
+        let _ = (xid, br, ver);
+        Err(Error::store(
+            StoreOp::Get,
+            StoreTarget::Root,
+            "the store keeps no versions",
+        ))
+    }
 }
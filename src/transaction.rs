@@ -0,0 +1,84 @@
+//!
+//! Stage updates and removals, then write them in one batch.
+//!
+
+use crate::{
+    error::Result,
+    traits::{Hasher, Store, Value},
+    tree::leaf_hash,
+    SparseMerkleTree, H256,
+};
+use std::collections::BTreeMap;
+use vsdb::VsMgmt;
+
+impl<H: Hasher, V: Value<H>, S: Store<V>, const DEPTH: usize>
+    SparseMerkleTree<H, V, S, DEPTH>
+{
+    /// Start a transaction, nothing reaches the store before `commit`
+    #[inline(always)]
+    pub fn transaction(&mut self) -> Transaction<'_, H, V, S, DEPTH> {
+        Transaction {
+            tree: self,
+            staged: BTreeMap::new(),
+        }
+    }
+}
+
+/// Staged changes of a tree, dropping it without `commit` discards them
+pub struct Transaction<'a, H, V, S: VsMgmt, const DEPTH: usize = 256> {
+    tree: &'a mut SparseMerkleTree<H, V, S, DEPTH>,
+    // `None` means removal, the last change of a key wins
+    staged: BTreeMap<H256, Option<V>>,
+}
+
+impl<H: Hasher, V: Value<H>, S: Store<V>, const DEPTH: usize>
+    Transaction<'_, H, V, S, DEPTH>
+{
+    /// Stage an update, a zero value removes the key
+    #[inline(always)]
+    pub fn update(&mut self, key: H256, value: V) {
+        self.staged.insert(key, Some(value));
+    }
+
+    /// Stage a removal
+    #[inline(always)]
+    pub fn remove(&mut self, key: H256) {
+        self.staged.insert(key, None);
+    }
+
+    /// Number of staged keys
+    #[inline(always)]
+    pub fn len(&self) -> usize {
+        self.staged.len()
+    }
+
+    #[inline(always)]
+    pub fn is_empty(&self) -> bool {
+        self.staged.is_empty()
+    }
+
+    /// Write all staged changes and recompute the root once,
+    /// branches are computed before anything is written,
+    /// so a failed read leaves the store untouched.
+    #[inline(always)]
+    pub fn commit(self) -> Result<H256> {
+        self.tree.write_batch(self.staged.into_iter().collect())
+    }
+
+    /// Discard all staged changes
+    #[inline(always)]
+    pub fn abort(self) {}
+}
+
+impl<H: Hasher, V: Value<H> + Clone, S: Store<V>, const DEPTH: usize>
+    Transaction<'_, H, V, S, DEPTH>
+{
+    /// Get value of a leaf, staged changes included
+    pub fn get(&self, key: &H256) -> Result<Option<V>> {
+        match self.staged.get(key) {
+            Some(Some(v)) if !leaf_hash(Some(v)).is_zero() => Ok(Some(v.clone())),
+            Some(_) => Ok(None),
+            None => self.tree.get(key),
+        }
+    }
+}
@@ -1,12 +1,22 @@
 use crate::{
-    error::{Error, Result},
+    error::{Error, Result, StoreOp, StoreTarget},
     merge::{merge, MergeValue},
-    merkle_proof::MerkleProof,
-    traits::{Hasher, Store, Store2, Value},
+    merkle_proof::{
+        range_subtrees, MerkleProof, SubtreeProof, XMerkleProof, XMultiMerkleProof,
+    },
+    traits::{BoundedValue, Hasher, Key, Store, Store2, Value},
     H256, MAX_STACK_SIZE,
 };
-use core::{cmp::Ordering, marker::PhantomData};
+use core::{
+    cmp::Ordering,
+    marker::PhantomData,
+    ops::{Bound, RangeBounds},
+};
 use serde::{Deserialize, Serialize};
+use std::{
+    collections::{btree_map::Entry, BTreeMap, BTreeSet, HashMap},
+    sync::Mutex,
+};
 use vsdb::{BranchName, KeyEnDe, VersionName, Vs, VsMgmt};
 
 /// The branch key
@@ -37,326 +47,2440 @@ impl Ord for BranchKey {
 }
 
 /// A branch in the SMT
-#[derive(Debug, Eq, PartialEq, Clone, Deserialize, Serialize)]
+#[derive(Debug, Eq, PartialEq, Clone, Copy, Deserialize, Serialize)]
+#[cfg_attr(
+    feature = "borsh",
+    derive(borsh::BorshSerialize, borsh::BorshDeserialize)
+)]
 pub struct BranchNode {
     pub left: MergeValue,
     pub right: MergeValue,
 }
 
-/// Sparse merkle tree
-#[derive(Vs, Clone, Default, Debug, Deserialize, Serialize)]
-pub struct SparseMerkleTree<H, V, S: VsMgmt> {
-    store: S,
-    phantom: PhantomData<(H, V)>,
-}
+impl BranchNode {
+    /// Encode into the compact layout `DefaultStore` stores branches in:
+    /// `tags(u8) | left | right`, the low 4 bits of `tags` tag the left child
+    /// and the high 4 bits the right one, 0 for zero(no bytes), 1 for `Value`
+    /// (32 bytes) and 2 for `MergeWithZero` as `zero_count(u8) | base_node(32)
+    /// | first(u8) | len(u8) | zero_bits[first..first + len]`,
+    /// the zero bytes around the set bits of `zero_bits` are elided.
+    pub fn to_compact_bytes(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(1 + 2 * 67);
+        buf.push(compact_tag(&self.left) | (compact_tag(&self.right) << 4));
+        for child in [&self.left, &self.right] {
+            match child {
+                _ if child.is_zero() => {}
+                MergeValue::Value(v) => buf.extend_from_slice(v.as_slice()),
+                MergeValue::MergeWithZero {
+                    base_node,
+                    zero_bits,
+                    zero_count,
+                } => {
+                    let bits = zero_bits.as_slice();
+                    let first = bits.iter().position(|b| *b != 0).unwrap_or(0);
+                    let end = bits.iter().rposition(|b| *b != 0).map_or(0, |i| i + 1);
+                    buf.push(*zero_count);
+                    buf.extend_from_slice(base_node.as_slice());
+                    buf.push(first as u8);
+                    buf.push((end - first) as u8);
+                    buf.extend_from_slice(&bits[first..end]);
+                }
+            }
+        }
+        buf
+    }
 
-impl<H: Hasher, V: Value<H>, S: Store<V>> SparseMerkleTree<H, V, S> {
-    /// Build a merkle tree from root and store
-    #[inline(always)]
-    pub fn new(store: S) -> SparseMerkleTree<H, V, S> {
-        SparseMerkleTree {
-            store,
-            phantom: PhantomData,
+    /// Decode a branch from the layout produced by `to_compact_bytes`
+    pub fn from_compact_bytes(bytes: &[u8]) -> Result<Self> {
+        let mut pos = 0;
+        let mut take = |n: usize| -> Result<&[u8]> {
+            let end = pos + n;
+            let slice = bytes.get(pos..end).ok_or(Error::InvalidLength {
+                expected: end,
+                actual: bytes.len(),
+            })?;
+            pos = end;
+            Ok(slice)
+        };
+        let tags = take(1)?[0];
+        let mut children = [MergeValue::zero(); 2];
+        for (child, tag) in children.iter_mut().zip([tags & 0x0F, tags >> 4]) {
+            *child = match tag {
+                0 => MergeValue::zero(),
+                1 => MergeValue::Value(h256_of(take(32)?)),
+                2 => {
+                    let zero_count = take(1)?[0];
+                    let base_node = h256_of(take(32)?);
+                    let first = take(1)?[0] as usize;
+                    let len = take(1)?[0] as usize;
+                    if first + len > 32 {
+                        return Err(Error::InvalidLength {
+                            expected: 32,
+                            actual: first + len,
+                        });
+                    }
+                    let mut zero_bits = [0u8; 32];
+                    zero_bits[first..first + len].copy_from_slice(take(len)?);
+                    MergeValue::MergeWithZero {
+                        base_node,
+                        zero_bits: zero_bits.into(),
+                        zero_count,
+                    }
+                }
+                tag => return Err(Error::InvalidCode(tag)),
+            };
+        }
+        if pos != bytes.len() {
+            return Err(Error::InvalidLength {
+                expected: pos,
+                actual: bytes.len(),
+            });
         }
+        let [left, right] = children;
+        Ok(BranchNode { left, right })
     }
+}
 
-    /// Merkle root
-    #[inline(always)]
-    pub fn root(&self) -> H256 {
-        self.store.get_root().unwrap()
+#[inline(always)]
+fn compact_tag(child: &MergeValue) -> u8 {
+    match child {
+        _ if child.is_zero() => 0,
+        MergeValue::Value(_) => 1,
+        MergeValue::MergeWithZero { .. } => 2,
     }
+}
 
-    /// Check empty of the tree
+#[inline(always)]
+fn h256_of(bytes: &[u8]) -> H256 {
+    let mut buf = [0u8; 32];
+    buf.copy_from_slice(bytes);
+    buf.into()
+}
+
+/// Result of `SparseMerkleTree::verify_integrity`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct IntegrityReport {
+    /// root recorded in the store
+    pub stored_root: H256,
+    /// root recomputed from all stored leaves
+    pub computed_root: H256,
+    /// branches that are missing or differ from the recomputed ones
+    pub mismatched_branches: Vec<BranchKey>,
+    /// stored branches that the leaves do not need,
+    /// such as those left over by a store written before only forks were stored
+    pub dangling_branches: Vec<BranchKey>,
+}
+
+impl IntegrityReport {
+    /// No corruption has been found
     #[inline(always)]
-    pub fn is_empty(&self) -> bool {
-        self.root().is_zero()
+    pub fn is_ok(&self) -> bool {
+        self.stored_root == self.computed_root
+            && self.mismatched_branches.is_empty()
+            && self.dangling_branches.is_empty()
     }
+}
 
-    /// Get backend store
-    #[cfg(test)]
+/// Result of `SparseMerkleTree::compact`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CompactionReport {
+    /// dangling branches removed, see `IntegrityReport`
+    pub dangling_branches: usize,
+    /// versions of the default branch merged into a later one
+    pub merged_versions: usize,
+    /// bytes freed as reported by the store, see `Store::compact`
+    pub bytes_freed: u64,
+}
+
+/// What a batch write did to one key
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LeafChange {
+    Inserted,
+    Updated,
+    Deleted,
+    /// the same value was written, or an absent key was removed
+    Unchanged,
+}
+
+/// Result of `update_all_with_report` and `remove_all_with_report`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BatchReport {
+    /// the change of every distinct key of the batch, sorted by key
+    pub changes: Vec<(H256, LeafChange)>,
+    /// entries dropped in favor of a later one with the same key
+    pub duplicates: usize,
+}
+
+impl BatchReport {
+    /// Number of keys with the given change
     #[inline(always)]
-    pub(crate) fn store(&self) -> &S {
-        &self.store
+    pub fn count(&self, change: LeafChange) -> usize {
+        self.changes.iter().filter(|(_, c)| *c == change).count()
     }
+}
 
-    pub fn remove(&mut self, key: H256) -> Result<H256> {
-        self.store
-            .remove_leaf(&key)
-            .and_then(|_| self.hash_recompute(key, MergeValue::zero()))
+// The change writing the hash `new` makes to a leaf whose hash was `old`,
+// a zero hash removes the leaf
+fn leaf_change(old: Option<H256>, new: H256) -> LeafChange {
+    match (old, new.is_zero()) {
+        (None, true) => LeafChange::Unchanged,
+        (Some(_), true) => LeafChange::Deleted,
+        (None, false) => LeafChange::Inserted,
+        (Some(old), false) if old == new => LeafChange::Unchanged,
+        (Some(_), false) => LeafChange::Updated,
     }
+}
 
-    /// Update a leaf, return new merkle root
-    /// set to zero value to delete a key
-    pub fn update(&mut self, key: H256, value: V) -> Result<H256> {
-        // compute and store new leaf
-        let node = MergeValue::from_h256(value.to_h256());
-        // notice when value is zero the leaf is deleted, so we do not need to store it
-        if !node.is_zero() {
-            self.store.insert_leaf(key, value)?;
-        } else {
-            self.store.remove_leaf(&key)?;
+// The hash writing `value` gives to a leaf, zero when it removes the leaf
+#[inline(always)]
+pub(crate) fn leaf_hash<H, V: Value<H>>(value: Option<&V>) -> H256 {
+    match value {
+        Some(v) if !v.is_deletion() => v.to_h256(),
+        _ => H256::zero(),
+    }
+}
+
+// Store a leaf, with its hash if `V` caches it(`Value::CACHE_HASH`)
+#[inline(always)]
+fn store_leaf<H, V: Value<H>, S: Store<V>>(
+    store: &mut S,
+    key: H256,
+    value: V,
+    hash: H256,
+) -> Result<()> {
+    if <V as Value<H>>::CACHE_HASH {
+        store.insert_leaf_with_hash(key, value, hash)
+    } else {
+        store.insert_leaf(key, value)
+    }
+}
+
+// Split planned leaf writes into inserts, with the hash if `V` caches it
+// (`Value::CACHE_HASH`), and the keys of the removed leaves
+fn split_writes<H, V: Value<H>>(
+    writes: Vec<(H256, Option<V>, H256)>,
+) -> (LeafInserts<V>, Vec<H256>) {
+    let mut inserts = Vec::with_capacity(writes.len());
+    let mut removes = vec![];
+    for (k, v, hash) in writes {
+        match v {
+            Some(v) if !hash.is_zero() => {
+                inserts.push((k, v, <V as Value<H>>::CACHE_HASH.then_some(hash)))
+            }
+            _ => removes.push(k),
+        }
+    }
+    (inserts, removes)
+}
+
+// Store leaves, with their hashes if given
+fn insert_leaves<V, S: Store<V>>(
+    store: &mut S,
+    leaves: LeafInserts<V>,
+) -> Result<()> {
+    for (k, v, hash) in leaves {
+        match hash {
+            Some(hash) => store.insert_leaf_with_hash(k, v, hash)?,
+            None => store.insert_leaf(k, v)?,
+        }
+    }
+    Ok(())
+}
+
+// `insert_leaves` under `xid` of a double-key store
+fn insert_leaves2<X, V, S: Store2<X, V>>(
+    store: &mut S,
+    xid: &X,
+    leaves: LeafInserts<V>,
+) -> Result<()> {
+    for (k, v, hash) in leaves {
+        match hash {
+            Some(hash) => store.insert_leaf_with_hash(xid, k, v, hash)?,
+            None => store.insert_leaf(xid, k, v)?,
         }
+    }
+    Ok(())
+}
+
+// Return ImplicitDelete error for the first value removing its leaf
+fn check_no_deletion<H, V: Value<H>>(leaves: &[(H256, V)]) -> Result<()> {
+    match leaves.iter().find(|(_, v)| leaf_hash(Some(v)).is_zero()) {
+        Some((k, _)) => Err(Error::ImplicitDelete(*k)),
+        None => Ok(()),
+    }
+}
+
+/// Merge sorted and deduplicated leaves of a `depth`-level tree bottom-up
+/// without touching any store, `visit` is called with every branch
+/// the tree stores for these leaves.
+pub(crate) fn merge_leaves<H: Hasher>(
+    depth: usize,
+    nodes: Vec<(H256, MergeValue)>,
+    visit: impl FnMut(BranchKey, BranchNode) -> Result<()>,
+) -> Result<H256> {
+    if nodes.is_empty() {
+        return Ok(H256::zero());
+    }
+    for (k, _) in nodes.iter() {
+        check_key_depth(depth, k)?;
+    }
+    let node = merge_levels::<H>(depth, nodes, visit)?;
+    Ok(fold_to_root::<H>(depth, node))
+}
 
-        self.hash_recompute(key, node)
+/// Merge sorted and deduplicated leaves bottom-up into their node at `level`,
+/// the keys must share all bits from `level` up; `visit` is called with every branch
+/// stored below that node, `level` being the top one.
+pub(crate) fn merge_levels<H: Hasher>(
+    level: usize,
+    mut nodes: Vec<(H256, MergeValue)>,
+    mut visit: impl FnMut(BranchKey, BranchNode) -> Result<()>,
+) -> Result<MergeValue> {
+    if nodes.is_empty() {
+        return Ok(MergeValue::zero());
     }
 
-    fn hash_recompute(&mut self, key: H256, node: MergeValue) -> Result<H256> {
-        // recompute the tree from bottom to top
-        let mut current_key = key;
-        let mut current_node = node;
-        for height in 0..=u8::MAX {
+    for height in 0..level {
+        let height = height as u8;
+        let mut next_nodes: Vec<(H256, MergeValue)> = Vec::with_capacity(nodes.len());
+        let mut iter = nodes.into_iter().peekable();
+        while let Some((current_key, current_merge_value)) = iter.next() {
             let parent_key = current_key.parent_path(height);
-            let parent_branch_key = BranchKey::new(height, parent_key);
-            let (left, right) = if let Some(parent_branch) =
-                self.store.get_branch(&parent_branch_key)?
-            {
-                if current_key.is_right(height) {
-                    (parent_branch.left, current_node)
-                } else {
-                    (current_node, parent_branch.right)
-                }
-            } else if current_key.is_right(height) {
-                (MergeValue::zero(), current_node)
+
+            let (left, right) = if current_key.is_right(height) {
+                (MergeValue::zero(), current_merge_value)
             } else {
-                (current_node, MergeValue::zero())
+                let mut right_key = current_key;
+                right_key.set_bit(height);
+                match iter.next_if(|(k, _)| *k == right_key) {
+                    Some((_, right)) => (current_merge_value, right),
+                    None => (current_merge_value, MergeValue::zero()),
+                }
             };
 
-            if !left.is_zero() || !right.is_zero() {
-                // insert or update branch
-                self.store.insert_branch(
-                    parent_branch_key,
-                    BranchNode {
-                        left: left.clone(),
-                        right: right.clone(),
-                    },
+            let parent = merge::<H>(height, &parent_key, &left, &right);
+            if is_stored(
+                height as usize + 1 == level,
+                !left.is_zero(),
+                !right.is_zero(),
+            ) {
+                visit(
+                    BranchKey::new(height, parent_key),
+                    BranchNode { left, right },
                 )?;
-            } else {
-                // remove empty branch
-                self.store.remove_branch(&parent_branch_key)?;
             }
-            // prepare for next round
-            current_key = parent_key;
-            current_node = merge::<H>(height, &parent_key, &left, &right);
+            next_nodes.push((parent_key, parent));
         }
-
-        let root = current_node.hash::<H>();
-        self.store.update_root(root).map(|_| root)
+        nodes = next_nodes;
     }
 
-    pub fn remove_all(&mut self, mut keys: Vec<H256>) -> Result<H256> {
-        // Dedup(only keep the last of each key) and sort leaves
-        keys.reverse();
-        keys.sort();
-        keys.dedup();
+    debug_assert_eq!(nodes.len(), 1);
+
+    Ok(nodes.swap_remove(0).1)
+}
 
-        let mut nodes: Vec<(H256, MergeValue)> = Vec::with_capacity(keys.len());
-        for k in keys {
-            self.store.remove_leaf(&k)?;
-            nodes.push((k, MergeValue::zero()));
+// A node of `SortedMerger`: its key(path), value and level
+type LevelNode = (H256, MergeValue, usize);
+
+// Merges leaves arriving in key order bottom-up, with the same result
+// and the same branches as `merge_leaves`, keeping only the left nodes
+// that wait for their right siblings(at most one per level).
+struct SortedMerger<H> {
+    depth: usize,
+    // lifted to the height they merge at, the lowest on the top
+    waiting: Vec<LevelNode>,
+    // the last leaf
+    last: Option<LevelNode>,
+    phantom: NotOwned<H>,
+}
+
+impl<H: Hasher> SortedMerger<H> {
+    fn new(depth: usize) -> Self {
+        SortedMerger {
+            depth,
+            waiting: vec![],
+            last: None,
+            phantom: PhantomData,
         }
+    }
 
-        self.hash_recompute_all(nodes)
+    // `key` must be greater than the key pushed before it
+    fn push(
+        &mut self,
+        key: H256,
+        value: MergeValue,
+        visit: &mut impl FnMut(BranchKey, BranchNode) -> Result<()>,
+    ) -> Result<()> {
+        if let Some(mut node) = self.last.take() {
+            // the last leaf is the left sibling of `key` at this height
+            let fork_height = node.0.fork_height(&key) as usize;
+            while self.waiting.last().is_some_and(|left| left.2 < fork_height) {
+                let left = self.waiting.pop().unwrap();
+                node = self.lift(node, left.2, visit)?;
+                node = self.merge_left(left, node, visit)?;
+            }
+            node = self.lift(node, fork_height, visit)?;
+            self.waiting.push(node);
+        }
+        self.last = Some((key, value, 0));
+        Ok(())
     }
 
-    /// Update multiple leaves at once
-    pub fn update_all(&mut self, mut leaves: Vec<(H256, V)>) -> Result<H256> {
-        // Dedup(only keep the last of each key) and sort leaves
-        leaves.reverse();
-        leaves.sort_by_key(|(a, _)| *a);
-        leaves.dedup_by_key(|(a, _)| *a);
+    fn finish(
+        mut self,
+        visit: &mut impl FnMut(BranchKey, BranchNode) -> Result<()>,
+    ) -> Result<H256> {
+        let mut node = match self.last.take() {
+            Some(node) => node,
+            None => return Ok(H256::zero()),
+        };
+        while let Some(left) = self.waiting.pop() {
+            node = self.lift(node, left.2, visit)?;
+            node = self.merge_left(left, node, visit)?;
+        }
+        node = self.lift(node, self.depth, visit)?;
+        Ok(fold_to_root::<H>(self.depth, node.1))
+    }
 
-        let mut nodes: Vec<(H256, MergeValue)> = Vec::new();
-        for (k, v) in leaves {
-            let value = MergeValue::from_h256(v.to_h256());
-            if !value.is_zero() {
-                self.store.insert_leaf(k, v)?;
+    // Merge `node` with zero siblings up to level `to`
+    fn lift(
+        &self,
+        (mut key, mut value, level): LevelNode,
+        to: usize,
+        visit: &mut impl FnMut(BranchKey, BranchNode) -> Result<()>,
+    ) -> Result<LevelNode> {
+        for height in level..to {
+            let h = height as u8;
+            let parent_key = key.parent_path(h);
+            let (left, right) = if key.is_right(h) {
+                (MergeValue::zero(), value)
             } else {
-                self.store.remove_leaf(&k)?;
+                (value, MergeValue::zero())
+            };
+            let parent = merge::<H>(h, &parent_key, &left, &right);
+            if is_stored(height + 1 == self.depth, !left.is_zero(), !right.is_zero()) {
+                visit(BranchKey::new(h, parent_key), BranchNode { left, right })?;
             }
-            nodes.push((k, value));
+            key = parent_key;
+            value = parent;
         }
+        Ok((key, value, to))
+    }
 
-        self.hash_recompute_all(nodes)
+    // Merge two siblings at the level of both
+    fn merge_left(
+        &self,
+        (key, left, level): LevelNode,
+        (_, right, _): LevelNode,
+        visit: &mut impl FnMut(BranchKey, BranchNode) -> Result<()>,
+    ) -> Result<LevelNode> {
+        let h = level as u8;
+        let parent_key = key.parent_path(h);
+        let parent = merge::<H>(h, &parent_key, &left, &right);
+        visit(BranchKey::new(h, parent_key), BranchNode { left, right })?;
+        Ok((parent_key, parent, level + 1))
     }
+}
 
-    fn hash_recompute_all(
-        &mut self,
-        mut nodes: Vec<(H256, MergeValue)>,
-    ) -> Result<H256> {
-        if nodes.is_empty() {
-            return self.store.get_root();
-        }
+// Merge the top node of a `depth`-level tree with the empty levels above it,
+// so the root is the same as that of a 256-level tree with the same leaves.
+// No store is involved, only one hash at most is computed.
+#[inline(always)]
+fn fold_to_root<H: Hasher>(depth: usize, mut node: MergeValue) -> H256 {
+    for height in depth..256 {
+        node = merge::<H>(height as u8, &H256::zero(), &node, &MergeValue::zero());
+    }
+    node.hash::<H>()
+}
 
-        for height in 0..=u8::MAX {
-            let mut next_nodes: Vec<(H256, MergeValue)> = Vec::new();
-            let mut i = 0;
-            while i < nodes.len() {
-                let (current_key, current_merge_value) = &nodes[i];
-                i += 1;
-                let parent_key = current_key.parent_path(height);
-                let parent_branch_key = BranchKey::new(height, parent_key);
-
-                // Test for neighbors
-                let mut right = None;
-                if i < nodes.len() && (!current_key.is_right(height)) {
-                    let (neighbor_key, neighbor_value) = &nodes[i];
-                    let mut right_key = *current_key;
-                    right_key.set_bit(height);
-                    if right_key == *neighbor_key {
-                        right = Some(neighbor_value.clone());
-                        i += 1;
-                    }
-                }
+// Keys of a `depth`-level tree must fit in the lowest `depth` bits
+#[inline(always)]
+fn check_key_depth(depth: usize, key: &H256) -> Result<()> {
+    if depth < 256 && !key.copy_bits(depth as u8).is_zero() {
+        return Err(Error::KeyOutOfRange(*key));
+    }
+    Ok(())
+}
 
-                let (left, right) = if let Some(right_merge_value) = right {
-                    (current_merge_value.clone(), right_merge_value)
-                } else {
-                    // In case neighbor is not available, fetch from store
-                    if let Some(parent_branch) =
-                        self.store.get_branch(&parent_branch_key)?
-                    {
-                        if current_key.is_right(height) {
-                            (parent_branch.left, current_merge_value.clone())
-                        } else {
-                            (current_merge_value.clone(), parent_branch.right)
-                        }
-                    } else if current_key.is_right(height) {
-                        (MergeValue::zero(), current_merge_value.clone())
-                    } else {
-                        (current_merge_value.clone(), MergeValue::zero())
-                    }
-                };
+// Branches to insert(`Some`) or remove(`None`)
+type BranchChanges = Vec<(BranchKey, Option<BranchNode>)>;
 
-                if !left.is_zero() || !right.is_zero() {
-                    self.store.insert_branch(
-                        parent_branch_key,
-                        BranchNode {
-                            left: left.clone(),
-                            right: right.clone(),
-                        },
-                    )?;
-                } else {
-                    self.store.remove_branch(&parent_branch_key)?;
-                }
-                next_nodes
-                    .push((parent_key, merge::<H>(height, &parent_key, &left, &right)));
-            }
-            nodes = next_nodes;
-        }
+// Leaves to insert, with their hashes if cached(`Value::CACHE_HASH`)
+type LeafInserts<V> = Vec<(H256, V, Option<H256>)>;
 
-        debug_assert_eq!(nodes.len(), 1);
+// Only the branches where both children are non-zero, and the top one, are stored.
+// A branch in between has a zero child, its other child is a node inside a run of
+// merges with zero, and is rebuilt from the `MergeWithZero` recorded in the stored
+// branch above the run.
+#[inline(always)]
+fn is_stored(top: bool, left: bool, right: bool) -> bool {
+    (left && right) || (top && (left || right))
+}
 
-        let root = nodes[0].1.hash::<H>();
-        self.store.update_root(root).map(|_| root)
+// Lowest level of the run of merges with zero that ends in `node` at `level`,
+// the node at that level is a leaf or the merge of a stored branch
+#[inline(always)]
+fn base_level(level: u8, node: &MergeValue) -> Option<u8> {
+    match node {
+        MergeValue::Value(_) => Some(level),
+        MergeValue::MergeWithZero { zero_count, .. } => level.checked_sub(*zero_count),
     }
+}
 
-    /// Get value of a leaf
-    /// return zero value if leaf not exists
+// Node at `level` inside a run of merges with zero starting at `base`(< `level`)
+#[inline(always)]
+fn run_node(base_node: &H256, zero_bits: &H256, base: u8, level: u8) -> MergeValue {
+    let mut zero_bits = *zero_bits;
+    for h in level..=u8::MAX {
+        zero_bits.clear_bit(h);
+    }
+    MergeValue::MergeWithZero {
+        base_node: *base_node,
+        zero_bits,
+        zero_count: level - base,
+    }
+}
+
+// Merge `node` at level `from` with zero siblings up to level `to`, along `path`
+fn lift<H: Hasher>(mut node: MergeValue, path: &H256, from: u8, to: u8) -> MergeValue {
+    for height in from..to {
+        let parent_key = path.parent_path(height);
+        node = if path.is_right(height) {
+            merge::<H>(height, &parent_key, &MergeValue::zero(), &node)
+        } else {
+            merge::<H>(height, &parent_key, &node, &MergeValue::zero())
+        };
+    }
+    node
+}
+
+// Pop the sibling at `height` from the siblings of a `KeyPath`
+fn take_sibling(siblings: &mut Vec<(u8, MergeValue)>, height: u8) -> MergeValue {
+    while siblings.last().is_some_and(|(h, _)| *h < height) {
+        siblings.pop();
+    }
+    match siblings.last() {
+        Some((h, _)) if *h == height => siblings.pop().unwrap().1,
+        _ => MergeValue::zero(),
+    }
+}
+
+// A key path as read from the store
+struct KeyPath {
+    // non-zero siblings as `(height, sibling)`, from the top down
+    siblings: Vec<(u8, MergeValue)>,
+    // lowest level where the node on the path is non-zero,
+    // 0 if the key is in the tree, `depth + 1` if the tree is empty
+    own_level: u16,
+    // the child on the key side of the lowest stored branch, with its height,
+    // present if the key is in the tree
+    leaf_run: Option<(u8, MergeValue)>,
+}
+
+// Branches of the top levels of a tree, as read from or written to the store
+// when its root was `root`. The branches of a root are the same whatever
+// the version or branch it is reached on, so entries are dropped only
+// when the root they belong to is no longer the current one.
+#[derive(Debug, Default)]
+pub(crate) struct BranchCache {
+    // number of top levels cached, 0 if the cache is off
+    levels: usize,
+    state: Mutex<(H256, HashMap<BranchKey, Option<BranchNode>>)>,
+}
+
+impl BranchCache {
     #[inline(always)]
-    pub fn get(&self, key: &H256) -> Result<Option<V>> {
-        self.store.get_leaf(key)
+    fn is_on(&self) -> bool {
+        self.levels > 0
     }
 
     #[inline(always)]
-    pub fn get_by_branch(&self, key: &H256, br: BranchName) -> Result<Option<V>> {
-        self.store.get_leaf_by_branch(key, br)
+    fn covers(&self, depth: usize, key: &BranchKey) -> bool {
+        key.height as usize + self.levels >= depth
+    }
+
+    // The branch `key` of the tree at `root`, read by `fetch` on a miss
+    fn get(
+        &self,
+        root: H256,
+        key: &BranchKey,
+        fetch: impl FnOnce() -> Result<Option<BranchNode>>,
+    ) -> Result<Option<BranchNode>> {
+        let mut state = self.state.lock().unwrap();
+        if state.0 != root {
+            *state = (root, HashMap::new());
+        }
+        if let Some(branch) = state.1.get(key) {
+            metric!(branch_cache_hits);
+            return Ok(*branch);
+        }
+        let branch = fetch()?;
+        state.1.insert(key.clone(), branch);
+        Ok(branch)
     }
 
+    // Carry the entries over a write from `old_root` to `new_root`
+    fn apply(
+        &self,
+        depth: usize,
+        old_root: H256,
+        new_root: H256,
+        changes: &BranchChanges,
+    ) {
+        let mut state = self.state.lock().unwrap();
+        if state.0 != old_root {
+            *state = (new_root, HashMap::new());
+            return;
+        }
+        state.0 = new_root;
+        for (key, branch) in changes.iter().filter(|(k, _)| self.covers(depth, k)) {
+            state.1.insert(key.clone(), *branch);
+        }
+    }
+}
+
+// the cache of a clone starts empty
+impl Clone for BranchCache {
+    fn clone(&self) -> Self {
+        BranchCache {
+            levels: self.levels,
+            state: Mutex::default(),
+        }
+    }
+}
+
+// it follows the root, not the versions
+impl VsMgmt for BranchCache {
+    vsdb::impl_vs_methods_nope!();
+}
+
+// a branch, `None` if it is not stored; a read from the store counts `branch_reads`
+type FetchBranch<'a> = Box<dyn Fn(&BranchKey) -> Result<Option<BranchNode>> + 'a>;
+// leaf hash, `None` if the leaf does not exist
+type FetchLeaf<'a> = Box<dyn Fn(&H256) -> Result<Option<H256>> + 'a>;
+
+// Reads of a `depth`-level tree, from the current state or a past version
+struct Layout<'a, H> {
+    depth: usize,
+    fetch_branch: FetchBranch<'a>,
+    fetch_leaf: FetchLeaf<'a>,
+    // verify every read against this root
+    root: Option<H256>,
+    phantom: PhantomData<H>,
+}
+
+impl<H: Hasher> Layout<'_, H> {
     #[inline(always)]
-    pub fn get_by_branch_version(
+    fn top_height(&self) -> u8 {
+        (self.depth - 1) as u8
+    }
+
+    // Read the branch at `height` on `path`, verify it against the run
+    // recorded in its parent(`(level, node)`), or the root if it is the top one
+    fn branch(
         &self,
-        key: &H256,
-        br: BranchName,
-        ver: VersionName,
-    ) -> Result<Option<V>> {
-        self.store.get_leaf_by_branch_version(key, br, ver)
+        height: u8,
+        path: &H256,
+        parent: Option<(u8, &MergeValue)>,
+    ) -> Result<Option<BranchNode>> {
+        let node_key = path.parent_path(height);
+        let branch = (self.fetch_branch)(&BranchKey::new(height, node_key))?;
+        #[cfg(feature = "tracing")]
+        tracing::trace!(height, found = branch.is_some(), "read branch");
+        if let Some(root) = self.root {
+            let node = branch
+                .as_ref()
+                .map(|b| merge::<H>(height, &node_key, &b.left, &b.right))
+                .unwrap_or_else(MergeValue::zero);
+            let valid = match parent {
+                None => fold_to_root::<H>(self.depth, node) == root,
+                Some((level, recorded)) => {
+                    &lift::<H>(node, path, height + 1, level) == recorded
+                }
+            };
+            if !valid {
+                return Err(Error::CorruptedNode {
+                    key: node_key,
+                    height,
+                });
+            }
+        }
+        Ok(branch)
     }
 
-    /// Generate merkle proof
-    pub fn merkle_proof(&self, mut keys: Vec<H256>) -> Result<MerkleProof> {
-        if keys.is_empty() {
-            return Err(Error::EmptyKeys);
+    // Node at the base `level` of the run that ends in `run`(`(level, node)`),
+    // `path` must follow the run
+    fn base_node(
+        &self,
+        level: u8,
+        path: &H256,
+        run: (u8, &MergeValue),
+    ) -> Result<MergeValue> {
+        if level > 0 {
+            let height = level - 1;
+            let node_key = path.parent_path(height);
+            let branch = self
+                .branch(height, path, Some(run))?
+                .ok_or(Error::MissingBranch(height, node_key))?;
+            return Ok(merge::<H>(height, &node_key, &branch.left, &branch.right));
         }
 
-        // sort keys
-        keys.sort_unstable();
+        let corrupted = Error::CorruptedNode {
+            key: *path,
+            height: 0,
+        };
+        metric!(leaf_reads);
+        #[cfg(feature = "tracing")]
+        tracing::trace!("read leaf");
+        let node = match (self.fetch_leaf)(path)? {
+            Some(hash) if !hash.is_zero() => MergeValue::from_h256(hash),
+            _ => return Err(corrupted),
+        };
+        if self.root.is_some() && &lift::<H>(node, path, 0, run.0) != run.1 {
+            return Err(corrupted);
+        }
+        Ok(node)
+    }
+
+    // Walk down from the top branch along `key`
+    fn path(&self, key: &H256) -> Result<KeyPath> {
+        let mut siblings = Vec::new();
+        let mut height = self.top_height();
+        let mut parent: Option<(u8, MergeValue)> = None;
+        loop {
+            let branch =
+                match self.branch(height, key, parent.as_ref().map(|(l, n)| (*l, n)))? {
+                    Some(branch) => branch,
+                    None if parent.is_none() => {
+                        return Ok(KeyPath {
+                            siblings,
+                            own_level: self.depth as u16 + 1,
+                            leaf_run: None,
+                        });
+                    }
+                    None => {
+                        return Err(Error::MissingBranch(
+                            height,
+                            key.parent_path(height),
+                        ))
+                    }
+                };
+            let (own, sibling) = if key.is_right(height) {
+                (branch.right, branch.left)
+            } else {
+                (branch.left, branch.right)
+            };
+            if !sibling.is_zero() {
+                siblings.push((height, sibling));
+            }
+            if own.is_zero() {
+                return Ok(KeyPath {
+                    siblings,
+                    own_level: height as u16 + 1,
+                    leaf_run: None,
+                });
+            }
 
-        // Collect leaf bitmaps
-        let mut leaves_bitmap: Vec<H256> = Default::default();
-        for current_key in &keys {
-            let mut bitmap = H256::zero();
-            for height in 0..=u8::MAX {
-                let parent_key = current_key.parent_path(height);
-                let parent_branch_key = BranchKey::new(height, parent_key);
-                if let Some(parent_branch) = self.store.get_branch(&parent_branch_key)? {
-                    let sibling = if current_key.is_right(height) {
-                        parent_branch.left
+            let base = base_level(height, &own).ok_or(Error::CorruptedNode {
+                key: key.parent_path(height),
+                height,
+            })?;
+            if let MergeValue::MergeWithZero {
+                base_node,
+                zero_bits,
+                ..
+            } = &own
+            {
+                // the run leaves the key path at `h`, where it is the sibling
+                if let Some(h) = (base..height)
+                    .rev()
+                    .find(|h| zero_bits.get_bit(*h) != key.get_bit(*h))
+                {
+                    let sibling = if h > base {
+                        run_node(base_node, zero_bits, base, h)
                     } else {
-                        parent_branch.right
+                        let mut run_path = *key;
+                        if key.get_bit(h) {
+                            run_path.clear_bit(h);
+                        } else {
+                            run_path.set_bit(h);
+                        }
+                        self.base_node(h, &run_path, (height, &own))?
                     };
-                    if !sibling.is_zero() {
-                        bitmap.set_bit(height);
-                    }
-                } else {
-                    // The key is not in the tree (support non-inclusion proof)
+                    siblings.push((h, sibling));
+                    return Ok(KeyPath {
+                        siblings,
+                        own_level: h as u16 + 1,
+                        leaf_run: None,
+                    });
                 }
             }
-            leaves_bitmap.push(bitmap);
+
+            if base == 0 {
+                return Ok(KeyPath {
+                    siblings,
+                    own_level: 0,
+                    leaf_run: Some((height, own)),
+                });
+            }
+            parent = Some((height, own));
+            height = base - 1;
+        }
+    }
+
+    // Node at `level`(0..=256) on the path of `key`
+    fn node_at(&self, key: &H256, level: u16) -> Result<MergeValue> {
+        if level as usize > self.depth {
+            // nothing but zeros above the top height
+            let mut node = self.node_at(key, self.depth as u16)?;
+            for height in self.depth..level as usize {
+                let height = height as u8;
+                node = merge::<H>(
+                    height,
+                    &key.parent_path(height),
+                    &node,
+                    &MergeValue::zero(),
+                );
+            }
+            return Ok(node);
         }
 
-        let mut proof: Vec<MergeValue> = Default::default();
-        let mut stack_fork_height = [0u8; MAX_STACK_SIZE]; // store fork height
-        let mut stack_top = 0;
-        let mut leaf_index = 0;
-        while leaf_index < keys.len() {
-            let leaf_key = keys[leaf_index];
-            let fork_height = if leaf_index + 1 < keys.len() {
-                leaf_key.fork_height(&keys[leaf_index + 1])
+        let mut height = self.top_height();
+        let mut parent: Option<(u8, MergeValue)> = None;
+        loop {
+            let branch =
+                match self.branch(height, key, parent.as_ref().map(|(l, n)| (*l, n)))? {
+                    Some(branch) => branch,
+                    None if parent.is_none() => return Ok(MergeValue::zero()),
+                    None => {
+                        return Err(Error::MissingBranch(
+                            height,
+                            key.parent_path(height),
+                        ))
+                    }
+                };
+            if level == height as u16 + 1 {
+                let node_key = key.parent_path(height);
+                return Ok(merge::<H>(height, &node_key, &branch.left, &branch.right));
+            }
+
+            let own = if key.is_right(height) {
+                branch.right
             } else {
-                u8::MAX
+                branch.left
             };
-            for height in 0..=fork_height {
-                if height == fork_height && leaf_index + 1 < keys.len() {
-                    // If it's not final round, we don't need to merge to root (height=255)
-                    break;
+            let level = level as u8;
+            if own.is_zero() || level == height {
+                return Ok(own);
+            }
+            let base = base_level(height, &own).ok_or(Error::CorruptedNode {
+                key: key.parent_path(height),
+                height,
+            })?;
+            if let MergeValue::MergeWithZero {
+                base_node,
+                zero_bits,
+                ..
+            } = &own
+            {
+                // the key path leaves the run above `level`, where it is empty
+                if (base.max(level)..height).any(|h| zero_bits.get_bit(h) != key.get_bit(h))
+                {
+                    return Ok(MergeValue::zero());
                 }
-                let parent_key = leaf_key.parent_path(height);
-                let is_right = leaf_key.is_right(height);
-
-                // has non-zero sibling
-                if stack_top > 0 && stack_fork_height[stack_top - 1] == height {
-                    stack_top -= 1;
-                } else if leaves_bitmap[leaf_index].get_bit(height) {
-                    let parent_branch_key = BranchKey::new(height, parent_key);
-                    if let Some(parent_branch) =
-                        self.store.get_branch(&parent_branch_key)?
-                    {
-                        let sibling = if is_right {
-                            parent_branch.left
-                        } else {
-                            parent_branch.right
-                        };
-                        if !sibling.is_zero() {
-                            proof.push(sibling);
-                        } else {
-                            unreachable!();
-                        }
-                    } else {
-                        // The key is not in the tree (support non-inclusion proof)
+                if level > base {
+                    return Ok(run_node(base_node, zero_bits, base, level));
+                }
+            }
+            if level == base {
+                return self.base_node(base, key, (height, &own));
+            }
+            parent = Some((height, own));
+            height = base - 1;
+        }
+    }
+
+    // Children of the top branch, zeros if the tree is empty
+    fn top_children(&self) -> Result<(MergeValue, MergeValue)> {
+        Ok(match self.branch(self.top_height(), &H256::zero(), None)? {
+            Some(branch) => (branch.left, branch.right),
+            None => (MergeValue::zero(), MergeValue::zero()),
+        })
+    }
+
+    // Children of `node` at `level`(0 < `level` < depth) on `path`,
+    // as the nodes at `level - 1`
+    fn children(
+        &self,
+        level: u8,
+        path: &H256,
+        node: &MergeValue,
+    ) -> Result<(MergeValue, MergeValue)> {
+        let height = level - 1;
+        match node {
+            _ if node.is_zero() => Ok((MergeValue::zero(), MergeValue::zero())),
+            // merge of a stored branch
+            MergeValue::Value(_) => {
+                let branch = self
+                    .branch(height, path, Some((level, node)))?
+                    .ok_or(Error::MissingBranch(height, path.parent_path(height)))?;
+                Ok((branch.left, branch.right))
+            }
+            MergeValue::MergeWithZero {
+                base_node,
+                zero_bits,
+                ..
+            } => {
+                let base = base_level(level, node).ok_or(Error::CorruptedNode {
+                    key: path.parent_path(height),
+                    height,
+                })?;
+                let mut child_path = *path;
+                let right = zero_bits.get_bit(height);
+                if right {
+                    child_path.set_bit(height);
+                }
+                let child = if height > base {
+                    run_node(base_node, zero_bits, base, height)
+                } else {
+                    self.base_node(height, &child_path, (level, node))?
+                };
+                if right {
+                    Ok((MergeValue::zero(), child))
+                } else {
+                    Ok((child, MergeValue::zero()))
+                }
+            }
+        }
+    }
+
+    // Check the hash of the leaf `key`(`None` if absent) against its run
+    fn verify_leaf(&self, key: &H256, leaf: Option<H256>) -> Result<()> {
+        let path = self.path(key)?;
+        let valid = match (leaf.filter(|h| !h.is_zero()), path.leaf_run) {
+            (Some(hash), Some((level, node))) => {
+                lift::<H>(MergeValue::from_h256(hash), key, 0, level) == node
+            }
+            (None, None) => true,
+            _ => false,
+        };
+        if !valid {
+            return Err(Error::CorruptedNode {
+                key: *key,
+                height: 0,
+            });
+        }
+        Ok(())
+    }
+
+    // Smallest key in the subtree at `level`(> 0) that starts at `key`
+    fn first_key_in(&self, key: H256, level: u16) -> Result<Option<H256>> {
+        let path = self.path(&key)?;
+        if path.own_level > level {
+            return Ok(None);
+        }
+        if path.own_level == 0 {
+            return Ok(Some(key));
+        }
+
+        // the path of `key` is empty below `own_level`,
+        // so the subtree continues on the right there
+        let height = (path.own_level - 1) as u8;
+        let sibling = match path.siblings.last() {
+            Some((h, sibling)) if *h == height => *sibling,
+            _ => return Err(Error::MissingBranch(height, key.parent_path(height))),
+        };
+        let mut path = key;
+        path.set_bit(height);
+
+        // walk down the leftmost non-zero children
+        let (mut level, mut node) = (height, sibling);
+        loop {
+            let base = base_level(level, &node).ok_or(Error::CorruptedNode {
+                key: path.parent_path(level),
+                height: level,
+            })?;
+            if let MergeValue::MergeWithZero { zero_bits, .. } = &node {
+                for h in base..level {
+                    if zero_bits.get_bit(h) {
+                        path.set_bit(h);
                     }
                 }
             }
+            if base == 0 {
+                return Ok(Some(path));
+            }
+
+            let height = base - 1;
+            let node_key = path.parent_path(height);
+            let branch = self
+                .branch(height, &path, Some((level, &node)))?
+                .ok_or(Error::MissingBranch(height, node_key))?;
+            node = if !branch.left.is_zero() {
+                branch.left
+            } else if !branch.right.is_zero() {
+                path.set_bit(height);
+                branch.right
+            } else {
+                return Err(Error::MissingBranch(height, node_key));
+            };
+            level = height;
+        }
+    }
+}
+
+// Recompute the tree from bottom to top without writing,
+// `nodes` must be sorted and deduped, a zero value removes the leaf;
+// return the branches to insert(`Some`) or remove(`None`), the new root,
+// and the change of the number of leaves.
+fn plan_recompute<H: Hasher>(
+    layout: &Layout<'_, H>,
+    nodes: Vec<(H256, MergeValue)>,
+) -> Result<(BranchChanges, H256, i64)> {
+    let top = layout.top_height();
+    let mut paths = Vec::with_capacity(nodes.len());
+    let mut leaves_delta = 0;
+    for (k, v) in nodes.iter() {
+        let path = layout.path(k)?;
+        leaves_delta += !v.is_zero() as i64 - path.leaf_run.is_some() as i64;
+        paths.push(path);
+    }
+    // each node carries the index of a leaf under it, whose path gives the siblings
+    // out of the batch, and the lowest level where it was non-zero before the batch
+    let mut nodes = nodes
+        .into_iter()
+        .zip(paths.iter())
+        .enumerate()
+        .map(|(i, ((k, v), path))| (k, v, i, path.own_level))
+        .collect::<Vec<_>>();
+    let mut siblings = paths
+        .into_iter()
+        .map(|path| path.siblings)
+        .collect::<Vec<_>>();
+    let mut branches = Vec::new();
+
+    for height in 0..=top {
+        let mut next_nodes = Vec::with_capacity(nodes.len());
+        let mut iter = nodes.into_iter().peekable();
+        while let Some((current_key, current_node, idx, own_level)) = iter.next() {
+            let parent_key = current_key.parent_path(height);
+            let was_own = own_level <= height as u16;
+
+            let mut right_key = current_key;
+            right_key.set_bit(height);
+            let neighbor = if current_key.is_right(height) {
+                None
+            } else {
+                iter.next_if(|(k, ..)| *k == right_key)
+            };
+            let (left, right, was_left, was_right, own_level) = match neighbor {
+                Some((_, right, _, right_level)) => (
+                    current_node,
+                    right,
+                    was_own,
+                    right_level <= height as u16,
+                    own_level.min(right_level),
+                ),
+                None => {
+                    // out of the batch, the sibling is the same as before
+                    let sibling = take_sibling(&mut siblings[idx], height);
+                    let was_sibling = !sibling.is_zero();
+                    if current_key.is_right(height) {
+                        (sibling, current_node, was_sibling, was_own, own_level)
+                    } else {
+                        (current_node, sibling, was_own, was_sibling, own_level)
+                    }
+                }
+            };
+
+            let branch_key = BranchKey::new(height, parent_key);
+            if is_stored(height == top, !left.is_zero(), !right.is_zero()) {
+                branches.push((branch_key, Some(BranchNode { left, right })));
+            } else if is_stored(height == top, was_left, was_right) {
+                branches.push((branch_key, None));
+            }
+            let parent = merge::<H>(height, &parent_key, &left, &right);
+            next_nodes.push((parent_key, parent, idx, own_level));
+        }
+        nodes = next_nodes;
+    }
+
+    debug_assert_eq!(nodes.len(), 1);
+
+    Ok((
+        branches,
+        fold_to_root::<H>(layout.depth, nodes.swap_remove(0).1),
+        leaves_delta,
+    ))
+}
+
+// Generate a merkle proof of `keys` from the layout
+fn merkle_proof_in<H: Hasher>(
+    layout: &Layout<'_, H>,
+    mut keys: Vec<H256>,
+) -> Result<MerkleProof> {
+    if keys.is_empty() {
+        return Err(Error::EmptyKeys);
+    }
+    for k in keys.iter() {
+        check_key_depth(layout.depth, k)?;
+    }
+
+    // sort keys
+    keys.sort_unstable();
+    // a duplicated key would throw off the fork height stack
+    if let Some(k) = keys.windows(2).find(|w| w[0] == w[1]) {
+        return Err(Error::DuplicatedKeys(k[0]));
+    }
+
+    // Collect leaf bitmaps
+    let mut leaves_bitmap: Vec<H256> = Default::default();
+    let mut siblings = Vec::with_capacity(keys.len());
+    for current_key in &keys {
+        let path = layout.path(current_key)?;
+        let mut bitmap = H256::zero();
+        for (height, _) in path.siblings.iter() {
+            bitmap.set_bit(*height);
+        }
+        leaves_bitmap.push(bitmap);
+        siblings.push(path.siblings);
+    }
+
+    let mut proof: Vec<MergeValue> = Default::default();
+    let mut stack_fork_height = [0u8; MAX_STACK_SIZE]; // store fork height
+    let mut stack_top = 0;
+    let mut leaf_index = 0;
+    while leaf_index < keys.len() {
+        let leaf_key = keys[leaf_index];
+        let fork_height = if leaf_index + 1 < keys.len() {
+            leaf_key.fork_height(&keys[leaf_index + 1])
+        } else {
+            u8::MAX
+        };
+        for height in 0..=fork_height {
+            if height == fork_height && leaf_index + 1 < keys.len() {
+                // If it's not final round, we don't need to merge to root (height=255)
+                break;
+            }
+
+            // has non-zero sibling
+            if stack_top > 0 && stack_fork_height[stack_top - 1] == height {
+                stack_top -= 1;
+            } else if leaves_bitmap[leaf_index].get_bit(height) {
+                // the bitmap is built from the non-zero siblings read on the path,
+                // report a mismatch instead of proving a wrong sibling
+                let sibling = take_sibling(&mut siblings[leaf_index], height);
+                if sibling.is_zero() {
+                    return Err(Error::CorruptedNode {
+                        key: leaf_key.parent_path(height),
+                        height,
+                    });
+                }
+                proof.push(sibling);
+            }
+        }
+
+        debug_assert!(stack_top < MAX_STACK_SIZE);
+
+        stack_fork_height[stack_top] = fork_height;
+        stack_top += 1;
+        leaf_index += 1;
+    }
+
+    debug_assert_eq!(stack_top, 1);
+
+    metric!(proofs);
+    metric!(proof_siblings, proof.len());
+    #[cfg(feature = "tracing")]
+    tracing::debug!(siblings = proof.len(), "merkle proof");
+    Ok(MerkleProof::new(leaves_bitmap, proof))
+}
+
+// Type parameters that are not owned, so a tree is `Send`/`Sync` as long as its store is
+type NotOwned<T> = PhantomData<fn() -> T>;
+
+/// Changed leaves between two versions: `(key, old value, new value)`.
+pub type VersionDiff<V> = Vec<(H256, Option<V>, Option<V>)>;
+
+/// Iterator of `SparseMerkleTree::diff`, yielding `(key, value in self, value in other)`
+/// for the differing leaves, sorted by key; it ends after yielding an error.
+pub struct DiffIter<'a, H, V, S: VsMgmt, const DEPTH: usize> {
+    trees: [&'a SparseMerkleTree<H, V, S, DEPTH>; 2],
+    layouts: [Layout<'a, H>; 2],
+    // subtrees left to compare as `(level, path, node of self, node of other)`,
+    // the leftmost one on the top
+    pending: Vec<(u8, H256, MergeValue, MergeValue)>,
+}
+
+impl<H: Hasher, V: Value<H>, S: Store<V>, const DEPTH: usize> Iterator
+    for DiffIter<'_, H, V, S, DEPTH>
+{
+    type Item = Result<(H256, Option<V>, Option<V>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some((level, path, left, right)) = self.pending.pop() {
+            // identical subtrees are skipped as a whole
+            if left == right {
+                continue;
+            }
+            let item = if level == 0 {
+                self.trees[0].get(&path).and_then(|l| {
+                    self.trees[1].get(&path).map(|r| Some((path, l, r)))
+                })
+            } else {
+                self.layouts[0]
+                    .children(level, &path, &left)
+                    .and_then(|l| {
+                        self.layouts[1].children(level, &path, &right).map(|r| (l, r))
+                    })
+                    .map(|((ll, lr), (rl, rr))| {
+                        let height = level - 1;
+                        let mut right_path = path;
+                        right_path.set_bit(height);
+                        self.pending.push((height, right_path, lr, rr));
+                        self.pending.push((height, path, ll, rl));
+                        None
+                    })
+            };
+            match item {
+                Ok(None) => {}
+                Ok(Some(leaf)) => return Some(Ok(leaf)),
+                Err(e) => {
+                    self.pending.clear();
+                    return Some(Err(e));
+                }
+            }
+        }
+        None
+    }
+}
+
+/// Iterator of `SparseMerkleTree::export_leaves_sorted`, yielding the leaves
+/// in ascending key order; it ends after yielding an error.
+pub struct LeafIter<'a, H, V, S: VsMgmt, const DEPTH: usize> {
+    tree: &'a SparseMerkleTree<H, V, S, DEPTH>,
+    layout: Layout<'a, H>,
+    // non-zero subtrees left to walk as `(level, path, node)`, the leftmost one on the top
+    pending: Vec<(u8, H256, MergeValue)>,
+}
+
+impl<H: Hasher, V: Value<H>, S: Store<V>, const DEPTH: usize> Iterator
+    for LeafIter<'_, H, V, S, DEPTH>
+{
+    type Item = Result<(H256, V)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some((level, mut path, node)) = self.pending.pop() {
+            if node.is_zero() {
+                continue;
+            }
+            // a run down to a leaf leads to a single key
+            let leaf_run = match &node {
+                MergeValue::MergeWithZero {
+                    zero_bits,
+                    zero_count,
+                    ..
+                } if *zero_count == level => Some(zero_bits),
+                _ => None,
+            };
+            if level == 0 || leaf_run.is_some() {
+                if let Some(zero_bits) = leaf_run {
+                    for h in (0..level).filter(|h| zero_bits.get_bit(*h)) {
+                        path.set_bit(h);
+                    }
+                }
+                let item = match self.tree.get(&path) {
+                    Ok(Some(value)) => Ok((path, value)),
+                    Ok(None) => Err(Error::CorruptedNode {
+                        key: path,
+                        height: 0,
+                    }),
+                    Err(e) => Err(e),
+                };
+                if item.is_err() {
+                    self.pending.clear();
+                }
+                return Some(item);
+            }
+            match self.layout.children(level, &path, &node) {
+                Ok((left, right)) => {
+                    let height = level - 1;
+                    let mut right_path = path;
+                    right_path.set_bit(height);
+                    self.pending.push((height, right_path, right));
+                    self.pending.push((height, path, left));
+                }
+                Err(e) => {
+                    self.pending.clear();
+                    return Some(Err(e));
+                }
+            }
+        }
+        None
+    }
+}
+
+/// A merkle proof and the leaves it proves, sorted by key.
+pub type ProofWithValues<V> = (MerkleProof, Vec<(H256, Option<V>)>);
+
+/// Sparse merkle tree
+///
+/// Only the branches where two subtrees fork, and the top one, are stored;
+/// the nodes between them are rebuilt from the `MergeWithZero` runs
+/// recorded in the stored branches.
+///
+/// `DEPTH`(1..=256) is the number of levels of the tree,
+/// keys must fit in its lowest `DEPTH` bits. Shallow trees walk fewer levels
+/// per update, while roots and proofs stay the same as those
+/// of a 256-level tree with the same leaves.
+#[derive(Vs, Clone, Default, Debug, Deserialize, Serialize)]
+pub struct SparseMerkleTree<H, V, S: VsMgmt, const DEPTH: usize = 256> {
+    pub(crate) store: S,
+    #[serde(default)]
+    pub(crate) verify_reads: bool,
+    #[serde(skip)]
+    cache: BranchCache,
+    phantom: NotOwned<(H, V)>,
+}
+
+impl<H: Hasher, V: Value<H>, S: Store<V>, const DEPTH: usize>
+    SparseMerkleTree<H, V, S, DEPTH>
+{
+    // Checked at compile time wherever the tree is written or proved
+    const ASSERT_DEPTH: () =
+        assert!(DEPTH > 0 && DEPTH <= 256, "DEPTH must be in 1..=256");
+
+    /// Build a merkle tree from root and store
+    #[inline(always)]
+    pub fn new(store: S) -> SparseMerkleTree<H, V, S, DEPTH> {
+        SparseMerkleTree {
+            store,
+            verify_reads: false,
+            cache: BranchCache::default(),
+            phantom: PhantomData,
+        }
+    }
+
+    // Reads of the current state
+    fn layout(&self) -> Result<Layout<'_, H>> {
+        #[allow(clippy::let_unit_value)]
+        let () = Self::ASSERT_DEPTH;
+        let cached_root = if self.cache.is_on() {
+            Some(self.store.get_root()?)
+        } else {
+            None
+        };
+        Ok(Layout {
+            depth: DEPTH,
+            fetch_branch: Box::new(move |k| {
+                let read = || {
+                    metric!(branch_reads);
+                    self.store.get_branch(k)
+                };
+                match cached_root {
+                    Some(root) if self.cache.covers(DEPTH, k) => {
+                        self.cache.get(root, k, read)
+                    }
+                    _ => read(),
+                }
+            }),
+            fetch_leaf: Box::new(|k| self.stored_leaf_hash(k)),
+            root: if self.verify_reads {
+                Some(self.store.get_root()?)
+            } else {
+                None
+            },
+            phantom: PhantomData,
+        })
+    }
+
+    // The cached leaf hashes are not read for a verified read, which must hash
+    // the leaf itself, nor for values that are not worth caching
+    #[inline(always)]
+    fn uses_hash_cache(&self) -> bool {
+        <V as Value<H>>::CACHE_HASH && !self.verify_reads
+    }
+
+    // Hash of the leaf at `k`, `None` if it does not exist
+    fn stored_leaf_hash(&self, k: &H256) -> Result<Option<H256>> {
+        if self.uses_hash_cache() {
+            if let Some(hash) = self.store.get_leaf_hash(k)? {
+                metric!(leaf_hash_hits);
+                return Ok(Some(hash));
+            }
+        }
+        Ok(self.store.get_leaf(k)?.map(|v| v.to_h256()))
+    }
+
+    // Whether the leaf at `k` already has the non-zero `hash`; when the hash
+    // cache is used, a leaf whose hash is not cached is taken as changed
+    fn is_unchanged(&self, k: &H256, hash: H256) -> Result<bool> {
+        if hash.is_zero() {
+            return Ok(false);
+        }
+        if !self.uses_hash_cache() {
+            return Ok(self.store.get_leaf(k)?.map(|v| v.to_h256()) == Some(hash));
+        }
+        let unchanged = self.store.get_leaf_hash(k)? == Some(hash);
+        if unchanged {
+            metric!(leaf_hash_hits);
+        }
+        Ok(unchanged)
+    }
+
+    /// Verify every branch and leaf read against the hash recorded in its parent,
+    /// mismatches are reported as `Error::CorruptedNode` instead of being used.
+    /// It roughly doubles the store reads, so it is off by default.
+    #[inline(always)]
+    pub fn set_verify_reads(&mut self, enable: bool) {
+        self.verify_reads = enable;
+    }
+
+    /// Keep the branches of the top `levels` levels in memory,
+    /// 0 turns it off(the default).
+    ///
+    /// The branches near the root are read by every update and proof,
+    /// with the cache they are read from the store once and then kept up to date
+    /// by the writes of this tree; any other change of the root, e.g. a rollback,
+    /// or writes through another handle of the store, empties it.
+    /// Each cached level holds at most `2^level` branches.
+    pub fn set_branch_cache(&mut self, levels: usize) {
+        self.cache = BranchCache {
+            levels: levels.min(DEPTH),
+            state: Mutex::default(),
+        };
+    }
+
+    /// Merkle root, panic on store errors, see `try_root`
+    #[inline(always)]
+    pub fn root(&self) -> H256 {
+        self.try_root().unwrap()
+    }
+
+    /// Merkle root, or the error of the store
+    #[inline(always)]
+    pub fn try_root(&self) -> Result<H256> {
+        self.store.get_root()
+    }
+
+    /// Merkle root committed at a specified version
+    pub fn root_by_branch_version(
+        &self,
+        br: BranchName,
+        ver: VersionName,
+    ) -> Result<H256> {
+        if !self.store.version_exists_on_branch(ver, br) {
+            return Err(Error::VersionNotFound(ver.0.to_vec()));
+        }
+        self.store.get_root_by_branch_version(br, ver)
+    }
+
+    /// Merkle root at the head of a branch
+    #[inline(always)]
+    pub fn root_by_branch(&self, br: BranchName) -> Result<H256> {
+        self.store.get_root_by_branch(br)
+    }
+
+    /// Roots recorded by the writes with sequence numbers in `range`,
+    /// as `(sequence number, root)` in order.
+    ///
+    /// Every write that reaches the store records its new root,
+    /// sequence numbers start from 0 and grow by one per write.
+    /// The history is versioned with the rest of the tree,
+    /// so `rollback_to` also drops the roots recorded after the target version.
+    pub fn root_history(&self, range: impl RangeBounds<u64>) -> Vec<(u64, H256)> {
+        let range: (Bound<u64>, Bound<u64>) =
+            (range.start_bound().cloned(), range.end_bound().cloned());
+        self.store.iter_root_history(range).collect()
+    }
+
+    /// Root recorded by the write with sequence number `seq`
+    #[inline(always)]
+    pub fn root_at_seq(&self, seq: u64) -> Result<Option<H256>> {
+        self.store.get_root_by_seq(seq)
+    }
+
+    /// Discard all versions created after `ver` on the default branch,
+    /// the root, branches and leaves are restored as they were at the end of `ver`.
+    ///
+    /// Nothing is touched if `ver` is not a version of the default branch itself.
+    pub fn rollback_to(&mut self, ver: VersionName) -> Result<()> {
+        let versions = self.store.version_list().map_err(|e| {
+            Error::store(StoreOp::Get, StoreTarget::Version(ver.0.to_vec()), e)
+        })?;
+        let pos = versions
+            .iter()
+            .position(|v| v.0 == ver.0)
+            .ok_or_else(|| Error::VersionNotFound(ver.0.to_vec()))?;
+        for popped in versions[(pos + 1)..].iter().rev() {
+            self.store.version_pop().map_err(|e| {
+                Error::store(StoreOp::Remove, StoreTarget::Version(popped.0.clone()), e)
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Merge all versions created before `ver` on the default branch into `ver`,
+    /// reclaiming the space taken by their change sets.
+    ///
+    /// The current state, `ver` and the versions after it are left as they are,
+    /// while the states of the merged versions can no longer be read.
+    pub fn prune_versions_before(&mut self, ver: VersionName) -> Result<()> {
+        let target = || StoreTarget::Version(ver.0.to_vec());
+        let versions = self
+            .store
+            .version_list()
+            .map_err(|e| Error::store(StoreOp::Get, target(), e))?;
+        let pos = versions
+            .iter()
+            .position(|v| v.0 == ver.0)
+            .ok_or_else(|| Error::VersionNotFound(ver.0.to_vec()))?;
+        self.store
+            .prune(Some(versions.len() - pos))
+            .map_err(|e| Error::store(StoreOp::Remove, target(), e))
+    }
+
+    /// Mark the current state with `name`, so it can be brought back by `restore`.
+    ///
+    /// A checkpoint is a version of the default branch: the following writes
+    /// are recorded in it until the next checkpoint. A versioned store only takes
+    /// writes once it has a version, so a new tree usually starts with one.
+    ///
+    /// Names are unique among all versions, an existing one is rejected.
+    pub fn checkpoint(&mut self, name: impl AsRef<[u8]>) -> Result<()> {
+        let name = name.as_ref();
+        self.store.version_create(VersionName(name)).map_err(|e| {
+            Error::store(StoreOp::Insert, StoreTarget::Version(name.to_vec()), e)
+        })
+    }
+
+    /// Bring back the state of the checkpoint `name` as it was when it was taken,
+    /// dropping all later writes and checkpoints; `name` itself is kept
+    /// and takes the following writes again.
+    pub fn restore(&mut self, name: impl AsRef<[u8]>) -> Result<()> {
+        let name = name.as_ref();
+        self.rollback_to(VersionName(name))?;
+        let target = || StoreTarget::Version(name.to_vec());
+        self.store
+            .version_pop()
+            .map_err(|e| Error::store(StoreOp::Remove, target(), e))?;
+        // popped versions keep their names until cleaned up
+        self.store
+            .version_clean_up_globally()
+            .map_err(|e| Error::store(StoreOp::Remove, target(), e))?;
+        self.store
+            .version_create(VersionName(name))
+            .map_err(|e| Error::store(StoreOp::Insert, target(), e))
+    }
+
+    /// Names of the checkpoints(versions) of the default branch, oldest first
+    pub fn list_checkpoints(&self) -> Result<Vec<Vec<u8>>> {
+        let versions = self.store.version_list().map_err(|e| {
+            Error::store(StoreOp::Get, StoreTarget::Versions, e)
+        })?;
+        Ok(versions.into_iter().map(|v| v.0).collect())
+    }
+
+    /// Fork the tree into a new branch `br`, starting with version `ver`,
+    /// and make it the default branch: all following writes go to the fork,
+    /// while the state of the parent branch stays readable by `*_by_branch*`.
+    ///
+    /// The fork shares all unchanged branches and leaves with its parent,
+    /// see `merge_fork` and `drop_fork` for ending it.
+    pub fn fork(&mut self, br: BranchName, ver: VersionName) -> Result<()> {
+        let target = || StoreTarget::VsBranch(br.0.to_vec());
+        self.store
+            .branch_create(br, ver, false)
+            .map_err(|e| Error::store(StoreOp::Insert, target(), e))?;
+        self.store
+            .branch_set_default(br)
+            .map_err(|e| Error::store(StoreOp::SetDefaultBranch, target(), e))
+    }
+
+    /// Merge the fork `br` into its parent branch `into`,
+    /// remove it and make `into` the default branch again.
+    ///
+    /// Fails if new versions were created on `into` after the fork.
+    pub fn merge_fork(&mut self, br: BranchName, into: BranchName) -> Result<()> {
+        self.store.branch_merge_to(br, into).map_err(|e| {
+            Error::store(StoreOp::Insert, StoreTarget::VsBranch(into.0.to_vec()), e)
+        })?;
+        self.drop_fork(br, into)
+    }
+
+    /// Throw the fork `br` away and make its parent branch `back_to`
+    /// the default branch again.
+    pub fn drop_fork(&mut self, br: BranchName, back_to: BranchName) -> Result<()> {
+        self.store.branch_set_default(back_to).map_err(|e| {
+            Error::store(
+                StoreOp::SetDefaultBranch,
+                StoreTarget::VsBranch(back_to.0.to_vec()),
+                e,
+            )
+        })?;
+        self.store.branch_remove(br).map_err(|e| {
+            Error::store(StoreOp::Remove, StoreTarget::VsBranch(br.0.to_vec()), e)
+        })
+    }
+
+    // Run `f` with `br` as the default branch, then switch back
+    // to the current default branch, whether `f` failed or not.
+    fn on_branch<T>(
+        &mut self,
+        br: BranchName,
+        f: impl FnOnce(&mut Self) -> Result<T>,
+    ) -> Result<T> {
+        let default = self.store.branch_get_default();
+        if default.0 == br.0 {
+            return f(self);
+        }
+        let set_default = |store: &mut S, br: BranchName| {
+            store.branch_set_default(br).map_err(|e| {
+                let target = StoreTarget::VsBranch(br.0.to_vec());
+                Error::store(StoreOp::SetDefaultBranch, target, e)
+            })
+        };
+        set_default(&mut self.store, br)?;
+        let res = f(self);
+        set_default(&mut self.store, default.as_deref())?;
+        res
+    }
+
+    /// Same as `update`, on the head of branch `br` instead of the default branch,
+    /// so speculative chains can be built on a branch while the default one
+    /// keeps taking writes; return the new root of `br`.
+    #[inline(always)]
+    pub fn update_by_branch(
+        &mut self,
+        br: BranchName,
+        key: H256,
+        value: V,
+    ) -> Result<H256> {
+        self.on_branch(br, |tree| tree.update(key, value))
+    }
+
+    /// Same as `update_all`, on the head of branch `br`
+    #[inline(always)]
+    pub fn update_all_by_branch(
+        &mut self,
+        br: BranchName,
+        leaves: Vec<(H256, V)>,
+    ) -> Result<H256> {
+        self.on_branch(br, |tree| tree.update_all(leaves))
+    }
+
+    /// Same as `remove`, on the head of branch `br`
+    #[inline(always)]
+    pub fn remove_by_branch(
+        &mut self,
+        br: BranchName,
+        key: H256,
+    ) -> Result<(H256, Option<V>)> {
+        self.on_branch(br, |tree| tree.remove(key))
+    }
+
+    /// Check empty of the tree
+    #[inline(always)]
+    pub fn is_empty(&self) -> bool {
+        self.root().is_zero()
+    }
+
+    /// Check empty of the tree, or the error of the store
+    #[inline(always)]
+    pub fn try_is_empty(&self) -> Result<bool> {
+        self.try_root().map(|root| root.is_zero())
+    }
+
+    /// Number of leaves, read from the counter of the store
+    #[inline(always)]
+    pub fn len(&self) -> Result<usize> {
+        self.store.leaves_count().map(|n| n as usize)
+    }
+
+    /// Whether the tree has no leaves, by the leaf counter of the store
+    /// rather than by a zero root as `is_empty`
+    #[inline(always)]
+    pub fn is_empty_exact(&self) -> Result<bool> {
+        self.len().map(|n| n == 0)
+    }
+
+    /// Get backend store
+    #[cfg(test)]
+    #[inline(always)]
+    pub(crate) fn store(&self) -> &S {
+        &self.store
+    }
+
+    /// Get backend store
+    #[cfg(test)]
+    #[inline(always)]
+    pub(crate) fn store_mut(&mut self) -> &mut S {
+        &mut self.store
+    }
+
+    /// Remove a leaf, return new merkle root and the removed value
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip_all))]
+    pub fn remove(&mut self, key: H256) -> Result<(H256, Option<V>)> {
+        check_key_depth(DEPTH, &key)?;
+        // a verified value is read before the write,
+        // otherwise it is taken by the write
+        if self.verify_reads {
+            let value = self.get(&key)?;
+            return Ok((self.write_batch(vec![(key, None)])?, value));
+        }
+        let (root, mut taken) =
+            self.write_leaves(vec![(key, None)], true, insert_leaves)?;
+        Ok((root, taken.pop().flatten()))
+    }
+
+    /// Update a leaf, return new merkle root
+    /// set to zero value to delete a key
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip_all))]
+    #[inline(always)]
+    pub fn update(&mut self, key: H256, value: V) -> Result<H256> {
+        self.write_batch(vec![(key, Some(value))])
+    }
+
+    /// Same as `update`, but a deletion value is rejected instead of removing the leaf,
+    /// return ImplicitDelete error for it; use `remove` to delete.
+    #[inline(always)]
+    pub fn update_strict(&mut self, key: H256, value: V) -> Result<H256> {
+        self.update_all_strict(vec![(key, value)])
+    }
+
+    /// Same as `update`, with any `Key` type
+    #[inline(always)]
+    pub fn update_by_key<K: Key>(&mut self, key: &K, value: V) -> Result<H256> {
+        self.update(key.to_path(), value)
+    }
+
+    /// Same as `remove`, with any `Key` type
+    #[inline(always)]
+    pub fn remove_by_key<K: Key>(&mut self, key: &K) -> Result<(H256, Option<V>)> {
+        self.remove(key.to_path())
+    }
+
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip_all, fields(keys = keys.len())))]
+    pub fn remove_all(&mut self, mut keys: Vec<H256>) -> Result<H256> {
+        // Dedup(only keep the last of each key) and sort leaves
+        keys.reverse();
+        keys.sort();
+        keys.dedup();
+
+        self.write_batch(keys.into_iter().map(|k| (k, None)).collect())
+    }
+
+    /// Remove all leaves whose key bytes(`H256::as_slice`) start with `prefix`
+    /// and recompute the root once, return the new root.
+    ///
+    /// The leaves of a byte prefix are spread over the tree(the first byte
+    /// holds the lowest bits of the path), they are not a range of keys,
+    /// so they are found by an ordered scan of all leaves(`Store::iter_leaves_range`);
+    /// branches are computed before anything is written.
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip_all))]
+    pub fn remove_prefix(&mut self, prefix: &[u8]) -> Result<H256> {
+        let nodes: Vec<(H256, MergeValue)> = self
+            .store
+            .iter_leaves_range((Bound::Unbounded, Bound::Unbounded))
+            .filter(|(k, _)| k.as_slice().starts_with(prefix))
+            .map(|(k, _)| (k, MergeValue::zero()))
+            .collect();
+        if nodes.is_empty() {
+            return self.store.get_root();
+        }
+        metric!(leaf_writes, nodes.len());
+        let old_root = self.cache.is_on().then(|| self.store.get_root()).transpose()?;
+        let (branches, root, leaves_delta) = plan_recompute(&self.layout()?, nodes)?;
+        metric!(branch_writes, branches.len());
+
+        self.store.remove_leaves_by_prefix(prefix)?;
+        self.store.add_leaves_count(leaves_delta)?;
+        self.write_branches(old_root, branches, root)
+    }
+
+    /// Update multiple leaves at once
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip_all, fields(keys = leaves.len())))]
+    pub fn update_all(&mut self, mut leaves: Vec<(H256, V)>) -> Result<H256> {
+        // Dedup(only keep the last of each key) and sort leaves
+        leaves.reverse();
+        leaves.sort_by_key(|(a, _)| *a);
+        leaves.dedup_by_key(|(a, _)| *a);
+
+        self.write_batch(leaves.into_iter().map(|(k, v)| (k, Some(v))).collect())
+    }
+
+    /// Same as `update_all`, but nothing is written if any value is a deletion,
+    /// return ImplicitDelete error for the first one; use `remove_all` to delete.
+    pub fn update_all_strict(&mut self, leaves: Vec<(H256, V)>) -> Result<H256> {
+        check_no_deletion(&leaves)?;
+        self.update_all(leaves)
+    }
+
+    /// Build the tree from leaves sorted by key in a single bottom-up pass,
+    /// the tree must be empty; return the new root.
+    ///
+    /// Leaves and branches are written as they are done, and at most one
+    /// pending node per level is kept in memory, so imports far larger than
+    /// an `update_all` batch can be streamed. Leaves with a zero value are skipped.
+    ///
+    /// Return UnsortedKeys error if a key is not greater than the one before it.
+    /// The writes are not atomic, a failed build leaves the tree partially written.
+    pub fn build_from_sorted(
+        &mut self,
+        leaves: impl IntoIterator<Item = (H256, V)>,
+    ) -> Result<H256> {
+        #[allow(clippy::let_unit_value)]
+        let () = Self::ASSERT_DEPTH;
+        if !self.try_is_empty()? {
+            return Err(Error::TreeNotEmpty);
+        }
+
+        let store = &mut self.store;
+        let mut merger = SortedMerger::<H>::new(DEPTH);
+        let mut last = None;
+        let mut count = 0;
+        for (k, v) in leaves {
+            check_key_depth(DEPTH, &k)?;
+            if last.is_some_and(|last| last >= k) {
+                return Err(Error::UnsortedKeys(k));
+            }
+            last = Some(k);
+            let hash = leaf_hash(Some(&v));
+            if hash.is_zero() {
+                continue;
+            }
+            store_leaf::<H, _, _>(store, k, v, hash)?;
+            count += 1;
+            merger.push(k, MergeValue::from_h256(hash), &mut |branch_key, branch| {
+                store.insert_branch(branch_key, branch)
+            })?;
+            metric!(leaf_writes);
+        }
+        let root = merger
+            .finish(&mut |branch_key, branch| store.insert_branch(branch_key, branch))?;
+
+        self.store.add_leaves_count(count)?;
+        self.store.update_root(root)?;
+        self.store.push_root_history(root)?;
+        Ok(root)
+    }
+
+    /// Same as `update_all`, also reporting what happened to each key
+    pub fn update_all_with_report(
+        &mut self,
+        mut leaves: Vec<(H256, V)>,
+    ) -> Result<(H256, BatchReport)> {
+        let len = leaves.len();
+        // Dedup(only keep the last of each key) and sort leaves
+        leaves.reverse();
+        leaves.sort_by_key(|(a, _)| *a);
+        leaves.dedup_by_key(|(a, _)| *a);
+
+        let leaves: Vec<_> = leaves.into_iter().map(|(k, v)| (k, Some(v))).collect();
+        let report = self.batch_report(&leaves, len)?;
+        self.write_batch(leaves).map(|root| (root, report))
+    }
+
+    /// Same as `remove_all`, also reporting what happened to each key
+    pub fn remove_all_with_report(
+        &mut self,
+        mut keys: Vec<H256>,
+    ) -> Result<(H256, BatchReport)> {
+        let len = keys.len();
+        keys.sort();
+        keys.dedup();
+
+        let leaves: Vec<_> = keys.into_iter().map(|k| (k, None)).collect();
+        let report = self.batch_report(&leaves, len)?;
+        self.write_batch(leaves).map(|root| (root, report))
+    }
+
+    // The changes the sorted and deduped `leaves` make,
+    // `len` is the number of entries before dedup
+    fn batch_report(
+        &self,
+        leaves: &[(H256, Option<V>)],
+        len: usize,
+    ) -> Result<BatchReport> {
+        let changes = leaves
+            .iter()
+            .map(|(k, v)| {
+                let old = self.stored_leaf_hash(k)?;
+                let new = leaf_hash(v.as_ref());
+                Ok((*k, leaf_change(old, new)))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(BatchReport {
+            changes,
+            duplicates: len - leaves.len(),
+        })
+    }
+
+    // Compute all branch changes before writing anything,
+    // so a failed read leaves the store untouched.
+    // `leaves` must be sorted and deduped, `None` or a zero value removes the leaf.
+    #[inline(always)]
+    pub(crate) fn write_batch(
+        &mut self,
+        leaves: Vec<(H256, Option<V>)>,
+    ) -> Result<H256> {
+        self.write_leaves(leaves, false, insert_leaves).map(|(root, _)| root)
+    }
+
+    // `write_batch`, if `take` the removed leaves are taken(`Store::take_leaf`)
+    // and their old values are returned in order;
+    // the stored leaves are written by `insert`, before any removal
+    fn write_leaves(
+        &mut self,
+        leaves: Vec<(H256, Option<V>)>,
+        take: bool,
+        insert: impl FnOnce(&mut S, LeafInserts<V>) -> Result<()>,
+    ) -> Result<(H256, Vec<Option<V>>)> {
+        if leaves.is_empty() {
+            return Ok((self.store.get_root()?, vec![]));
+        }
+        #[cfg(feature = "metrics")]
+        let _timer = crate::metrics::UpdateTimer::start();
+        for (k, _) in leaves.iter() {
+            check_key_depth(DEPTH, k)?;
+        }
+
+        let mut nodes = Vec::with_capacity(leaves.len());
+        let mut writes = Vec::with_capacity(leaves.len());
+        for (k, v) in leaves {
+            let hash = leaf_hash(v.as_ref());
+            // skip leaves whose hash is unchanged
+            if self.is_unchanged(&k, hash)? {
+                continue;
+            }
+            nodes.push((k, MergeValue::from_h256(hash)));
+            writes.push((k, v, hash));
+        }
+        if nodes.is_empty() {
+            return Ok((self.store.get_root()?, vec![]));
+        }
+        let old_root = self.cache.is_on().then(|| self.store.get_root()).transpose()?;
+        let (branches, root, leaves_delta) = plan_recompute(&self.layout()?, nodes)?;
+
+        metric!(leaf_writes, writes.len());
+        metric!(branch_writes, branches.len());
+        #[cfg(feature = "tracing")]
+        tracing::debug!(
+            leaves = writes.len(),
+            branches = branches.len(),
+            lowest_height = branches.iter().map(|(k, _)| k.height).min(),
+            "write batch"
+        );
+
+        // notice when value is zero the leaf is deleted, so we do not need to store it
+        let (inserts, removes) = split_writes::<H, _>(writes);
+        insert(&mut self.store, inserts)?;
+        let mut taken = vec![];
+        for k in removes {
+            if take {
+                taken.push(self.store.take_leaf(&k)?);
+            } else {
+                self.store.remove_leaf(&k)?;
+            }
+        }
+        self.store.add_leaves_count(leaves_delta)?;
+        Ok((self.write_branches(old_root, branches, root)?, taken))
+    }
+
+    // Write planned branch changes and the new root, after the leaves;
+    // `old_root` is the root the changes were planned on if the cache is on.
+    fn write_branches(
+        &mut self,
+        old_root: Option<H256>,
+        branches: BranchChanges,
+        root: H256,
+    ) -> Result<H256> {
+        // on a failed write the root stays the old one while the cache
+        // follows the new one, it is emptied by the next read of the old root
+        if let Some(old_root) = old_root {
+            self.cache.apply(DEPTH, old_root, root, &branches);
+        }
+        for (branch_key, branch) in branches {
+            if let Some(branch) = branch {
+                self.store.insert_branch(branch_key, branch)?;
+            } else {
+                self.store.remove_branch(&branch_key)?;
+            }
+        }
+        self.store.update_root(root)?;
+        self.store.push_root_history(root)?;
+        Ok(root)
+    }
+
+    // The root `write_batch` would return for `leaves`, nothing is written.
+    // `leaves` must be sorted and deduped.
+    pub(crate) fn root_after<'a>(
+        &self,
+        leaves: impl IntoIterator<Item = (&'a H256, Option<&'a V>)>,
+    ) -> Result<H256>
+    where
+        V: 'a,
+    {
+        let mut nodes = Vec::new();
+        for (k, v) in leaves {
+            check_key_depth(DEPTH, k)?;
+            let hash = leaf_hash(v);
+            if self.is_unchanged(k, hash)? {
+                continue;
+            }
+            nodes.push((*k, MergeValue::from_h256(hash)));
+        }
+        if nodes.is_empty() {
+            return self.store.get_root();
+        }
+        plan_recompute(&self.layout()?, nodes).map(|(_, root, _)| root)
+    }
+
+    /// Get value of a leaf
+    /// return zero value if leaf not exists
+    #[inline(always)]
+    pub fn get(&self, key: &H256) -> Result<Option<V>> {
+        let leaf = self.store.get_leaf(key)?;
+        if self.verify_reads {
+            self.layout()?
+                .verify_leaf(key, leaf.as_ref().map(|v| v.to_h256()))?;
+        }
+        Ok(leaf)
+    }
+
+    /// Same as `get`, with any `Key` type
+    #[inline(always)]
+    pub fn get_by_key<K: Key>(&self, key: &K) -> Result<Option<V>> {
+        self.get(&key.to_path())
+    }
+
+    /// Values of several leaves, in the order of `keys`,
+    /// the store may batch the reads(see `Store::get_leaves`)
+    pub fn get_many(&self, keys: &[H256]) -> Result<Vec<Option<V>>> {
+        let leaves = self.store.get_leaves(keys)?;
+        self.verify_leaves(keys.iter().zip(leaves.iter().map(Option::as_ref)))?;
+        Ok(leaves)
+    }
+
+    // Check leaves read from the store(`None` if absent) if `verify_reads` is on
+    pub(crate) fn verify_leaves<'a>(
+        &self,
+        leaves: impl IntoIterator<Item = (&'a H256, Option<&'a V>)>,
+    ) -> Result<()>
+    where
+        V: 'a,
+    {
+        if self.verify_reads {
+            let layout = self.layout()?;
+            for (key, leaf) in leaves {
+                layout.verify_leaf(key, leaf.map(|v| v.to_h256()))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether a leaf exists, without decoding its value
+    #[inline(always)]
+    pub fn contains_key(&self, key: &H256) -> Result<bool> {
+        if self.verify_reads {
+            return self.get(key).map(|leaf| leaf.is_some());
+        }
+        self.store.has_leaf(key)
+    }
+
+    #[inline(always)]
+    pub fn get_by_branch(&self, key: &H256, br: BranchName) -> Result<Option<V>> {
+        self.store.get_leaf_by_branch(key, br)
+    }
+
+    #[inline(always)]
+    pub fn get_by_branch_version(
+        &self,
+        key: &H256,
+        br: BranchName,
+        ver: VersionName,
+    ) -> Result<Option<V>> {
+        self.store.get_leaf_by_branch_version(key, br, ver)
+    }
+
+    /// Walk this tree and `other` side by side, yield the leaves that differ
+    /// as `(key, value in self, value in other)` sorted by key.
+    ///
+    /// Subtrees with the same node in both trees are skipped without reading them,
+    /// so the reads grow with the number of differing leaves, not the tree size.
+    pub fn diff<'a>(&'a self, other: &'a Self) -> Result<DiffIter<'a, H, V, S, DEPTH>> {
+        let layouts = [self.layout()?, other.layout()?];
+        let top = layouts[0].top_height();
+        let (ll, lr) = layouts[0].top_children()?;
+        let (rl, rr) = layouts[1].top_children()?;
+        let mut right_path = H256::zero();
+        right_path.set_bit(top);
+        Ok(DiffIter {
+            trees: [self, other],
+            layouts,
+            pending: vec![(top, right_path, lr, rr), (top, H256::zero(), ll, rl)],
+        })
+    }
+
+    /// Stream all leaves in ascending key order, walking the tree from the left,
+    /// only the pending nodes of the walk(two per level at most) are kept in memory.
+    ///
+    /// Writes are not possible while the iterator borrows the tree,
+    /// so it yields a consistent state.
+    pub fn export_leaves_sorted(&self) -> Result<LeafIter<'_, H, V, S, DEPTH>> {
+        let layout = self.layout()?;
+        let top = layout.top_height();
+        let (left, right) = layout.top_children()?;
+        let mut right_path = H256::zero();
+        right_path.set_bit(top);
+        Ok(LeafIter {
+            tree: self,
+            layout,
+            pending: vec![(top, right_path, right), (top, H256::zero(), left)],
+        })
+    }
+
+    /// Fold the leaves of `other` into this tree in one batch, return the new root.
+    ///
+    /// Keys only in `other` are copied, keys only in this tree are kept,
+    /// and keys with different values in both get `resolve(key, ours, theirs)`,
+    /// which removes the leaf if it returns a deletion value.
+    /// Identical subtrees are skipped, see `diff`.
+    pub fn merge_from(
+        &mut self,
+        other: &Self,
+        resolve: impl Fn(&H256, &V, &V) -> V,
+    ) -> Result<H256> {
+        let mut leaves = Vec::new();
+        for item in self.diff(other)? {
+            match item? {
+                (k, None, Some(theirs)) => leaves.push((k, Some(theirs))),
+                (k, Some(ours), Some(theirs)) => {
+                    leaves.push((k, Some(resolve(&k, &ours, &theirs))))
+                }
+                _ => {}
+            }
+        }
+        // already sorted and deduped by `diff`
+        self.write_batch(leaves)
+    }
+
+    /// Report keys whose values differ between two versions of a branch,
+    /// as `(key, value in ver_a, value in ver_b)` sorted by key.
+    pub fn diff_versions(
+        &self,
+        br: BranchName,
+        ver_a: VersionName,
+        ver_b: VersionName,
+    ) -> Result<VersionDiff<V>> {
+        for ver in [ver_a, ver_b] {
+            if !self.store.version_exists_on_branch(ver, br) {
+                return Err(Error::VersionNotFound(ver.0.to_vec()));
+            }
+        }
+
+        let mut diff: BTreeMap<H256, (Option<V>, Option<V>)> = self
+            .store
+            .iter_leaves_by_branch_version(br, ver_a)
+            .map(|(k, v)| (k, (Some(v), None)))
+            .collect();
+        for (k, v) in self.store.iter_leaves_by_branch_version(br, ver_b) {
+            match diff.get_mut(&k) {
+                Some((Some(a), _)) if a.to_h256() == v.to_h256() => {
+                    diff.remove(&k);
+                }
+                Some(entry) => entry.1 = Some(v),
+                None => {
+                    diff.insert(k, (None, Some(v)));
+                }
+            }
+        }
+
+        Ok(diff.into_iter().map(|(k, (a, b))| (k, a, b)).collect())
+    }
+
+    /// Generate merkle proof,
+    /// return DuplicatedKeys error if a key is given more than once
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip_all, fields(keys = keys.len())))]
+    #[inline(always)]
+    pub fn merkle_proof(&self, keys: Vec<H256>) -> Result<MerkleProof> {
+        merkle_proof_in(&self.layout()?, keys)
+    }
+
+    /// Same as `merkle_proof`, with any `Key` type,
+    /// verify it with the `to_path` form of the keys.
+    #[inline(always)]
+    pub fn merkle_proof_by_keys<K: Key>(&self, keys: &[K]) -> Result<MerkleProof> {
+        self.merkle_proof(keys.iter().map(Key::to_path).collect())
+    }
+
+    /// Generate merkle proof along with the values of `keys`,
+    /// the values are sorted by key, in the same order as the proof expects.
+    pub fn merkle_proof_with_values(
+        &self,
+        mut keys: Vec<H256>,
+    ) -> Result<ProofWithValues<V>> {
+        keys.sort_unstable();
+        let values = keys
+            .iter()
+            .map(|k| self.get(k).map(|v| (*k, v)))
+            .collect::<Result<Vec<_>>>()?;
+        self.merkle_proof(keys).map(|proof| (proof, values))
+    }
+
+    /// Generate a non-membership proof of `keys`,
+    /// return KeyExists error if any of them is in the tree.
+    ///
+    /// Check it with `MerkleProof::verify_absence`.
+    pub fn prove_absence(&self, keys: Vec<H256>) -> Result<MerkleProof> {
+        for k in &keys {
+            if self.get(k)?.is_some() {
+                return Err(Error::KeyExists(*k));
+            }
+        }
+        self.merkle_proof(keys)
+    }
+
+    /// Generate a proof that no key lies strictly between `start` and `end`,
+    /// return KeyExists error with the smallest such key otherwise.
+    ///
+    /// Check it with `MerkleProof::verify_empty_range`.
+    pub fn prove_empty_range(&self, start: &H256, end: &H256) -> Result<MerkleProof> {
+        let subtrees = range_subtrees(start, end)?;
+        let layout = self.layout()?;
+        for (key, height) in &subtrees {
+            let found = if *height == 0 {
+                self.get(key)?.map(|_| *key)
+            } else {
+                // nothing is stored above the top height
+                let height = (*height).min(DEPTH as u16);
+                layout.first_key_in(*key, height)?
+            };
+            if let Some(k) = found {
+                return Err(Error::KeyExists(k));
+            }
+        }
+        self.merkle_proof(subtrees.into_iter().map(|(k, _)| k).collect())
+    }
+
+    /// Generate a proof of the node of the subtree at `height`(0..=256) that holds `key`,
+    /// i.e. of all the `2^height` keys sharing the bits of `key` from `height` up.
+    ///
+    /// Check it with `SubtreeProof::verify`, or `SubtreeProof::verify_leaves`
+    /// against the leaves of the subtree fetched on their own.
+    pub fn prove_subtree(&self, key: &H256, height: u16) -> Result<SubtreeProof> {
+        if height > 256 {
+            return Err(Error::InvalidHeight(height));
+        }
+        check_key_depth(DEPTH, key)?;
+        let key = if height == 256 {
+            H256::zero()
+        } else {
+            key.copy_bits(height as u8)
+        };
+
+        let layout = self.layout()?;
+        let node = layout.node_at(&key, height)?;
+        let mut bitmap = H256::zero();
+        let mut merkle_path = vec![];
+        // from the top down, the proof goes bottom-up
+        for (h, sibling) in layout.path(&key)?.siblings.into_iter().rev() {
+            if h as u16 >= height {
+                bitmap.set_bit(h);
+                merkle_path.push(sibling);
+            }
+        }
+        Ok(SubtreeProof::new(key, height, node, bitmap, merkle_path))
+    }
+
+    /// Generate merkle proof against the state of a specified version,
+    /// so proofs can still be served for past roots.
+    pub fn merkle_proof_by_branch_version(
+        &self,
+        keys: Vec<H256>,
+        br: BranchName,
+        ver: VersionName,
+    ) -> Result<MerkleProof> {
+        if !self.store.version_exists_on_branch(ver, br) {
+            return Err(Error::VersionNotFound(ver.0.to_vec()));
+        }
+        let layout: Layout<'_, H> = Layout {
+            depth: DEPTH,
+            fetch_branch: Box::new(|k| {
+                metric!(branch_reads);
+                self.store.get_branch_by_branch_version(k, br, ver)
+            }),
+            fetch_leaf: Box::new(|k| {
+                Ok(self
+                    .store
+                    .get_leaf_by_branch_version(k, br, ver)?
+                    .map(|v| v.to_h256()))
+            }),
+            root: None,
+            phantom: PhantomData,
+        };
+        merkle_proof_in(&layout, keys)
+    }
+
+    /// Walk all leaves, recompute every branch bottom-up,
+    /// and compare the results with what is recorded in the store.
+    pub fn verify_integrity(&self) -> Result<IntegrityReport> {
+        let mut leaves = self
+            .store
+            .iter_leaves()
+            .map(|(k, v)| (k, MergeValue::from_h256(v.to_h256())))
+            .filter(|(_, v)| !v.is_zero())
+            .collect::<Vec<_>>();
+        leaves.sort_unstable_by_key(|(k, _)| *k);
+
+        let mut report = IntegrityReport {
+            stored_root: self.store.get_root()?,
+            ..Default::default()
+        };
+
+        let mut expected = BTreeSet::new();
+        report.computed_root =
+            merge_leaves::<H>(DEPTH, leaves, |branch_key, branch| {
+                if self.store.get_branch(&branch_key)?.as_ref() != Some(&branch) {
+                    report.mismatched_branches.push(branch_key.clone());
+                }
+                expected.insert(branch_key);
+                Ok(())
+            })?;
+
+        for (branch_key, _) in self.store.iter_branches() {
+            if !expected.contains(&branch_key) {
+                report.dangling_branches.push(branch_key);
+            }
+        }
+        report.dangling_branches.sort_unstable();
+
+        Ok(report)
+    }
+
+    /// Shrink the store after heavy churn: remove dangling branches,
+    /// merge all but the newest `keep_versions` versions of the default branch
+    /// (`None` merges nothing, see `prune_versions_before`),
+    /// then let the store reclaim the space, see `Store::compact`.
+    ///
+    /// Nothing is removed from a corrupted store, return the error
+    /// of the first problem `verify_integrity` finds besides dangling branches.
+    pub fn compact(&mut self, keep_versions: Option<usize>) -> Result<CompactionReport> {
+        let integrity = self.verify_integrity()?;
+        if integrity.stored_root != integrity.computed_root {
+            return Err(Error::RootMismatch {
+                expected: integrity.stored_root,
+                actual: integrity.computed_root,
+            });
+        }
+        if let Some(branch_key) = integrity.mismatched_branches.first() {
+            return Err(Error::CorruptedNode {
+                key: branch_key.node_key,
+                height: branch_key.height,
+            });
+        }
+        for branch_key in integrity.dangling_branches.iter() {
+            self.store.remove_branch(branch_key)?;
+        }
+
+        let mut merged_versions = 0;
+        if let Some(keep) = keep_versions {
+            let target = || StoreTarget::Versions;
+            let versions = self
+                .store
+                .version_list()
+                .map_err(|e| Error::store(StoreOp::Get, target(), e))?
+                .len();
+            if versions > keep.max(1) {
+                self.store
+                    .prune(Some(keep.max(1)))
+                    .map_err(|e| Error::store(StoreOp::Remove, target(), e))?;
+                let left = self
+                    .store
+                    .version_list()
+                    .map_err(|e| Error::store(StoreOp::Get, target(), e))?
+                    .len();
+                merged_versions = versions.saturating_sub(left);
+            }
+        }
+
+        Ok(CompactionReport {
+            dangling_branches: integrity.dangling_branches.len(),
+            merged_versions,
+            bytes_freed: self.store.compact()?,
+        })
+    }
+}
+
+impl<H: Hasher, V: Value<H> + BoundedValue, S: Store<V>, const DEPTH: usize>
+    SparseMerkleTree<H, V, S, DEPTH>
+{
+    /// Same as `update_all`, but the store rejects the batch
+    /// (`Store::insert_leaves_bounded`) before anything is written
+    /// if a value is encoded longer than `BoundedValue::MAX_ENCODED_LEN`.
+    pub fn update_all_bounded(&mut self, mut leaves: Vec<(H256, V)>) -> Result<H256> {
+        #[allow(clippy::let_unit_value)]
+        let () = V::ASSERT_MAX_ENCODED_LEN;
+        // Dedup(only keep the last of each key) and sort leaves
+        leaves.reverse();
+        leaves.sort_by_key(|(a, _)| *a);
+        leaves.dedup_by_key(|(a, _)| *a);
 
-            debug_assert!(stack_top < MAX_STACK_SIZE);
+        let leaves = leaves.into_iter().map(|(k, v)| (k, Some(v))).collect();
+        self.write_leaves(leaves, false, |s, l| {
+            s.insert_leaves_bounded(l, V::MAX_ENCODED_LEN)
+        })
+        .map(|(root, _)| root)
+    }
+}
 
-            stack_fork_height[stack_top] = fork_height;
-            stack_top += 1;
-            leaf_index += 1;
+impl<H: Hasher, V: Value<H>, S: Store<V> + Default, const DEPTH: usize>
+    SparseMerkleTree<H, V, S, DEPTH>
+{
+    // An empty tree on a new store, with the same settings as this one
+    pub(crate) fn empty_copy(&self) -> Result<Self> {
+        let mut store = S::default();
+        // a versioned store only takes writes once it has a version
+        let no_version = store
+            .version_list()
+            .map_err(|e| Error::store(StoreOp::Get, StoreTarget::Versions, e))?
+            .is_empty();
+        if no_version {
+            let name = self
+                .store
+                .version_list()
+                .map_err(|e| {
+                    Error::store(StoreOp::Get, StoreTarget::Versions, e)
+                })?
+                .pop()
+                .map(|v| v.0)
+                .unwrap_or_default();
+            store.version_create(VersionName(&name)).map_err(|e| {
+                Error::store(StoreOp::Insert, StoreTarget::Version(name.clone()), e)
+            })?;
         }
 
-        debug_assert_eq!(stack_top, 1);
+        let mut tree = Self::new(store);
+        tree.set_verify_reads(self.verify_reads);
+        Ok(tree)
+    }
 
-        Ok(MerkleProof::new(leaves_bitmap, proof))
+    /// Split the leaves into two new trees, each on its own store:
+    /// those matching `pred` and the rest; this tree is left as it is.
+    pub fn split_by(
+        &self,
+        pred: impl Fn(&H256, &V) -> bool,
+    ) -> Result<(Self, Self)> {
+        let (mut matched, mut rest): (Vec<_>, Vec<_>) =
+            self.store.iter_leaves().partition(|(k, v)| pred(k, v));
+        matched.sort_unstable_by_key(|(k, _)| *k);
+        rest.sort_unstable_by_key(|(k, _)| *k);
+
+        let mut left = self.empty_copy()?;
+        left.build_from_sorted(matched)?;
+        let mut right = self.empty_copy()?;
+        right.build_from_sorted(rest)?;
+        Ok((left, right))
     }
 }
 
@@ -366,7 +2490,9 @@ impl<H: Hasher, V: Value<H>, S: Store<V>> SparseMerkleTree<H, V, S> {
 pub struct SparseMerkleTree2<X, H, V, S: VsMgmt, S2: VsMgmt> {
     store: S2,
     xroot: SparseMerkleTree<H, H256, S>,
-    phantom: PhantomData<(X, H, V)>,
+    #[serde(default)]
+    verify_reads: bool,
+    phantom: NotOwned<(X, H, V)>,
 }
 
 impl<X: KeyEnDe, H: Hasher, V: Value<H>, S: Store<H256>, S2: Store2<X, V>>
@@ -378,20 +2504,94 @@ impl<X: KeyEnDe, H: Hasher, V: Value<H>, S: Store<H256>, S2: Store2<X, V>>
         SparseMerkleTree2 {
             store: store2,
             xroot: SparseMerkleTree::new(store),
+            verify_reads: false,
             phantom: PhantomData,
         }
     }
 
-    /// Global merkle root
+    /// Verify every branch and leaf read against the hash recorded in its parent,
+    /// mismatches are reported as `Error::CorruptedNode` instead of being used.
+    /// It roughly doubles the store reads, so it is off by default.
+    #[inline(always)]
+    pub fn set_verify_reads(&mut self, enable: bool) {
+        self.verify_reads = enable;
+        self.xroot.set_verify_reads(enable);
+    }
+
+    /// Get backend store
+    #[cfg(test)]
+    #[inline(always)]
+    pub(crate) fn store(&self) -> &S2 {
+        &self.store
+    }
+
+    /// Get backend store
+    #[cfg(test)]
+    #[inline(always)]
+    pub(crate) fn store_mut(&mut self) -> &mut S2 {
+        &mut self.store
+    }
+
+    /// Global merkle root, committing to the root of every xid,
+    /// each stored as a leaf keyed by the hash of the encoded xid.
+    ///
+    /// Prove leaves against it with `xmerkle_proof`.
     #[inline(always)]
     pub fn xroot(&self) -> H256 {
         self.xroot.root()
     }
 
-    /// Merkle root
+    /// Global merkle root, or the error of the store
+    #[inline(always)]
+    pub fn try_xroot(&self) -> Result<H256> {
+        self.xroot.try_root()
+    }
+
+    /// Global merkle root committed at a specified version
+    #[inline(always)]
+    pub fn xroot_by_branch_version(
+        &self,
+        br: BranchName,
+        ver: VersionName,
+    ) -> Result<H256> {
+        self.xroot.root_by_branch_version(br, ver)
+    }
+
+    /// History of the global merkle root, see `SparseMerkleTree::root_history`
+    #[inline(always)]
+    pub fn xroot_history(&self, range: impl RangeBounds<u64>) -> Vec<(u64, H256)> {
+        self.xroot.root_history(range)
+    }
+
+    /// Global merkle root recorded by the write with sequence number `seq`
+    #[inline(always)]
+    pub fn xroot_at_seq(&self, seq: u64) -> Result<Option<H256>> {
+        self.xroot.root_at_seq(seq)
+    }
+
+    /// Merkle root, panic on store errors, see `try_root`
     #[inline(always)]
     pub fn root(&self, xid: &X) -> H256 {
-        self.store.get_root(xid).unwrap()
+        self.try_root(xid).unwrap()
+    }
+
+    /// Merkle root, or the error of the store
+    #[inline(always)]
+    pub fn try_root(&self, xid: &X) -> Result<H256> {
+        self.store.get_root(xid)
+    }
+
+    /// Merkle root committed at a specified version
+    pub fn root_by_branch_version(
+        &self,
+        xid: &X,
+        br: BranchName,
+        ver: VersionName,
+    ) -> Result<H256> {
+        if !self.store.version_exists_on_branch(ver, br) {
+            return Err(Error::VersionNotFound(ver.0.to_vec()));
+        }
+        self.store.get_root_by_branch_version(xid, br, ver)
     }
 
     /// Check empty of the tree
@@ -400,202 +2600,385 @@ impl<X: KeyEnDe, H: Hasher, V: Value<H>, S: Store<H256>, S2: Store2<X, V>>
         self.root(xid).is_zero()
     }
 
-    pub fn remove(&mut self, xid: &X, key: H256) -> Result<H256> {
-        let new_root = self
-            .store
-            .remove_leaf(xid, &key)
-            .and_then(|_| self.hash_recompute(xid, key, MergeValue::zero()))?;
+    /// Check empty of the tree, or the error of the store
+    #[inline(always)]
+    pub fn try_is_empty(&self, xid: &X) -> Result<bool> {
+        self.try_root(xid).map(|root| root.is_zero())
+    }
 
-        self.xroot.update(H::hash(&xid.encode()[..]), new_root)?;
+    /// Number of leaves under the xid, read from the counter of the store
+    #[inline(always)]
+    pub fn len(&self, xid: &X) -> Result<usize> {
+        self.store.leaves_count(xid).map(|n| n as usize)
+    }
+
+    /// Whether the xid has no leaves, by the leaf counter of the store
+    /// rather than by a zero root as `is_empty`
+    #[inline(always)]
+    pub fn is_empty_exact(&self, xid: &X) -> Result<bool> {
+        self.len(xid).map(|n| n == 0)
+    }
+
+    /// All xids(top-level keys) that currently have a non-empty subtree
+    #[inline(always)]
+    pub fn xids(&self) -> Vec<X> {
+        self.store.iter_x().collect()
+    }
 
-        Ok(new_root)
+    /// Remove a leaf, return new merkle root and the removed value
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip_all))]
+    pub fn remove(&mut self, xid: &X, key: H256) -> Result<(H256, Option<V>)> {
+        // a verified value is read before the write,
+        // otherwise it is taken by the write
+        if self.verify_reads {
+            let value = self.get(xid, &key)?;
+            return Ok((self.write_batch(xid, vec![(key, None)])?, value));
+        }
+        let (root, mut taken) = self.write_leaves(xid, vec![(key, None)], true, |s, l| {
+            insert_leaves2(s, xid, l)
+        })?;
+        Ok((root, taken.pop().flatten()))
     }
 
     /// Update a leaf, return new merkle root
     /// set to zero value to delete a key
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip_all))]
+    #[inline(always)]
     pub fn update(&mut self, xid: &X, key: H256, value: V) -> Result<H256> {
-        // compute and store new leaf
-        let node = MergeValue::from_h256(value.to_h256());
-        // notice when value is zero the leaf is deleted, so we do not need to store it
-        if !node.is_zero() {
-            self.store.insert_leaf(xid, key, value)?;
-        } else {
-            self.store.remove_leaf(xid, &key)?;
-        }
-
-        let new_root = self.hash_recompute(xid, key, node)?;
-
-        self.xroot.update(H::hash(&xid.encode()[..]), new_root)?;
-
-        Ok(new_root)
+        self.write_batch(xid, vec![(key, Some(value))])
     }
 
-    fn hash_recompute(&mut self, xid: &X, key: H256, node: MergeValue) -> Result<H256> {
-        // recompute the tree from bottom to top
-        let mut current_key = key;
-        let mut current_node = node;
-        for height in 0..=u8::MAX {
-            let parent_key = current_key.parent_path(height);
-            let parent_branch_key = BranchKey::new(height, parent_key);
-            let (left, right) = if let Some(parent_branch) =
-                self.store.get_branch(xid, &parent_branch_key)?
-            {
-                if current_key.is_right(height) {
-                    (parent_branch.left, current_node)
-                } else {
-                    (current_node, parent_branch.right)
-                }
-            } else if current_key.is_right(height) {
-                (MergeValue::zero(), current_node)
-            } else {
-                (current_node, MergeValue::zero())
-            };
+    /// Same as `update`, but a deletion value is rejected instead of removing the leaf,
+    /// return ImplicitDelete error for it; use `remove` to delete.
+    #[inline(always)]
+    pub fn update_strict(&mut self, xid: &X, key: H256, value: V) -> Result<H256> {
+        self.update_all_strict(xid, vec![(key, value)])
+    }
 
-            if !left.is_zero() || !right.is_zero() {
-                // insert or update branch
-                self.store.insert_branch(
-                    xid,
-                    parent_branch_key,
-                    BranchNode {
-                        left: left.clone(),
-                        right: right.clone(),
-                    },
-                )?;
-            } else {
-                // remove empty branch
-                self.store.remove_branch(xid, &parent_branch_key)?;
-            }
-            // prepare for next round
-            current_key = parent_key;
-            current_node = merge::<H>(height, &parent_key, &left, &right);
-        }
+    /// Same as `update`, with any `Key` type
+    #[inline(always)]
+    pub fn update_by_key<K: Key>(&mut self, xid: &X, key: &K, value: V) -> Result<H256> {
+        self.update(xid, key.to_path(), value)
+    }
 
-        let root = current_node.hash::<H>();
-        self.store.update_root(xid, root).map(|_| root)
+    /// Same as `remove`, with any `Key` type
+    #[inline(always)]
+    pub fn remove_by_key<K: Key>(
+        &mut self,
+        xid: &X,
+        key: &K,
+    ) -> Result<(H256, Option<V>)> {
+        self.remove(xid, key.to_path())
     }
 
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip_all, fields(keys = keys.len())))]
     pub fn remove_all(&mut self, xid: &X, mut keys: Vec<H256>) -> Result<H256> {
         // Dedup(only keep the last of each key) and sort leaves
         keys.reverse();
         keys.sort();
         keys.dedup();
 
-        let mut nodes: Vec<(H256, MergeValue)> = Vec::with_capacity(keys.len());
-        for k in keys {
-            self.store.remove_leaf(xid, &k)?;
-            nodes.push((k, MergeValue::zero()));
-        }
+        self.write_batch(xid, keys.into_iter().map(|k| (k, None)).collect())
+    }
 
-        let new_root = self.hash_recompute_all(xid, nodes)?;
+    /// Update multiple leaves at once
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip_all, fields(keys = leaves.len())))]
+    pub fn update_all(&mut self, xid: &X, mut leaves: Vec<(H256, V)>) -> Result<H256> {
+        // Dedup(only keep the last of each key) and sort leaves
+        leaves.reverse();
+        leaves.sort_by_key(|(a, _)| *a);
+        leaves.dedup_by_key(|(a, _)| *a);
 
-        self.xroot.update(H::hash(&xid.encode()[..]), new_root)?;
+        self.write_batch(xid, leaves.into_iter().map(|(k, v)| (k, Some(v))).collect())
+    }
 
-        Ok(new_root)
+    /// Same as `update_all`, but nothing is written if any value is a deletion,
+    /// return ImplicitDelete error for the first one; use `remove_all` to delete.
+    pub fn update_all_strict(&mut self, xid: &X, leaves: Vec<(H256, V)>) -> Result<H256> {
+        check_no_deletion(&leaves)?;
+        self.update_all(xid, leaves)
     }
 
-    /// Update multiple leaves at once
-    pub fn update_all(&mut self, xid: &X, mut leaves: Vec<(H256, V)>) -> Result<H256> {
+    /// Same as `update_all`, also reporting what happened to each key
+    pub fn update_all_with_report(
+        &mut self,
+        xid: &X,
+        mut leaves: Vec<(H256, V)>,
+    ) -> Result<(H256, BatchReport)> {
+        let len = leaves.len();
         // Dedup(only keep the last of each key) and sort leaves
         leaves.reverse();
         leaves.sort_by_key(|(a, _)| *a);
         leaves.dedup_by_key(|(a, _)| *a);
 
-        let mut nodes: Vec<(H256, MergeValue)> = Vec::new();
-        for (k, v) in leaves {
-            let value = MergeValue::from_h256(v.to_h256());
-            if !value.is_zero() {
-                self.store.insert_leaf(xid, k, v)?;
-            } else {
-                self.store.remove_leaf(xid, &k)?;
+        let leaves: Vec<_> = leaves.into_iter().map(|(k, v)| (k, Some(v))).collect();
+        let report = self.batch_report(xid, &leaves, len)?;
+        self.write_batch(xid, leaves).map(|root| (root, report))
+    }
+
+    /// Same as `remove_all`, also reporting what happened to each key
+    pub fn remove_all_with_report(
+        &mut self,
+        xid: &X,
+        mut keys: Vec<H256>,
+    ) -> Result<(H256, BatchReport)> {
+        let len = keys.len();
+        keys.sort();
+        keys.dedup();
+
+        let leaves: Vec<_> = keys.into_iter().map(|k| (k, None)).collect();
+        let report = self.batch_report(xid, &leaves, len)?;
+        self.write_batch(xid, leaves).map(|root| (root, report))
+    }
+
+    // The changes the sorted and deduped `leaves` make,
+    // `len` is the number of entries before dedup
+    fn batch_report(
+        &self,
+        xid: &X,
+        leaves: &[(H256, Option<V>)],
+        len: usize,
+    ) -> Result<BatchReport> {
+        let changes = leaves
+            .iter()
+            .map(|(k, v)| {
+                let old = self.stored_leaf_hash(xid, k)?;
+                let new = leaf_hash(v.as_ref());
+                Ok((*k, leaf_change(old, new)))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(BatchReport {
+            changes,
+            duplicates: len - leaves.len(),
+        })
+    }
+
+    /// Update multiple leaves under multiple xids at once,
+    /// return the new merkle root of each xid, in the order they first appear.
+    ///
+    /// Batches of the same xid are merged, later leaves win;
+    /// the global root is updated once for all of them.
+    /// A failure may leave the xids before the failed one updated.
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip_all, fields(batches = batches.len())))]
+    pub fn update_all_multi(
+        &mut self,
+        batches: Vec<(X, Vec<(H256, V)>)>,
+    ) -> Result<Vec<(X, H256)>> {
+        #[cfg(feature = "metrics")]
+        let _timer = crate::metrics::UpdateTimer::start();
+        let mut index: BTreeMap<Box<[u8]>, usize> = BTreeMap::new();
+        let mut groups: Vec<(X, Vec<(H256, V)>)> = vec![];
+        for (xid, leaves) in batches {
+            match index.entry(xid.encode()) {
+                Entry::Occupied(i) => groups[*i.get()].1.extend(leaves),
+                Entry::Vacant(i) => {
+                    i.insert(groups.len());
+                    groups.push((xid, leaves));
+                }
             }
-            nodes.push((k, value));
         }
 
-        let new_root = self.hash_recompute_all(xid, nodes)?;
+        let mut roots = Vec::with_capacity(groups.len());
+        let mut xleaves = Vec::with_capacity(groups.len());
+        for (xid, mut leaves) in groups {
+            // Dedup(only keep the last of each key) and sort leaves
+            leaves.reverse();
+            leaves.sort_by_key(|(a, _)| *a);
+            leaves.dedup_by_key(|(a, _)| *a);
+
+            let leaves = leaves.into_iter().map(|(k, v)| (k, Some(v))).collect();
+            let (root, _) = self.write_subtree(&xid, leaves, false, |s, l| {
+                insert_leaves2(s, &xid, l)
+            })?;
+            xleaves.push((H::hash(&xid.encode()[..]), root));
+            roots.push((xid, root));
+        }
+        self.xroot.update_all(xleaves)?;
 
-        self.xroot.update(H::hash(&xid.encode()[..]), new_root)?;
+        Ok(roots)
+    }
 
-        Ok(new_root)
+    // `write_subtree`, then record the new root in the global tree
+    #[inline(always)]
+    fn write_batch(&mut self, xid: &X, leaves: Vec<(H256, Option<V>)>) -> Result<H256> {
+        self.write_leaves(xid, leaves, false, |s, l| insert_leaves2(s, xid, l))
+            .map(|(root, _)| root)
     }
 
-    fn hash_recompute_all(
+    // `write_batch`, taking the removed leaves if `take`(see `write_subtree`)
+    fn write_leaves(
         &mut self,
         xid: &X,
-        mut nodes: Vec<(H256, MergeValue)>,
-    ) -> Result<H256> {
-        if nodes.is_empty() {
-            return self.store.get_root(xid);
-        }
-
-        for height in 0..=u8::MAX {
-            let mut next_nodes: Vec<(H256, MergeValue)> = Vec::new();
-            let mut i = 0;
-            while i < nodes.len() {
-                let (current_key, current_merge_value) = &nodes[i];
-                i += 1;
-                let parent_key = current_key.parent_path(height);
-                let parent_branch_key = BranchKey::new(height, parent_key);
-
-                // Test for neighbors
-                let mut right = None;
-                if i < nodes.len() && (!current_key.is_right(height)) {
-                    let (neighbor_key, neighbor_value) = &nodes[i];
-                    let mut right_key = *current_key;
-                    right_key.set_bit(height);
-                    if right_key == *neighbor_key {
-                        right = Some(neighbor_value.clone());
-                        i += 1;
-                    }
-                }
+        leaves: Vec<(H256, Option<V>)>,
+        take: bool,
+        insert: impl FnOnce(&mut S2, LeafInserts<V>) -> Result<()>,
+    ) -> Result<(H256, Vec<Option<V>>)> {
+        if leaves.is_empty() {
+            return Ok((self.store.get_root(xid)?, vec![]));
+        }
+        #[cfg(feature = "metrics")]
+        let _timer = crate::metrics::UpdateTimer::start();
+        let (root, taken) = self.write_subtree(xid, leaves, take, insert)?;
+        self.xroot.update(H::hash(&xid.encode()[..]), root)?;
+        Ok((root, taken))
+    }
 
-                let (left, right) = if let Some(right_merge_value) = right {
-                    (current_merge_value.clone(), right_merge_value)
-                } else {
-                    // In case neighbor is not available, fetch from store
-                    if let Some(parent_branch) =
-                        self.store.get_branch(xid, &parent_branch_key)?
-                    {
-                        if current_key.is_right(height) {
-                            (parent_branch.left, current_merge_value.clone())
-                        } else {
-                            (current_merge_value.clone(), parent_branch.right)
-                        }
-                    } else if current_key.is_right(height) {
-                        (MergeValue::zero(), current_merge_value.clone())
-                    } else {
-                        (current_merge_value.clone(), MergeValue::zero())
-                    }
-                };
+    // Compute all branch changes before writing anything,
+    // so a failed read leaves the store untouched.
+    // `leaves` must be sorted and deduped, `None` or a zero value removes the leaf.
+    // If `take` the removed leaves are taken(`Store2::take_leaf`)
+    // and their old values are returned in order.
+    // The stored leaves are written by `insert`, before any removal.
+    fn write_subtree(
+        &mut self,
+        xid: &X,
+        leaves: Vec<(H256, Option<V>)>,
+        take: bool,
+        insert: impl FnOnce(&mut S2, LeafInserts<V>) -> Result<()>,
+    ) -> Result<(H256, Vec<Option<V>>)> {
+        if leaves.is_empty() {
+            return Ok((self.store.get_root(xid)?, vec![]));
+        }
 
-                if !left.is_zero() || !right.is_zero() {
-                    self.store.insert_branch(
-                        xid,
-                        parent_branch_key,
-                        BranchNode {
-                            left: left.clone(),
-                            right: right.clone(),
-                        },
-                    )?;
-                } else {
-                    self.store.remove_branch(xid, &parent_branch_key)?;
-                }
-                next_nodes
-                    .push((parent_key, merge::<H>(height, &parent_key, &left, &right)));
+        let mut nodes = Vec::with_capacity(leaves.len());
+        let mut writes = Vec::with_capacity(leaves.len());
+        for (k, v) in leaves {
+            let hash = leaf_hash(v.as_ref());
+            // skip leaves whose hash is unchanged
+            if self.is_unchanged(xid, &k, hash)? {
+                continue;
+            }
+            nodes.push((k, MergeValue::from_h256(hash)));
+            writes.push((k, v, hash));
+        }
+        if nodes.is_empty() {
+            return Ok((self.store.get_root(xid)?, vec![]));
+        }
+        let (branches, root, leaves_delta) = plan_recompute(&self.layout(xid)?, nodes)?;
+
+        metric!(leaf_writes, writes.len());
+        metric!(branch_writes, branches.len());
+        #[cfg(feature = "tracing")]
+        tracing::debug!(
+            leaves = writes.len(),
+            branches = branches.len(),
+            lowest_height = branches.iter().map(|(k, _)| k.height).min(),
+            "write batch"
+        );
+
+        // notice when value is zero the leaf is deleted, so we do not need to store it
+        let (inserts, removes) = split_writes::<H, _>(writes);
+        insert(&mut self.store, inserts)?;
+        let mut taken = vec![];
+        for k in removes {
+            if take {
+                taken.push(self.store.take_leaf(xid, &k)?);
+            } else {
+                self.store.remove_leaf(xid, &k)?;
+            }
+        }
+        self.store.add_leaves_count(xid, leaves_delta)?;
+        for (branch_key, branch) in branches {
+            if let Some(branch) = branch {
+                self.store.insert_branch(xid, branch_key, branch)?;
+            } else {
+                self.store.remove_branch(xid, &branch_key)?;
             }
-            nodes = next_nodes;
         }
+        self.store.update_root(xid, root)?;
 
-        debug_assert_eq!(nodes.len(), 1);
+        Ok((root, taken))
+    }
+
+    // Reads of the current state under `xid`
+    fn layout<'a>(&'a self, xid: &'a X) -> Result<Layout<'a, H>> {
+        Ok(Layout {
+            depth: 256,
+            fetch_branch: Box::new(move |k| {
+                metric!(branch_reads);
+                self.store.get_branch(xid, k)
+            }),
+            fetch_leaf: Box::new(move |k| self.stored_leaf_hash(xid, k)),
+            root: if self.verify_reads {
+                Some(self.store.get_root(xid)?)
+            } else {
+                None
+            },
+            phantom: PhantomData,
+        })
+    }
+
+    // See `SparseMerkleTree::uses_hash_cache`
+    #[inline(always)]
+    fn uses_hash_cache(&self) -> bool {
+        <V as Value<H>>::CACHE_HASH && !self.verify_reads
+    }
 
-        let root = nodes[0].1.hash::<H>();
-        self.store.update_root(xid, root).map(|_| root)
+    // Hash of the leaf at `k` under `xid`, `None` if it does not exist
+    fn stored_leaf_hash(&self, xid: &X, k: &H256) -> Result<Option<H256>> {
+        if self.uses_hash_cache() {
+            if let Some(hash) = self.store.get_leaf_hash(xid, k)? {
+                metric!(leaf_hash_hits);
+                return Ok(Some(hash));
+            }
+        }
+        Ok(self.store.get_leaf(xid, k)?.map(|v| v.to_h256()))
+    }
+
+    // See `SparseMerkleTree::is_unchanged`
+    fn is_unchanged(&self, xid: &X, k: &H256, hash: H256) -> Result<bool> {
+        if hash.is_zero() {
+            return Ok(false);
+        }
+        if !self.uses_hash_cache() {
+            let old = self.store.get_leaf(xid, k)?.map(|v| v.to_h256());
+            return Ok(old == Some(hash));
+        }
+        let unchanged = self.store.get_leaf_hash(xid, k)? == Some(hash);
+        if unchanged {
+            metric!(leaf_hash_hits);
+        }
+        Ok(unchanged)
     }
 
     /// Get value of a leaf
     /// return zero value if leaf not exists
     #[inline(always)]
     pub fn get(&self, xid: &X, key: &H256) -> Result<Option<V>> {
-        self.store.get_leaf(xid, key)
+        let leaf = self.store.get_leaf(xid, key)?;
+        if self.verify_reads {
+            self.layout(xid)?
+                .verify_leaf(key, leaf.as_ref().map(|v| v.to_h256()))?;
+        }
+        Ok(leaf)
+    }
+
+    /// Same as `get`, with any `Key` type
+    #[inline(always)]
+    pub fn get_by_key<K: Key>(&self, xid: &X, key: &K) -> Result<Option<V>> {
+        self.get(xid, &key.to_path())
+    }
+
+    /// Values of several leaves under `xid`, in the order of `keys`,
+    /// the store may batch the reads(see `Store2::get_leaves`)
+    pub fn get_many(&self, xid: &X, keys: &[H256]) -> Result<Vec<Option<V>>> {
+        let leaves = self.store.get_leaves(xid, keys)?;
+        if self.verify_reads {
+            let layout = self.layout(xid)?;
+            for (key, leaf) in keys.iter().zip(leaves.iter()) {
+                layout.verify_leaf(key, leaf.as_ref().map(|v| v.to_h256()))?;
+            }
+        }
+        Ok(leaves)
+    }
+
+    /// Whether a leaf exists, without decoding its value
+    #[inline(always)]
+    pub fn contains_key(&self, xid: &X, key: &H256) -> Result<bool> {
+        if self.verify_reads {
+            return self.get(xid, key).map(|leaf| leaf.is_some());
+        }
+        self.store.has_leaf(xid, key)
     }
 
     #[inline(always)]
@@ -621,96 +3004,267 @@ impl<X: KeyEnDe, H: Hasher, V: Value<H>, S: Store<H256>, S2: Store2<X, V>>
 
     /// Remove all data under the xid(top-level key).
     pub fn remove_x(&mut self, xid: &X) -> Result<()> {
-        crate::chg_store!(self.store.remove_x(xid));
+        self.store.remove_x(xid)?;
         self.xroot.remove(H::hash(&xid.encode()[..])).map(|_| ())
     }
 
-    /// Generate merkle proof
-    pub fn merkle_proof(&self, xid: &X, mut keys: Vec<H256>) -> Result<MerkleProof> {
-        if keys.is_empty() {
-            return Err(Error::EmptyKeys);
-        }
+    /// Copy the whole subtree under `src` to `dst` without rehashing,
+    /// the old data under `dst` is replaced, return the new global root.
+    pub fn clone_x(&mut self, src: &X, dst: &X) -> Result<H256> {
+        self.store.clone_x(src, dst)?;
+        let root = self.store.get_root(dst)?;
+        self.xroot.update(H::hash(&dst.encode()[..]), root)
+    }
 
-        // sort keys
+    /// Generate merkle proof along with the values of `keys`,
+    /// the values are sorted by key, in the same order as the proof expects.
+    pub fn merkle_proof_with_values(
+        &self,
+        xid: &X,
+        mut keys: Vec<H256>,
+    ) -> Result<ProofWithValues<V>> {
         keys.sort_unstable();
+        let values = keys
+            .iter()
+            .map(|k| self.get(xid, k).map(|v| (*k, v)))
+            .collect::<Result<Vec<_>>>()?;
+        self.merkle_proof(xid, keys).map(|proof| (proof, values))
+    }
 
-        // Collect leaf bitmaps
-        let mut leaves_bitmap: Vec<H256> = Default::default();
-        for current_key in &keys {
-            let mut bitmap = H256::zero();
-            for height in 0..=u8::MAX {
-                let parent_key = current_key.parent_path(height);
-                let parent_branch_key = BranchKey::new(height, parent_key);
-                if let Some(parent_branch) =
-                    self.store.get_branch(xid, &parent_branch_key)?
-                {
-                    let sibling = if current_key.is_right(height) {
-                        parent_branch.left
-                    } else {
-                        parent_branch.right
-                    };
-                    if !sibling.is_zero() {
-                        bitmap.set_bit(height);
-                    }
-                } else {
-                    // The key is not in the tree (support non-inclusion proof)
-                }
+    /// Generate a non-membership proof of `keys` under `xid`,
+    /// return KeyExists error if any of them is in the tree.
+    ///
+    /// Check it with `MerkleProof::verify_absence`.
+    pub fn prove_absence(&self, xid: &X, keys: Vec<H256>) -> Result<MerkleProof> {
+        for k in &keys {
+            if self.get(xid, k)?.is_some() {
+                return Err(Error::KeyExists(*k));
             }
-            leaves_bitmap.push(bitmap);
         }
+        self.merkle_proof(xid, keys)
+    }
 
-        let mut proof: Vec<MergeValue> = Default::default();
-        let mut stack_fork_height = [0u8; MAX_STACK_SIZE]; // store fork height
-        let mut stack_top = 0;
-        let mut leaf_index = 0;
-        while leaf_index < keys.len() {
-            let leaf_key = keys[leaf_index];
-            let fork_height = if leaf_index + 1 < keys.len() {
-                leaf_key.fork_height(&keys[leaf_index + 1])
+    /// Generate a proof that no key under `xid` lies strictly between `start` and `end`,
+    /// return KeyExists error with the smallest such key otherwise.
+    ///
+    /// Check it with `MerkleProof::verify_empty_range`.
+    pub fn prove_empty_range(
+        &self,
+        xid: &X,
+        start: &H256,
+        end: &H256,
+    ) -> Result<MerkleProof> {
+        let subtrees = range_subtrees(start, end)?;
+        let layout = self.layout(xid)?;
+        for (key, height) in &subtrees {
+            let found = if *height == 0 {
+                self.get(xid, key)?.map(|_| *key)
             } else {
-                u8::MAX
+                layout.first_key_in(*key, *height)?
             };
-            for height in 0..=fork_height {
-                if height == fork_height && leaf_index + 1 < keys.len() {
-                    // If it's not final round, we don't need to merge to root (height=255)
-                    break;
-                }
-                let parent_key = leaf_key.parent_path(height);
-                let is_right = leaf_key.is_right(height);
-
-                // has non-zero sibling
-                if stack_top > 0 && stack_fork_height[stack_top - 1] == height {
-                    stack_top -= 1;
-                } else if leaves_bitmap[leaf_index].get_bit(height) {
-                    let parent_branch_key = BranchKey::new(height, parent_key);
-                    if let Some(parent_branch) =
-                        self.store.get_branch(xid, &parent_branch_key)?
-                    {
-                        let sibling = if is_right {
-                            parent_branch.left
-                        } else {
-                            parent_branch.right
-                        };
-                        if !sibling.is_zero() {
-                            proof.push(sibling);
-                        } else {
-                            unreachable!();
-                        }
-                    } else {
-                        // The key is not in the tree (support non-inclusion proof)
-                    }
-                }
+            if let Some(k) = found {
+                return Err(Error::KeyExists(k));
             }
+        }
+        self.merkle_proof(xid, subtrees.into_iter().map(|(k, _)| k).collect())
+    }
+
+    /// Same as `merkle_proof`, with any `Key` type,
+    /// verify it with the `to_path` form of the keys.
+    #[inline(always)]
+    pub fn merkle_proof_by_keys<K: Key>(
+        &self,
+        xid: &X,
+        keys: &[K],
+    ) -> Result<MerkleProof> {
+        self.merkle_proof(xid, keys.iter().map(Key::to_path).collect())
+    }
+
+    /// Generate merkle proof,
+    /// return DuplicatedKeys error if a key is given more than once
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip_all, fields(keys = keys.len())))]
+    #[inline(always)]
+    pub fn merkle_proof(&self, xid: &X, keys: Vec<H256>) -> Result<MerkleProof> {
+        merkle_proof_in(&self.layout(xid)?, keys)
+    }
+
+    /// Generate a two-layer merkle proof of `keys` under `xid`,
+    /// which is checked against the global root(`xroot`).
+    pub fn xmerkle_proof(&self, xid: &X, keys: Vec<H256>) -> Result<XMerkleProof> {
+        let xroot_proof = self.xroot.merkle_proof(vec![H::hash(&xid.encode()[..])])?;
+        let proof = self.merkle_proof(xid, keys)?;
+        Ok(XMerkleProof::new(self.try_root(xid)?, xroot_proof, proof))
+    }
 
-            debug_assert!(stack_top < MAX_STACK_SIZE);
+    /// Generate one proof of `keys` under several xids, each tied to its subtree root
+    /// and all the roots proved together against the global root(`xroot`);
+    /// return DuplicatedKeys error, with the hash of the xid, if an xid is repeated.
+    pub fn merkle_proof_multi_x(
+        &self,
+        items: Vec<(X, Vec<H256>)>,
+    ) -> Result<XMultiMerkleProof> {
+        let mut xid_keys: Vec<H256> = items
+            .iter()
+            .map(|(xid, _)| H::hash(&xid.encode()[..]))
+            .collect();
+        xid_keys.sort_unstable();
+        if let Some(w) = xid_keys.windows(2).find(|w| w[0] == w[1]) {
+            return Err(Error::DuplicatedKeys(w[0]));
+        }
+        let xroot_proof = self.xroot.merkle_proof(xid_keys)?;
+        let proofs = items
+            .into_iter()
+            .map(|(xid, keys)| {
+                Ok((self.try_root(&xid)?, self.merkle_proof(&xid, keys)?))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(XMultiMerkleProof::new(xroot_proof, proofs))
+    }
 
-            stack_fork_height[stack_top] = fork_height;
-            stack_top += 1;
-            leaf_index += 1;
+    /// Generate merkle proof of `keys` under `xid` at a specified version,
+    /// which is checked against `root_by_branch_version`.
+    pub fn merkle_proof_by_branch_version(
+        &self,
+        xid: &X,
+        keys: Vec<H256>,
+        br: BranchName,
+        ver: VersionName,
+    ) -> Result<MerkleProof> {
+        if !self.store.version_exists_on_branch(ver, br) {
+            return Err(Error::VersionNotFound(ver.0.to_vec()));
         }
+        let layout: Layout<'_, H> = Layout {
+            depth: 256,
+            fetch_branch: Box::new(|k| {
+                metric!(branch_reads);
+                self.store.get_branch_by_branch_version(xid, k, br, ver)
+            }),
+            fetch_leaf: Box::new(|k| {
+                Ok(self
+                    .store
+                    .get_leaf_by_branch_version(xid, k, br, ver)?
+                    .map(|v| v.to_h256()))
+            }),
+            root: None,
+            phantom: PhantomData,
+        };
+        merkle_proof_in(&layout, keys)
+    }
+
+    /// Same as `xmerkle_proof` at a specified version,
+    /// which is checked against `xroot_by_branch_version`.
+    pub fn xmerkle_proof_by_branch_version(
+        &self,
+        xid: &X,
+        keys: Vec<H256>,
+        br: BranchName,
+        ver: VersionName,
+    ) -> Result<XMerkleProof> {
+        let xroot_proof = self.xroot.merkle_proof_by_branch_version(
+            vec![H::hash(&xid.encode()[..])],
+            br,
+            ver,
+        )?;
+        let proof = self.merkle_proof_by_branch_version(xid, keys, br, ver)?;
+        Ok(XMerkleProof::new(
+            self.root_by_branch_version(xid, br, ver)?,
+            xroot_proof,
+            proof,
+        ))
+    }
+}
+
+impl<X, H, V, S, S2> SparseMerkleTree2<X, H, V, S, S2>
+where
+    X: KeyEnDe,
+    H: Hasher,
+    V: Value<H> + BoundedValue,
+    S: Store<H256>,
+    S2: Store2<X, V>,
+{
+    /// Same as `update_all`, but the store rejects the batch
+    /// (`Store2::insert_leaves_bounded`) before anything is written
+    /// if a value is encoded longer than `BoundedValue::MAX_ENCODED_LEN`.
+    pub fn update_all_bounded(
+        &mut self,
+        xid: &X,
+        mut leaves: Vec<(H256, V)>,
+    ) -> Result<H256> {
+        #[allow(clippy::let_unit_value)]
+        let () = V::ASSERT_MAX_ENCODED_LEN;
+        // Dedup(only keep the last of each key) and sort leaves
+        leaves.reverse();
+        leaves.sort_by_key(|(a, _)| *a);
+        leaves.dedup_by_key(|(a, _)| *a);
+
+        let leaves = leaves.into_iter().map(|(k, v)| (k, Some(v))).collect();
+        self.write_leaves(xid, leaves, false, |s, l| {
+            s.insert_leaves_bounded(xid, l, V::MAX_ENCODED_LEN)
+        })
+        .map(|(root, _)| root)
+    }
+}
 
-        debug_assert_eq!(stack_top, 1);
+/// Move a single-key tree under `xid` of a double-key tree,
+/// copying its branches, leaves and root as they are.
+/// Only the current state is carried over, not the version history.
+pub fn promote_to_smt2<X, H, V, S0, S, S2>(
+    tree: SparseMerkleTree<H, V, S0>,
+    xid: &X,
+    store: S,
+    store2: S2,
+) -> Result<SparseMerkleTree2<X, H, V, S, S2>>
+where
+    X: KeyEnDe,
+    H: Hasher,
+    V: Value<H>,
+    S0: Store<V>,
+    S: Store<H256>,
+    S2: Store2<X, V>,
+{
+    let mut smt2 = SparseMerkleTree2::new(store, store2);
+    if !smt2.try_is_empty(xid)? {
+        return Err(Error::store(
+            StoreOp::Insert,
+            StoreTarget::Xid(xid.encode().into()),
+            "xid already exists in the target store",
+        ));
+    }
+    smt2.set_verify_reads(tree.verify_reads);
 
-        Ok(MerkleProof::new(leaves_bitmap, proof))
+    for (branch_key, branch) in tree.store.iter_branches() {
+        smt2.store.insert_branch(xid, branch_key, branch)?;
+    }
+    let mut count = 0;
+    for (leaf_key, leaf) in tree.store.iter_leaves() {
+        smt2.store.insert_leaf(xid, leaf_key, leaf)?;
+        count += 1;
     }
+    smt2.store.add_leaves_count(xid, count)?;
+
+    let root = tree.try_root()?;
+    smt2.store.update_root(xid, root)?;
+    smt2.xroot.update(H::hash(&xid.encode()[..]), root)?;
+
+    Ok(smt2)
+}
+
+/// Root of the tree holding `leaves`, computed in memory without any store.
+///
+/// Leaves need not be sorted; a later leaf replaces an earlier one with the same key,
+/// and a deletion value leaves the key out, the same as `update_all` does.
+pub fn compute_root_from_leaves<H: Hasher, V: Value<H>>(
+    leaves: impl IntoIterator<Item = (H256, V)>,
+) -> H256 {
+    let leaves: BTreeMap<H256, H256> = leaves
+        .into_iter()
+        .map(|(k, v)| (k, leaf_hash(Some(&v))))
+        .collect();
+    let nodes = leaves
+        .into_iter()
+        .filter(|(_, v)| !v.is_zero())
+        .map(|(k, v)| (k, MergeValue::from_h256(v)))
+        .collect();
+    // every key fits a 256-level tree, and nothing is visited
+    merge_leaves::<H>(256, nodes, |_, _| Ok(())).unwrap()
 }
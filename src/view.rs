@@ -0,0 +1,94 @@
+//!
+//! A read-only view of a tree pinned to a branch and version.
+//!
+//! `SparseMerkleTree::at` resolves every read of the view against
+//! the state committed at that version, e.g. to serve historical queries
+//! while the tree goes on being written on its default branch.
+//!
+
+use crate::{
+    error::{Error, Result},
+    merkle_proof::MerkleProof,
+    traits::{Hasher, Store, Value},
+    tree::SparseMerkleTree,
+    H256,
+};
+use vsdb::{BranchName, VersionName, VsMgmt};
+
+/// A `SparseMerkleTree` as it was at a version of a branch
+#[derive(Debug)]
+pub struct SmtView<'a, H, V, S: VsMgmt, const DEPTH: usize = 256> {
+    tree: &'a SparseMerkleTree<H, V, S, DEPTH>,
+    br: BranchName<'a>,
+    ver: VersionName<'a>,
+}
+
+impl<H, V, S: VsMgmt, const DEPTH: usize> Clone for SmtView<'_, H, V, S, DEPTH> {
+    #[inline(always)]
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<H, V, S: VsMgmt, const DEPTH: usize> Copy for SmtView<'_, H, V, S, DEPTH> {}
+
+impl<'a, H: Hasher, V: Value<H>, S: Store<V>, const DEPTH: usize>
+    SmtView<'a, H, V, S, DEPTH>
+{
+    /// The pinned branch
+    #[inline(always)]
+    pub fn branch(&self) -> BranchName<'a> {
+        self.br
+    }
+
+    /// The pinned version
+    #[inline(always)]
+    pub fn version(&self) -> VersionName<'a> {
+        self.ver
+    }
+
+    /// Merkle root at the pinned version, panic on store errors, see `try_root`
+    #[inline(always)]
+    pub fn root(&self) -> H256 {
+        self.try_root().unwrap()
+    }
+
+    /// Merkle root at the pinned version, or the error of the store
+    #[inline(always)]
+    pub fn try_root(&self) -> Result<H256> {
+        self.tree.root_by_branch_version(self.br, self.ver)
+    }
+
+    /// Get value of a leaf at the pinned version
+    #[inline(always)]
+    pub fn get(&self, key: &H256) -> Result<Option<V>> {
+        self.tree.get_by_branch_version(key, self.br, self.ver)
+    }
+
+    /// Generate merkle proof at the pinned version,
+    /// which is checked against `root`
+    #[inline(always)]
+    pub fn merkle_proof(&self, keys: Vec<H256>) -> Result<MerkleProof> {
+        self.tree.merkle_proof_by_branch_version(keys, self.br, self.ver)
+    }
+}
+
+impl<H: Hasher, V: Value<H>, S: Store<V>, const DEPTH: usize>
+    SparseMerkleTree<H, V, S, DEPTH>
+{
+    /// A read-only view of the state committed at version `ver` of branch `br`,
+    /// return VersionNotFound error if `br` does not have `ver`.
+    ///
+    /// The state of a version stays the same once a newer one is created,
+    /// while the newest version keeps taking the writes to the tree.
+    pub fn at<'a>(
+        &'a self,
+        br: BranchName<'a>,
+        ver: VersionName<'a>,
+    ) -> Result<SmtView<'a, H, V, S, DEPTH>> {
+        if !self.store.version_exists_on_branch(ver, br) {
+            return Err(Error::VersionNotFound(ver.0.to_vec()));
+        }
+        Ok(SmtView { tree: self, br, ver })
+    }
+}